@@ -1,20 +1,29 @@
 use anyhow::anyhow;
-use itertools::Itertools as _;
 use moka::{
     Expiry,
     future::{Cache, CacheBuilder},
+    notification::RemovalCause,
 };
+use rand::RngExt;
 use reso_dns::{
-    DnsMessage, DnsRecord, DnsResponseCode,
+    DnsMessage, DnsRecord, DnsResponseCode, RRset,
     domain_name::DomainName,
     message::{ClassType, DnsRecordData, RecordType},
 };
+use serde::Serialize;
 use std::{
     hash::Hash,
-    sync::Arc,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU8, AtomicU64, Ordering},
+    },
     time::{Duration, Instant},
 };
 
+mod snapshot;
+
+pub use snapshot::CacheSnapshot;
+
 /// Cache key for positive entries.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct CacheKey {
@@ -42,6 +51,24 @@ enum NegativeCacheKey {
     },
 }
 
+/// Key of the record types cached for a name.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+struct NameKey {
+    name: DomainName,
+    class_type: ClassType,
+    do_bit: bool,
+}
+
+impl From<&CacheKey> for NameKey {
+    fn from(key: &CacheKey) -> Self {
+        Self {
+            name: key.name.clone(),
+            class_type: key.class_type,
+            do_bit: key.do_bit,
+        }
+    }
+}
+
 fn has_do_bit(message: &DnsMessage) -> bool {
     message.edns().as_ref().is_some_and(|e| e.do_bit())
 }
@@ -93,6 +120,9 @@ pub struct NegativeEntry {
     kind: NegKind,
     /// Expires at
     expires_at: Instant,
+    /// End of the window in which the entry may still be served stale, see
+    /// [`DnsMessageCache::set_negative_stale_window`].
+    stale_until: Instant,
     /// The SOA that came with the denial (https://datatracker.ietf.org/doc/html/rfc2308#section-5)
     soa_record: DnsRecord,
     /// CNAME chain from the answer section; the denial is about the last name in it (https://datatracker.ietf.org/doc/html/rfc2308#section-1)
@@ -112,11 +142,52 @@ pub struct CacheEntry {
 const MIN_TTL_SECS: u32 = 30;
 /// Maximum TTL (seconds) applied to all cached entries.
 const MAX_TTL_SECS: u32 = 86_400;
+/// TTL (seconds) of answers served stale, as recommended by RFC 8767.
+const STALE_TTL_SECS: u32 = 30;
+
+/// Counters of entries removed from the cache.
+#[derive(Debug, Default)]
+struct RemovalCounters {
+    size_evictions: AtomicU64,
+    expirations: AtomicU64,
+}
+
+impl RemovalCounters {
+    fn record(&self, cause: RemovalCause) {
+        let counter = match cause {
+            RemovalCause::Size => &self.size_evictions,
+            RemovalCause::Expired => &self.expirations,
+            RemovalCause::Explicit | RemovalCause::Replaced => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Cache occupancy and removal counts, of positive and negative entries combined.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    /// Number of entries currently cached.
+    pub entries: u64,
+    /// Entries evicted to stay within the size limit. A high count means the cache is undersized.
+    pub size_evictions: u64,
+    /// Entries removed because their TTL ran out.
+    pub expirations: u64,
+}
 
 /// A RFC 2308 compliant DNS message cache.
 pub struct DnsMessageCache {
     cache: Cache<CacheKey, CacheEntry>,
     negative_cache: Cache<NegativeCacheKey, NegativeEntry>,
+    /// Types of the RRsets cached for each name, so ANY queries can be answered with all of them. Entries may
+    /// outlive the RRsets they list, those are skipped on lookup.
+    record_types: Cache<NameKey, Arc<[RecordType]>>,
+    /// Maximum share of the TTL, in percent, randomly cut from each entry's lifetime.
+    ttl_jitter_percent: AtomicU8,
+    /// Minimum TTLs of names and their subdomains, see [`DnsMessageCache::set_ttl_floors`].
+    ttl_floors: RwLock<Vec<(DomainName, u32)>>,
+    /// How long after expiry negative entries may be served stale, in seconds.
+    negative_stale_secs: AtomicU64,
+    removals: Arc<RemovalCounters>,
 }
 
 impl Default for DnsMessageCache {
@@ -127,16 +198,128 @@ impl Default for DnsMessageCache {
 
 impl DnsMessageCache {
     pub fn new(max_entries: u64) -> Self {
+        let removals = Arc::new(RemovalCounters::default());
+
+        let counters = removals.clone();
+        let cache = CacheBuilder::new(max_entries)
+            .expire_after(CacheExpiry)
+            .eviction_listener(move |_, _, cause| counters.record(cause))
+            .build();
+
+        let counters = removals.clone();
+        let negative_cache = CacheBuilder::new(max_entries)
+            .expire_after(CacheExpiry)
+            .eviction_listener(move |_, _, cause| counters.record(cause))
+            .build();
+
+        // no RRset is cached for longer, every insert refreshes the entry of its name.
+        let record_types = CacheBuilder::new(max_entries)
+            .time_to_live(Duration::from_secs(MAX_TTL_SECS.into()))
+            .build();
+
         Self {
-            cache: CacheBuilder::new(max_entries).expire_after(CacheExpiry).build(),
-            negative_cache: CacheBuilder::new(max_entries).expire_after(CacheExpiry).build(),
+            cache,
+            negative_cache,
+            record_types,
+            ttl_jitter_percent: AtomicU8::new(0),
+            ttl_floors: RwLock::default(),
+            negative_stale_secs: AtomicU64::new(0),
+            removals,
+        }
+    }
+
+    /// Current entry count and how many entries were evicted or expired so far.
+    ///
+    /// The counts are updated as the cache does its housekeeping, so they may lag slightly behind.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.cache.entry_count() + self.negative_cache.entry_count(),
+            size_evictions: self.removals.size_evictions.load(Ordering::Relaxed),
+            expirations: self.removals.expirations.load(Ordering::Relaxed),
         }
     }
 
+    /// Shorten each entry's lifetime by a random amount of up to `percent` of its TTL, so records that share
+    /// a TTL don't all expire at the same time. The lifetime is never extended past the TTL.
+    pub fn with_ttl_jitter(self, percent: u8) -> Self {
+        self.set_ttl_jitter(percent);
+        self
+    }
+
+    /// Change the jitter set by [`DnsMessageCache::with_ttl_jitter`]. Only applies to entries inserted afterwards.
+    pub fn set_ttl_jitter(&self, percent: u8) {
+        self.ttl_jitter_percent.store(percent.min(100), Ordering::Relaxed);
+    }
+
+    /// Cache answers for each name and its subdomains for at least the given number of seconds, even if
+    /// upstream sent a lower TTL. The most specific name applies. Replaces the floors set before.
+    ///
+    /// Meant for high-volume names like CDN endpoints, where serving slightly stale answers is worth the
+    /// upstream queries saved.
+    pub fn set_ttl_floors(&self, floors: impl IntoIterator<Item = (DomainName, u32)>) {
+        let mut floors: Vec<_> = floors.into_iter().collect();
+        // most specific names first, so the first match wins.
+        floors.sort_by_key(|(name, _)| std::cmp::Reverse(name.label_count()));
+        *self.ttl_floors.write().unwrap_or_else(|e| e.into_inner()) = floors;
+    }
+
+    /// Keep negative entries for `window` after they expire, so [`DnsMessageCache::lookup_stale_negative`] can
+    /// still answer with them while upstreams are unreachable (RFC 8767). Zero disables serving stale.
+    ///
+    /// Only applies to entries inserted afterwards.
+    pub fn set_negative_stale_window(&self, window: Duration) {
+        self.negative_stale_secs.store(window.as_secs(), Ordering::Relaxed);
+    }
+
+    /// Negative entry of `key`, fresh or within its stale window, with its TTLs lowered for serving stale.
+    ///
+    /// Meant as a fallback when resolving failed, regular lookups never return stale entries.
+    pub async fn lookup_stale_negative(&self, key: &CacheKey) -> Option<NegativeResult> {
+        self.stale_negative_entry(Instant::now(), key).await
+    }
+
+    async fn stale_negative_entry(&self, now: Instant, key: &CacheKey) -> Option<NegativeResult> {
+        let entry = self.negative_entry(key).await?;
+        if entry.stale_until <= now {
+            return None;
+        }
+
+        Some(negative_result(&entry, STALE_TTL_SECS))
+    }
+
+    /// TTL an answer for `name` is cached with: `ttl` clamped to the global limits, raised to the floor of
+    /// `name` if one is set.
+    fn cache_ttl(&self, name: &DomainName, ttl: u32) -> u32 {
+        let ttl = ttl.clamp(MIN_TTL_SECS, MAX_TTL_SECS);
+        let floors = self.ttl_floors.read().unwrap_or_else(|e| e.into_inner());
+        match floors.iter().find(|(suffix, _)| name.is_subdomain_of(suffix)) {
+            Some((_, floor)) => ttl.max(*floor).min(MAX_TTL_SECS),
+            None => ttl,
+        }
+    }
+
+    /// Expiry for an entry inserted now with the given TTL, with jitter applied.
+    fn expires_at(&self, ttl_secs: u64) -> Instant {
+        let ttl = Duration::from_secs(ttl_secs);
+        let percent = self.ttl_jitter_percent.load(Ordering::Relaxed);
+        if percent == 0 {
+            return Instant::now() + ttl;
+        }
+
+        let max_jitter = ttl.as_millis() as u64 * percent as u64 / 100;
+        let jitter = rand::rng().random_range(0..=max_jitter);
+        Instant::now() + ttl - Duration::from_millis(jitter)
+    }
+
     pub async fn lookup(&self, key: &CacheKey) -> CacheResult {
         let now = Instant::now();
 
-        if let Some(res) = self.handle_entry(now, key).await {
+        let positive = if key.record_type == RecordType::ANY {
+            self.handle_any(now, key).await
+        } else {
+            self.handle_entry(now, key).await
+        };
+        if let Some(res) = positive {
             return res;
         }
 
@@ -148,6 +331,20 @@ impl DnsMessageCache {
     }
 
     async fn handle_negative_entry(&self, now: Instant, key: &CacheKey) -> Option<CacheResult> {
+        let entry = self.negative_entry(key).await?;
+
+        if entry.expires_at <= now {
+            return None;
+        }
+
+        let remaining = entry.expires_at.saturating_duration_since(now).as_secs();
+        let updated_ttl = remaining.min(u32::MAX as u64) as u32;
+
+        Some(CacheResult::Negative(negative_result(&entry, updated_ttl)))
+    }
+
+    /// NXDOMAIN or NODATA entry answering `key`, regardless of whether it expired.
+    async fn negative_entry(&self, key: &CacheKey) -> Option<NegativeEntry> {
         let nxdomain_key = NegativeCacheKey::NxDomain {
             qname: key.name.clone(),
             class_type: key.class_type,
@@ -161,47 +358,25 @@ impl DnsMessageCache {
         };
 
         // QTYPE=ANY cannot have nodata, only NXDOMAIN (or positive).
-        let entry = if key.record_type == RecordType::ANY {
-            self.negative_cache.get(&nxdomain_key).await?
-        } else {
-            let (nx, nd) = tokio::join!(
-                self.negative_cache.get(&nxdomain_key),
-                self.negative_cache.get(&no_data_key),
-            );
-            match (nx, nd) {
-                (Some(e), _) => e,
-                (None, Some(e)) => e,
-                (None, None) => return None,
-            }
-        };
-
-        let remaining = entry.expires_at.saturating_duration_since(now).as_secs();
-        let updated_ttl = remaining.min(u32::MAX as u64) as u32;
-
-        // The 30s floor can leave more time remaining than a record's original TTL, never serve a TTL higher than upstream sent.
-        let mut soa_record = entry.soa_record.clone();
-        soa_record.ttl = updated_ttl.min(soa_record.ttl);
-
-        let answer_records: Vec<DnsRecord> = entry
-            .chain
-            .iter()
-            .cloned()
-            .map(|mut r| {
-                r.ttl = updated_ttl.min(r.ttl);
-                r
-            })
-            .collect();
+        if key.record_type == RecordType::ANY {
+            return self.negative_cache.get(&nxdomain_key).await;
+        }
 
-        Some(CacheResult::Negative(NegativeResult {
-            kind: entry.kind,
-            soa_record,
-            answer_records: answer_records.into(),
-        }))
+        let (nx, nd) = tokio::join!(
+            self.negative_cache.get(&nxdomain_key),
+            self.negative_cache.get(&no_data_key),
+        );
+        nx.or(nd)
     }
 
     async fn handle_entry(&self, now: Instant, key: &CacheKey) -> Option<CacheResult> {
         let entry = self.cache.get(key).await?;
 
+        // moka evicts lazily, never serve an entry that outlived its TTL.
+        if entry.expires_at <= now {
+            return None;
+        }
+
         let remaining = entry.expires_at.saturating_duration_since(now).as_secs();
         let updated_ttl = remaining.min(u32::MAX as u64) as u32;
 
@@ -211,6 +386,51 @@ impl DnsMessageCache {
         })
     }
 
+    /// Union of the RRsets cached for the name of `key`, answering an ANY query with whatever is cached. The TTL
+    /// is the lowest of the RRsets.
+    async fn handle_any(&self, now: Instant, key: &CacheKey) -> Option<CacheResult> {
+        let record_types = self.record_types.get(&NameKey::from(key)).await?;
+
+        let mut records = Vec::new();
+        let mut min_ttl: Option<u32> = None;
+        for &record_type in record_types.iter() {
+            let rrset_key = CacheKey {
+                record_type,
+                ..key.clone()
+            };
+            if let Some(CacheResult::Positive { records: rrset, ttl }) = self.handle_entry(now, &rrset_key).await {
+                // entries cached for a query can hold a CNAME chain that continues at other names.
+                records.extend(rrset.iter().filter(|r| r.name == key.name).cloned());
+                min_ttl = Some(min_ttl.map_or(ttl, |m| m.min(ttl)));
+            }
+        }
+
+        if records.is_empty() {
+            return None;
+        }
+
+        Some(CacheResult::Positive {
+            records: records.into(),
+            ttl: min_ttl.unwrap_or(0),
+        })
+    }
+
+    /// Remember that an RRset of `key`'s type is cached for its name.
+    async fn index_rrset(&self, key: &CacheKey) {
+        let record_type = key.record_type;
+        self.record_types
+            .entry(NameKey::from(key))
+            .and_upsert_with(|existing| {
+                let record_types: Arc<[RecordType]> = match existing {
+                    Some(entry) if entry.value().contains(&record_type) => entry.into_value(),
+                    Some(entry) => entry.value().iter().copied().chain([record_type]).collect(),
+                    None => Arc::from([record_type]),
+                };
+                std::future::ready(record_types)
+            })
+            .await;
+    }
+
     pub async fn insert(&self, query_msg: &DnsMessage, resp_msg: &DnsMessage) -> bool {
         // Don't cache truncated or non-responses.
         if resp_msg.flags.truncated || !resp_msg.flags.response {
@@ -233,37 +453,34 @@ impl DnsMessageCache {
         let mut inserted = false;
         let mut min_ttl: Option<u32> = None;
 
-        for ((name, class, record_type), records) in resp_msg
-            .answers()
-            .iter()
-            .into_group_map_by(|r| (r.name.clone(), r.class, r.record_type))
-        {
-            if matches!(record_type, RecordType::OPT) {
+        for rrset in RRset::group(resp_msg.answers()) {
+            if matches!(rrset.record_type, RecordType::OPT) {
                 continue;
             }
 
-            let ttl = records.iter().map(|r| r.ttl()).min().unwrap_or(0);
+            let ttl = rrset.min_ttl();
             if ttl == 0 {
                 continue;
             }
-            let ttl = ttl.clamp(MIN_TTL_SECS, MAX_TTL_SECS);
+            let ttl = self.cache_ttl(rrset.name, ttl);
             min_ttl = Some(min_ttl.map_or(ttl, |m| m.min(ttl)));
 
             let cache_key = CacheKey {
-                name: name.clone(),
-                class_type: class,
-                record_type,
+                name: rrset.name.clone(),
+                class_type: rrset.class,
+                record_type: rrset.record_type,
                 do_bit: has_do_bit(query_msg),
             };
 
-            let expires_at = Instant::now() + Duration::from_secs(ttl.into());
+            let expires_at = self.expires_at(ttl.into());
             let entry = CacheEntry {
-                name,
+                name: rrset.name.clone(),
                 record_type: cache_key.record_type,
-                records: records.into_iter().cloned().collect::<Vec<_>>().into(),
+                records: dedup_records(rrset.records).into(),
                 expires_at,
             };
 
+            self.index_rrset(&cache_key).await;
             self.cache.insert(cache_key, entry).await;
             inserted = true;
         }
@@ -286,9 +503,9 @@ impl DnsMessageCache {
                     .collect();
                 let ttl = cacheable.iter().map(|r| r.ttl()).min().unwrap_or(0);
                 if ttl > 0 {
-                    let ttl = ttl.clamp(MIN_TTL_SECS, MAX_TTL_SECS);
+                    let ttl = self.cache_ttl(&query_key.name, ttl);
                     min_ttl = Some(min_ttl.map_or(ttl, |m| m.min(ttl)));
-                    let expires_at = Instant::now() + Duration::from_secs(ttl.into());
+                    let expires_at = self.expires_at(ttl.into());
                     let entry = CacheEntry {
                         name: query_key.name.clone(),
                         record_type: query_key.record_type,
@@ -357,9 +574,22 @@ impl DnsMessageCache {
             },
         };
 
+        let expires_at = self.expires_at(ttl);
+        let mut stale_until = expires_at + Duration::from_secs(self.negative_stale_secs.load(Ordering::Relaxed));
+
+        // A stale answer served while upstreams are down comes back here, it must not extend the stale window.
+        if stale_until > expires_at
+            && let Some(existing) = self.negative_cache.get(&neg_key).await
+            && existing.expires_at <= Instant::now()
+            && existing.soa_record.same_rdata(soa_record)
+        {
+            stale_until = existing.stale_until.max(expires_at);
+        }
+
         let negative_entry = NegativeEntry {
             kind,
-            expires_at: Instant::now() + Duration::from_secs(ttl),
+            expires_at,
+            stale_until,
             soa_record: soa_record.clone(),
             chain: chain.into(),
         };
@@ -370,6 +600,41 @@ impl DnsMessageCache {
     }
 }
 
+/// Result of a negative entry, served with at most `ttl`.
+fn negative_result(entry: &NegativeEntry, ttl: u32) -> NegativeResult {
+    // The 30s floor can leave more time remaining than a record's original TTL, never serve a TTL higher than upstream sent.
+    let mut soa_record = entry.soa_record.clone();
+    soa_record.ttl = ttl.min(soa_record.ttl);
+
+    let answer_records: Vec<DnsRecord> = entry
+        .chain
+        .iter()
+        .cloned()
+        .map(|mut r| {
+            r.ttl = ttl.min(r.ttl);
+            r
+        })
+        .collect();
+
+    NegativeResult {
+        kind: entry.kind.clone(),
+        soa_record,
+        answer_records: answer_records.into(),
+    }
+}
+
+/// Drop repeated records from an RRset, keeping the first occurrence of each. Records only differing in
+/// TTL are repeats too.
+fn dedup_records(records: Vec<&DnsRecord>) -> Vec<DnsRecord> {
+    let mut unique: Vec<DnsRecord> = Vec::with_capacity(records.len());
+    for record in records {
+        if !unique.iter().any(|seen| seen.same_rdata(record)) {
+            unique.push(record.clone());
+        }
+    }
+    unique
+}
+
 /// Check if a resp is of type NODATA (https://datatracker.ietf.org/doc/html/rfc2308#section-2.2)
 fn is_nodata(query_msg: &DnsMessage, resp_msg: &DnsMessage) -> bool {
     let Some(question) = query_msg.questions().first() else {
@@ -397,32 +662,55 @@ fn is_nodata(query_msg: &DnsMessage, resp_msg: &DnsMessage) -> bool {
 }
 
 trait Expirable {
-    fn expires_at(&self) -> Instant;
+    /// When the cache may drop the entry.
+    fn evict_at(&self) -> Instant;
 }
 
 impl Expirable for CacheEntry {
-    fn expires_at(&self) -> Instant {
+    fn evict_at(&self) -> Instant {
         self.expires_at
     }
 }
 
 impl Expirable for NegativeEntry {
-    fn expires_at(&self) -> Instant {
-        self.expires_at
+    // kept around until its stale window ends, fresh lookups check `expires_at` themselves.
+    fn evict_at(&self) -> Instant {
+        self.stale_until
     }
 }
+/// Ties moka's eviction to the DNS TTL of the entry.
+///
+/// Every event recomputes the remaining lifetime from the entry's `expires_at`, so neither reads nor
+/// updates can keep an entry alive past its TTL.
 struct CacheExpiry;
 
+impl CacheExpiry {
+    fn remaining(value: &impl Expirable) -> Option<Duration> {
+        Some(value.evict_at().saturating_duration_since(Instant::now()))
+    }
+}
+
 impl<K, V> Expiry<K, V> for CacheExpiry
 where
     V: Expirable,
 {
     fn expire_after_create(&self, _: &K, value: &V, _: std::time::Instant) -> Option<Duration> {
-        Some(value.expires_at().saturating_duration_since(Instant::now()))
+        Self::remaining(value)
+    }
+
+    fn expire_after_read(
+        &self,
+        _: &K,
+        value: &V,
+        _: std::time::Instant,
+        _: Option<Duration>,
+        _: std::time::Instant,
+    ) -> Option<Duration> {
+        Self::remaining(value)
     }
 
     fn expire_after_update(&self, _: &K, value: &V, _: std::time::Instant, _: Option<Duration>) -> Option<Duration> {
-        Some(value.expires_at().saturating_duration_since(Instant::now()))
+        Self::remaining(value)
     }
 }
 
@@ -508,6 +796,40 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn filling_past_capacity_counts_size_evictions() {
+        let cache = DnsMessageCache::new(4);
+
+        for i in 0..32 {
+            let qname = format!("host{i}.example.com");
+            let query = DnsMessageBuilder::new()
+                .with_id(i)
+                .with_flags(query_flags())
+                .add_question(question(&qname, RecordType::A))
+                .build();
+            let response = DnsMessageBuilder::new()
+                .with_id(i)
+                .with_flags(response_flags())
+                .with_response(DnsResponseCode::NoError)
+                .add_question(question(&qname, RecordType::A))
+                .add_answer(DnsRecord::new(
+                    name(&qname),
+                    RecordType::A,
+                    ClassType::IN,
+                    300,
+                    DnsRecordData::Ipv4(std::net::Ipv4Addr::new(192, 0, 2, i as u8)),
+                ))
+                .build();
+            cache.insert(&query, &response).await;
+        }
+        cache.cache.run_pending_tasks().await;
+
+        let stats = cache.stats();
+        assert!(stats.entries <= 4, "unexpected entry count {}", stats.entries);
+        assert_eq!(stats.entries + stats.size_evictions, 32);
+        assert_eq!(stats.expirations, 0);
+    }
+
     // The min-TTL floor keeps negative entries alive past short SOA TTLs.
     #[tokio::test]
     async fn negative_entry_ttl_floor_outlives_short_soa() {
@@ -536,4 +858,331 @@ mod tests {
 
         assert!(matches!(cache.lookup(&key).await, CacheResult::Negative(_)));
     }
+
+    fn nxdomain(qname: &str, soa_ttl: u32) -> (DnsMessage, DnsMessage) {
+        let query = DnsMessageBuilder::new()
+            .with_id(3)
+            .with_flags(query_flags())
+            .add_question(question(qname, RecordType::A))
+            .build();
+
+        let response = DnsMessageBuilder::new()
+            .with_id(3)
+            .with_flags(response_flags())
+            .with_response(DnsResponseCode::NxDomain)
+            .add_question(question(qname, RecordType::A))
+            .add_authority_record(soa_record("example.com", soa_ttl, soa_ttl))
+            .build();
+
+        (query, response)
+    }
+
+    #[tokio::test]
+    async fn expired_negative_entry_is_served_stale_within_window() {
+        let cache = DnsMessageCache::default();
+        cache.set_negative_stale_window(Duration::from_secs(3600));
+
+        let (query, response) = nxdomain("gone.example.com", 300);
+        cache.insert(&query, &response).await;
+
+        let key = CacheKey::try_from(&query).unwrap();
+        let past_expiry = Instant::now() + Duration::from_secs(301);
+
+        assert!(cache.handle_negative_entry(past_expiry, &key).await.is_none());
+
+        let stale = cache.stale_negative_entry(past_expiry, &key).await.unwrap();
+        assert_eq!(stale.kind, NegKind::NxDomain);
+        assert!(stale.soa_record.ttl <= STALE_TTL_SECS);
+
+        let past_window = Instant::now() + Duration::from_secs(301 + 3600);
+        assert!(cache.stale_negative_entry(past_window, &key).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn negative_entries_are_not_served_stale_by_default() {
+        let cache = DnsMessageCache::default();
+
+        let (query, response) = nxdomain("gone.example.com", 300);
+        cache.insert(&query, &response).await;
+
+        let key = CacheKey::try_from(&query).unwrap();
+        assert!(cache.lookup_stale_negative(&key).await.is_some());
+
+        let past_expiry = Instant::now() + Duration::from_secs(301);
+        assert!(cache.stale_negative_entry(past_expiry, &key).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn reinserting_a_stale_answer_does_not_extend_the_window() {
+        let cache = DnsMessageCache::default();
+        cache.set_negative_stale_window(Duration::from_secs(3600));
+
+        let (query, response) = nxdomain("gone.example.com", 1);
+        cache.insert(&query, &response).await;
+        let key = CacheKey::try_from(&query).unwrap();
+        let first = cache.negative_entry(&key).await.unwrap();
+
+        // the TTL floor keeps it fresh for 30s, expire it by hand.
+        let mut expired = first.clone();
+        expired.expires_at = Instant::now() - Duration::from_secs(1);
+        let neg_key = NegativeCacheKey::NxDomain {
+            qname: key.name.clone(),
+            class_type: key.class_type,
+            do_bit: key.do_bit,
+        };
+        cache.negative_cache.insert(neg_key, expired).await;
+
+        cache.insert(&query, &response).await;
+        let second = cache.negative_entry(&key).await.unwrap();
+        assert_eq!(second.stale_until, first.stale_until);
+        assert!(second.expires_at > Instant::now());
+    }
+
+    #[test]
+    fn expiry_read_never_extends_past_ttl() {
+        let now = Instant::now();
+        let entry = CacheEntry {
+            name: name("example.com"),
+            record_type: RecordType::A,
+            records: Arc::new([]),
+            expires_at: now + Duration::from_secs(5),
+        };
+
+        let remaining = CacheExpiry
+            .expire_after_read(&(), &entry, now, Some(Duration::from_secs(3600)), now)
+            .unwrap();
+        assert!(remaining <= Duration::from_secs(5));
+
+        let expired = CacheEntry {
+            expires_at: now - Duration::from_secs(1),
+            ..entry
+        };
+        let remaining = CacheExpiry
+            .expire_after_read(&(), &expired, now, Some(Duration::from_secs(3600)), now)
+            .unwrap();
+        assert_eq!(remaining, Duration::ZERO);
+    }
+
+    /// Remaining TTL of the cached A record of `qname` after answering it with `ttl`.
+    async fn cached_a_ttl(cache: &DnsMessageCache, qname: &str, ttl: u32) -> u32 {
+        let query = DnsMessageBuilder::new()
+            .with_id(5)
+            .with_flags(query_flags())
+            .add_question(question(qname, RecordType::A))
+            .build();
+        let response = DnsMessageBuilder::new()
+            .with_id(5)
+            .with_flags(response_flags())
+            .with_response(DnsResponseCode::NoError)
+            .add_question(question(qname, RecordType::A))
+            .add_answer(DnsRecord::new(
+                name(qname),
+                RecordType::A,
+                ClassType::IN,
+                ttl,
+                DnsRecordData::Ipv4(std::net::Ipv4Addr::new(192, 0, 2, 1)),
+            ))
+            .build();
+
+        assert!(cache.insert(&query, &response).await);
+        match cache.lookup(&CacheKey::try_from(&query).unwrap()).await {
+            CacheResult::Positive { ttl, .. } => ttl,
+            other => panic!("expected positive hit, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn ttl_floor_raises_matching_names_only() {
+        let cache = DnsMessageCache::default();
+        cache.set_ttl_floors([(name("cdn.example.com"), 3600), (name("img.cdn.example.com"), 600)]);
+
+        let floored = cached_a_ttl(&cache, "edge.cdn.example.com", 60).await;
+        assert!((3599..=3600).contains(&floored), "floored ttl {floored}");
+
+        // the most specific floor applies, even if it is lower.
+        let specific = cached_a_ttl(&cache, "a.img.cdn.example.com", 60).await;
+        assert!((599..=600).contains(&specific), "specific ttl {specific}");
+
+        // a TTL above the floor is kept.
+        let above = cached_a_ttl(&cache, "static.cdn.example.com", 7200).await;
+        assert!((7199..=7200).contains(&above), "above ttl {above}");
+
+        // other names only get the global clamp.
+        assert!(cached_a_ttl(&cache, "example.net", 60).await <= 60);
+        assert!(cached_a_ttl(&cache, "short.example.net", 5).await <= MIN_TTL_SECS);
+    }
+
+    #[tokio::test]
+    async fn duplicate_records_are_cached_once() {
+        let cache = DnsMessageCache::default();
+
+        let query = DnsMessageBuilder::new()
+            .with_id(4)
+            .with_flags(query_flags())
+            .add_question(question("example.com", RecordType::A))
+            .build();
+
+        let a_record = |last_octet, ttl| {
+            DnsRecord::new(
+                name("example.com"),
+                RecordType::A,
+                ClassType::IN,
+                ttl,
+                DnsRecordData::Ipv4(std::net::Ipv4Addr::new(192, 0, 2, last_octet)),
+            )
+        };
+
+        let response = DnsMessageBuilder::new()
+            .with_id(4)
+            .with_flags(response_flags())
+            .with_response(DnsResponseCode::NoError)
+            .add_question(question("example.com", RecordType::A))
+            .add_answer(a_record(1, 300))
+            .add_answer(a_record(2, 300))
+            .add_answer(a_record(1, 300))
+            .add_answer(a_record(1, 600))
+            .build();
+
+        cache.insert(&query, &response).await;
+
+        let key = CacheKey::try_from(&query).unwrap();
+        match cache.lookup(&key).await {
+            CacheResult::Positive { records, .. } => {
+                assert_eq!(records.as_ref(), [a_record(1, 300), a_record(2, 300)]);
+            }
+            other => panic!("expected positive hit, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn any_is_answered_with_all_cached_rrsets() {
+        let cache = DnsMessageCache::default();
+
+        let a = DnsRecord::new(
+            name("example.com"),
+            RecordType::A,
+            ClassType::IN,
+            300,
+            DnsRecordData::Ipv4(std::net::Ipv4Addr::new(192, 0, 2, 1)),
+        );
+        let mx = DnsRecord::new(
+            name("example.com"),
+            RecordType::MX,
+            ClassType::IN,
+            600,
+            DnsRecordData::MX {
+                priority: 10,
+                host: name("mail.example.com"),
+            },
+        );
+
+        for (id, record) in [(1, &a), (2, &mx)] {
+            let query = DnsMessageBuilder::new()
+                .with_id(id)
+                .with_flags(query_flags())
+                .add_question(question("example.com", record.record_type))
+                .build();
+            let response = DnsMessageBuilder::new()
+                .with_id(id)
+                .with_flags(response_flags())
+                .with_response(DnsResponseCode::NoError)
+                .add_question(question("example.com", record.record_type))
+                .add_answer(record.clone())
+                .build();
+            assert!(cache.insert(&query, &response).await);
+        }
+
+        let any = DnsMessageBuilder::new()
+            .with_flags(query_flags())
+            .add_question(question("example.com", RecordType::ANY))
+            .build();
+        match cache.lookup(&CacheKey::try_from(&any).unwrap()).await {
+            CacheResult::Positive { records, ttl } => {
+                assert_eq!(records.as_ref(), [a, mx]);
+                assert!(ttl <= 300, "ttl {ttl}");
+            }
+            other => panic!("expected positive hit, got {other:?}"),
+        }
+
+        // nothing is cached for other names.
+        let other = DnsMessageBuilder::new()
+            .with_flags(query_flags())
+            .add_question(question("example.net", RecordType::ANY))
+            .build();
+        assert_eq!(
+            cache.lookup(&CacheKey::try_from(&other).unwrap()).await,
+            CacheResult::Miss
+        );
+    }
+
+    // Entries moka hasn't evicted yet must not be served once their TTL has passed.
+    #[tokio::test]
+    async fn expired_entry_is_not_served() {
+        let cache = DnsMessageCache::default();
+
+        let query = DnsMessageBuilder::new()
+            .with_id(3)
+            .with_flags(query_flags())
+            .add_question(question("example.com", RecordType::A))
+            .build();
+
+        let response = DnsMessageBuilder::new()
+            .with_id(3)
+            .with_flags(response_flags())
+            .with_response(DnsResponseCode::NoError)
+            .add_question(question("example.com", RecordType::A))
+            .add_answer(DnsRecord::new(
+                name("example.com"),
+                RecordType::A,
+                ClassType::IN,
+                60,
+                DnsRecordData::Ipv4(std::net::Ipv4Addr::new(192, 0, 2, 1)),
+            ))
+            .build();
+
+        cache.insert(&query, &response).await;
+
+        let key = CacheKey::try_from(&query).unwrap();
+        assert!(cache.handle_entry(Instant::now(), &key).await.is_some());
+        // Reading the entry repeatedly must not push it past its TTL.
+        let after_ttl = Instant::now() + Duration::from_secs(61);
+        assert!(cache.handle_entry(after_ttl, &key).await.is_none());
+    }
+
+    // Jitter spreads expiries out, but only ever below the TTL.
+    #[tokio::test]
+    async fn ttl_jitter_only_shortens_lifetime() {
+        let cache = DnsMessageCache::default().with_ttl_jitter(5);
+
+        let query = DnsMessageBuilder::new()
+            .with_id(4)
+            .with_flags(query_flags())
+            .add_question(question("example.com", RecordType::A))
+            .build();
+
+        let response = DnsMessageBuilder::new()
+            .with_id(4)
+            .with_flags(response_flags())
+            .with_response(DnsResponseCode::NoError)
+            .add_question(question("example.com", RecordType::A))
+            .add_answer(DnsRecord::new(
+                name("example.com"),
+                RecordType::A,
+                ClassType::IN,
+                1000,
+                DnsRecordData::Ipv4(std::net::Ipv4Addr::new(192, 0, 2, 1)),
+            ))
+            .build();
+
+        let key = CacheKey::try_from(&query).unwrap();
+        for _ in 0..20 {
+            let before = Instant::now();
+            cache.insert(&query, &response).await;
+            let after = Instant::now();
+
+            let expires_at = cache.cache.get(&key).await.unwrap().expires_at;
+            assert!(expires_at <= after + Duration::from_secs(1000));
+            assert!(expires_at >= before + Duration::from_secs(950));
+        }
+    }
 }
@@ -0,0 +1,370 @@
+use std::{
+    path::Path,
+    sync::atomic::Ordering,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use reso_dns::{
+    DnsMessage, DnsMessageBuilder, DnsRecord,
+    domain_name::DomainName,
+    message::{ClassType, RecordType},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{CacheEntry, CacheKey, DnsMessageCache, NegKind, NegativeCacheKey, NegativeEntry};
+
+/// Live cache entries with absolute expiry times, so a warm cache can survive a restart.
+///
+/// Records are stored in DNS wire format, the sections of an encoded message.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CacheSnapshot {
+    entries: Vec<SnapshotEntry>,
+    negative_entries: Vec<SnapshotNegativeEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotEntry {
+    name: String,
+    record_type: u16,
+    class_type: u16,
+    do_bit: bool,
+    /// Expiry in milliseconds since the unix epoch.
+    expires_at: u64,
+    /// Message with the records in its answer section.
+    records: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotNegativeEntry {
+    name: String,
+    /// Record type of a NODATA entry, `None` for NXDOMAIN.
+    record_type: Option<u16>,
+    class_type: u16,
+    do_bit: bool,
+    /// Expiry in milliseconds since the unix epoch.
+    expires_at: u64,
+    /// Message with the CNAME chain in its answer section and the SOA in its authority section.
+    records: Vec<u8>,
+}
+
+impl CacheSnapshot {
+    /// Number of entries in the snapshot.
+    pub fn len(&self) -> usize {
+        self.entries.len() + self.negative_entries.len()
+    }
+
+    /// Whether the snapshot has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read a snapshot written by [`CacheSnapshot::write`].
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path).with_context(|| format!("failed to read {:?}", path))?;
+        serde_json::from_slice(&bytes).with_context(|| format!("failed to parse {:?}", path))
+    }
+
+    /// Write the snapshot to `path`, replacing any existing file.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let bytes = serde_json::to_vec(self)?;
+        std::fs::write(path, bytes).with_context(|| format!("failed to write {:?}", path))
+    }
+}
+
+impl DnsMessageCache {
+    /// Capture all entries that have not expired yet.
+    pub fn snapshot(&self) -> CacheSnapshot {
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+        let expires_at = |at: Instant| unix_millis(system_now + at.saturating_duration_since(now));
+
+        let entries = self
+            .cache
+            .iter()
+            .filter(|(_, entry)| entry.expires_at > now)
+            .filter_map(|(key, entry)| {
+                let records = encode_records(entry.records.to_vec(), vec![])?;
+                Some(SnapshotEntry {
+                    name: key.name.to_string(),
+                    record_type: key.record_type.to_u16(),
+                    class_type: key.class_type.to_u16(),
+                    do_bit: key.do_bit,
+                    expires_at: expires_at(entry.expires_at),
+                    records,
+                })
+            })
+            .collect();
+
+        let negative_entries = self
+            .negative_cache
+            .iter()
+            .filter(|(_, entry)| entry.expires_at > now)
+            .filter_map(|(key, entry)| {
+                let (name, record_type, class_type, do_bit) = match key.as_ref() {
+                    NegativeCacheKey::NoData {
+                        name,
+                        qtype,
+                        class_type,
+                        do_bit,
+                    } => (name, Some(qtype.to_u16()), class_type, do_bit),
+                    NegativeCacheKey::NxDomain {
+                        qname,
+                        class_type,
+                        do_bit,
+                    } => (qname, None, class_type, do_bit),
+                };
+                let records = encode_records(entry.chain.to_vec(), vec![entry.soa_record.clone()])?;
+                Some(SnapshotNegativeEntry {
+                    name: name.to_string(),
+                    record_type,
+                    class_type: class_type.to_u16(),
+                    do_bit: *do_bit,
+                    expires_at: expires_at(entry.expires_at),
+                    records,
+                })
+            })
+            .collect();
+
+        CacheSnapshot {
+            entries,
+            negative_entries,
+        }
+    }
+
+    /// Insert the entries of `snapshot`, skipping ones that expired since it was taken.
+    /// Returns the number of entries restored.
+    pub async fn restore(&self, snapshot: CacheSnapshot) -> usize {
+        self.restore_at(snapshot, SystemTime::now()).await
+    }
+
+    async fn restore_at(&self, snapshot: CacheSnapshot, system_now: SystemTime) -> usize {
+        let now = Instant::now();
+        let now_millis = unix_millis(system_now);
+        let remaining = |expires_at: u64| {
+            let remaining = expires_at.saturating_sub(now_millis);
+            (remaining > 0).then(|| now + Duration::from_millis(remaining))
+        };
+
+        let mut restored = 0;
+
+        for entry in snapshot.entries {
+            let Some(expires_at) = remaining(entry.expires_at) else {
+                continue;
+            };
+            let (Ok(name), Ok(message)) = (DomainName::from_ascii(&entry.name), DnsMessage::decode(&entry.records))
+            else {
+                continue;
+            };
+
+            let key = CacheKey {
+                name: name.clone(),
+                record_type: RecordType::from(entry.record_type),
+                class_type: ClassType::from(entry.class_type),
+                do_bit: entry.do_bit,
+            };
+            let entry = CacheEntry {
+                name,
+                record_type: key.record_type,
+                records: message.answers().to_vec().into(),
+                expires_at,
+            };
+
+            // entries holding a CNAME chain were cached for a query, not as an RRset of the name.
+            if entry
+                .records
+                .iter()
+                .all(|r| r.record_type == key.record_type && r.name == key.name)
+            {
+                self.index_rrset(&key).await;
+            }
+            self.cache.insert(key, entry).await;
+            restored += 1;
+        }
+
+        for entry in snapshot.negative_entries {
+            let Some(expires_at) = remaining(entry.expires_at) else {
+                continue;
+            };
+            let (Ok(name), Ok(message)) = (DomainName::from_ascii(&entry.name), DnsMessage::decode(&entry.records))
+            else {
+                continue;
+            };
+            let Some(soa_record) = message.authority_records().first().cloned() else {
+                continue;
+            };
+
+            let class_type = ClassType::from(entry.class_type);
+            let (key, kind) = match entry.record_type {
+                Some(qtype) => (
+                    NegativeCacheKey::NoData {
+                        name,
+                        qtype: RecordType::from(qtype),
+                        class_type,
+                        do_bit: entry.do_bit,
+                    },
+                    NegKind::NoData,
+                ),
+                None => (
+                    NegativeCacheKey::NxDomain {
+                        qname: name,
+                        class_type,
+                        do_bit: entry.do_bit,
+                    },
+                    NegKind::NxDomain,
+                ),
+            };
+            let entry = NegativeEntry {
+                kind,
+                expires_at,
+                stale_until: expires_at + Duration::from_secs(self.negative_stale_secs.load(Ordering::Relaxed)),
+                soa_record,
+                chain: message.answers().to_vec().into(),
+            };
+
+            self.negative_cache.insert(key, entry).await;
+            restored += 1;
+        }
+
+        restored
+    }
+}
+
+/// Milliseconds since the unix epoch.
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Encode records as the sections of a message, so they round-trip through the regular DNS codec.
+fn encode_records(answers: Vec<DnsRecord>, authority: Vec<DnsRecord>) -> Option<Vec<u8>> {
+    let message = DnsMessageBuilder::new()
+        .with_answers(answers)
+        .with_authority_records(authority)
+        .build();
+    message.encode().ok().map(|bytes| bytes.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use reso_dns::{
+        DnsFlags, DnsOpcode, DnsResponseCode,
+        message::{DnsQuestion, DnsRecordData},
+    };
+
+    use super::*;
+    use crate::{CacheResult, NegativeResult};
+
+    fn name(s: &str) -> DomainName {
+        DomainName::from_ascii(s).unwrap()
+    }
+
+    fn query(qname: &str) -> DnsMessage {
+        DnsMessageBuilder::new()
+            .with_flags(DnsFlags::new(
+                false,
+                DnsOpcode::Query,
+                false,
+                false,
+                true,
+                false,
+                false,
+                false,
+            ))
+            .add_question(DnsQuestion::new(name(qname), RecordType::A, ClassType::IN))
+            .build()
+    }
+
+    fn response_flags() -> DnsFlags {
+        DnsFlags::new(true, DnsOpcode::Query, false, false, true, true, false, false)
+    }
+
+    fn a_response(query: &DnsMessage, ttl: u32) -> DnsMessage {
+        DnsMessageBuilder::new()
+            .with_flags(response_flags())
+            .with_response(DnsResponseCode::NoError)
+            .with_questions(query.questions().to_vec())
+            .add_answer(DnsRecord::new(
+                query.questions()[0].qname.clone(),
+                RecordType::A,
+                ClassType::IN,
+                ttl,
+                DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1)),
+            ))
+            .build()
+    }
+
+    fn nxdomain_response(query: &DnsMessage) -> DnsMessage {
+        DnsMessageBuilder::new()
+            .with_flags(response_flags())
+            .with_response(DnsResponseCode::NxDomain)
+            .with_questions(query.questions().to_vec())
+            .add_authority_record(DnsRecord::new(
+                name("example.com"),
+                RecordType::SOA,
+                ClassType::IN,
+                600,
+                DnsRecordData::SOA {
+                    mname: name("ns1.example.com"),
+                    rname: name("hostmaster.example.com"),
+                    serial: 1,
+                    refresh: 7200,
+                    retry: 3600,
+                    expire: 1209600,
+                    minimum: 600,
+                },
+            ))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn snapshot_restores_live_entries_with_reduced_ttls() {
+        let cache = DnsMessageCache::default();
+
+        let long = query("long.example.com");
+        let short = query("short.example.com");
+        let missing = query("missing.example.com");
+        assert!(cache.insert(&long, &a_response(&long, 300)).await);
+        assert!(cache.insert(&short, &a_response(&short, 60)).await);
+        assert!(cache.insert(&missing, &nxdomain_response(&missing)).await);
+
+        let path = std::env::temp_dir().join(format!("reso-cache-snapshot-{}.json", std::process::id()));
+        cache.snapshot().write(&path).unwrap();
+        let snapshot = CacheSnapshot::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(snapshot.len(), 3);
+
+        // Restore as if the process was down for 100 seconds, the 60 second entry expired meanwhile.
+        let restored_cache = DnsMessageCache::default();
+        let restored = restored_cache
+            .restore_at(snapshot, SystemTime::now() + Duration::from_secs(100))
+            .await;
+        assert_eq!(restored, 2);
+
+        match restored_cache.lookup(&CacheKey::try_from(&long).unwrap()).await {
+            CacheResult::Positive { records, ttl } => {
+                assert!((190..=200).contains(&ttl), "unexpected ttl {ttl}");
+                assert_eq!(records.as_ref(), a_response(&long, 300).answers());
+            }
+            other => panic!("expected positive hit, got {other:?}"),
+        }
+
+        assert_eq!(
+            restored_cache.lookup(&CacheKey::try_from(&short).unwrap()).await,
+            CacheResult::Miss
+        );
+
+        match restored_cache.lookup(&CacheKey::try_from(&missing).unwrap()).await {
+            CacheResult::Negative(NegativeResult { kind, soa_record, .. }) => {
+                assert_eq!(kind, NegKind::NxDomain);
+                assert!(
+                    (490..=500).contains(&soa_record.ttl),
+                    "unexpected ttl {}",
+                    soa_record.ttl
+                );
+            }
+            other => panic!("expected negative hit, got {other:?}"),
+        }
+    }
+}
@@ -132,6 +132,21 @@ impl DnsResponse {
     pub fn message(&self) -> reso_dns::Result<&DnsMessage> {
         self.message.get_or_try_init(|| DnsMessage::decode(&self.bytes))
     }
+
+    /// Replace the bytes with `f(bytes)`, keeping the decoded message if there is one.
+    ///
+    /// Only for changes the message doesn't represent, like the case of names.
+    pub fn map_bytes(self, f: impl FnOnce(Bytes) -> Bytes) -> Self {
+        Self {
+            bytes: f(self.bytes),
+            message: self.message,
+        }
+    }
+
+    /// Whether the message is available without decoding the bytes.
+    pub fn is_decoded(&self) -> bool {
+        self.message.get().is_some()
+    }
 }
 
 /// Trait for DNS middlewares that can process DNS requests.
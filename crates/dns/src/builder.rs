@@ -1,6 +1,10 @@
-use crate::Edns;
+use std::net::IpAddr;
 
-use super::message::{DnsFlags, DnsMessage, DnsOpcode, DnsQuestion, DnsRecord, DnsResponseCode};
+use crate::{Edns, EdnsOption};
+
+use super::message::{
+    ClientSubnet, DnsFlags, DnsMessage, DnsQuestion, DnsRecord, DnsResponseCode, EdnsOptionCode, EdnsOptionData,
+};
 
 /// Builder for constructing DNS messages.
 #[derive(Debug, Clone, Default)]
@@ -20,18 +24,7 @@ impl DnsMessageBuilder {
     pub fn new() -> Self {
         Self {
             id: 0,
-            flags: DnsFlags {
-                response: false,
-                opcode: DnsOpcode::Query,
-                authorative_answer: false,
-                truncated: false,
-                recursion_desired: true,
-                recursion_available: false,
-                z: false,
-                authentic_data: false,
-                checking_disabled: false,
-                rcode_low: 0,
-            },
+            flags: DnsFlags::query(),
             questions: Vec::new(),
             answers: Vec::new(),
             authority_records: Vec::new(),
@@ -126,3 +119,153 @@ impl DnsMessageBuilder {
         message
     }
 }
+
+/// Builder for constructing EDNS OPT records.
+#[derive(Debug, Clone, Default)]
+pub struct EdnsBuilder {
+    edns: Edns,
+}
+
+impl EdnsBuilder {
+    /// Create a new EDNS builder with the default UDP payload size and no options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum UDP payload size the sender can handle.
+    pub fn with_udp_payload_size(mut self, size: u16) -> Self {
+        self.edns.udp_payload_size = size;
+        self
+    }
+
+    /// Set the DNSSEC OK bit.
+    pub fn with_do_bit(mut self, do_bit: bool) -> Self {
+        self.edns.set_do_bit(do_bit);
+        self
+    }
+
+    /// Add an arbitrary option.
+    pub fn add_option(mut self, option: EdnsOption) -> Self {
+        self.edns.options.push(option);
+        self
+    }
+
+    /// Add a cookie option (RFC 7873) with the client cookie and, if known, the server cookie.
+    pub fn add_cookie(self, client: [u8; 8], server: Option<&[u8]>) -> Self {
+        let mut data = client.to_vec();
+        data.extend_from_slice(server.unwrap_or_default());
+        self.add_option(EdnsOption::new(EdnsOptionCode::Cookie, EdnsOptionData::Raw(data)))
+    }
+
+    /// Add a client subnet option (RFC 7871) revealing the first `source_prefix` bits of `addr`.
+    pub fn add_client_subnet(self, addr: IpAddr, source_prefix: u8) -> Self {
+        self.add_option(EdnsOption::new(
+            EdnsOptionCode::ClientSubnet,
+            EdnsOptionData::ClientSubnet(ClientSubnet::new(addr, source_prefix)),
+        ))
+    }
+
+    /// Add a padding option (RFC 7830) of `len` zero bytes.
+    pub fn add_padding(self, len: u16) -> Self {
+        self.add_option(EdnsOption::new(EdnsOptionCode::Padding, EdnsOptionData::Padding(len)))
+    }
+
+    /// Add a name server identifier option (RFC 5001). Queries send it empty.
+    pub fn add_nsid(self, nsid: &[u8]) -> Self {
+        self.add_option(EdnsOption::new(
+            EdnsOptionCode::NSID,
+            EdnsOptionData::Raw(nsid.to_vec()),
+        ))
+    }
+
+    /// Build the EDNS record.
+    pub fn build(self) -> Edns {
+        self.edns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::{DnsMessageWriter, writer::DnsWritable};
+
+    use super::*;
+
+    fn encode(edns: &Edns) -> Vec<u8> {
+        let mut writer = DnsMessageWriter::new();
+        edns.write_to(&mut writer).unwrap();
+        writer.into_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_edns_builder_defaults() {
+        let edns = EdnsBuilder::new().build();
+
+        #[rustfmt::skip]
+        let expected = vec![
+            0x00, // root name
+            0x00, 0x29, // OPT
+            0x04, 0xD0, // 1232
+            0x00, 0x00, 0x00, 0x00, // ext rcode, version, flags
+            0x00, 0x00, // rdlen
+        ];
+        assert_eq!(encode(&edns), expected);
+    }
+
+    #[test]
+    fn test_edns_builder_encodes_options() {
+        let edns = EdnsBuilder::new()
+            .with_udp_payload_size(4096)
+            .with_do_bit(true)
+            .add_cookie([1, 2, 3, 4, 5, 6, 7, 8], None)
+            .add_client_subnet(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 200)), 25)
+            .add_padding(3)
+            .add_nsid(&[])
+            .build();
+
+        assert!(edns.do_bit());
+
+        #[rustfmt::skip]
+        let expected = vec![
+            0x00,
+            0x00, 0x29,
+            0x10, 0x00, // 4096
+            0x00, 0x00, 0x80, 0x00, // DO bit
+            0x00, 0x23, // rdlen = 12 + 12 + 7 + 4
+            // Cookie: code 10, len 8, client cookie
+            0x00, 0x0A, 0x00, 0x08, 1, 2, 3, 4, 5, 6, 7, 8,
+            // Client subnet: code 8, len 8, family 1, /25, scope 0, masked address
+            0x00, 0x08, 0x00, 0x08, 0x00, 0x01, 25, 0, 192, 168, 1, 128,
+            // Padding: code 12, len 3, zeroes
+            0x00, 0x0C, 0x00, 0x03, 0, 0, 0,
+            // NSID: code 3, len 0
+            0x00, 0x03, 0x00, 0x00,
+        ];
+        assert_eq!(encode(&edns), expected);
+    }
+
+    #[test]
+    fn test_edns_builder_cookie_with_server_cookie() {
+        let server = [9u8; 16];
+        let edns = EdnsBuilder::new().add_cookie([0; 8], Some(&server)).build();
+
+        assert_eq!(edns.options[0].wire_len(), 24);
+    }
+
+    #[test]
+    fn test_message_builder_with_edns_roundtrips_do_bit() {
+        let message = DnsMessageBuilder::new()
+            .with_id(3)
+            .with_edns(EdnsBuilder::new().with_do_bit(true).build())
+            .build();
+
+        let decoded = DnsMessage::decode(&message.encode().unwrap()).unwrap();
+
+        assert_eq!(decoded.additional_records().len(), 0);
+        let edns = decoded.edns().as_ref().expect("OPT record should be encoded");
+        assert!(edns.do_bit());
+        assert_eq!(edns.udp_payload_size, 1232);
+        assert_eq!(decoded, message);
+    }
+}
@@ -223,6 +223,15 @@ impl DomainName {
         &self.display
     }
 
+    /// Fully qualified presentation form, e.g. `example.com.`.
+    pub fn to_fqdn(&self) -> String {
+        if self.is_root() {
+            ".".to_string()
+        } else {
+            format!("{}.", self.display)
+        }
+    }
+
     pub fn wire_len(&self) -> usize {
         self.labels.len()
     }
@@ -230,6 +239,42 @@ impl DomainName {
     pub fn label_iter(&self) -> impl Iterator<Item = &[u8]> {
         LabelIter { data: &self.labels }
     }
+
+    /// Number of labels in the name, the root has zero labels.
+    pub fn label_count(&self) -> usize {
+        self.label_iter().count()
+    }
+
+    /// Whether this name is equal to or below `other`, e.g. `a.b.example.com` is a subdomain of `example.com`.
+    /// Comparison is done on whole labels, so `badexample.com` is not a subdomain of `example.com`.
+    pub fn is_subdomain_of(&self, other: &DomainName) -> bool {
+        let ours: Vec<&[u8]> = self.label_iter().collect();
+        let theirs: Vec<&[u8]> = other.label_iter().collect();
+
+        if theirs.len() > ours.len() {
+            return false;
+        }
+
+        // labels are stored lowercased, so a byte comparison is case-insensitive.
+        ours.iter().rev().zip(theirs.iter().rev()).all(|(a, b)| a == b)
+    }
+
+    /// Canonical form of the name, with every label ASCII lowercased.
+    ///
+    /// Names are lowercased when they are created, so this is a cheap copy whose wire format and display
+    /// form are the same regardless of the casing the name was read with.
+    pub fn to_canonical(&self) -> DomainName {
+        self.clone()
+    }
+
+    /// The name with its leftmost label removed, `None` for the root.
+    pub fn parent(&self) -> Option<DomainName> {
+        if self.is_root() {
+            return None;
+        }
+        let labels: Vec<&[u8]> = self.label_iter().skip(1).collect();
+        Self::from_labels(&labels).ok()
+    }
 }
 
 impl Deref for DomainName {
@@ -310,6 +355,12 @@ mod tests {
         assert!(!DomainName::from_ascii("example.com").unwrap().is_root());
     }
 
+    #[test]
+    fn test_to_fqdn() {
+        assert_eq!(DomainName::root().to_fqdn(), ".");
+        assert_eq!(DomainName::from_ascii("example.com").unwrap().to_fqdn(), "example.com.");
+    }
+
     #[test]
     fn test_label_iter_returns_raw_bytes() {
         let labels = vec![vec![0x80, 0xFF], b"com".to_vec()];
@@ -329,4 +380,71 @@ mod tests {
         set.insert(dn1.clone());
         assert!(set.contains(&dn2));
     }
+
+    #[test]
+    fn test_is_subdomain_of() {
+        let parent = DomainName::from_ascii("example.com").unwrap();
+
+        assert!(
+            DomainName::from_ascii("a.b.example.com")
+                .unwrap()
+                .is_subdomain_of(&parent)
+        );
+        assert!(
+            DomainName::from_ascii("A.B.EXAMPLE.COM")
+                .unwrap()
+                .is_subdomain_of(&parent)
+        );
+        assert!(parent.is_subdomain_of(&parent));
+        assert!(parent.is_subdomain_of(&DomainName::root()));
+
+        assert!(
+            !DomainName::from_ascii("badexample.com")
+                .unwrap()
+                .is_subdomain_of(&parent)
+        );
+        assert!(!DomainName::from_ascii("example.org").unwrap().is_subdomain_of(&parent));
+        assert!(!DomainName::from_ascii("com").unwrap().is_subdomain_of(&parent));
+    }
+
+    #[test]
+    fn test_parent() {
+        let dn = DomainName::from_ascii("a.b.example.com").unwrap();
+        assert_eq!(dn.parent().unwrap().as_str(), "b.example.com");
+
+        let tld = DomainName::from_ascii("com").unwrap();
+        assert!(tld.parent().unwrap().is_root());
+        assert!(DomainName::root().parent().is_none());
+    }
+
+    #[test]
+    fn test_label_count() {
+        assert_eq!(DomainName::root().label_count(), 0);
+        assert_eq!(DomainName::from_ascii("a.b.example.com").unwrap().label_count(), 4);
+    }
+
+    #[test]
+    fn test_to_canonical_is_lowercase() {
+        let dn = DomainName::from_labels(&[b"WwW".as_slice(), b"ExAmPlE", b"CoM"]).unwrap();
+        let canonical = dn.to_canonical();
+
+        assert_eq!(canonical.as_str(), "www.example.com");
+        let labels: Vec<&[u8]> = canonical.label_iter().collect();
+        assert_eq!(labels, vec![&b"www"[..], b"example", b"com"]);
+    }
+
+    #[test]
+    fn test_to_canonical_roundtrips() {
+        use crate::{DnsMessageReader, DnsMessageWriter};
+
+        let canonical = DomainName::from_ascii("MiXeD.Example.COM").unwrap().to_canonical();
+
+        let mut writer = DnsMessageWriter::new();
+        writer.write_qname(&canonical).unwrap();
+        let bytes = writer.into_bytes();
+        let decoded = DnsMessageReader::new(&bytes).read_qname().unwrap();
+
+        assert_eq!(decoded.as_str(), "mixed.example.com");
+        assert_eq!(decoded, canonical);
+    }
 }
@@ -1,6 +1,6 @@
 use std::borrow::Cow;
 
-use crate::DnsResponseCode;
+use crate::{DnsOpcode, DnsResponseCode};
 
 /// Error that can occur during DNS message reading.
 #[derive(Debug, thiserror::Error)]
@@ -29,6 +29,9 @@ pub enum DnsReadError {
     #[error("name exceeds 255 octets (wire format length: {len})")]
     NameTooLong { len: usize },
 
+    #[error("name exceeds {max} labels")]
+    TooManyLabels { max: usize },
+
     #[error("label exceeds 63 octets: {len}")]
     LabelTooLong { len: usize },
 
@@ -54,6 +57,9 @@ pub enum DnsWriteError {
 
     #[error("overwrite out of bounds: pos {pos}, len {len}, buf len {buf_len}")]
     OverwriteOutOfBounds { pos: usize, len: usize, buf_len: usize },
+
+    #[error("character-string of {len} bytes exceeds 255 bytes")]
+    CharacterStringTooLong { len: usize },
 }
 
 /// General error type for DNS processing errors.
@@ -78,12 +84,18 @@ pub enum DnsError {
     #[error("RDATA length overflow: {len} bytes exceeds u16")]
     RdataLengthOverflow { len: usize },
 
-    #[error("EDNS version {0} not supported")]
-    UnsupportedEdnsVersion(u8),
-
     #[error("ECS prefix {prefix} exceeds max {max} for family {family}")]
     EcsPrefixTooLarge { family: u16, prefix: u8, max: u8 },
 
+    #[error("unknown mnemonic: {0}")]
+    UnknownMnemonic(String),
+
+    #[error("message of {len} bytes exceeds {max} bytes")]
+    MessageTooLarge { len: usize, max: usize },
+
+    #[error("message declares {count} records, more than the limit of {max}")]
+    TooManyRecords { count: usize, max: usize },
+
     #[error(transparent)]
     Read(#[from] DnsReadError),
 
@@ -101,9 +113,53 @@ impl DnsError {
             DnsError::Read(_) => DnsResponseCode::FormatError,
             DnsError::Write(_) => DnsResponseCode::ServerFailure,
             DnsError::RdataLengthOverflow { .. } => DnsResponseCode::FormatError,
-            DnsError::UnsupportedEdnsVersion(_) => DnsResponseCode::FormatError,
             DnsError::EcsPrefixTooLarge { .. } => DnsResponseCode::FormatError,
             DnsError::MultipleOptRecords => DnsResponseCode::FormatError,
+            DnsError::UnknownMnemonic(_) => DnsResponseCode::FormatError,
+            DnsError::MessageTooLarge { .. } => DnsResponseCode::FormatError,
+            DnsError::TooManyRecords { .. } => DnsResponseCode::FormatError,
+        }
+    }
+}
+
+/// A well-formed message that breaks the rules for a query or a response to a query.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DnsValidationError {
+    /// A response where a query was expected. Servers should drop it rather than answer, as answering lets
+    /// two servers bounce errors back and forth, so its response code is only a fallback.
+    #[error("QR bit set on a query")]
+    UnexpectedResponse,
+
+    #[error("QR bit not set on a response")]
+    NotAResponse,
+
+    #[error("expected exactly one question, got {count}")]
+    QuestionCount { count: usize },
+
+    #[error("unsupported opcode {0:?}")]
+    UnsupportedOpcode(DnsOpcode),
+
+    #[error("response opcode {actual:?} does not match query opcode {expected:?}")]
+    OpcodeMismatch { expected: DnsOpcode, actual: DnsOpcode },
+
+    #[error("response id {actual} does not match query id {expected}")]
+    IdMismatch { expected: u16, actual: u16 },
+
+    #[error("response questions do not match the query")]
+    QuestionMismatch,
+
+    #[error("response uses unsupported EDNS version {version}")]
+    UnsupportedEdnsVersion { version: u8 },
+}
+
+impl DnsValidationError {
+    /// Map the error to the response code for answering the offending query.
+    pub fn response_code(&self) -> DnsResponseCode {
+        match self {
+            // A caching resolver is not authoritative for any zone, so updates are refused rather than unsupported.
+            DnsValidationError::UnsupportedOpcode(DnsOpcode::Update) => DnsResponseCode::Refused,
+            DnsValidationError::UnsupportedOpcode(_) => DnsResponseCode::NotImp,
+            _ => DnsResponseCode::FormatError,
         }
     }
 }
@@ -1,3 +1,5 @@
+use bytes::{Bytes, BytesMut};
+
 /// Extract the transaction ID from a DNS message.
 pub fn extract_transaction_id(data: &[u8]) -> Option<u16> {
     if data.len() < 2 {
@@ -14,3 +16,255 @@ pub fn is_truncated(data: &[u8]) -> Option<bool> {
     let flags = u16::from_be_bytes([data[2], data[3]]);
     Some((flags & 0x0200) != 0)
 }
+
+/// Check if a dns message has the QR flag set, i.e. it is a response.
+pub fn is_response(data: &[u8]) -> Option<bool> {
+    if data.len() < 3 {
+        return None;
+    }
+    Some(data[2] & 0x80 != 0)
+}
+
+/// Check if a dns message has the REFUSED response code.
+pub fn is_refused(data: &[u8]) -> Option<bool> {
+    if data.len() < 4 {
+        return None;
+    }
+    Some(data[3] & 0x0F == 5)
+}
+
+/// Overwrite the transaction ID of a DNS message.
+///
+/// The buffer is modified in place when `bytes` is the only handle to its allocation. If the
+/// buffer is shared (e.g. a cached response), it is copied once and the copy is modified, leaving
+/// the other handles untouched. Messages shorter than the header ID are returned unchanged.
+pub fn rewrite_transaction_id(bytes: Bytes, id: u16) -> Bytes {
+    if bytes.len() < 2 {
+        return bytes;
+    }
+
+    let mut buf = bytes
+        .try_into_mut()
+        .unwrap_or_else(|shared| BytesMut::from(&shared[..]));
+    buf[..2].copy_from_slice(&id.to_be_bytes());
+    buf.freeze()
+}
+
+/// Length of the DNS header, the question follows it.
+const HEADER_LEN: usize = 12;
+
+/// Spell the question name of `response`, and answer owner names that repeat it in full, the way the question of
+/// `query` does.
+///
+/// Names are case-insensitive (RFC 4343), but clients expect their own spelling back, while upstreams may answer
+/// in another case and coalesced queries share the response of whichever client asked first. Owner names
+/// compressed to the question follow it. Responses whose question is not the query's are returned unchanged.
+pub fn restore_question_case(response: Bytes, query: &[u8]) -> Bytes {
+    let Some(qname) = uncompressed_name_end(query, HEADER_LEN).map(|end| &query[HEADER_LEN..end]) else {
+        return response;
+    };
+
+    let offsets = name_offsets(&response, qname);
+    if offsets.iter().all(|&pos| response[pos..pos + qname.len()] == *qname) {
+        return response;
+    }
+
+    let mut buf = response
+        .try_into_mut()
+        .unwrap_or_else(|shared| BytesMut::from(&shared[..]));
+    for pos in offsets {
+        buf[pos..pos + qname.len()].copy_from_slice(qname);
+    }
+    buf.freeze()
+}
+
+/// Offsets of the question name of `response` and of the answer owner names spelling it out in full, empty if
+/// the question is not `qname`.
+fn name_offsets(response: &[u8], qname: &[u8]) -> Vec<usize> {
+    // length octets are below 64, so only the letters of the labels are compared case-insensitively.
+    let is_qname = |pos: usize| {
+        response
+            .get(pos..pos + qname.len())
+            .is_some_and(|name| name.eq_ignore_ascii_case(qname))
+    };
+
+    if response.len() < HEADER_LEN || u16::from_be_bytes([response[4], response[5]]) != 1 || !is_qname(HEADER_LEN) {
+        return vec![];
+    }
+
+    let mut offsets = vec![HEADER_LEN];
+    let answers = u16::from_be_bytes([response[6], response[7]]);
+
+    // past the question name, type and class.
+    let mut pos = HEADER_LEN + qname.len() + 4;
+    for _ in 0..answers {
+        if is_qname(pos) {
+            offsets.push(pos);
+        }
+
+        let Some(end) = name_end(response, pos) else {
+            break;
+        };
+        // past the type, class, TTL and RDATA length, then the RDATA.
+        let Some(rdlength) = response.get(end + 8..end + 10) else {
+            break;
+        };
+        pos = end + 10 + u16::from_be_bytes([rdlength[0], rdlength[1]]) as usize;
+    }
+
+    offsets
+}
+
+/// End of the name at `pos`, `None` if it is compressed or runs past the end of `data`.
+fn uncompressed_name_end(data: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        match *data.get(pos)? {
+            0 => return Some(pos + 1),
+            len @ 1..=63 => pos += 1 + len as usize,
+            _ => return None,
+        }
+    }
+}
+
+/// End of the possibly compressed name at `pos`, `None` if it runs past the end of `data`.
+fn name_end(data: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        match *data.get(pos)? {
+            0 => return Some(pos + 1),
+            len @ 1..=63 => pos += 1 + len as usize,
+            len if len & 0xC0 == 0xC0 => return (pos + 2 <= data.len()).then_some(pos + 2),
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A message with the header counts, followed by `sections` as is.
+    fn message(questions: u16, answers: u16, sections: &[&[u8]]) -> Vec<u8> {
+        let mut message = vec![0x12, 0x34, 0x81, 0x80];
+        for count in [questions, answers, 0, 0] {
+            message.extend_from_slice(&count.to_be_bytes());
+        }
+        for section in sections {
+            message.extend_from_slice(section);
+        }
+        message
+    }
+
+    /// Type A, class IN.
+    const A_IN: &[u8] = &[0x00, 0x01, 0x00, 0x01];
+    /// Type A, class IN, TTL 300 and the RDATA of 192.0.2.1.
+    const A_IN_RECORD: &[u8] = &[0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00, 0x04, 192, 0, 2, 1];
+
+    #[test]
+    fn test_restore_question_case() {
+        let query = message(1, 0, &[b"\x07ExAmPlE\x03cOm\x00", A_IN]);
+        let response = message(
+            1,
+            3,
+            &[
+                b"\x07example\x03com\x00",
+                A_IN,
+                // owner compressed to the question.
+                &[0xC0, 0x0C],
+                A_IN_RECORD,
+                b"\x07EXAMPLE\x03COM\x00",
+                A_IN_RECORD,
+                b"\x03www\x07example\x03com\x00",
+                A_IN_RECORD,
+            ],
+        );
+
+        let restored = restore_question_case(Bytes::from(response), &query);
+
+        let expected = message(
+            1,
+            3,
+            &[
+                b"\x07ExAmPlE\x03cOm\x00",
+                A_IN,
+                &[0xC0, 0x0C],
+                A_IN_RECORD,
+                b"\x07ExAmPlE\x03cOm\x00",
+                A_IN_RECORD,
+                b"\x03www\x07example\x03com\x00",
+                A_IN_RECORD,
+            ],
+        );
+        assert_eq!(restored.as_ref(), expected.as_slice());
+    }
+
+    #[test]
+    fn test_restore_question_case_leaves_other_questions() {
+        let query = message(1, 0, &[b"\x07ExAmPlE\x03cOm\x00", A_IN]);
+
+        for response in [
+            message(1, 0, &[b"\x07example\x03org\x00", A_IN]),
+            // already in the query's case.
+            message(1, 0, &[b"\x07ExAmPlE\x03cOm\x00", A_IN]),
+            message(0, 0, &[]),
+            vec![0x12, 0x34],
+        ] {
+            let bytes = Bytes::from(response);
+            let ptr = bytes.as_ptr();
+
+            assert_eq!(restore_question_case(bytes, &query).as_ptr(), ptr);
+        }
+    }
+
+    #[test]
+    fn test_rewrite_transaction_id() {
+        let bytes = Bytes::from_static(&[0x12, 0x34, 0x81, 0x80]);
+
+        let rewritten = rewrite_transaction_id(bytes, 0xBEEF);
+
+        assert_eq!(extract_transaction_id(&rewritten), Some(0xBEEF));
+        assert_eq!(&rewritten[2..], &[0x81, 0x80]);
+    }
+
+    #[test]
+    fn test_rewrite_transaction_id_unique_buffer_is_not_reallocated() {
+        let bytes = Bytes::from(vec![0x12, 0x34, 0x81, 0x80]);
+        let ptr = bytes.as_ptr();
+
+        let rewritten = rewrite_transaction_id(bytes, 0xBEEF);
+
+        assert_eq!(rewritten.as_ptr(), ptr);
+        assert_eq!(extract_transaction_id(&rewritten), Some(0xBEEF));
+    }
+
+    #[test]
+    fn test_rewrite_transaction_id_shared_buffer_is_untouched() {
+        let bytes = Bytes::from(vec![0x12, 0x34, 0x81, 0x80]);
+        let shared = bytes.clone();
+
+        let rewritten = rewrite_transaction_id(bytes, 0xBEEF);
+
+        assert_ne!(rewritten.as_ptr(), shared.as_ptr());
+        assert_eq!(extract_transaction_id(&rewritten), Some(0xBEEF));
+        assert_eq!(extract_transaction_id(&shared), Some(0x1234));
+    }
+
+    #[test]
+    fn test_is_response() {
+        assert_eq!(is_response(&[0x12, 0x34, 0x81, 0x80]), Some(true));
+        assert_eq!(is_response(&[0x12, 0x34, 0x01, 0x00]), Some(false));
+        assert_eq!(is_response(&[0x12, 0x34]), None);
+    }
+
+    #[test]
+    fn test_is_refused() {
+        assert_eq!(is_refused(&[0x12, 0x34, 0x81, 0x85]), Some(true));
+        assert_eq!(is_refused(&[0x12, 0x34, 0x81, 0x82]), Some(false));
+        assert_eq!(is_refused(&[0x12, 0x34, 0x81]), None);
+    }
+
+    #[test]
+    fn test_rewrite_transaction_id_short_message() {
+        let bytes = Bytes::from_static(&[0x12]);
+        assert_eq!(rewrite_transaction_id(bytes, 0xBEEF).as_ref(), &[0x12]);
+    }
+}
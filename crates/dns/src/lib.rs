@@ -8,11 +8,12 @@ pub mod message;
 pub mod reader;
 pub mod writer;
 
-pub use error::{DnsError, DnsReadError, DnsWriteError, Result};
+pub use error::{DnsError, DnsReadError, DnsValidationError, DnsWriteError, Result};
 
-pub use builder::DnsMessageBuilder;
+pub use builder::{DnsMessageBuilder, EdnsBuilder};
 pub use message::{
-    ClassType, DnsFlags, DnsMessage, DnsOpcode, DnsQuestion, DnsRecord, DnsResponseCode, Edns, EdnsOption, RecordType,
+    ClassType, DnsFlags, DnsMessage, DnsOpcode, DnsQuestion, DnsRecord, DnsResponseCode, Edns, EdnsOption, RRset,
+    RecordType,
 };
 
 pub use reader::DnsMessageReader;
@@ -36,5 +36,21 @@ macro_rules! u16_enum_with_unknown {
                 }
             }
         }
+
+        /// Parses either the (case-insensitive) variant name or the numeric value.
+        impl std::str::FromStr for $name {
+            type Err = $crate::error::DnsError;
+
+            fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+                $(
+                    if s.eq_ignore_ascii_case(stringify!($variant)) {
+                        return Ok(Self::$variant);
+                    }
+                )*
+                s.parse::<u16>()
+                    .map(Self::from)
+                    .map_err(|_| $crate::error::DnsError::UnknownMnemonic(s.to_string()))
+            }
+        }
     };
 }
@@ -1,7 +1,7 @@
 use std::{
     borrow::Cow,
     hash::Hash,
-    net::{Ipv4Addr, Ipv6Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
     sync::LazyLock,
 };
 
@@ -11,11 +11,27 @@ use smallvec::SmallVec;
 
 use crate::{
     domain_name::DomainName,
-    error::{DnsError, DnsReadError, ReadResult, Result, WriteResult},
+    error::{DnsError, DnsReadError, DnsValidationError, DnsWriteError, ReadResult, Result, WriteResult},
+    helpers::rewrite_transaction_id,
     reader::{DnsMessageReader, DnsReadable},
     writer::{DnsMessageWriter, DnsWritable},
 };
 
+/// Largest DNS message, the limit of the two byte length prefix used over TCP.
+pub const MAX_MESSAGE_SIZE: usize = u16::MAX as usize;
+
+/// Smallest wire size of a question: a root name, type and class.
+const MIN_QUESTION_LEN: usize = 5;
+
+/// Smallest wire size of a record: a root name, type, class, TTL and RDATA length.
+const MIN_RECORD_LEN: usize = 11;
+
+/// Capacity to reserve for `count` entries of at least `min_len` bytes each. The count is untrusted,
+/// so only what the `remaining` bytes could hold is reserved.
+fn capacity(count: u16, remaining: usize, min_len: usize) -> usize {
+    (count as usize).min(remaining / min_len)
+}
+
 /// DNS Message
 ///
 /// This struct encapsulates various components of a DNS message and does not represent the full wire structure.
@@ -62,29 +78,37 @@ impl DnsMessage {
         let number_of_authority_records = reader.read_u16()?; // NSCOUNT
         let number_of_additional_records = reader.read_u16()?; // ARCOUNT
 
-        let mut questions: SmallVec<[DnsQuestion; 1]> = SmallVec::with_capacity(number_of_questions as usize);
+        let mut questions: SmallVec<[DnsQuestion; 1]> =
+            SmallVec::with_capacity(capacity(number_of_questions, reader.remaining(), MIN_QUESTION_LEN));
 
         for _ in 0..number_of_questions {
             let question = DnsQuestion::read_from(&mut reader)?;
             questions.push(question);
         }
 
-        let mut answers: SmallVec<[DnsRecord; 1]> = SmallVec::with_capacity(number_of_answers as usize);
+        let mut answers: SmallVec<[DnsRecord; 1]> =
+            SmallVec::with_capacity(capacity(number_of_answers, reader.remaining(), MIN_RECORD_LEN));
 
         for _ in 0..number_of_answers {
             let answer = DnsRecord::read_from(&mut reader)?;
             answers.push(answer);
         }
 
-        let mut authority_records: SmallVec<[DnsRecord; 1]> =
-            SmallVec::with_capacity(number_of_authority_records as usize);
+        let mut authority_records: SmallVec<[DnsRecord; 1]> = SmallVec::with_capacity(capacity(
+            number_of_authority_records,
+            reader.remaining(),
+            MIN_RECORD_LEN,
+        ));
 
         for _ in 0..number_of_authority_records {
             authority_records.push(DnsRecord::read_from(&mut reader)?);
         }
 
-        let mut additional_records: SmallVec<[DnsRecord; 1]> =
-            SmallVec::with_capacity(number_of_additional_records as usize);
+        let mut additional_records: SmallVec<[DnsRecord; 1]> = SmallVec::with_capacity(capacity(
+            number_of_additional_records,
+            reader.remaining(),
+            MIN_RECORD_LEN,
+        ));
 
         let mut edns: Option<Edns> = None;
 
@@ -118,6 +142,54 @@ impl DnsMessage {
         })
     }
 
+    /// Decode a DNS message from untrusted bytes, rejecting messages larger than [`MAX_MESSAGE_SIZE`] or
+    /// declaring more than `max_records` questions and records in total before parsing them.
+    pub fn decode_limited(data: &[u8], max_records: usize) -> crate::error::Result<Self> {
+        if data.len() > MAX_MESSAGE_SIZE {
+            return Err(DnsError::MessageTooLarge {
+                len: data.len(),
+                max: MAX_MESSAGE_SIZE,
+            });
+        }
+
+        // QDCOUNT, ANCOUNT, NSCOUNT, ARCOUNT, a short header is left to `decode` to reject.
+        if let Some(counts) = data.get(4..12) {
+            let count: usize = counts
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]) as usize)
+                .sum();
+            if count > max_records {
+                return Err(DnsError::TooManyRecords {
+                    count,
+                    max: max_records,
+                });
+            }
+        }
+
+        Self::decode(data)
+    }
+
+    /// Decode only the header and question section of a DNS message, ignoring the other sections.
+    ///
+    /// UPDATE messages (RFC 2136) carry their zone in the question section, but their other sections use
+    /// record encodings, such as empty RDATA, that [`DnsMessage::decode`] rejects.
+    pub fn decode_questions(data: &[u8]) -> crate::error::Result<Self> {
+        let mut reader = DnsMessageReader::new(data);
+
+        let id = reader.read_u16()?;
+        let flags = DnsFlags::read_from(&mut reader)?;
+        let number_of_questions = reader.read_u16()?;
+
+        // ANCOUNT, NSCOUNT, ARCOUNT
+        reader.read_bytes(6)?;
+
+        let questions = (0..number_of_questions)
+            .map(|_| DnsQuestion::read_from(&mut reader))
+            .collect::<crate::error::Result<Vec<_>>>()?;
+
+        Ok(Self::new(id, flags, questions, vec![], vec![], vec![]))
+    }
+
     /// Encode the DNS message into raw bytes.
     pub fn encode(&self) -> std::result::Result<Bytes, DnsError> {
         let mut writer = DnsMessageWriter::new();
@@ -138,7 +210,7 @@ impl DnsMessage {
         writer.write_u16(self.authority_records.len() as u16)?;
 
         // ARCOUNT
-        let additional_records_count = self.additional_records.len() + self.edns.is_some() as usize;
+        let additional_records_count = self.encoded_additional_records().count() + self.edns.is_some() as usize;
         writer.write_u16(additional_records_count as u16)?;
 
         // Questions
@@ -162,7 +234,7 @@ impl DnsMessage {
         }
 
         // Additional records
-        for additional_record in &self.additional_records {
+        for additional_record in self.encoded_additional_records() {
             additional_record.write_to(&mut writer)?;
         }
 
@@ -185,6 +257,254 @@ impl DnsMessage {
         &self.additional_records
     }
 
+    /// Check that this message is a standard query with a single question.
+    pub fn validate_query(&self) -> std::result::Result<(), DnsValidationError> {
+        if self.flags.response {
+            return Err(DnsValidationError::UnexpectedResponse);
+        }
+
+        if self.flags.opcode != DnsOpcode::Query {
+            return Err(DnsValidationError::UnsupportedOpcode(self.flags.opcode));
+        }
+
+        if self.questions.len() != 1 {
+            return Err(DnsValidationError::QuestionCount {
+                count: self.questions.len(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Check that this message is a response to `query` using an EDNS version we speak, if any.
+    pub fn validate_response(&self, query: &DnsMessage) -> std::result::Result<(), DnsValidationError> {
+        if !self.flags.response {
+            return Err(DnsValidationError::NotAResponse);
+        }
+
+        if self.id != query.id {
+            return Err(DnsValidationError::IdMismatch {
+                expected: query.id,
+                actual: self.id,
+            });
+        }
+
+        if self.flags.opcode != query.flags.opcode {
+            return Err(DnsValidationError::OpcodeMismatch {
+                expected: query.flags.opcode,
+                actual: self.flags.opcode,
+            });
+        }
+
+        if self.questions != query.questions {
+            return Err(DnsValidationError::QuestionMismatch);
+        }
+
+        // Only version 0 is defined, a different layout can't be trusted to mean what version 0 does.
+        if let Some(edns) = &self.edns
+            && edns.version != 0
+        {
+            return Err(DnsValidationError::UnsupportedEdnsVersion { version: edns.version });
+        }
+
+        Ok(())
+    }
+
+    /// Create an empty response to the given query with the given response code.
+    ///
+    /// The ID, opcode and questions are copied from the query, `RD` is mirrored and `QR` and `RA` are set.
+    pub fn response_from_query(query: &DnsMessage, response_code: DnsResponseCode) -> Self {
+        let flags = DnsFlags {
+            response: true,
+            opcode: query.flags.opcode,
+            recursion_desired: query.flags.recursion_desired,
+            recursion_available: true,
+            ..Default::default()
+        };
+
+        let mut message = Self::new(query.id, flags, query.questions.to_vec(), vec![], vec![], vec![]);
+        message.set_response_code(response_code);
+        message
+    }
+
+    /// Remove all records from the authority section.
+    pub fn clear_authority_records(&mut self) {
+        self.authority_records.clear();
+    }
+
+    /// Keep only the authority records for which `keep` returns true.
+    pub fn retain_authority_records(&mut self, mut keep: impl FnMut(&DnsRecord) -> bool) {
+        self.authority_records.retain(|record| keep(record));
+    }
+
+    /// Size of the encoded message in bytes, computed without encoding it.
+    ///
+    /// Names are counted uncompressed, so this is an upper bound of `encode().len()`. It is exact when
+    /// no name shares a suffix with a name before it, e.g. for queries.
+    pub fn wire_size(&self) -> usize {
+        let header = 12;
+        let questions: usize = self.questions.iter().map(|q| q.qname.wire_len() + 4).sum();
+        let records: usize = self
+            .answers
+            .iter()
+            .chain(&self.authority_records)
+            .chain(self.encoded_additional_records())
+            .map(DnsRecord::wire_len)
+            .sum();
+        let edns = self.edns.as_ref().map_or(0, Edns::wire_len);
+
+        header + questions + records + edns
+    }
+
+    /// Additional records to encode. With `edns` set, OPT records added to the additional section are skipped,
+    /// so a message never carries two OPT records.
+    fn encoded_additional_records(&self) -> impl Iterator<Item = &DnsRecord> {
+        let has_edns = self.edns.is_some();
+        self.additional_records
+            .iter()
+            .filter(move |record| !(has_edns && record.record_type == RecordType::OPT))
+    }
+
+    /// Remove all records from the additional section. The EDNS OPT record is kept.
+    pub fn clear_additional_records(&mut self) {
+        self.additional_records.clear();
+    }
+
+    /// Remove DNSSEC records from the answer and authority sections for a client without the DO bit that asked
+    /// for `qtype`. Records of `qtype` itself are kept, as the client asked for them explicitly, and nothing is
+    /// removed for ANY queries (RFC 4035 section 3.2.1).
+    pub fn strip_dnssec_records(&mut self, qtype: RecordType) {
+        self.answers.retain(|r| !strips_dnssec(r.record_type, qtype));
+        self.authority_records.retain(|r| !strips_dnssec(r.record_type, qtype));
+    }
+
+    /// Whether [`Self::strip_dnssec_records`] would remove any record.
+    pub fn has_strippable_dnssec_records(&self, qtype: RecordType) -> bool {
+        self.answers
+            .iter()
+            .chain(&self.authority_records)
+            .any(|r| strips_dnssec(r.record_type, qtype))
+    }
+
+    /// Remove records unrelated to the question, as injected into responses to poison caches.
+    ///
+    /// Answers must be owned by the question name or a name a CNAME in the answers aliases it to, in any order,
+    /// or be a DNAME above one of those. Authority records must be owned by a zone containing one of those names,
+    /// except for the NSEC and NSEC3 records proving a denial, which may be owned by any name in the zone of such
+    /// an SOA. Additional records must be owned by one of those names or by a target of a kept NS, MX or SRV
+    /// record. Returns the number of records removed.
+    pub fn strip_out_of_bailiwick_records(&mut self) -> usize {
+        let Some(question) = self.questions.first() else {
+            return 0;
+        };
+        let count = self.answers.len() + self.authority_records.len() + self.additional_records.len();
+
+        // The chain may be listed in any order, so follow it until no CNAME adds a name.
+        let mut names = vec![question.qname.clone()];
+        let mut followed = 0;
+        while followed < names.len() {
+            for record in &self.answers {
+                if record.record_type == RecordType::CNAME
+                    && record.name == names[followed]
+                    && let DnsRecordData::DomainName(target) = &record.data
+                    && !names.contains(target)
+                {
+                    names.push(target.clone());
+                }
+            }
+            followed += 1;
+        }
+
+        self.answers.retain(|record| {
+            if record.record_type == RecordType::DNAME {
+                names.iter().any(|name| name.is_subdomain_of(&record.name))
+            } else {
+                names.contains(&record.name)
+            }
+        });
+
+        let in_zone = |record: &DnsRecord| names.iter().any(|name| name.is_subdomain_of(&record.name));
+        let denial_zones: Vec<DomainName> = self
+            .authority_records
+            .iter()
+            .filter(|record| record.record_type == RecordType::SOA && in_zone(record))
+            .map(|record| record.name.clone())
+            .collect();
+        self.authority_records.retain(|record| {
+            let proves_denial = matches!(
+                record.record_type,
+                RecordType::NSEC | RecordType::NSEC3 | RecordType::RRSIG
+            );
+            in_zone(record) || proves_denial && denial_zones.iter().any(|zone| record.name.is_subdomain_of(zone))
+        });
+
+        let targets: Vec<&DomainName> = self
+            .answers
+            .iter()
+            .chain(&self.authority_records)
+            .filter_map(|record| match &record.data {
+                DnsRecordData::DomainName(target) if record.record_type == RecordType::NS => Some(target),
+                DnsRecordData::MX { host, .. } => Some(host),
+                DnsRecordData::SRV { target, .. } => Some(target),
+                _ => None,
+            })
+            .collect();
+        self.additional_records.retain(|record| {
+            record.record_type == RecordType::OPT || names.contains(&record.name) || targets.contains(&&record.name)
+        });
+
+        count - self.answers.len() - self.authority_records.len() - self.additional_records.len()
+    }
+
+    /// Set the transaction ID.
+    pub fn with_id(mut self, id: u16) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Set the transaction ID.
+    pub fn set_id(&mut self, id: u16) {
+        self.id = id;
+    }
+
+    /// Set the RA bit.
+    pub fn set_recursion_available(&mut self, recursion_available: bool) {
+        self.flags.recursion_available = recursion_available;
+    }
+
+    /// Set the RD bit.
+    pub fn set_recursion_desired(&mut self, recursion_desired: bool) {
+        self.flags.recursion_desired = recursion_desired;
+    }
+
+    /// Set the AD bit.
+    pub fn set_authentic_data(&mut self, authentic_data: bool) {
+        self.flags.authentic_data = authentic_data;
+    }
+
+    /// Set the CD bit.
+    pub fn set_checking_disabled(&mut self, checking_disabled: bool) {
+        self.flags.checking_disabled = checking_disabled;
+    }
+
+    /// Set the TC bit.
+    pub fn set_truncated(&mut self, truncated: bool) {
+        self.flags.truncated = truncated;
+    }
+
+    /// Change the transaction ID of this message and of `raw`, its encoding, returning the new encoding.
+    ///
+    /// The ID is the only thing that changes, so `raw` is patched instead of encoding the message again, and
+    /// neither is touched when the ID already matches.
+    pub fn reencode_with_id(&mut self, raw: Bytes, id: u16) -> Bytes {
+        if self.id == id {
+            return raw;
+        }
+
+        self.id = id;
+        rewrite_transaction_id(raw, id)
+    }
+
     pub fn set_edns(&mut self, edns: Option<Edns>) {
         self.edns = edns
     }
@@ -280,6 +600,64 @@ impl DnsFlags {
             rcode_low: 0,
         }
     }
+
+    /// Flags of a standard query that asks for recursion.
+    pub fn query() -> Self {
+        Self::default().with_recursion_desired(true)
+    }
+
+    /// Flags of a response from a server that offers recursion.
+    pub fn response() -> Self {
+        Self::default().with_response(true).with_recursion_available(true)
+    }
+
+    /// Set the QR bit.
+    pub fn with_response(mut self, response: bool) -> Self {
+        self.response = response;
+        self
+    }
+
+    /// Set the opcode.
+    pub fn with_opcode(mut self, opcode: DnsOpcode) -> Self {
+        self.opcode = opcode;
+        self
+    }
+
+    /// Set the AA bit.
+    pub fn with_authorative_answer(mut self, authorative_answer: bool) -> Self {
+        self.authorative_answer = authorative_answer;
+        self
+    }
+
+    /// Set the TC bit.
+    pub fn with_truncated(mut self, truncated: bool) -> Self {
+        self.truncated = truncated;
+        self
+    }
+
+    /// Set the RD bit.
+    pub fn with_recursion_desired(mut self, recursion_desired: bool) -> Self {
+        self.recursion_desired = recursion_desired;
+        self
+    }
+
+    /// Set the RA bit.
+    pub fn with_recursion_available(mut self, recursion_available: bool) -> Self {
+        self.recursion_available = recursion_available;
+        self
+    }
+
+    /// Set the AD bit.
+    pub fn with_authentic_data(mut self, authentic_data: bool) -> Self {
+        self.authentic_data = authentic_data;
+        self
+    }
+
+    /// Set the CD bit.
+    pub fn with_checking_disabled(mut self, checking_disabled: bool) -> Self {
+        self.checking_disabled = checking_disabled;
+        self
+    }
 }
 
 impl DnsReadable for DnsFlags {
@@ -381,6 +759,10 @@ pub enum DnsOpcode {
     IQuery = 1,
     /// Server status request, obsolete
     Status = 2,
+    /// Zone change notification (RFC 1996)
+    Notify = 4,
+    /// Dynamic update (RFC 2136)
+    Update = 5,
 }
 
 impl TryFrom<u8> for DnsOpcode {
@@ -391,6 +773,8 @@ impl TryFrom<u8> for DnsOpcode {
             0 => Ok(Self::Query),
             1 => Ok(Self::IQuery),
             2 => Ok(Self::Status),
+            4 => Ok(Self::Notify),
+            5 => Ok(Self::Update),
             _ => Err(DnsError::InvalidOpcode(value)),
         }
     }
@@ -628,6 +1012,16 @@ pub enum RecordType {
     IPN = 264,
 }}
 
+impl RecordType {
+    /// Whether this is a DNSSEC record type that is only useful to clients that set the DO bit.
+    pub fn is_dnssec(&self) -> bool {
+        matches!(
+            self,
+            RecordType::RRSIG | RecordType::NSEC | RecordType::NSEC3 | RecordType::DNSKEY | RecordType::DS
+        )
+    }
+}
+
 u16_enum_with_unknown! {
     /// DNS class types.
     pub enum ClassType {
@@ -676,9 +1070,230 @@ pub enum DnsRecordData {
         port: u16,
         target: DomainName,
     },
+    /// Host information (RFC 1035).
+    Hinfo {
+        cpu: Box<[u8]>,
+        os: Box<[u8]>,
+    },
+    /// Location information (RFC 1876).
+    Loc {
+        /// Version, always 0.
+        version: u8,
+        /// Diameter of the enclosing sphere, as a base 10 mantissa and exponent in centimeters.
+        size: u8,
+        /// Horizontal precision, encoded like `size`.
+        horiz_pre: u8,
+        /// Vertical precision, encoded like `size`.
+        vert_pre: u8,
+        /// Latitude in thousandths of an arc second, offset by 2^31 at the equator.
+        latitude: u32,
+        /// Longitude in thousandths of an arc second, offset by 2^31 at the prime meridian.
+        longitude: u32,
+        /// Altitude in centimeters above a base of 100,000m below the WGS 84 ellipsoid.
+        altitude: u32,
+    },
+    /// Uniform resource identifier (RFC 7553).
+    Uri {
+        priority: u16,
+        weight: u16,
+        target: String,
+    },
+    /// Naming authority pointer (RFC 3403).
+    Naptr {
+        order: u16,
+        preference: u16,
+        flags: Box<[u8]>,
+        services: Box<[u8]>,
+        regexp: Box<[u8]>,
+        replacement: DomainName,
+    },
+    /// Service binding, used by SVCB and HTTPS records (RFC 9460).
+    Svcb {
+        /// 0 for alias mode, the preference of the endpoint otherwise.
+        priority: u16,
+        target: DomainName,
+        /// Parameters in wire order, which is ascending by key.
+        params: Vec<SvcParam>,
+    },
+    /// Geographical position (RFC 1712), each a decimal number as a character-string.
+    Gpos {
+        /// Degrees east of the prime meridian, negative for west.
+        longitude: Box<[u8]>,
+        /// Degrees north of the equator, negative for south.
+        latitude: Box<[u8]>,
+        /// Meters above sea level.
+        altitude: Box<[u8]>,
+    },
+    /// Lists of address prefixes (RFC 3123).
+    Apl(Vec<AplItem>),
     DomainName(DomainName),
 }
 
+/// Address prefix of an APL record.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AplItem {
+    /// IANA address family, see [`AplItem::IPV4`] and [`AplItem::IPV6`].
+    pub family: u16,
+    /// Prefix length in bits.
+    pub prefix: u8,
+    /// Whether the prefix is excluded.
+    pub negation: bool,
+    /// Address bytes, without trailing zero bytes on the wire.
+    pub address: Vec<u8>,
+}
+
+impl AplItem {
+    /// Address family of IPv4 prefixes.
+    pub const IPV4: u16 = 1;
+    /// Address family of IPv6 prefixes.
+    pub const IPV6: u16 = 2;
+
+    /// Item for the prefix of `addr`, dropping the trailing zero bytes of the address as RFC 3123 requires.
+    pub fn new(addr: IpAddr, prefix: u8, negation: bool) -> Self {
+        let (family, mut address) = match addr {
+            IpAddr::V4(addr) => (Self::IPV4, addr.octets().to_vec()),
+            IpAddr::V6(addr) => (Self::IPV6, addr.octets().to_vec()),
+        };
+        while address.last() == Some(&0) {
+            address.pop();
+        }
+
+        Self {
+            family,
+            prefix,
+            negation,
+            address,
+        }
+    }
+
+    /// Address of the prefix, `None` for families other than IPv4 and IPv6 or an overlong address.
+    pub fn addr(&self) -> Option<IpAddr> {
+        match self.family {
+            Self::IPV4 if self.address.len() <= 4 => {
+                let mut octets = [0; 4];
+                octets[..self.address.len()].copy_from_slice(&self.address);
+                Some(IpAddr::from(octets))
+            }
+            Self::IPV6 if self.address.len() <= 16 => {
+                let mut octets = [0; 16];
+                octets[..self.address.len()].copy_from_slice(&self.address);
+                Some(IpAddr::from(octets))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Presentation format of the item, e.g. `!1:192.0.2.0/24`.
+impl std::fmt::Display for AplItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.negation {
+            write!(f, "!")?;
+        }
+        write!(f, "{}:", self.family)?;
+        match self.addr() {
+            Some(addr) => write!(f, "{}", addr)?,
+            None => {
+                for byte in &self.address {
+                    write!(f, "{:02x}", byte)?;
+                }
+            }
+        }
+        write!(f, "/{}", self.prefix)
+    }
+}
+
+/// Parameter of an SVCB or HTTPS record, with its value in wire format.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SvcParam {
+    pub key: u16,
+    pub value: Vec<u8>,
+}
+
+impl SvcParam {
+    /// Keys the client must support to use the record.
+    pub const MANDATORY: u16 = 0;
+    /// Application protocols supported by the endpoint.
+    pub const ALPN: u16 = 1;
+    /// The default protocol of the scheme is not supported.
+    pub const NO_DEFAULT_ALPN: u16 = 2;
+    /// Port of the endpoint.
+    pub const PORT: u16 = 3;
+    /// IPv4 addresses of the endpoint.
+    pub const IPV4_HINT: u16 = 4;
+    /// Encrypted Client Hello config list.
+    pub const ECH: u16 = 5;
+    /// IPv6 addresses of the endpoint.
+    pub const IPV6_HINT: u16 = 6;
+
+    pub fn new(key: u16, value: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key,
+            value: value.into(),
+        }
+    }
+
+    /// Presentation name of `key`, e.g. `alpn` or `key65000`.
+    fn key_name(key: u16) -> Cow<'static, str> {
+        match key {
+            Self::MANDATORY => "mandatory".into(),
+            Self::ALPN => "alpn".into(),
+            Self::NO_DEFAULT_ALPN => "no-default-alpn".into(),
+            Self::PORT => "port".into(),
+            Self::IPV4_HINT => "ipv4hint".into(),
+            Self::ECH => "ech".into(),
+            Self::IPV6_HINT => "ipv6hint".into(),
+            other => format!("key{}", other).into(),
+        }
+    }
+}
+
+/// Presentation format of the parameter, e.g. `alpn="h2,h3"` or `port=8443`.
+impl std::fmt::Display for SvcParam {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Self::key_name(self.key))?;
+        if self.value.is_empty() {
+            return Ok(());
+        }
+
+        let value = &self.value;
+        match self.key {
+            Self::MANDATORY if value.len().is_multiple_of(2) => {
+                let keys: Vec<_> = value
+                    .chunks_exact(2)
+                    .map(|key| Self::key_name(u16::from_be_bytes([key[0], key[1]])))
+                    .collect();
+                write!(f, "={}", keys.join(","))
+            }
+            Self::ALPN if let Some(protocols) = alpn_protocols(value) => {
+                write!(f, "={}", quoted(&protocols.join(",")))
+            }
+            Self::PORT if value.len() == 2 => write!(f, "={}", u16::from_be_bytes([value[0], value[1]])),
+            Self::IPV4_HINT if let Some(hints) = ipv4_hints(value) => {
+                let hints: Vec<_> = hints.iter().map(Ipv4Addr::to_string).collect();
+                write!(f, "={}", hints.join(","))
+            }
+            Self::IPV6_HINT if let Some(hints) = ipv6_hints(value) => {
+                let hints: Vec<_> = hints.iter().map(Ipv6Addr::to_string).collect();
+                write!(f, "={}", hints.join(","))
+            }
+            Self::ECH => write!(f, "={}", base64(value)),
+            _ => {
+                // RFC 9460 generic value, a character-string with non-printable bytes escaped.
+                write!(f, "=\"")?;
+                for &byte in value {
+                    match byte {
+                        b'"' | b'\\' => write!(f, "\\{}", byte as char)?,
+                        0x21..=0x7e => write!(f, "{}", byte as char)?,
+                        _ => write!(f, "\\{:03}", byte)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+        }
+    }
+}
+
 impl DnsRecordData {
     /// Write the DNS record data to the DNS message.
     pub fn write(&self, writer: &mut DnsMessageWriter) -> WriteResult<()> {
@@ -727,9 +1342,127 @@ impl DnsRecordData {
                 writer.write_u16(*priority)?;
                 writer.write_u16(*weight)?;
                 writer.write_u16(*port)?;
-                writer.write_qname(target)?;
+                // RFC 2782 forbids compression of the target name.
+                writer.write_qname_uncompressed(target)?;
+                Ok(())
+            }
+            DnsRecordData::Hinfo { cpu, os } => {
+                write_character_string(writer, cpu)?;
+                write_character_string(writer, os)?;
+                Ok(())
+            }
+            DnsRecordData::Loc {
+                version,
+                size,
+                horiz_pre,
+                vert_pre,
+                latitude,
+                longitude,
+                altitude,
+            } => {
+                writer.write_u8(*version)?;
+                writer.write_u8(*size)?;
+                writer.write_u8(*horiz_pre)?;
+                writer.write_u8(*vert_pre)?;
+                writer.write_u32(*latitude)?;
+                writer.write_u32(*longitude)?;
+                writer.write_u32(*altitude)?;
+                Ok(())
+            }
+            DnsRecordData::Uri {
+                priority,
+                weight,
+                target,
+            } => {
+                writer.write_u16(*priority)?;
+                writer.write_u16(*weight)?;
+                writer.write_bytes(target.as_bytes())?;
+                Ok(())
+            }
+            DnsRecordData::Naptr {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => {
+                writer.write_u16(*order)?;
+                writer.write_u16(*preference)?;
+                write_character_string(writer, flags)?;
+                write_character_string(writer, services)?;
+                write_character_string(writer, regexp)?;
+                // RFC 3403 forbids compression of the replacement field.
+                writer.write_qname_uncompressed(replacement)?;
+                Ok(())
+            }
+            DnsRecordData::Svcb {
+                priority,
+                target,
+                params,
+            } => {
+                writer.write_u16(*priority)?;
+                // RFC 9460 forbids compression of the target name.
+                writer.write_qname_uncompressed(target)?;
+                for param in params {
+                    writer.write_u16(param.key)?;
+                    writer.write_u16(param.value.len() as u16)?;
+                    writer.write_bytes(&param.value)?;
+                }
+                Ok(())
+            }
+            DnsRecordData::Gpos {
+                longitude,
+                latitude,
+                altitude,
+            } => {
+                write_character_string(writer, longitude)?;
+                write_character_string(writer, latitude)?;
+                write_character_string(writer, altitude)?;
                 Ok(())
             }
+            DnsRecordData::Apl(items) => {
+                for item in items {
+                    writer.write_u16(item.family)?;
+                    writer.write_u8(item.prefix)?;
+                    writer.write_u8((item.negation as u8) << 7 | item.address.len() as u8)?;
+                    writer.write_bytes(&item.address)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Wire length of the record data, with any names counted uncompressed.
+    pub fn wire_len(&self) -> usize {
+        match self {
+            DnsRecordData::Raw(data) => data.len(),
+            DnsRecordData::Ipv4(_) => 4,
+            DnsRecordData::Ipv6(_) => 16,
+            DnsRecordData::Text(chunks) => chunks.iter().map(|chunk| 1 + chunk.len()).sum(),
+            DnsRecordData::DomainName(name) => name.wire_len(),
+            DnsRecordData::SOA { mname, rname, .. } => mname.wire_len() + rname.wire_len() + 20,
+            DnsRecordData::MX { host, .. } => 2 + host.wire_len(),
+            DnsRecordData::SRV { target, .. } => 6 + target.wire_len(),
+            DnsRecordData::Hinfo { cpu, os } => 2 + cpu.len() + os.len(),
+            DnsRecordData::Loc { .. } => 16,
+            DnsRecordData::Uri { target, .. } => 4 + target.len(),
+            DnsRecordData::Naptr {
+                flags,
+                services,
+                regexp,
+                replacement,
+                ..
+            } => 7 + flags.len() + services.len() + regexp.len() + replacement.wire_len(),
+            DnsRecordData::Svcb { target, params, .. } => {
+                2 + target.wire_len() + params.iter().map(|param| 4 + param.value.len()).sum::<usize>()
+            }
+            DnsRecordData::Gpos {
+                longitude,
+                latitude,
+                altitude,
+            } => 3 + longitude.len() + latitude.len() + altitude.len(),
+            DnsRecordData::Apl(items) => items.iter().map(|item| 4 + item.address.len()).sum(),
         }
     }
 
@@ -805,23 +1538,439 @@ impl DnsRecordData {
                 port: reader.read_u16()?,
                 target: reader.read_qname()?,
             },
+            RecordType::HINFO => {
+                let start = reader.position();
+                let cpu = read_character_string(reader)?;
+                let os = read_character_string(reader)?;
+                check_rdata_consumed(reader, start, data_length)?;
+                DnsRecordData::Hinfo { cpu, os }
+            }
+            // Version 0 is the only defined layout and is always 16 bytes long.
+            RecordType::LOC if data_length == 16 => DnsRecordData::Loc {
+                version: reader.read_u8()?,
+                size: reader.read_u8()?,
+                horiz_pre: reader.read_u8()?,
+                vert_pre: reader.read_u8()?,
+                latitude: reader.read_u32()?,
+                longitude: reader.read_u32()?,
+                altitude: reader.read_u32()?,
+            },
+            // The target is not length-prefixed and takes up the rest of the record.
+            RecordType::URI if data_length >= 4 => DnsRecordData::Uri {
+                priority: reader.read_u16()?,
+                weight: reader.read_u16()?,
+                target: String::from_utf8_lossy(reader.read_bytes(data_length - 4)?).into_owned(),
+            },
+            RecordType::NAPTR => {
+                let start = reader.position();
+                let order = reader.read_u16()?;
+                let preference = reader.read_u16()?;
+                let flags = read_character_string(reader)?;
+                let services = read_character_string(reader)?;
+                let regexp = read_character_string(reader)?;
+                let consumed = reader.position() - start;
+                if consumed > data_length {
+                    return Err(DnsReadError::BufferUnderflow {
+                        pos: start + data_length,
+                        need: consumed,
+                        have: data_length,
+                    });
+                }
+                let replacement = reader.read_qname_uncompressed(data_length - consumed)?;
+                check_rdata_consumed(reader, start, data_length)?;
+                DnsRecordData::Naptr {
+                    order,
+                    preference,
+                    flags,
+                    services,
+                    regexp,
+                    replacement,
+                }
+            }
+            RecordType::SVCB | RecordType::HTTPS => {
+                let end = reader.position() + data_length;
+                let priority = reader.read_u16()?;
+                let target = reader.read_qname()?;
+                let mut params = Vec::new();
+
+                while reader.position() < end {
+                    let key = reader.read_u16()?;
+                    let len = reader.read_u16()? as usize;
+                    let have = end.saturating_sub(reader.position());
+                    if len > have {
+                        return Err(DnsReadError::BufferUnderflow {
+                            pos: reader.position(),
+                            need: len,
+                            have,
+                        });
+                    }
+                    params.push(SvcParam::new(key, reader.read_bytes(len)?));
+                }
+
+                DnsRecordData::Svcb {
+                    priority,
+                    target,
+                    params,
+                }
+            }
+            RecordType::GPOS => {
+                let start = reader.position();
+                let longitude = read_character_string(reader)?;
+                let latitude = read_character_string(reader)?;
+                let altitude = read_character_string(reader)?;
+                check_rdata_consumed(reader, start, data_length)?;
+                DnsRecordData::Gpos {
+                    longitude,
+                    latitude,
+                    altitude,
+                }
+            }
+            RecordType::APL => {
+                let end = reader.position() + data_length;
+                let mut items = Vec::new();
+
+                while reader.position() < end {
+                    let family = reader.read_u16()?;
+                    let prefix = reader.read_u8()?;
+                    let flags = reader.read_u8()?;
+                    let len = (flags & 0x7F) as usize;
+                    let have = end.saturating_sub(reader.position());
+                    if len > have {
+                        return Err(DnsReadError::BufferUnderflow {
+                            pos: reader.position(),
+                            need: len,
+                            have,
+                        });
+                    }
+                    items.push(AplItem {
+                        family,
+                        prefix,
+                        negation: flags & 0x80 != 0,
+                        address: reader.read_bytes(len)?.to_vec(),
+                    });
+                }
+
+                DnsRecordData::Apl(items)
+            }
             _ => {
                 let raw_data = reader.read_bytes(data_length)?;
                 DnsRecordData::Raw(raw_data.into())
             }
         })
     }
-}
 
-/// Record in the answer, authority, and additional sections of a DNS message.
-#[derive(Debug, Clone, Eq)]
-pub struct DnsRecord {
-    pub name: DomainName,
-    pub record_type: RecordType,
-    pub class: ClassType,
-    pub ttl: u32,
-    pub data: DnsRecordData,
-}
+    /// Wire format value of the SVCB or HTTPS parameter `key`, `None` if the parameter is absent or this isn't
+    /// service binding data.
+    pub fn svc_param(&self, key: u16) -> Option<&[u8]> {
+        let DnsRecordData::Svcb { params, .. } = self else {
+            return None;
+        };
+        params
+            .iter()
+            .find(|param| param.key == key)
+            .map(|param| param.value.as_slice())
+    }
+
+    /// Application protocols of the `alpn` parameter.
+    pub fn alpn(&self) -> Option<Vec<String>> {
+        alpn_protocols(self.svc_param(SvcParam::ALPN)?)
+    }
+
+    /// Port of the `port` parameter.
+    pub fn port(&self) -> Option<u16> {
+        let value = self.svc_param(SvcParam::PORT)?;
+        Some(u16::from_be_bytes(value.try_into().ok()?))
+    }
+
+    /// Addresses of the `ipv4hint` parameter.
+    pub fn ipv4_hint(&self) -> Option<Vec<Ipv4Addr>> {
+        ipv4_hints(self.svc_param(SvcParam::IPV4_HINT)?)
+    }
+
+    /// Encrypted Client Hello config list of the `ech` parameter.
+    pub fn ech(&self) -> Option<&[u8]> {
+        self.svc_param(SvcParam::ECH)
+    }
+
+    /// Addresses of the `ipv6hint` parameter.
+    pub fn ipv6_hint(&self) -> Option<Vec<Ipv6Addr>> {
+        ipv6_hints(self.svc_param(SvcParam::IPV6_HINT)?)
+    }
+}
+
+/// Decode an `alpn` value, a sequence of character-strings. `None` if a string runs past the value.
+fn alpn_protocols(value: &[u8]) -> Option<Vec<String>> {
+    let mut protocols = Vec::new();
+    let mut rest = value;
+    while let Some((&len, tail)) = rest.split_first() {
+        let protocol = tail.get(..len as usize)?;
+        protocols.push(String::from_utf8_lossy(protocol).into_owned());
+        rest = &tail[len as usize..];
+    }
+    Some(protocols)
+}
+
+/// Decode an `ipv4hint` value, `None` unless it is a non-empty list of addresses.
+fn ipv4_hints(value: &[u8]) -> Option<Vec<Ipv4Addr>> {
+    if value.is_empty() || !value.len().is_multiple_of(4) {
+        return None;
+    }
+    Some(
+        value
+            .chunks_exact(4)
+            .map(|octets| Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]))
+            .collect(),
+    )
+}
+
+/// Decode an `ipv6hint` value, `None` unless it is a non-empty list of addresses.
+fn ipv6_hints(value: &[u8]) -> Option<Vec<Ipv6Addr>> {
+    if value.is_empty() || !value.len().is_multiple_of(16) {
+        return None;
+    }
+    Some(
+        value
+            .chunks_exact(16)
+            .map(|octets| Ipv6Addr::from(<[u8; 16]>::try_from(octets).unwrap()))
+            .collect(),
+    )
+}
+
+/// Standard base64 with padding, the presentation format of the `ech` parameter.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Read a length-prefixed character-string.
+fn read_character_string(reader: &mut DnsMessageReader) -> ReadResult<Box<[u8]>> {
+    let len = reader.read_u8()? as usize;
+    Ok(reader.read_bytes(len)?.into())
+}
+
+/// Write a length-prefixed character-string, which holds at most 255 bytes.
+fn write_character_string(writer: &mut DnsMessageWriter, s: &[u8]) -> WriteResult<()> {
+    let len = u8::try_from(s.len()).map_err(|_| DnsWriteError::CharacterStringTooLong { len: s.len() })?;
+    writer.write_u8(len)?;
+    writer.write_bytes(s)
+}
+
+/// Check that record data starting at `start` took up exactly `data_length` bytes.
+fn check_rdata_consumed(reader: &DnsMessageReader, start: usize, data_length: usize) -> ReadResult<()> {
+    let pos = reader.position();
+    let end = start + data_length;
+    if pos > end {
+        return Err(DnsReadError::BufferUnderflow {
+            pos: end,
+            need: pos - start,
+            have: data_length,
+        });
+    }
+    if pos < end {
+        return Err(DnsReadError::TrailingBytes { pos, end });
+    }
+    Ok(())
+}
+
+/// Whether a DNSSEC record of `record_type` is removed from a response to a `qtype` query without the DO bit.
+fn strips_dnssec(record_type: RecordType, qtype: RecordType) -> bool {
+    qtype != RecordType::ANY && record_type != qtype && record_type.is_dnssec()
+}
+
+/// Quoted character-string, escaping backslashes and quotes.
+fn quoted(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Quoted raw character-string, escaping bytes outside printable ASCII as `\DDD`.
+fn quoted_bytes(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() + 2);
+    out.push('"');
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            0x20..=0x7e => out.push(byte as char),
+            _ => out.push_str(&format!("\\{byte:03}")),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Write a LOC latitude or longitude as degrees, minutes, seconds and hemisphere.
+fn write_loc_coordinate(f: &mut std::fmt::Formatter<'_>, value: u32, hemispheres: [char; 2]) -> std::fmt::Result {
+    let offset = value as i64 - (1 << 31);
+    let hemisphere = if offset < 0 { hemispheres[1] } else { hemispheres[0] };
+    let millis = offset.unsigned_abs();
+    write!(
+        f,
+        "{} {:02} {:02}.{:03} {}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000,
+        hemisphere
+    )
+}
+
+/// Presentation format of the record data, as used in zone files.
+impl std::fmt::Display for DnsRecordData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DnsRecordData::Raw(data) => {
+                // RFC 3597 generic encoding.
+                write!(f, "\\# {}", data.len())?;
+                if !data.is_empty() {
+                    write!(f, " ")?;
+                    for byte in data {
+                        write!(f, "{:02x}", byte)?;
+                    }
+                }
+                Ok(())
+            }
+            DnsRecordData::Ipv4(addr) => write!(f, "{}", addr),
+            DnsRecordData::Ipv6(addr) => write!(f, "{}", addr),
+            DnsRecordData::Text(chunks) => {
+                for (i, chunk) in chunks.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", quoted(chunk))?;
+                }
+                Ok(())
+            }
+            DnsRecordData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                mname.to_fqdn(),
+                rname.to_fqdn(),
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum
+            ),
+            DnsRecordData::MX { priority, host } => write!(f, "{} {}", priority, host.to_fqdn()),
+            DnsRecordData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(f, "{} {} {} {}", priority, weight, port, target.to_fqdn()),
+            DnsRecordData::Hinfo { cpu, os } => write!(f, "{} {}", quoted_bytes(cpu), quoted_bytes(os)),
+            DnsRecordData::Loc {
+                size,
+                horiz_pre,
+                vert_pre,
+                latitude,
+                longitude,
+                altitude,
+                ..
+            } => {
+                write_loc_coordinate(f, *latitude, ['N', 'S'])?;
+                write!(f, " ")?;
+                write_loc_coordinate(f, *longitude, ['E', 'W'])?;
+                let altitude = *altitude as i64 - 10_000_000;
+                let sign = if altitude < 0 { "-" } else { "" };
+                write!(
+                    f,
+                    " {}{}.{:02}m",
+                    sign,
+                    altitude.unsigned_abs() / 100,
+                    altitude.unsigned_abs() % 100
+                )?;
+                for precision in [size, horiz_pre, vert_pre] {
+                    let centimeters = (*precision >> 4) as u64 * 10u64.pow((*precision & 0x0F) as u32);
+                    write!(f, " {}.{:02}m", centimeters / 100, centimeters % 100)?;
+                }
+                Ok(())
+            }
+            DnsRecordData::Uri {
+                priority,
+                weight,
+                target,
+            } => write!(f, "{} {} {}", priority, weight, quoted(target)),
+            DnsRecordData::Naptr {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => write!(
+                f,
+                "{} {} {} {} {} {}",
+                order,
+                preference,
+                quoted_bytes(flags),
+                quoted_bytes(services),
+                quoted_bytes(regexp),
+                replacement.to_fqdn()
+            ),
+            DnsRecordData::Svcb {
+                priority,
+                target,
+                params,
+            } => {
+                write!(f, "{} {}", priority, target.to_fqdn())?;
+                for param in params {
+                    write!(f, " {}", param)?;
+                }
+                Ok(())
+            }
+            DnsRecordData::Gpos {
+                longitude,
+                latitude,
+                altitude,
+            } => write!(
+                f,
+                "{} {} {}",
+                quoted_bytes(longitude),
+                quoted_bytes(latitude),
+                quoted_bytes(altitude)
+            ),
+            DnsRecordData::Apl(items) => {
+                let items: Vec<_> = items.iter().map(AplItem::to_string).collect();
+                write!(f, "{}", items.join(" "))
+            }
+            DnsRecordData::DomainName(name) => write!(f, "{}", name.to_fqdn()),
+        }
+    }
+}
+
+/// Record in the answer, authority, and additional sections of a DNS message.
+#[derive(Debug, Clone, Eq)]
+pub struct DnsRecord {
+    pub name: DomainName,
+    pub record_type: RecordType,
+    pub class: ClassType,
+    pub ttl: u32,
+    pub data: DnsRecordData,
+}
 
 impl DnsRecord {
     pub fn new(name: DomainName, record_type: RecordType, class: ClassType, ttl: u32, data: DnsRecordData) -> Self {
@@ -853,6 +2002,58 @@ impl DnsRecord {
     pub fn data(&self) -> &DnsRecordData {
         &self.data
     }
+    /// Uncompressed wire length of the record, including its name and fixed fields.
+    pub fn wire_len(&self) -> usize {
+        // type, class, ttl and rdlength.
+        self.name.wire_len() + 10 + self.data.wire_len()
+    }
+    /// Whether both records have the same name, type, class and data, ignoring the TTL.
+    ///
+    /// Use this to compare the contents of RRsets, `PartialEq` also compares the TTL.
+    pub fn same_rdata(&self, other: &DnsRecord) -> bool {
+        self.name == other.name
+            && self.record_type == other.record_type
+            && self.class == other.class
+            && self.data == other.data
+    }
+}
+
+/// Records sharing a name, type and class (RFC 2181 section 5).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RRset<'a> {
+    pub name: &'a DomainName,
+    pub record_type: RecordType,
+    pub class: ClassType,
+    pub records: Vec<&'a DnsRecord>,
+}
+
+impl<'a> RRset<'a> {
+    /// Group `records` into RRsets, in the order each RRset first appears.
+    pub fn group(records: &'a [DnsRecord]) -> Vec<RRset<'a>> {
+        let mut rrsets: Vec<RRset<'a>> = Vec::new();
+        for record in records {
+            match rrsets.iter_mut().find(|rrset| rrset.contains_type_of(record)) {
+                Some(rrset) => rrset.records.push(record),
+                None => rrsets.push(RRset {
+                    name: &record.name,
+                    record_type: record.record_type,
+                    class: record.class,
+                    records: vec![record],
+                }),
+            }
+        }
+        rrsets
+    }
+
+    /// Lowest TTL of the records, which all of them must be treated as having (RFC 2181 section 5.2).
+    pub fn min_ttl(&self) -> u32 {
+        self.records.iter().map(|r| r.ttl).min().unwrap_or(0)
+    }
+
+    /// Whether `record` belongs to this RRset.
+    fn contains_type_of(&self, record: &DnsRecord) -> bool {
+        *self.name == record.name && self.record_type == record.record_type && self.class == record.class
+    }
 }
 
 impl DnsReadable for DnsRecord {
@@ -916,7 +2117,7 @@ pub struct Edns {
     pub udp_payload_size: u16,
     /// High bits of RCODE (ttl[31:24])
     extended_rcode: u8,
-    /// EDNS version, must be 0.
+    /// EDNS version, only version 0 is supported. Queries with other versions are answered with BADVERS.
     pub version: u8,
     /// Z flags
     z_flags: u16,
@@ -961,10 +2162,6 @@ impl DnsReadable for Edns {
         let extended_rcode = ((ttl >> 24) & 0xFF) as u8;
         let version = ((ttl >> 16) & 0xFF) as u8;
 
-        if version != 0 {
-            return Err(DnsError::UnsupportedEdnsVersion(version));
-        }
-
         let z_flags = (ttl & 0xFFFF) as u16;
 
         // RDLEN + options;
@@ -989,6 +2186,18 @@ impl DnsReadable for Edns {
 
 static ROOT: LazyLock<DomainName> = LazyLock::new(DomainName::root);
 
+impl Edns {
+    /// Wire length of the OPT record.
+    fn wire_len(&self) -> usize {
+        // root name, type, class, ttl and rdlength.
+        11 + self
+            .options
+            .iter()
+            .map(|opt| 4 + opt.wire_len() as usize)
+            .sum::<usize>()
+    }
+}
+
 impl DnsWritable for Edns {
     fn write_to(&self, writer: &mut DnsMessageWriter) -> Result<()> {
         // NAME = root
@@ -1037,6 +2246,26 @@ impl EdnsOption {
     pub fn wire_len(&self) -> u16 {
         self.data.as_ref().map_or(0, |d| d.wire_len())
     }
+
+    /// Option code.
+    pub fn code(&self) -> EdnsOptionCode {
+        self.code
+    }
+
+    /// Length of the option data, as written in the option's length field.
+    pub fn len(&self) -> u16 {
+        self.wire_len()
+    }
+
+    /// Whether the option carries no data.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Option data, if any.
+    pub fn data(&self) -> Option<&EdnsOptionData> {
+        self.data.as_ref()
+    }
 }
 
 u16_enum_with_unknown! {
@@ -1097,6 +2326,34 @@ pub struct ClientSubnet {
     pub address: Vec<u8>,
 }
 
+impl ClientSubnet {
+    /// Create a query client subnet for `addr`, revealing only the first `source_prefix` bits.
+    ///
+    /// The prefix is clamped to the address length.
+    pub fn new(addr: IpAddr, source_prefix: u8) -> Self {
+        let (family, max_prefix, octets) = match addr {
+            IpAddr::V4(v4) => (1, 32, v4.octets().to_vec()),
+            IpAddr::V6(v6) => (2, 128, v6.octets().to_vec()),
+        };
+        let source_prefix = source_prefix.min(max_prefix);
+
+        let mut address = octets[..(source_prefix as usize).div_ceil(8)].to_vec();
+        let trailing_bits = source_prefix % 8;
+        if trailing_bits != 0
+            && let Some(last) = address.last_mut()
+        {
+            *last &= 0xFF << (8 - trailing_bits);
+        }
+
+        Self {
+            family,
+            source_prefix,
+            scope_prefix: 0,
+            address,
+        }
+    }
+}
+
 /// Data for an EDNS option.
 #[derive(Debug, Clone, PartialEq)]
 pub enum EdnsOptionData {
@@ -1438,11 +2695,182 @@ u16_enum_with_unknown! {
 #[cfg(test)]
 mod tests {
 
-    use crate::DnsMessageBuilder;
+    use crate::{DnsMessageBuilder, EdnsBuilder};
 
     use super::*;
     use smallvec::smallvec;
 
+    fn a_record(name: &str, ttl: u32, last_octet: u8) -> DnsRecord {
+        DnsRecord::new(
+            DomainName::from_ascii(name).unwrap(),
+            RecordType::A,
+            ClassType::IN,
+            ttl,
+            DnsRecordData::Ipv4(std::net::Ipv4Addr::new(192, 0, 2, last_octet)),
+        )
+    }
+
+    fn record(name: &str, record_type: RecordType, data: DnsRecordData) -> DnsRecord {
+        DnsRecord::new(
+            DomainName::from_ascii(name).unwrap(),
+            record_type,
+            ClassType::IN,
+            300,
+            data,
+        )
+    }
+
+    fn name(name: &str) -> DomainName {
+        DomainName::from_ascii(name).unwrap()
+    }
+
+    #[test]
+    fn test_strip_out_of_bailiwick_records() {
+        let mut response = DnsMessageBuilder::new()
+            .add_question(DnsQuestion::new(name("www.example.com"), RecordType::A, ClassType::IN))
+            .add_answer(record(
+                "www.example.com",
+                RecordType::CNAME,
+                DnsRecordData::DomainName(name("edge.cdn.net")),
+            ))
+            .add_answer(a_record("edge.cdn.net", 300, 1))
+            .add_answer(a_record("bank.example.org", 300, 66))
+            .add_authority_record(record(
+                "cdn.net",
+                RecordType::NS,
+                DnsRecordData::DomainName(name("ns1.cdn.net")),
+            ))
+            .add_authority_record(record(
+                "example.org",
+                RecordType::NS,
+                DnsRecordData::DomainName(name("ns.attacker.test")),
+            ))
+            .add_additional_record(a_record("ns1.cdn.net", 300, 53))
+            .add_additional_record(a_record("ns.attacker.test", 300, 66))
+            .build();
+
+        assert_eq!(response.strip_out_of_bailiwick_records(), 3);
+
+        let answer_names: Vec<_> = response.answers().iter().map(|r| r.name()).collect();
+        assert_eq!(answer_names, ["www.example.com", "edge.cdn.net"]);
+        assert_eq!(response.authority_records().len(), 1);
+        assert_eq!(response.authority_records()[0].name(), "cdn.net");
+        assert_eq!(response.additional_records().len(), 1);
+        assert_eq!(response.additional_records()[0].name(), "ns1.cdn.net");
+    }
+
+    #[test]
+    fn test_strip_out_of_bailiwick_follows_cname_listed_after_its_target() {
+        let mut response = DnsMessageBuilder::new()
+            .add_question(DnsQuestion::new(name("www.example.com"), RecordType::A, ClassType::IN))
+            .add_answer(a_record("edge.cdn.net", 300, 1))
+            .add_answer(record(
+                "cdn.example.com",
+                RecordType::CNAME,
+                DnsRecordData::DomainName(name("edge.cdn.net")),
+            ))
+            .add_answer(record(
+                "www.example.com",
+                RecordType::CNAME,
+                DnsRecordData::DomainName(name("cdn.example.com")),
+            ))
+            .build();
+
+        assert_eq!(response.strip_out_of_bailiwick_records(), 0);
+        assert_eq!(response.answers().len(), 3);
+    }
+
+    #[test]
+    fn test_strip_out_of_bailiwick_ignores_zones_claimed_by_authority_records() {
+        let mut response = DnsMessageBuilder::new()
+            .add_question(DnsQuestion::new(name("www.example.com"), RecordType::A, ClassType::IN))
+            .add_answer(a_record("www.example.com", 300, 1))
+            .add_authority_record(record(
+                "com",
+                RecordType::NS,
+                DnsRecordData::DomainName(name("a.gtld-servers.net")),
+            ))
+            .add_additional_record(a_record("a.gtld-servers.net", 300, 30))
+            .add_additional_record(a_record("bank.com", 300, 66))
+            .add_additional_record(a_record("mail.example.com", 300, 66))
+            .build();
+
+        assert_eq!(response.strip_out_of_bailiwick_records(), 2);
+
+        let additional_names: Vec<_> = response.additional_records().iter().map(|r| r.name()).collect();
+        assert_eq!(additional_names, ["a.gtld-servers.net"]);
+    }
+
+    #[test]
+    fn test_strip_out_of_bailiwick_keeps_denial_of_existence() {
+        let soa = record(
+            "example.com",
+            RecordType::SOA,
+            DnsRecordData::SOA {
+                mname: name("ns1.example.com"),
+                rname: name("hostmaster.example.com"),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86_400,
+                minimum: 300,
+            },
+        );
+        // NSEC owners are neighbours of the name, not its ancestors.
+        let nsec = record("a.example.com", RecordType::NSEC, DnsRecordData::Raw(vec![0]));
+        let mut response = DnsMessageBuilder::new()
+            .add_question(DnsQuestion::new(name("b.example.com"), RecordType::A, ClassType::IN))
+            .add_authority_record(soa)
+            .add_authority_record(nsec)
+            .build();
+
+        assert_eq!(response.strip_out_of_bailiwick_records(), 0);
+        assert_eq!(response.authority_records().len(), 2);
+    }
+
+    #[test]
+    fn test_record_new() {
+        let record = a_record("example.com", 300, 1);
+
+        assert_eq!(record.name(), "example.com");
+        assert_eq!(record.record_type(), RecordType::A);
+        assert_eq!(record.class(), ClassType::IN);
+        assert_eq!(record.ttl(), 300);
+        assert_eq!(
+            record.data(),
+            &DnsRecordData::Ipv4(std::net::Ipv4Addr::new(192, 0, 2, 1))
+        );
+    }
+
+    #[test]
+    fn test_same_rdata_ignores_ttl() {
+        let record = a_record("example.com", 300, 1);
+
+        assert!(record.same_rdata(&a_record("example.com", 60, 1)));
+        assert_ne!(record, a_record("example.com", 60, 1));
+        assert!(!record.same_rdata(&a_record("example.com", 300, 2)));
+        assert!(!record.same_rdata(&a_record("other.example.com", 300, 1)));
+    }
+
+    #[test]
+    fn test_rrset_group_and_min_ttl() {
+        let records = vec![
+            a_record("example.com", 300, 1),
+            a_record("other.example.com", 30, 2),
+            a_record("example.com", 60, 3),
+        ];
+
+        let rrsets = RRset::group(&records);
+
+        assert_eq!(rrsets.len(), 2);
+        assert_eq!(rrsets[0].name.as_str(), "example.com");
+        assert_eq!(rrsets[0].records, vec![&records[0], &records[2]]);
+        assert_eq!(rrsets[0].min_ttl(), 60);
+        assert_eq!(rrsets[1].records, vec![&records[1]]);
+        assert_eq!(rrsets[1].min_ttl(), 30);
+        assert!(RRset::group(&[]).is_empty());
+    }
+
     #[test]
 
     fn test_writer_reader() {
@@ -1583,6 +3011,125 @@ mod tests {
         assert_eq!(flags, decoded_flags);
     }
 
+    fn flag_bits(flags: DnsFlags) -> Vec<u8> {
+        let mut writer = DnsMessageWriter::new();
+        flags.write_to(&mut writer).unwrap();
+        writer.into_bytes().to_vec()
+    }
+
+    fn decoded_response() -> (Bytes, DnsMessage) {
+        let message = DnsMessageBuilder::new()
+            .with_id(0x1234)
+            .with_response(DnsResponseCode::NoError)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+            ))
+            .add_answer(DnsRecord::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+                300,
+                DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1)),
+            ))
+            .build();
+        let raw = message.encode().unwrap();
+        (raw.clone(), DnsMessage::decode(&raw).unwrap())
+    }
+
+    #[test]
+    fn test_mutated_message_reencodes() {
+        let (_, mut message) = decoded_response();
+
+        message.set_id(0xBEEF);
+        message.set_recursion_available(true);
+        message.set_authentic_data(true);
+        message.set_truncated(true);
+
+        let decoded = DnsMessage::decode(&message.encode().unwrap()).unwrap();
+        assert_eq!(decoded.id, 0xBEEF);
+        assert!(decoded.flags.recursion_available);
+        assert!(decoded.flags.authentic_data);
+        assert!(decoded.flags.truncated);
+        assert_eq!(decoded, message);
+
+        assert_eq!(message.clone().with_id(7).id, 7);
+    }
+
+    #[test]
+    fn test_reencode_with_id() {
+        let (raw, mut message) = decoded_response();
+
+        // the same ID leaves the encoding alone.
+        let same = message.reencode_with_id(raw.clone(), 0x1234);
+        assert_eq!(same.as_ptr(), raw.as_ptr());
+
+        let reencoded = message.reencode_with_id(raw, 0xBEEF);
+        assert_eq!(message.id, 0xBEEF);
+        assert_eq!(reencoded, message.encode().unwrap());
+        assert_eq!(DnsMessage::decode(&reencoded).unwrap(), message);
+    }
+
+    #[test]
+    fn test_dns_flags_builder_matches_constructor() {
+        assert_eq!(
+            flag_bits(DnsFlags::query()),
+            flag_bits(DnsFlags::new(
+                false,
+                DnsOpcode::Query,
+                false,
+                false,
+                true,
+                false,
+                false,
+                false
+            ))
+        );
+        assert_eq!(
+            flag_bits(DnsFlags::response()),
+            flag_bits(DnsFlags::new(
+                true,
+                DnsOpcode::Query,
+                false,
+                false,
+                false,
+                true,
+                false,
+                false
+            ))
+        );
+
+        let built = DnsFlags::response()
+            .with_opcode(DnsOpcode::Notify)
+            .with_authorative_answer(true)
+            .with_truncated(true)
+            .with_recursion_desired(true)
+            .with_recursion_available(false)
+            .with_authentic_data(true)
+            .with_checking_disabled(true);
+        let positional = DnsFlags::new(true, DnsOpcode::Notify, true, true, true, false, true, true);
+        assert_eq!(built, positional);
+        assert_eq!(flag_bits(built), flag_bits(positional));
+
+        // every setter controls exactly its own bit.
+        type Setter = fn(DnsFlags, bool) -> DnsFlags;
+        let setters: [(Setter, u16); 7] = [
+            (DnsFlags::with_response, 1 << 15),
+            (DnsFlags::with_authorative_answer, 1 << 10),
+            (DnsFlags::with_truncated, 1 << 9),
+            (DnsFlags::with_recursion_desired, 1 << 8),
+            (DnsFlags::with_recursion_available, 1 << 7),
+            (DnsFlags::with_authentic_data, 1 << 5),
+            (DnsFlags::with_checking_disabled, 1 << 4),
+        ];
+        for (set, bit) in setters {
+            let flags = set(DnsFlags::default(), true);
+            assert_eq!(flag_bits(flags), bit.to_be_bytes().to_vec());
+            assert_eq!(set(flags, false), DnsFlags::default());
+        }
+    }
+
     #[test]
     fn test_dns_response_code_conversions() {
         // Test known response codes
@@ -1620,16 +3167,55 @@ mod tests {
     }
 
     #[test]
-    fn test_record_type_conversions() {
-        assert_eq!(RecordType::from(1), RecordType::A);
-        assert_eq!(RecordType::from(28), RecordType::AAAA);
-        assert_eq!(RecordType::from(5), RecordType::CNAME);
-        assert_eq!(RecordType::from(2), RecordType::NS);
-        assert_eq!(RecordType::from(15), RecordType::MX);
+    fn test_response_from_query() {
+        let question = DnsQuestion::new(
+            DomainName::from_ascii("example.com").unwrap(),
+            RecordType::AAAA,
+            ClassType::IN,
+        );
+        let query = DnsMessageBuilder::new()
+            .with_id(0xBEEF)
+            .add_question(question.clone())
+            .build();
 
-        assert_eq!(RecordType::A.to_u16(), 1);
-        assert_eq!(RecordType::AAAA.to_u16(), 28);
-        assert_eq!(RecordType::CNAME.to_u16(), 5);
+        let response = DnsMessage::response_from_query(&query, DnsResponseCode::ServerFailure);
+
+        assert_eq!(response.id, 0xBEEF);
+        assert_eq!(response.questions(), &[question]);
+        assert_eq!(response.response_code(), DnsResponseCode::ServerFailure);
+        assert!(response.flags.response);
+        assert!(response.flags.recursion_available);
+        assert!(response.flags.recursion_desired);
+        assert!(!response.flags.authorative_answer);
+        assert!(response.answers().is_empty());
+
+        let decoded = DnsMessage::decode(&response.encode().unwrap()).unwrap();
+        assert_eq!(decoded.response_code(), DnsResponseCode::ServerFailure);
+        assert_eq!(decoded.questions(), response.questions());
+    }
+
+    #[test]
+    fn test_response_from_query_mirrors_rd() {
+        let query = DnsMessageBuilder::new().with_flags(DnsFlags::default()).build();
+
+        let response = DnsMessage::response_from_query(&query, DnsResponseCode::Refused);
+
+        assert!(!response.flags.recursion_desired);
+        assert!(response.flags.recursion_available);
+        assert_eq!(response.response_code(), DnsResponseCode::Refused);
+    }
+
+    #[test]
+    fn test_record_type_conversions() {
+        assert_eq!(RecordType::from(1), RecordType::A);
+        assert_eq!(RecordType::from(28), RecordType::AAAA);
+        assert_eq!(RecordType::from(5), RecordType::CNAME);
+        assert_eq!(RecordType::from(2), RecordType::NS);
+        assert_eq!(RecordType::from(15), RecordType::MX);
+
+        assert_eq!(RecordType::A.to_u16(), 1);
+        assert_eq!(RecordType::AAAA.to_u16(), 28);
+        assert_eq!(RecordType::CNAME.to_u16(), 5);
 
         // Unknown type
         let unknown = RecordType::from(9999);
@@ -1637,6 +3223,15 @@ mod tests {
         assert_eq!(unknown.to_u16(), 9999);
     }
 
+    #[test]
+    fn test_record_type_from_str() {
+        assert_eq!("AAAA".parse::<RecordType>().unwrap(), RecordType::AAAA);
+        assert_eq!("mx".parse::<RecordType>().unwrap(), RecordType::MX);
+        assert_eq!("65".parse::<RecordType>().unwrap(), RecordType::HTTPS);
+        assert_eq!("9999".parse::<RecordType>().unwrap(), RecordType::Unknown(9999));
+        assert!("NOPE".parse::<RecordType>().is_err());
+    }
+
     #[test]
     fn test_class_type_conversions() {
         assert_eq!(ClassType::from(1), ClassType::IN);
@@ -1666,6 +3261,26 @@ mod tests {
         assert_eq!(&bytes[..], &[192, 168, 1, 1]);
     }
 
+    #[test]
+    fn test_dns_record_data_display() {
+        let host = DomainName::from_ascii("mail.example.com").unwrap();
+
+        assert_eq!(
+            DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1)).to_string(),
+            "192.0.2.1"
+        );
+        assert_eq!(
+            DnsRecordData::MX { priority: 10, host }.to_string(),
+            "10 mail.example.com."
+        );
+        assert_eq!(
+            DnsRecordData::Text(vec![Box::from("v=spf1"), Box::from("say \"hi\"")]).to_string(),
+            "\"v=spf1\" \"say \\\"hi\\\"\""
+        );
+        assert_eq!(DnsRecordData::Raw(vec![0xab, 0x01]).to_string(), "\\# 2 ab01");
+        assert_eq!(DnsRecordData::Raw(vec![]).to_string(), "\\# 0");
+    }
+
     #[test]
     fn test_dns_record_data_text() {
         let chunks = vec![Box::from("hello"), Box::from("world")];
@@ -1852,6 +3467,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_srv_and_naptr_targets_are_not_compressed() {
+        let owner = DomainName::from_ascii("_sip._udp.example.com").unwrap();
+        let target = DomainName::from_ascii("sip.example.com").unwrap();
+        let srv = DnsRecord::new(
+            owner.clone(),
+            RecordType::SRV,
+            ClassType::IN,
+            600,
+            DnsRecordData::SRV {
+                priority: 10,
+                weight: 60,
+                port: 5060,
+                target: target.clone(),
+            },
+        );
+        let naptr = DnsRecord::new(
+            owner,
+            RecordType::NAPTR,
+            ClassType::IN,
+            600,
+            DnsRecordData::Naptr {
+                order: 100,
+                preference: 10,
+                flags: b"S".as_slice().into(),
+                services: b"SIP+D2U".as_slice().into(),
+                regexp: Box::default(),
+                replacement: target.clone(),
+            },
+        );
+        let message = DnsMessage::new(
+            3,
+            DnsFlags::default(),
+            vec![DnsQuestion::new(target, RecordType::SRV, ClassType::IN)],
+            vec![srv.clone(), naptr.clone()],
+            vec![],
+            vec![],
+        );
+
+        let encoded = message.encode().unwrap();
+
+        // the question, the SRV target and the NAPTR replacement all spell out the full name.
+        let name = b"\x03sip\x07example\x03com\x00";
+        assert_eq!(encoded.windows(name.len()).filter(|window| window == name).count(), 3);
+
+        let decoded = DnsMessage::decode(&encoded).unwrap();
+        assert_eq!(decoded.answers(), [srv, naptr]);
+    }
+
     #[test]
     fn test_full_message_with_all_sections() {
         let question = DnsQuestion::new(
@@ -2201,6 +3865,356 @@ mod tests {
         assert_eq!(decoded.answers()[0].data, DnsRecordData::Raw(raw_data));
     }
 
+    #[test]
+    fn test_unknown_record_type_wire_roundtrip_is_byte_exact() {
+        #[rustfmt::skip]
+        let packet: Vec<u8> = vec![
+            // Header: id 0x1234, QR|RD|RA, 1 question, 2 answers.
+            0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+            // Question: example.com NULL IN
+            7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+            0x00, 0x0A, 0x00, 0x01,
+            // Answer: NULL with opaque rdata, name compressed to the question.
+            0xC0, 0x0C, 0x00, 0x0A, 0x00, 0x01, 0x00, 0x00, 0x0E, 0x10,
+            0x00, 0x08, 3, b'x', b'8', b'6', 3, b'l', b'n', b'x',
+            // Answer: private-use type 65280 with opaque rdata.
+            0xC0, 0x0C, 0xFF, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3C,
+            0x00, 0x05, 0xDE, 0xAD, 0x00, 0xBE, 0xEF,
+        ];
+
+        let decoded = DnsMessage::decode(&packet).unwrap();
+        assert_eq!(
+            decoded.answers()[0].data,
+            DnsRecordData::Raw(vec![3, b'x', b'8', b'6', 3, b'l', b'n', b'x'])
+        );
+        assert_eq!(decoded.answers()[1].record_type(), RecordType::Unknown(0xFF00));
+        assert_eq!(
+            decoded.answers()[1].data,
+            DnsRecordData::Raw(vec![0xDE, 0xAD, 0x00, 0xBE, 0xEF])
+        );
+
+        let encoded = decoded.encode().unwrap();
+        assert_eq!(encoded.as_ref(), packet.as_slice());
+
+        let redecoded = DnsMessage::decode(&encoded).unwrap();
+        assert_eq!(redecoded.answers(), decoded.answers());
+    }
+
+    #[test]
+    fn test_hinfo_record_roundtrip() {
+        let hinfo = DnsRecord {
+            name: DomainName::from_ascii("host.example.com").unwrap(),
+            record_type: RecordType::HINFO,
+            class: ClassType::IN,
+            ttl: 3600,
+            data: DnsRecordData::Hinfo {
+                cpu: b"INTEL-386".as_slice().into(),
+                os: b"UNIX".as_slice().into(),
+            },
+        };
+
+        let message = DnsMessage::new(1, DnsFlags::default(), vec![], vec![hinfo.clone()], vec![], vec![]);
+
+        let encoded = message.encode().unwrap();
+        let decoded = DnsMessage::decode(&encoded).unwrap();
+
+        assert_eq!(decoded.answers()[0].data, hinfo.data);
+        assert_eq!(decoded.answers()[0].data.to_string(), "\"INTEL-386\" \"UNIX\"");
+    }
+
+    #[test]
+    fn test_hinfo_record_keeps_raw_bytes() {
+        let hinfo = DnsRecord {
+            name: DomainName::from_ascii("host.example.com").unwrap(),
+            record_type: RecordType::HINFO,
+            class: ClassType::IN,
+            ttl: 3600,
+            data: DnsRecordData::Hinfo {
+                cpu: b"\xff\xfe".as_slice().into(),
+                os: b"a\"b".as_slice().into(),
+            },
+        };
+
+        let message = DnsMessage::new(1, DnsFlags::default(), vec![], vec![hinfo.clone()], vec![], vec![]);
+
+        let encoded = message.encode().unwrap();
+        let decoded = DnsMessage::decode(&encoded).unwrap();
+        assert_eq!(decoded.answers()[0].data, hinfo.data);
+        assert_eq!(decoded.encode().unwrap(), encoded);
+        assert_eq!(decoded.answers()[0].data.to_string(), "\"\\255\\254\" \"a\\\"b\"");
+    }
+
+    #[test]
+    fn test_character_string_over_255_bytes_fails_to_encode() {
+        let hinfo = DnsRecord {
+            name: DomainName::from_ascii("host.example.com").unwrap(),
+            record_type: RecordType::HINFO,
+            class: ClassType::IN,
+            ttl: 3600,
+            data: DnsRecordData::Hinfo {
+                cpu: vec![b'a'; 256].into(),
+                os: Box::default(),
+            },
+        };
+
+        let message = DnsMessage::new(1, DnsFlags::default(), vec![], vec![hinfo], vec![], vec![]);
+
+        assert!(matches!(
+            message.encode(),
+            Err(DnsError::Write(DnsWriteError::CharacterStringTooLong { len: 256 }))
+        ));
+    }
+
+    #[test]
+    fn test_character_string_records_must_fill_rdlength() {
+        let mut header = vec![0, 1, 0x81, 0x80, 0, 0, 0, 1, 0, 0, 0, 0];
+        // Root owner, type HINFO, class IN, TTL 0.
+        header.extend_from_slice(&[0, 0, 13, 0, 1, 0, 0, 0, 0]);
+
+        // Two empty strings followed by a byte the rdlength claims but neither string covers.
+        let mut trailing = header.clone();
+        trailing.extend_from_slice(&[0, 3, 0, 0, 0xff]);
+        assert!(matches!(
+            DnsMessage::decode(&trailing),
+            Err(DnsError::Read(DnsReadError::TrailingBytes { .. }))
+        ));
+
+        // The second string runs past the rdlength into whatever follows.
+        let mut overrun = header;
+        overrun.extend_from_slice(&[0, 2, 0, 1, b'x']);
+        assert!(matches!(
+            DnsMessage::decode(&overrun),
+            Err(DnsError::Read(DnsReadError::BufferUnderflow { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_gpos_record_roundtrip() {
+        let gpos = DnsRecord {
+            name: DomainName::from_ascii("host.example.com").unwrap(),
+            record_type: RecordType::GPOS,
+            class: ClassType::IN,
+            ttl: 3600,
+            data: DnsRecordData::Gpos {
+                longitude: b"-32.6882".as_slice().into(),
+                latitude: b"116.8652".as_slice().into(),
+                altitude: b"10.0".as_slice().into(),
+            },
+        };
+
+        let message = DnsMessage::new(1, DnsFlags::default(), vec![], vec![gpos.clone()], vec![], vec![]);
+
+        let encoded = message.encode().unwrap();
+        let decoded = DnsMessage::decode(&encoded).unwrap();
+
+        assert_eq!(decoded.answers()[0].data, gpos.data);
+        assert_eq!(decoded.answers()[0].data.wire_len(), 3 + 8 + 8 + 4);
+        assert_eq!(
+            decoded.answers()[0].data.to_string(),
+            "\"-32.6882\" \"116.8652\" \"10.0\""
+        );
+    }
+
+    #[test]
+    fn test_apl_record_roundtrip() {
+        let items = vec![
+            AplItem::new("192.168.32.0".parse().unwrap(), 21, false),
+            AplItem::new("2001:db8::".parse().unwrap(), 32, true),
+        ];
+        assert_eq!(items[0].address, [192, 168, 32]);
+        assert_eq!(items[1].address, [0x20, 0x01, 0x0d, 0xb8]);
+
+        let apl = DnsRecord {
+            name: DomainName::from_ascii("nets.example.com").unwrap(),
+            record_type: RecordType::APL,
+            class: ClassType::IN,
+            ttl: 3600,
+            data: DnsRecordData::Apl(items),
+        };
+
+        let message = DnsMessage::new(1, DnsFlags::default(), vec![], vec![apl.clone()], vec![], vec![]);
+
+        let encoded = message.encode().unwrap();
+        // family 1, prefix 21, length 3, then family 2, prefix 32, negated with length 4.
+        let rdata = [
+            0x00, 0x01, 21, 0x03, 192, 168, 32, 0x00, 0x02, 32, 0x84, 0x20, 0x01, 0x0d, 0xb8,
+        ];
+        assert!(encoded.ends_with(&rdata));
+
+        let decoded = DnsMessage::decode(&encoded).unwrap();
+
+        assert_eq!(decoded.answers()[0].data, apl.data);
+        assert_eq!(decoded.answers()[0].data.wire_len(), rdata.len());
+        assert_eq!(
+            decoded.answers()[0].data.to_string(),
+            "1:192.168.32.0/21 !2:2001:db8::/32"
+        );
+    }
+
+    #[test]
+    fn test_loc_record_roundtrip() {
+        // cambridge-net.kei.com. LOC 42 21 54 N 71 06 18 W -24m 30m (RFC 1876)
+        let loc = DnsRecord {
+            name: DomainName::from_ascii("cambridge-net.kei.com").unwrap(),
+            record_type: RecordType::LOC,
+            class: ClassType::IN,
+            ttl: 3600,
+            data: DnsRecordData::Loc {
+                version: 0,
+                size: 0x33,
+                horiz_pre: 0x16,
+                vert_pre: 0x13,
+                latitude: (1u32 << 31) + (42 * 3600 + 21 * 60 + 54) * 1000,
+                longitude: (1u32 << 31) - (71 * 3600 + 6 * 60 + 18) * 1000,
+                altitude: 10_000_000 - 2400,
+            },
+        };
+
+        let message = DnsMessage::new(1, DnsFlags::default(), vec![], vec![loc.clone()], vec![], vec![]);
+
+        let encoded = message.encode().unwrap();
+        let decoded = DnsMessage::decode(&encoded).unwrap();
+
+        assert_eq!(decoded.answers()[0].data, loc.data);
+        assert_eq!(
+            decoded.answers()[0].data.to_string(),
+            "42 21 54.000 N 71 06 18.000 W -24.00m 30.00m 10000.00m 10.00m"
+        );
+    }
+
+    #[test]
+    fn test_uri_record_roundtrip() {
+        let uri = DnsRecord {
+            name: DomainName::from_ascii("_http._tcp.example.com").unwrap(),
+            record_type: RecordType::URI,
+            class: ClassType::IN,
+            ttl: 3600,
+            data: DnsRecordData::Uri {
+                priority: 10,
+                weight: 1,
+                target: "https://example.com/".to_string(),
+            },
+        };
+
+        let message = DnsMessage::new(1, DnsFlags::default(), vec![], vec![uri.clone()], vec![], vec![]);
+
+        let encoded = message.encode().unwrap();
+        let decoded = DnsMessage::decode(&encoded).unwrap();
+
+        assert_eq!(decoded.answers()[0].data, uri.data);
+        assert_eq!(decoded.answers()[0].data.to_string(), "10 1 \"https://example.com/\"");
+    }
+
+    #[test]
+    fn test_naptr_record_roundtrip() {
+        let naptr = DnsRecord {
+            name: DomainName::from_ascii("example.com").unwrap(),
+            record_type: RecordType::NAPTR,
+            class: ClassType::IN,
+            ttl: 3600,
+            data: DnsRecordData::Naptr {
+                order: 100,
+                preference: 10,
+                flags: b"S".as_slice().into(),
+                services: b"SIP+D2U".as_slice().into(),
+                regexp: Box::default(),
+                replacement: DomainName::from_ascii("_sip._udp.example.com").unwrap(),
+            },
+        };
+
+        let message = DnsMessage::new(1, DnsFlags::default(), vec![], vec![naptr.clone()], vec![], vec![]);
+
+        let encoded = message.encode().unwrap();
+        let decoded = DnsMessage::decode(&encoded).unwrap();
+
+        assert_eq!(decoded.answers()[0].data, naptr.data);
+        assert_eq!(
+            decoded.answers()[0].data.to_string(),
+            "100 10 \"S\" \"SIP+D2U\" \"\" _sip._udp.example.com."
+        );
+
+        // The replacement shares a suffix with the owner name but must not be compressed.
+        let replacement = b"\x04_sip\x04_udp\x07example\x03com\x00";
+        assert!(encoded.ends_with(replacement));
+    }
+
+    fn https_record() -> DnsRecord {
+        DnsRecord {
+            name: DomainName::from_ascii("example.com").unwrap(),
+            record_type: RecordType::HTTPS,
+            class: ClassType::IN,
+            ttl: 300,
+            data: DnsRecordData::Svcb {
+                priority: 1,
+                target: DomainName::from_ascii("svc.example.com").unwrap(),
+                params: vec![
+                    SvcParam::new(SvcParam::ALPN, b"\x02h2\x02h3".as_slice()),
+                    SvcParam::new(SvcParam::PORT, 8443u16.to_be_bytes()),
+                    SvcParam::new(SvcParam::IPV4_HINT, [192, 0, 2, 1, 192, 0, 2, 2]),
+                    SvcParam::new(SvcParam::ECH, b"ech".as_slice()),
+                    SvcParam::new(SvcParam::IPV6_HINT, "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets()),
+                ],
+            },
+        }
+    }
+
+    #[test]
+    fn test_https_record_roundtrip() {
+        let https = https_record();
+
+        let message = DnsMessage::new(1, DnsFlags::default(), vec![], vec![https.clone()], vec![], vec![]);
+
+        let encoded = message.encode().unwrap();
+        let decoded = DnsMessage::decode(&encoded).unwrap();
+
+        assert_eq!(decoded.answers()[0].data, https.data);
+        assert_eq!(
+            decoded.answers()[0].data.to_string(),
+            "1 svc.example.com. alpn=\"h2,h3\" port=8443 ipv4hint=192.0.2.1,192.0.2.2 ech=ZWNo ipv6hint=2001:db8::1"
+        );
+
+        // The target shares a suffix with the owner name but must not be compressed.
+        assert!(
+            encoded
+                .windows(17)
+                .any(|window| window == b"\x03svc\x07example\x03com\x00")
+        );
+    }
+
+    #[test]
+    fn test_https_record_params() {
+        let data = https_record().data;
+
+        assert_eq!(data.alpn(), Some(vec!["h2".to_string(), "h3".to_string()]));
+        assert_eq!(data.port(), Some(8443));
+        assert_eq!(
+            data.ipv4_hint(),
+            Some(vec![Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2)])
+        );
+        assert_eq!(data.ech(), Some(b"ech".as_slice()));
+        assert_eq!(data.ipv6_hint(), Some(vec!["2001:db8::1".parse().unwrap()]));
+        assert_eq!(data.svc_param(SvcParam::MANDATORY), None);
+        assert_eq!(DnsRecordData::Ipv4(Ipv4Addr::LOCALHOST).port(), None);
+    }
+
+    #[test]
+    fn test_svc_param_display() {
+        assert_eq!(
+            SvcParam::new(SvcParam::NO_DEFAULT_ALPN, []).to_string(),
+            "no-default-alpn"
+        );
+        assert_eq!(
+            SvcParam::new(SvcParam::MANDATORY, [0, 1, 0, 4]).to_string(),
+            "mandatory=alpn,ipv4hint"
+        );
+        assert_eq!(
+            SvcParam::new(65000, b"a\"b\x01".as_slice()).to_string(),
+            "key65000=\"a\\\"b\\001\""
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"abcd"), "YWJjZA==");
+    }
+
     #[test]
     fn test_txt_record_roundtrip() {
         let txt = DnsRecord {
@@ -2313,6 +4327,24 @@ mod tests {
         assert!(decoded.flags.recursion_available);
     }
 
+    #[test]
+    fn test_edns_option_new_computes_len() {
+        let cookie = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+        let option = EdnsOption::new(EdnsOptionCode::Cookie, EdnsOptionData::Raw(cookie.clone()));
+
+        assert_eq!(option.code(), EdnsOptionCode::Cookie);
+        assert_eq!(option.data(), Some(&EdnsOptionData::Raw(cookie.clone())));
+        assert_eq!(option.len(), 16);
+        assert!(!option.is_empty());
+
+        let mut writer = DnsMessageWriter::new();
+        option.write_to(&mut writer).unwrap();
+        let encoded = writer.into_bytes();
+
+        assert_eq!(u16::from_be_bytes([encoded[2], encoded[3]]), option.len());
+        assert_eq!(&encoded[4..], cookie.as_slice());
+    }
+
     #[test]
     fn test_multiple_edns_options() {
         let message = DnsMessage {
@@ -2532,6 +4564,132 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_response_opt_survives_roundtrip_once() {
+        let server_cookie = [9, 10, 11, 12, 13, 14, 15, 16];
+        let mut response = DnsMessageBuilder::new()
+            .with_id(9)
+            .with_edns(
+                EdnsBuilder::new()
+                    .with_udp_payload_size(1232)
+                    .add_cookie([1, 2, 3, 4, 5, 6, 7, 8], Some(&server_cookie))
+                    .build(),
+            )
+            .add_additional_record(DnsRecord::new(
+                DomainName::from_ascii("ns1.example.com").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+                3600,
+                DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1)),
+            ))
+            .build();
+        response.set_response_code(DnsResponseCode::BADCOOKIE);
+
+        let upstream = response.encode().unwrap();
+        let decoded = DnsMessage::decode(&upstream).unwrap();
+        let forwarded = decoded.encode().unwrap();
+
+        assert_eq!(forwarded, upstream);
+        // ARCOUNT holds the A record and a single OPT record.
+        assert_eq!(u16::from_be_bytes([forwarded[10], forwarded[11]]), 2);
+
+        let redecoded = DnsMessage::decode(&forwarded).unwrap();
+        assert_eq!(redecoded.response_code(), DnsResponseCode::BADCOOKIE);
+        assert_eq!(redecoded.additional_records().len(), 1);
+        let edns = redecoded.edns().as_ref().unwrap();
+        assert_eq!(edns.udp_payload_size, 1232);
+        assert_eq!(
+            edns.options,
+            vec![EdnsOption::new(
+                EdnsOptionCode::Cookie,
+                EdnsOptionData::Raw((1..=16).collect())
+            )]
+        );
+    }
+
+    #[test]
+    fn test_opt_in_additional_records_is_not_encoded_twice() {
+        let mut message = DnsMessage::new(
+            1,
+            DnsFlags::default(),
+            vec![],
+            vec![],
+            vec![],
+            vec![DnsRecord::new(
+                DomainName::root(),
+                RecordType::OPT,
+                ClassType::from(512),
+                0,
+                DnsRecordData::Raw(vec![]),
+            )],
+        );
+        message.set_edns(Some(Edns::default()));
+
+        let decoded = DnsMessage::decode(&message.encode().unwrap()).unwrap();
+
+        assert_eq!(decoded.edns(), &Some(Edns::default()));
+        assert!(decoded.additional_records().is_empty());
+    }
+
+    #[test]
+    fn test_wire_size_is_exact_without_compression() {
+        let query = DnsMessageBuilder::new()
+            .with_id(1)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+            ))
+            .with_edns(EdnsBuilder::new().add_cookie([1; 8], None).build())
+            .build();
+        assert_eq!(query.wire_size(), query.encode().unwrap().len());
+
+        let response = DnsMessageBuilder::new()
+            .add_answer(a_record("example.com", 300, 1))
+            .add_authority_record(DnsRecord::new(
+                DomainName::from_ascii("example.org").unwrap(),
+                RecordType::TXT,
+                ClassType::IN,
+                300,
+                DnsRecordData::Text(vec![Box::from("hello"), Box::from("world")]),
+            ))
+            .add_additional_record(DnsRecord {
+                name: DomainName::from_ascii("example.net").unwrap(),
+                ..https_record()
+            })
+            .build();
+        assert_eq!(response.wire_size(), response.encode().unwrap().len());
+
+        assert_eq!(DnsMessageBuilder::new().build().wire_size(), 12);
+    }
+
+    #[test]
+    fn test_wire_size_is_upper_bound_with_compression() {
+        let response = DnsMessageBuilder::new()
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::MX,
+                ClassType::IN,
+            ))
+            .add_answer(DnsRecord::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::MX,
+                ClassType::IN,
+                300,
+                DnsRecordData::MX {
+                    priority: 10,
+                    host: DomainName::from_ascii("mail.example.com").unwrap(),
+                },
+            ))
+            .add_answer(a_record("example.com", 300, 2))
+            .build();
+
+        let encoded = response.encode().unwrap().len();
+        assert!(response.wire_size() > encoded);
+        // three names point back to `example.com` instead of repeating its 13 bytes.
+        assert_eq!(response.wire_size() - encoded, 3 * (13 - 2));
+    }
+
     #[test]
     fn test_edns_multiple_opt() {
         let message = DnsMessage {
@@ -2567,4 +4725,286 @@ mod tests {
             "expected error when multiple OPT records are present"
         );
     }
+
+    fn validation_query() -> DnsMessage {
+        DnsMessageBuilder::new()
+            .with_id(7)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+            ))
+            .build()
+    }
+
+    #[test]
+    fn test_validate_query_accepts_standard_query() {
+        assert_eq!(validation_query().validate_query(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_query_rejects_qr_bit() {
+        let mut query = validation_query();
+        query.flags.response = true;
+
+        assert_eq!(query.validate_query(), Err(DnsValidationError::UnexpectedResponse));
+    }
+
+    #[test]
+    fn test_validate_query_rejects_question_count() {
+        let none = DnsMessageBuilder::new().build();
+        let two = DnsMessageBuilder::new()
+            .with_questions(vec![validation_query().questions()[0].clone(); 2])
+            .build();
+
+        assert_eq!(
+            none.validate_query(),
+            Err(DnsValidationError::QuestionCount { count: 0 })
+        );
+        assert_eq!(
+            two.validate_query(),
+            Err(DnsValidationError::QuestionCount { count: 2 })
+        );
+        assert_eq!(
+            two.validate_query().unwrap_err().response_code(),
+            DnsResponseCode::FormatError
+        );
+    }
+
+    #[test]
+    fn test_validate_query_rejects_opcode() {
+        let mut query = validation_query();
+        query.flags.opcode = DnsOpcode::Status;
+
+        let error = query.validate_query().unwrap_err();
+
+        assert_eq!(error, DnsValidationError::UnsupportedOpcode(DnsOpcode::Status));
+        assert_eq!(error.response_code(), DnsResponseCode::NotImp);
+    }
+
+    #[test]
+    fn test_notify_and_update_opcodes_roundtrip() {
+        for (opcode, response_code) in [
+            (DnsOpcode::Notify, DnsResponseCode::NotImp),
+            (DnsOpcode::Update, DnsResponseCode::Refused),
+        ] {
+            let mut query = validation_query();
+            query.flags.opcode = opcode;
+
+            let decoded = DnsMessage::decode(&query.encode().unwrap()).unwrap();
+
+            assert_eq!(decoded.flags.opcode, opcode);
+            assert_eq!(decoded.validate_query().unwrap_err().response_code(), response_code);
+        }
+    }
+
+    /// Header declaring 65535 entries in every section, followed by a single root question.
+    #[rustfmt::skip]
+    const HUGE_COUNTS: [u8; 17] = [
+        0x00, 0x01, 0x01, 0x00, // id, flags
+        0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // QDCOUNT, ANCOUNT, NSCOUNT, ARCOUNT
+        0x00, 0x00, 0x01, 0x00, 0x01, // root A IN
+    ];
+
+    #[test]
+    fn test_decode_limited_rejects_huge_record_counts() {
+        assert!(matches!(
+            DnsMessage::decode_limited(&HUGE_COUNTS, 100),
+            Err(DnsError::TooManyRecords {
+                count: 262140,
+                max: 100
+            })
+        ));
+
+        // without the limit the body runs out long before the counts, and nothing is reserved up front.
+        assert!(matches!(
+            DnsMessage::decode_limited(&HUGE_COUNTS, usize::MAX),
+            Err(DnsError::Read(DnsReadError::BufferUnderflow { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_decode_reserves_only_what_the_input_can_hold() {
+        assert_eq!(capacity(u16::MAX, 0, MIN_RECORD_LEN), 0);
+        assert_eq!(capacity(u16::MAX, 5, MIN_QUESTION_LEN), 1);
+        assert_eq!(capacity(2, 1000, MIN_RECORD_LEN), 2);
+
+        // a bare header declaring 65535 answers fails on the first missing record.
+        let mut header = [0u8; 12];
+        header[6..8].copy_from_slice(&u16::MAX.to_be_bytes());
+        assert!(matches!(
+            DnsMessage::decode(&header),
+            Err(DnsError::Read(DnsReadError::BufferUnderflow { pos: 12, .. }))
+        ));
+        assert!(DnsMessage::decode(&HUGE_COUNTS).is_err());
+    }
+
+    #[test]
+    fn test_decode_limited_rejects_oversized_messages() {
+        let oversized = vec![0u8; MAX_MESSAGE_SIZE + 1];
+
+        assert!(matches!(
+            DnsMessage::decode_limited(&oversized, usize::MAX),
+            Err(DnsError::MessageTooLarge { len, max: MAX_MESSAGE_SIZE }) if len == MAX_MESSAGE_SIZE + 1
+        ));
+    }
+
+    #[test]
+    fn test_decode_limited_accepts_messages_within_limits() {
+        let message = DnsMessageBuilder::new()
+            .with_id(9)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+            ))
+            .build();
+        let bytes = message.encode().unwrap();
+
+        assert_eq!(DnsMessage::decode_limited(&bytes, 1).unwrap(), message);
+        assert!(DnsMessage::decode_limited(&bytes, 0).is_err());
+        assert!(DnsMessage::decode_limited(&bytes[..5], 1).is_err());
+    }
+
+    #[test]
+    fn test_decode_limited_survives_mutated_input() {
+        let message = DnsMessageBuilder::new()
+            .with_id(9)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("www.example.com").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+            ))
+            .add_answer(DnsRecord::new(
+                DomainName::from_ascii("www.example.com").unwrap(),
+                RecordType::CNAME,
+                ClassType::IN,
+                300,
+                DnsRecordData::DomainName(DomainName::from_ascii("example.com").unwrap()),
+            ))
+            .with_edns(
+                crate::EdnsBuilder::new()
+                    .add_client_subnet("192.0.2.1".parse().unwrap(), 24)
+                    .build(),
+            )
+            .build();
+        let bytes = message.encode().unwrap();
+
+        // every truncation, and every byte replaced with a few interesting values, must fail cleanly or decode.
+        for len in 0..bytes.len() {
+            let _ = DnsMessage::decode_limited(&bytes[..len], 16);
+        }
+        for i in 0..bytes.len() {
+            for value in [0x00, 0x01, 0x3F, 0x40, 0xC0, 0xFF] {
+                let mut mutated = bytes.to_vec();
+                mutated[i] = value;
+                let _ = DnsMessage::decode_limited(&mutated, 16);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_questions_reads_update_zone() {
+        #[rustfmt::skip]
+        let update = [
+            0x00, 0x07, // id
+            0x28, 0x00, // opcode 5 (UPDATE)
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, // ZOCOUNT 1, PRCOUNT 0, UPCOUNT 1, ADCOUNT 0
+            // Zone: example.com SOA IN
+            7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, 0x00, 0x06, 0x00, 0x01,
+            // Update: delete the A RRset of example.com, class ANY with empty RDATA
+            0xC0, 0x0C, 0x00, 0x01, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+
+        assert!(DnsMessage::decode(&update).is_err());
+
+        let decoded = DnsMessage::decode_questions(&update).unwrap();
+        assert_eq!(decoded.id, 7);
+        assert_eq!(decoded.flags.opcode, DnsOpcode::Update);
+        assert_eq!(
+            decoded.questions(),
+            &[DnsQuestion::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::SOA,
+                ClassType::IN,
+            )]
+        );
+        assert!(decoded.authority_records().is_empty());
+    }
+
+    #[test]
+    fn test_validate_response_accepts_matching_response() {
+        let query = validation_query();
+        let response = DnsMessage::response_from_query(&query, DnsResponseCode::NoError);
+
+        assert_eq!(response.validate_response(&query), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_response_rejects_missing_qr_bit() {
+        let query = validation_query();
+
+        assert_eq!(query.validate_response(&query), Err(DnsValidationError::NotAResponse));
+    }
+
+    #[test]
+    fn test_validate_response_rejects_id_mismatch() {
+        let query = validation_query();
+        let mut response = DnsMessage::response_from_query(&query, DnsResponseCode::NoError);
+        response.id = 8;
+
+        assert_eq!(
+            response.validate_response(&query),
+            Err(DnsValidationError::IdMismatch { expected: 7, actual: 8 })
+        );
+    }
+
+    #[test]
+    fn test_validate_response_rejects_opcode_mismatch() {
+        let query = validation_query();
+        let mut response = DnsMessage::response_from_query(&query, DnsResponseCode::NoError);
+        response.flags.opcode = DnsOpcode::IQuery;
+
+        assert_eq!(
+            response.validate_response(&query),
+            Err(DnsValidationError::OpcodeMismatch {
+                expected: DnsOpcode::Query,
+                actual: DnsOpcode::IQuery,
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_response_rejects_question_mismatch() {
+        let query = validation_query();
+        let other = DnsMessageBuilder::new()
+            .with_id(7)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("example.org").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+            ))
+            .build();
+        let response = DnsMessage::response_from_query(&other, DnsResponseCode::NoError);
+
+        assert_eq!(
+            response.validate_response(&query),
+            Err(DnsValidationError::QuestionMismatch)
+        );
+    }
+
+    #[test]
+    fn test_validate_response_rejects_unsupported_edns_version() {
+        let query = validation_query();
+        let mut response = DnsMessage::response_from_query(&query, DnsResponseCode::NoError);
+        response.set_edns(Some(Edns {
+            version: 1,
+            ..Edns::default()
+        }));
+
+        assert_eq!(
+            response.validate_response(&query),
+            Err(DnsValidationError::UnsupportedEdnsVersion { version: 1 })
+        );
+    }
 }
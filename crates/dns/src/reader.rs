@@ -5,17 +5,32 @@ use crate::{
     error::{DnsReadError, ReadResult, Result},
 };
 
+/// Default maximum number of labels in a name, the most that fit in 255 octets (RFC 1035 section 3.1).
+pub const DEFAULT_MAX_LABELS: usize = 127;
+
 /// A reader for DNS messages that allows reading various components
 pub struct DnsMessageReader<'a> {
     /// Internal buffer containing the DNS message.
     buffer: &'a [u8],
     /// Position in bytes.
     position: usize,
+    /// Maximum number of labels in a name.
+    max_labels: usize,
 }
 
 impl<'a> DnsMessageReader<'a> {
     pub fn new(buffer: &'a [u8]) -> Self {
-        Self { buffer, position: 0 }
+        Self {
+            buffer,
+            position: 0,
+            max_labels: DEFAULT_MAX_LABELS,
+        }
+    }
+
+    /// Set the maximum number of labels accepted in a name.
+    pub fn with_max_labels(mut self, max_labels: usize) -> Self {
+        self.max_labels = max_labels;
+        self
     }
 
     /// Seek the a position inside the buffer.
@@ -149,6 +164,10 @@ impl<'a> DnsMessageReader<'a> {
                     });
                 }
 
+                if labels.len() == self.max_labels {
+                    return Err(DnsReadError::TooManyLabels { max: self.max_labels });
+                }
+
                 labels.push(SmallVec::from_slice(&self.buffer[pos..pos + label_len]));
 
                 pos += label_len;
@@ -213,6 +232,10 @@ impl<'a> DnsMessageReader<'a> {
                 });
             }
 
+            if labels.len() == self.max_labels {
+                return Err(DnsReadError::TooManyLabels { max: self.max_labels });
+            }
+
             labels.push(SmallVec::from_slice(&self.buffer[pos..pos + label_len]));
             pos += label_len;
         }
@@ -254,7 +277,7 @@ pub trait DnsReadable: Sized {
 
 #[cfg(test)]
 mod tests {
-    use crate::{DnsMessageWriter, domain_name::DomainName};
+    use crate::{DnsMessageWriter, domain_name::DomainName, error::DnsReadError};
 
     #[test]
     fn test_read_qname_uncompressed() {
@@ -581,4 +604,38 @@ mod tests {
         let name = reader.read_qname().unwrap();
         assert_eq!(name.as_str(), "mail.example.com");
     }
+
+    #[test]
+    fn test_read_qname_rejects_too_many_labels() {
+        use super::DnsMessageReader;
+        let labels = vec![b"a".as_slice(); 200];
+        let data = wire_name(&labels);
+        let mut reader = DnsMessageReader::new(&data);
+
+        assert!(matches!(
+            reader.read_qname(),
+            Err(DnsReadError::TooManyLabels { max: 127 })
+        ));
+
+        let mut reader = DnsMessageReader::new(&data);
+        assert!(matches!(
+            reader.read_qname_uncompressed(data.len()),
+            Err(DnsReadError::TooManyLabels { max: 127 })
+        ));
+    }
+
+    #[test]
+    fn test_read_qname_with_max_labels() {
+        use super::DnsMessageReader;
+        let data = wire_name(&[b"mail", b"example", b"com"]);
+
+        let mut reader = DnsMessageReader::new(&data).with_max_labels(3);
+        assert_eq!(reader.read_qname().unwrap().as_str(), "mail.example.com");
+
+        let mut reader = DnsMessageReader::new(&data).with_max_labels(2);
+        assert!(matches!(
+            reader.read_qname(),
+            Err(DnsReadError::TooManyLabels { max: 2 })
+        ));
+    }
 }
@@ -19,6 +19,7 @@ use tokio_util::sync::CancellationToken;
 /// A structure to manage inflight operations identified by keys.
 pub struct Inflight<K, V> {
     map: Arc<DashMap<K, Arc<Entry<V>>>>,
+    max_entries: Option<usize>,
 }
 
 impl<K, V> Inflight<K, V>
@@ -30,9 +31,29 @@ where
     pub fn new() -> Self {
         Self {
             map: Arc::new(DashMap::new()),
+            max_entries: None,
         }
     }
 
+    /// Cap the number of keys tracked at once. Operations for new keys beyond the cap run directly without
+    /// coalescing, so a flood of distinct keys can't grow the map without bound.
+    ///
+    /// The cap is checked before inserting, so concurrent callers may briefly exceed it by a few entries.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Number of keys currently inflight.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether no keys are inflight.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
     /// Run `make(token)` once per key; others await the same shared result.
     /// Cancels when the last waiter drops; removes the entry on completion or last-drop.
     pub async fn get_or_run<F, Fut>(&self, key: K, make: F) -> anyhow::Result<Arc<V>>
@@ -42,6 +63,14 @@ where
     {
         use dashmap::mapref::entry::Entry as DMEntry;
 
+        // checked before taking the entry lock, `len` locks every shard.
+        if let Some(max_entries) = self.max_entries
+            && self.map.len() >= max_entries
+            && !self.map.contains_key(&key)
+        {
+            return make(CancellationToken::new()).await.map(Arc::new);
+        }
+
         // create or get the Entry for this key
         let entry = match self.map.entry(key.clone()) {
             DMEntry::Occupied(e) => Arc::clone(e.get()),
@@ -146,3 +175,46 @@ impl<K: Eq + Hash + std::fmt::Debug, V> Drop for WaiterGuard<K, V> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn new_keys_bypass_coalescing_at_capacity() {
+        let inflight = Arc::new(Inflight::<u32, u32>::new().with_max_entries(2));
+        let release = CancellationToken::new();
+
+        let mut pending = Vec::new();
+        for key in [1, 2] {
+            let inflight = Arc::clone(&inflight);
+            let release = release.clone();
+            pending.push(tokio::spawn(async move {
+                inflight
+                    .get_or_run(key, async move |_| {
+                        release.cancelled().await;
+                        Ok(key)
+                    })
+                    .await
+            }));
+        }
+
+        while inflight.len() < 2 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        for key in 3..10 {
+            let value = inflight.get_or_run(key, async move |_| Ok(key * 10)).await.unwrap();
+            assert_eq!(*value, key * 10);
+            assert_eq!(inflight.len(), 2);
+        }
+
+        release.cancel();
+        for (task, key) in pending.into_iter().zip([1, 2]) {
+            assert_eq!(*task.await.unwrap().unwrap(), key);
+        }
+        assert!(inflight.is_empty());
+    }
+}
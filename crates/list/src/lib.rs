@@ -59,14 +59,22 @@ pub struct DomainListMatcher {
 impl DomainListMatcher {
     /// Check if a given domain matches any of the domain list patterns.
     pub fn exists(&self, name: &str) -> bool {
-        let labels = match normalize(name) {
-            Ok(labels) => labels,
-            Err(_) => return false,
-        };
+        match normalize(name) {
+            Ok(name) => self.matches(&name),
+            Err(_) => false,
+        }
+    }
 
+    /// Check each of `names` against the patterns, see [`DomainListMatcher::exists`].
+    pub fn exists_many<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> Vec<bool> {
+        names.into_iter().map(|name| self.exists(name)).collect()
+    }
+
+    /// Check if an already normalized domain matches any of the domain list patterns.
+    pub fn matches(&self, name: &NormalizedDomain) -> bool {
         let mut node = &self.root;
 
-        for label in labels.rev_labels() {
+        for label in name.rev_labels() {
             if node.subdomain_match {
                 return true;
             }
@@ -120,16 +128,37 @@ impl DomainListMatcher {
     }
 }
 
+/// Domain name in the ASCII form the matcher stores its patterns in.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NormalizedDomain(String);
 
 impl NormalizedDomain {
+    /// Normalize a name received as raw labels, e.g. from the wire.
+    /// Labels may be Unicode (UTF-8) or punycode, both normalize to the same form.
+    pub fn from_labels<'a>(labels: impl IntoIterator<Item = &'a [u8]>) -> anyhow::Result<Self> {
+        let mut name = String::new();
+        for label in labels {
+            let label = std::str::from_utf8(label).map_err(|_| anyhow::anyhow!("label is not valid UTF-8"))?;
+            if !name.is_empty() {
+                name.push('.');
+            }
+            name.push_str(label);
+        }
+
+        normalize(&name)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
     fn rev_labels(&self) -> impl Iterator<Item = &str> {
         self.0.split('.').filter(|l| !l.is_empty()).rev()
     }
 }
 
 /// Normalize a domain name using IDNA.
-fn normalize(input: &str) -> anyhow::Result<NormalizedDomain> {
+pub fn normalize(input: &str) -> anyhow::Result<NormalizedDomain> {
     let s = input.trim().trim_end_matches('.');
 
     // Convert Unicode to ASCII.
@@ -156,6 +185,28 @@ mod tests {
         assert!(matcher.exists("a.bla.com"));
     }
 
+    #[test]
+    fn test_exists_many() {
+        let patterns = vec![
+            DomainPattern::Domain("ads.example"),
+            DomainPattern::Exact("tracker.test"),
+        ];
+        let matcher = DomainListMatcher::load(patterns).unwrap();
+
+        assert_eq!(
+            matcher.exists_many([
+                "ads.example",
+                "cdn.ads.example",
+                "example",
+                "www.tracker.test",
+                "TRACKER.test",
+                ""
+            ]),
+            vec![true, true, false, false, true, false]
+        );
+        assert!(matcher.exists_many([]).is_empty());
+    }
+
     #[test]
     fn test_normalization() {
         let patterns = vec![
@@ -168,6 +219,35 @@ mod tests {
         assert!(!matcher.exists("example.com"));
     }
 
+    #[test]
+    fn test_unicode_pattern_matches_punycode_name() {
+        let matcher = DomainListMatcher::load(vec![DomainPattern::Domain("bücher.example")]).unwrap();
+        assert!(matcher.exists("xn--bcher-kva.example"));
+        assert!(matcher.exists("shop.XN--BCHER-KVA.example"));
+        assert!(matcher.exists("bücher.example"));
+    }
+
+    #[test]
+    fn test_punycode_pattern_matches_unicode_name() {
+        let matcher = DomainListMatcher::load(vec![DomainPattern::Domain("xn--bcher-kva.example")]).unwrap();
+        assert!(matcher.exists("bücher.example"));
+        assert!(matcher.exists("shop.BÜCHER.example"));
+        assert!(!matcher.exists("bucher.example"));
+    }
+
+    #[test]
+    fn test_raw_labels_normalize_like_strings() {
+        let matcher = DomainListMatcher::load(vec![DomainPattern::Exact("xn--bcher-kva.example")]).unwrap();
+
+        let unicode = NormalizedDomain::from_labels(["bücher".as_bytes(), b"example"]).unwrap();
+        let punycode = NormalizedDomain::from_labels([b"xn--bcher-kva".as_slice(), b"example"]).unwrap();
+
+        assert_eq!(unicode, punycode);
+        assert_eq!(unicode.as_str(), "xn--bcher-kva.example");
+        assert!(matcher.matches(&unicode));
+        assert!(NormalizedDomain::from_labels([[0xff].as_slice()]).is_err());
+    }
+
     #[test]
     fn test_domain_pattern_matches_domain_and_subdomains() {
         let patterns = vec![DomainPattern::Domain("example.com")];
@@ -177,4 +257,28 @@ mod tests {
         assert!(matcher.exists("deep.sub.example.com"));
         assert!(!matcher.exists("notexample.com"));
     }
+
+    #[test]
+    fn test_adblock_domain_rule_blocks_apex_but_wildcard_does_not() {
+        let mut rules = Vec::new();
+        let mut parser = parser::ListParser::new();
+        parser.push("||both.com^\n||*.sub.com^\n", |(pattern, _)| match pattern {
+            DomainPattern::Domain(s) => rules.push((true, s.to_owned())),
+            DomainPattern::Subdomain(s) => rules.push((false, s.to_owned())),
+            DomainPattern::Exact(_) => unreachable!("adblock rules have no exact patterns"),
+        });
+        let matcher = DomainListMatcher::load(rules.iter().map(|(apex, s)| {
+            if *apex {
+                DomainPattern::Domain(s)
+            } else {
+                DomainPattern::Subdomain(s)
+            }
+        }))
+        .unwrap();
+
+        assert!(matcher.exists("both.com"));
+        assert!(matcher.exists("www.both.com"));
+        assert!(!matcher.exists("sub.com"));
+        assert!(matcher.exists("www.sub.com"));
+    }
 }
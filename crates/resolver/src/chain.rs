@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reso_context::{DnsRequestCtx, DnsResponse};
+
+use crate::{DnsResolver, DynResolver, ResolveError};
+
+/// Resolver that tries a list of resolvers in order and returns the first successful response.
+pub struct ChainResolver<G, L> {
+    resolvers: Vec<Arc<DynResolver<G, L>>>,
+}
+
+impl<G, L> ChainResolver<G, L> {
+    /// Create a chain from resolvers, in the order they should be tried.
+    pub fn new(resolvers: Vec<Arc<DynResolver<G, L>>>) -> Self {
+        Self { resolvers }
+    }
+}
+
+#[async_trait]
+impl<G, L> DnsResolver<G, L> for ChainResolver<G, L>
+where
+    G: Send + Sync + 'static,
+    L: Send + Sync,
+{
+    async fn resolve(&self, ctx: &DnsRequestCtx<G, L>) -> Result<DnsResponse, ResolveError> {
+        let mut last_error = ResolveError::Other("resolver chain is empty".to_string());
+
+        for resolver in &self.resolvers {
+            match resolver.resolve(ctx).await {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    tracing::debug!("resolver in chain failed, trying the next one: {}", e);
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, time::Duration};
+
+    use reso_context::RequestType;
+    use reso_dns::{ClassType, DnsMessageBuilder, DnsQuestion, DnsResponseCode, RecordType, domain_name::DomainName};
+
+    use super::*;
+    use crate::mock::MockResolver;
+
+    fn ctx() -> DnsRequestCtx<(), ()> {
+        let query = DnsMessageBuilder::new()
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+            ))
+            .build()
+            .encode()
+            .unwrap();
+
+        DnsRequestCtx::new(
+            Duration::from_secs(1),
+            Ipv4Addr::LOCALHOST.into(),
+            RequestType::UDP,
+            query,
+            Arc::new(()),
+            (),
+        )
+    }
+
+    #[tokio::test]
+    async fn falls_through_to_next_resolver() {
+        let chain: ChainResolver<(), ()> = ChainResolver::new(vec![
+            Arc::new(MockResolver::new().with_error(ResolveError::Timeout)),
+            Arc::new(MockResolver::new()),
+        ]);
+
+        let response = chain.resolve(&ctx()).await.unwrap();
+
+        assert_eq!(
+            response.message().unwrap().response_code(),
+            DnsResponseCode::ServerFailure
+        );
+    }
+
+    #[tokio::test]
+    async fn returns_last_error_when_all_fail() {
+        let chain: ChainResolver<(), ()> = ChainResolver::new(vec![
+            Arc::new(MockResolver::new().with_error(ResolveError::Timeout)),
+            Arc::new(MockResolver::new().with_error(ResolveError::InvalidRequest("nope".to_string()))),
+        ]);
+
+        let result = chain.resolve(&ctx()).await;
+
+        assert!(matches!(result, Err(ResolveError::InvalidRequest(_))));
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Buckets per doubling of the latency, which bounds the error of a percentile to 1/8th of its value.
+const SUB_BUCKET_BITS: u32 = 3;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+
+/// Number of buckets, enough to cover latencies up to 2^32 microseconds (over an hour).
+const BUCKETS: usize = (32 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+/// Weight of a new sample in the moving average, 1/8th like TCP's smoothed round-trip time (RFC 6298).
+const EWMA_WEIGHT_DIVISOR: i64 = 8;
+
+/// Snapshot of the response latencies of an upstream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// Responses recorded since the upstream was created.
+    pub count: u64,
+    /// Median latency, `None` if nothing was recorded.
+    pub p50: Option<Duration>,
+    /// 99th percentile latency, `None` if nothing was recorded.
+    pub p99: Option<Duration>,
+}
+
+/// Lock-free histogram of latencies with microsecond resolution and log-linear buckets.
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: Box<[AtomicU64]>,
+    count: AtomicU64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// Record a single latency.
+    pub fn record(&self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket_index(micros)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Latency below which `percentile` percent of the recorded latencies fall, rounded up to the bucket
+    /// boundary. `None` if nothing was recorded.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let total = self.count.load(Ordering::Relaxed);
+        if total == 0 {
+            return None;
+        }
+
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * total as f64).ceil() as u64).max(1);
+
+        // a concurrent record may be counted in `total` but not in its bucket yet, so fall back to the
+        // highest non-empty bucket.
+        let mut seen = 0;
+        let index = self
+            .buckets
+            .iter()
+            .position(|bucket| {
+                seen += bucket.load(Ordering::Relaxed);
+                seen >= rank
+            })
+            .or_else(|| {
+                self.buckets
+                    .iter()
+                    .rposition(|bucket| bucket.load(Ordering::Relaxed) > 0)
+            })?;
+
+        Some(Duration::from_micros(bucket_upper_bound(index)))
+    }
+
+    /// Number of recorded latencies and their median and 99th percentile.
+    pub fn stats(&self) -> LatencyStats {
+        LatencyStats {
+            count: self.count.load(Ordering::Relaxed),
+            p50: self.percentile(50.0),
+            p99: self.percentile(99.0),
+        }
+    }
+}
+
+/// Lock-free exponentially weighted moving average of latencies in microseconds, which follows changes in
+/// an upstream's latency within a few dozen samples and costs a single atomic per query.
+#[derive(Debug, Default)]
+pub struct LatencyEwma {
+    /// Current average, 0 if nothing was recorded.
+    micros: AtomicU64,
+}
+
+impl LatencyEwma {
+    /// Fold a single latency into the average.
+    pub fn record(&self, latency: Duration) {
+        let sample = u64::try_from(latency.as_micros())
+            .unwrap_or(u64::MAX)
+            .clamp(1, u32::MAX as u64) as i64;
+
+        // the closure never returns `None`, so the update can't fail.
+        let _ = self
+            .micros
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                if current == 0 {
+                    return Some(sample as u64);
+                }
+
+                let current = current as i64;
+                Some((current + (sample - current) / EWMA_WEIGHT_DIVISOR).max(1) as u64)
+            });
+    }
+
+    /// Current average, `None` if nothing was recorded.
+    pub fn get(&self) -> Option<Duration> {
+        match self.micros.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+}
+
+/// Bucket of a latency in microseconds: exact below `SUB_BUCKETS`, then `SUB_BUCKETS` buckets per doubling.
+fn bucket_index(micros: u64) -> usize {
+    if micros < SUB_BUCKETS as u64 {
+        return micros as usize;
+    }
+
+    let exponent = 63 - micros.leading_zeros();
+    let sub_bucket = (micros >> (exponent - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+    let index = (exponent - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS + sub_bucket;
+
+    index.min(BUCKETS - 1)
+}
+
+/// Largest latency in microseconds that falls into bucket `index`.
+fn bucket_upper_bound(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+
+    let shift = (index / SUB_BUCKETS - 1) as u32;
+    let sub_bucket = (index % SUB_BUCKETS) as u64;
+    let lower = (SUB_BUCKETS as u64 + sub_bucket) << shift;
+
+    lower + (1 << shift) - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_contain_their_values() {
+        for micros in [
+            0,
+            1,
+            7,
+            8,
+            9,
+            15,
+            16,
+            17,
+            1000,
+            1234,
+            65_535,
+            1_000_000,
+            u32::MAX as u64,
+        ] {
+            let index = bucket_index(micros);
+            assert!(micros <= bucket_upper_bound(index), "{micros} above bucket {index}");
+            if index > 0 {
+                assert!(micros > bucket_upper_bound(index - 1), "{micros} below bucket {index}");
+            }
+        }
+
+        assert_eq!(bucket_index(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn moving_average_follows_recent_latencies() {
+        let average = LatencyEwma::default();
+        assert_eq!(average.get(), None);
+
+        average.record(Duration::from_millis(10));
+        assert_eq!(average.get(), Some(Duration::from_millis(10)));
+
+        for _ in 0..40 {
+            average.record(Duration::from_millis(500));
+        }
+
+        let latency = average.get().unwrap();
+        assert!(
+            latency >= Duration::from_millis(490) && latency <= Duration::from_millis(500),
+            "average {latency:?}"
+        );
+    }
+
+    #[test]
+    fn percentiles_of_recorded_latencies() {
+        let histogram = LatencyHistogram::default();
+        assert_eq!(histogram.stats(), LatencyStats::default());
+
+        // 98 fast responses around 10ms and two slow ones of 500ms.
+        for i in 0..98 {
+            histogram.record(Duration::from_micros(9_500 + i * 10));
+        }
+        histogram.record(Duration::from_millis(500));
+        histogram.record(Duration::from_millis(500));
+
+        let stats = histogram.stats();
+        assert_eq!(stats.count, 100);
+
+        let p50 = stats.p50.unwrap();
+        assert!(
+            p50 >= Duration::from_micros(9_980) && p50 <= Duration::from_micros(11_500),
+            "p50 {p50:?}"
+        );
+
+        let p99 = stats.p99.unwrap();
+        assert!(
+            p99 >= Duration::from_millis(500) && p99 <= Duration::from_millis(570),
+            "p99 {p99:?}"
+        );
+
+        assert_eq!(
+            histogram.percentile(0.0),
+            Some(Duration::from_micros(bucket_upper_bound(bucket_index(9_500))))
+        );
+    }
+}
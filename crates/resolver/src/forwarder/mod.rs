@@ -1,5 +1,10 @@
+mod latency;
 mod request;
 pub mod resolver;
 mod tcp;
 mod udp;
 mod upstream;
+
+pub use latency::LatencyStats;
+pub use tcp::TcpPoolStats;
+pub use upstream::UpstreamSelection;
@@ -1,13 +1,21 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use super::{tcp::TcpPool, upstream::Upstreams};
+use super::{
+    resolver::UpstreamResponseHook,
+    tcp::TcpPool,
+    upstream::{UpstreamSelection, Upstreams},
+};
 use crate::{
     ResolveError,
     forwarder::upstream::{Upstream, UpstreamError},
+    truncation::TruncationCounters,
 };
 use bytes::Bytes;
 use reso_context::{RequestBudget, RequestType};
-use reso_dns::helpers;
+use reso_dns::{DnsResponseCode, helpers};
 use tracing::Instrument;
 
 /// Minimum time remaining in the request budget to start a new upstream attempt.
@@ -18,6 +26,9 @@ pub struct UpstreamResolveRequest {
     query: Bytes,
     request_budget: RequestBudget,
     upstreams: Arc<Upstreams>,
+    selection: UpstreamSelection,
+    response_hook: Option<UpstreamResponseHook>,
+    truncation: Arc<TruncationCounters>,
 }
 
 impl UpstreamResolveRequest {
@@ -32,14 +43,35 @@ impl UpstreamResolveRequest {
             query,
             request_budget,
             upstreams,
+            selection: UpstreamSelection::default(),
+            response_hook: None,
+            truncation: Arc::default(),
         }
     }
 
+    /// Choose the first upstream tried with `selection`.
+    pub fn with_selection(mut self, selection: UpstreamSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Pass every raw upstream response to `hook` before it is checked.
+    pub fn with_response_hook(mut self, hook: Option<UpstreamResponseHook>) -> Self {
+        self.response_hook = hook;
+        self
+    }
+
+    /// Count truncated UDP responses and TCP fallbacks in `counters`.
+    pub fn with_truncation_counters(mut self, counters: Arc<TruncationCounters>) -> Self {
+        self.truncation = counters;
+        self
+    }
+
     /// Resolve a DNS query by forwarding it to configured upstreams.
     pub async fn resolve(&self) -> Result<Bytes, ResolveError> {
         let upstreams = self
             .upstreams
-            .iter()
+            .iter(self.selection)
             .ok_or(ResolveError::Other("no upstreams available".into()))?;
 
         let request_tid = helpers::extract_transaction_id(&self.query)
@@ -47,6 +79,9 @@ impl UpstreamResolveRequest {
 
         let req_type = self.request_type;
 
+        // error to report when no upstream gives a usable answer.
+        let mut last_error = None;
+
         // Try each upstream in round robin order once.
         for (attempt, upstream) in upstreams.enumerate() {
             if !self.has_budget(MIN_REMAINING_TO_START_ATTEMPT) {
@@ -55,11 +90,14 @@ impl UpstreamResolveRequest {
 
             let span = tracing::debug_span!("upstream_attempt", upstream = %upstream.addr, attempt=attempt);
 
+            let started = Instant::now();
             let attempt_res = self.try_upstream(&upstream, req_type).instrument(span).await;
 
             let resp = match attempt_res {
                 Ok(r) => {
                     upstream.health.record_success(upstream.addr);
+                    upstream.latency.record(started.elapsed());
+                    upstream.recent_latency.record(started.elapsed());
                     r
                 }
                 Err(ref e) => {
@@ -74,6 +112,11 @@ impl UpstreamResolveRequest {
                         upstream.health.record_failure(upstream.addr);
                     }
 
+                    // a timeout took at least as long as the time waited, count it so slow upstreams lose weight.
+                    if matches!(e, UpstreamError::SendTimeout | UpstreamError::RecvTimeout) {
+                        upstream.recent_latency.record(started.elapsed());
+                    }
+
                     if let UpstreamError::RecvTaskStopped = *e {
                         upstream.clone().trigger_udp_reconnect();
                     }
@@ -89,6 +132,10 @@ impl UpstreamResolveRequest {
                 }
             };
 
+            if let Some(hook) = &self.response_hook {
+                hook(upstream.addr, &resp);
+            }
+
             let response_tid = match helpers::extract_transaction_id(&resp) {
                 Some(t) => t,
                 None => {
@@ -112,10 +159,22 @@ impl UpstreamResolveRequest {
                 );
                 continue;
             }
+
+            // another upstream may be willing to answer, only report the refusal if none is.
+            if helpers::is_refused(&resp) == Some(true) {
+                tracing::warn!(
+                    upstream = %upstream.addr,
+                    req_type = ?req_type,
+                    "upstream refused the query"
+                );
+                last_error = Some(ResolveError::Upstream(DnsResponseCode::Refused));
+                continue;
+            }
+
             return Ok(resp);
         }
 
-        Err(ResolveError::Other("all upstreams failed".into()))
+        Err(last_error.unwrap_or(ResolveError::NoReachableUpstream))
     }
 
     async fn try_upstream(&self, upstream: &Upstream, req_type: RequestType) -> Result<Bytes, UpstreamError> {
@@ -129,10 +188,12 @@ impl UpstreamResolveRequest {
         let resp = self.resolve_udp(upstream, &self.query).await?;
         match helpers::is_truncated(&resp) {
             Some(true) => {
+                self.truncation.record_truncated();
                 if !self.has_budget(MIN_REMAINING_TO_START_ATTEMPT) {
                     return Err(UpstreamError::Timeout);
                 }
                 // TCP fallback for THIS upstream only.
+                self.truncation.record_tcp_fallback();
                 self.resolve_tcp(&upstream.tcp, &self.query).await
             }
             Some(false) => Ok(resp),
@@ -150,18 +211,11 @@ impl UpstreamResolveRequest {
         let deadline = self.request_budget.deadline();
         let mut conn = pool.get_or_connect(deadline).await?;
 
-        let result = conn.send_and_receive(query, deadline).await;
+        // on error the connection is dropped here, its stream may still hold part of a response.
+        let resp_bytes = conn.send_and_receive(query, deadline).await?;
 
-        match result {
-            Ok(resp_bytes) => {
-                pool.put_back(conn, true);
-                Ok(resp_bytes)
-            }
-            Err(e) => {
-                pool.put_back(conn, false);
-                Err(e)
-            }
-        }
+        pool.put_back(conn);
+        Ok(resp_bytes)
     }
 
     /// Resolve the upstream request over udp.
@@ -171,3 +225,283 @@ impl UpstreamResolveRequest {
         udp.send_and_receive(query, deadline).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use reso_dns::{ClassType, DnsMessage, DnsMessageBuilder, DnsQuestion, RecordType, domain_name::DomainName};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, UdpSocket},
+    };
+
+    use super::*;
+    use crate::{
+        forwarder::{udp::UdpSocketOptions, upstream::Limits},
+        truncation::TruncationStats,
+    };
+
+    fn limits() -> Limits {
+        Limits {
+            max_tcp_connections: 1,
+            max_idle_tcp_connections: 1,
+            connect_timeout: Duration::from_secs(1),
+            tcp_ttl: Duration::from_secs(10),
+            tcp_reap_interval: Duration::from_secs(5),
+            tcp_fast_open: false,
+            udp: UdpSocketOptions::default(),
+        }
+    }
+
+    fn query() -> Bytes {
+        DnsMessageBuilder::new()
+            .with_id(1)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+            ))
+            .build()
+            .encode()
+            .unwrap()
+    }
+
+    /// Spawn a UDP upstream that answers every query with `response_code`, or never answers if `None`.
+    async fn spawn_upstream(response_code: Option<DnsResponseCode>) -> SocketAddr {
+        spawn_upstream_on("127.0.0.1:0", response_code).await
+    }
+
+    async fn spawn_upstream_on(bind_addr: &str, response_code: Option<DnsResponseCode>) -> SocketAddr {
+        let socket = UdpSocket::bind(bind_addr).await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((len, client)) = socket.recv_from(&mut buf).await {
+                let Some(response_code) = response_code else {
+                    continue;
+                };
+                let query = DnsMessage::decode(&buf[..len]).unwrap();
+                let response = DnsMessage::response_from_query(&query, response_code).encode().unwrap();
+                let _ = socket.send_to(&response, client).await;
+            }
+        });
+
+        addr
+    }
+
+    /// Spawn a TCP upstream on `bind_addr` that answers every query with NOERROR.
+    async fn spawn_tcp_upstream_on(bind_addr: &str) -> SocketAddr {
+        let listener = TcpListener::bind(bind_addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    while let Ok(len) = stream.read_u16().await {
+                        let mut buf = vec![0u8; len as usize];
+                        if stream.read_exact(&mut buf).await.is_err() {
+                            return;
+                        }
+                        let query = DnsMessage::decode(&buf).unwrap();
+                        let response = DnsMessage::response_from_query(&query, DnsResponseCode::NoError)
+                            .encode()
+                            .unwrap();
+                        stream.write_u16(response.len() as u16).await.unwrap();
+                        stream.write_all(&response).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        addr
+    }
+
+    /// Spawn a TCP upstream that sends the length prefix of a response and then closes the connection.
+    async fn spawn_resetting_tcp_upstream() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let Ok(len) = stream.read_u16().await else {
+                    continue;
+                };
+                let mut buf = vec![0u8; len as usize];
+                if stream.read_exact(&mut buf).await.is_ok() {
+                    let _ = stream.write_u16(64).await;
+                }
+            }
+        });
+
+        addr
+    }
+
+    async fn resolve(upstreams: &[SocketAddr], timeout: Duration) -> Result<Bytes, ResolveError> {
+        resolve_over(RequestType::UDP, upstreams, timeout).await
+    }
+
+    async fn resolve_over(
+        request_type: RequestType,
+        upstreams: &[SocketAddr],
+        timeout: Duration,
+    ) -> Result<Bytes, ResolveError> {
+        let upstreams = Arc::new(Upstreams::new(upstreams, limits()).await.unwrap());
+        UpstreamResolveRequest::new(request_type, query(), RequestBudget::new(timeout), upstreams)
+            .resolve()
+            .await
+    }
+
+    #[tokio::test]
+    async fn ipv6_upstream_over_udp() {
+        let upstream = spawn_upstream_on("[::1]:0", Some(DnsResponseCode::NoError)).await;
+        assert!(upstream.is_ipv6());
+
+        let response = resolve_over(RequestType::UDP, &[upstream], Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            DnsMessage::decode(&response).unwrap().response_code(),
+            DnsResponseCode::NoError
+        );
+    }
+
+    #[tokio::test]
+    async fn ipv6_upstream_over_tcp() {
+        let upstream = spawn_tcp_upstream_on("[::1]:0").await;
+        assert!(upstream.is_ipv6());
+
+        let response = resolve_over(RequestType::TCP, &[upstream], Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            DnsMessage::decode(&response).unwrap().response_code(),
+            DnsResponseCode::NoError
+        );
+    }
+
+    #[tokio::test]
+    async fn dual_stack_upstreams() {
+        let v4 = spawn_upstream_on("127.0.0.1:0", Some(DnsResponseCode::NoError)).await;
+        let v6 = spawn_upstream_on("[::1]:0", Some(DnsResponseCode::NoError)).await;
+        let upstreams = Arc::new(Upstreams::new(&[v4, v6], limits()).await.unwrap());
+
+        // round robin starts each request at the next upstream, so both families get used.
+        for _ in 0..2 {
+            let request = UpstreamResolveRequest::new(
+                RequestType::UDP,
+                query(),
+                RequestBudget::new(Duration::from_secs(1)),
+                upstreams.clone(),
+            );
+            assert!(request.resolve().await.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn refused_upstream_is_reported_as_refused() {
+        let upstream = spawn_upstream(Some(DnsResponseCode::Refused)).await;
+
+        let error = resolve(&[upstream], Duration::from_secs(1)).await.unwrap_err();
+
+        assert!(matches!(error, ResolveError::Upstream(DnsResponseCode::Refused)));
+        assert_eq!(error.response_code(), DnsResponseCode::Refused);
+    }
+
+    #[tokio::test]
+    async fn refused_upstream_falls_through_to_next_upstream() {
+        let refused = spawn_upstream(Some(DnsResponseCode::Refused)).await;
+        let answering = spawn_upstream(Some(DnsResponseCode::NoError)).await;
+
+        let response = resolve(&[refused, answering], Duration::from_secs(1)).await.unwrap();
+
+        assert_eq!(
+            DnsMessage::decode(&response).unwrap().response_code(),
+            DnsResponseCode::NoError
+        );
+    }
+
+    #[tokio::test]
+    async fn timed_out_upstream_is_reported_as_servfail() {
+        let upstream = spawn_upstream(None).await;
+
+        let error = resolve(&[upstream], Duration::from_millis(200)).await.unwrap_err();
+
+        assert!(matches!(error, ResolveError::NoReachableUpstream));
+        assert_eq!(error.response_code(), DnsResponseCode::ServerFailure);
+    }
+
+    #[tokio::test]
+    async fn tcp_reset_mid_read_discards_connection_and_falls_through() {
+        let resetting = spawn_resetting_tcp_upstream().await;
+        let healthy = spawn_tcp_upstream_on("127.0.0.1:0").await;
+        let upstreams = Arc::new(Upstreams::new(&[resetting, healthy], limits()).await.unwrap());
+
+        // round robin starts at the resetting upstream for the first and third request.
+        for _ in 0..3 {
+            let response = UpstreamResolveRequest::new(
+                RequestType::TCP,
+                query(),
+                RequestBudget::new(Duration::from_secs(1)),
+                upstreams.clone(),
+            )
+            .resolve()
+            .await
+            .unwrap();
+
+            assert_eq!(
+                DnsMessage::decode(&response).unwrap().response_code(),
+                DnsResponseCode::NoError
+            );
+        }
+
+        let stats = upstreams.all()[0].tcp.stats();
+        assert_eq!(stats.created, 2, "the reset connection must not be reused");
+        assert_eq!(stats.reused, 0);
+        assert_eq!((stats.idle, stats.in_use), (0, 0));
+
+        assert_eq!(upstreams.all()[1].tcp.stats().idle, 1);
+    }
+
+    #[tokio::test]
+    async fn truncated_udp_response_falls_back_to_tcp() {
+        // answers over TCP, and with TC set over UDP on the same port.
+        let addr = spawn_tcp_upstream_on("127.0.0.1:0").await;
+        let socket = UdpSocket::bind(addr).await.unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((len, client)) = socket.recv_from(&mut buf).await {
+                let query = DnsMessage::decode(&buf[..len]).unwrap();
+                let mut response = DnsMessage::response_from_query(&query, DnsResponseCode::NoError);
+                response.flags.truncated = true;
+                let _ = socket.send_to(&response.encode().unwrap(), client).await;
+            }
+        });
+
+        let upstreams = Arc::new(Upstreams::new(&[addr], limits()).await.unwrap());
+        let counters = Arc::new(TruncationCounters::default());
+        let response = UpstreamResolveRequest::new(
+            RequestType::UDP,
+            query(),
+            RequestBudget::new(Duration::from_secs(1)),
+            upstreams.clone(),
+        )
+        .with_truncation_counters(counters.clone())
+        .resolve()
+        .await
+        .unwrap();
+
+        assert_eq!(helpers::is_truncated(&response), Some(false));
+        assert_eq!(upstreams.all()[0].tcp.stats().created, 1);
+        assert_eq!(
+            counters.stats(),
+            TruncationStats {
+                truncated: 1,
+                tcp_fallbacks: 1,
+            }
+        );
+    }
+}
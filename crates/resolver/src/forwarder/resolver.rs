@@ -1,21 +1,31 @@
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
 use async_trait::async_trait;
-use bytes::{Bytes, BytesMut};
+use bytes::Bytes;
 use rand::RngExt;
 use reso_context::DnsRequestCtx;
 use reso_dns::{
-    ClassType, DnsMessage, DnsOpcode, RecordType,
+    ClassType, DnsMessage, DnsOpcode, DnsResponseCode, Edns, RecordType,
     domain_name::DomainName,
-    message::{ClientSubnet, EdnsOptionData},
+    helpers::rewrite_transaction_id,
+    message::{ClientSubnet, EdnsOption, EdnsOptionCode, EdnsOptionData, ExtendedDnsErrorInfoCode},
 };
 use reso_inflight::Inflight;
 
-use crate::{DnsResolver, DnsResponse, ResolveError};
+use crate::{
+    DnsResolver, DnsResponse, ResolveError,
+    truncation::{TruncationCounters, TruncationStats},
+};
 
 use super::{
+    LatencyStats, TcpPoolStats,
     request::UpstreamResolveRequest,
-    upstream::{Limits, Upstreams},
+    udp::UdpSocketOptions,
+    upstream::{Limits, UpstreamSelection, Upstreams},
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -26,17 +36,14 @@ struct InflightCacheKey {
     pub do_bit: bool,
     pub client_subnet: Option<ClientSubnet>,
     pub opcode: DnsOpcode,
+    /// Queries with CD set are forwarded separately, so they keep the bit upstream.
+    pub checking_disabled: bool,
 }
 
 impl TryFrom<&DnsMessage> for InflightCacheKey {
     type Error = anyhow::Error;
     fn try_from(message: &DnsMessage) -> Result<Self, Self::Error> {
-        let client_subnet: Option<ClientSubnet> = message.edns().as_ref().and_then(|e| {
-            e.options.iter().find_map(|opt| match &opt.data {
-                Some(EdnsOptionData::ClientSubnet(cs)) => Some(cs.clone()),
-                _ => None,
-            })
-        });
+        let client_subnet = query_client_subnet(message).cloned();
 
         message
             .questions()
@@ -48,15 +55,129 @@ impl TryFrom<&DnsMessage> for InflightCacheKey {
                 opcode: message.flags.opcode,
                 do_bit: message.edns().as_ref().map(|e| e.do_bit()).unwrap_or(false),
                 client_subnet,
+                checking_disabled: message.flags.checking_disabled,
             })
             .ok_or_else(|| anyhow::anyhow!("no question in message"))
     }
 }
 
+/// Client subnet option of `message`, if it has one.
+fn query_client_subnet(message: &DnsMessage) -> Option<&ClientSubnet> {
+    message.edns().as_ref().and_then(|e| {
+        e.options.iter().find_map(|opt| match &opt.data {
+            Some(EdnsOptionData::ClientSubnet(cs)) => Some(cs),
+            _ => None,
+        })
+    })
+}
+
+/// How the client subnet option (ECS, RFC 7871) of forwarded queries is set.
+///
+/// Only queries with EDNS are changed, so the response doesn't gain an OPT record the client didn't ask for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EcsMode {
+    /// Forward the client's option, if any, unchanged.
+    #[default]
+    Off,
+    /// Send only the address family with a source prefix of 0, which asks the authoritative server not to
+    /// tailor the answer to the client's location.
+    Zeroed,
+    /// Send the client's subnet, revealing at most `v4_prefix` or `v6_prefix` bits of its address.
+    /// A subnet the client sent itself is kept if it is narrower.
+    Forward { v4_prefix: u8, v6_prefix: u8 },
+}
+
+impl EcsMode {
+    /// Client subnet to send upstream for `query` from `client`, or `None` to forward the query as is.
+    fn client_subnet(&self, query: &DnsMessage, client: IpAddr) -> Option<ClientSubnet> {
+        if *self == EcsMode::Off {
+            return None;
+        }
+        query.edns().as_ref()?;
+
+        // the client may have sent its own subnet, e.g. when it is a resolver itself.
+        let (addr, prefix) = query_client_subnet(query)
+            .and_then(subnet_address)
+            .unwrap_or((client.to_canonical(), u8::MAX));
+
+        let max_prefix = match *self {
+            EcsMode::Forward { v4_prefix, .. } if addr.is_ipv4() => v4_prefix,
+            EcsMode::Forward { v6_prefix, .. } => v6_prefix,
+            EcsMode::Off | EcsMode::Zeroed => 0,
+        };
+
+        Some(ClientSubnet::new(addr, prefix.min(max_prefix)))
+    }
+}
+
+/// Which EDNS options of the client's query are forwarded upstream.
+///
+/// Options reso sets itself, such as the client subnet of [`EcsMode`], are added after filtering.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum EdnsOptionFilter {
+    /// Forward every option.
+    #[default]
+    All,
+    /// Forward only options with a code reso knows, dropping unknown ones.
+    Known,
+    /// Forward only options with one of these codes.
+    Allow(Vec<EdnsOptionCode>),
+}
+
+impl EdnsOptionFilter {
+    /// Whether an option with `code` is forwarded.
+    fn forwards(&self, code: EdnsOptionCode) -> bool {
+        match self {
+            EdnsOptionFilter::All => true,
+            EdnsOptionFilter::Known => !matches!(code, EdnsOptionCode::Unknown(_)),
+            EdnsOptionFilter::Allow(codes) => codes.contains(&code),
+        }
+    }
+}
+
+/// Address and source prefix of a client subnet option, or `None` for an unknown family.
+fn subnet_address(subnet: &ClientSubnet) -> Option<(IpAddr, u8)> {
+    let addr = match subnet.family {
+        1 => {
+            let mut octets = [0u8; 4];
+            let len = subnet.address.len().min(octets.len());
+            octets[..len].copy_from_slice(&subnet.address[..len]);
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        2 => {
+            let mut octets = [0u8; 16];
+            let len = subnet.address.len().min(octets.len());
+            octets[..len].copy_from_slice(&subnet.address[..len]);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+
+    Some((addr, subnet.source_prefix))
+}
+
+/// Callback invoked with every raw response an upstream returns, before it is validated.
+pub type UpstreamResponseHook = Arc<dyn Fn(SocketAddr, &Bytes) + Send + Sync>;
+
+/// UDP payload size advertised to upstreams by default, as recommended by DNS flag day 2020.
+pub const DEFAULT_EDNS_UDP_PAYLOAD_SIZE: u16 = 1232;
+
+/// Most distinct queries coalesced at once, further distinct queries are forwarded without coalescing.
+const MAX_INFLIGHT_QUERIES: usize = 16_384;
+
+/// How long an upstream TCP connection is kept for reuse.
+const TCP_TTL: Duration = Duration::from_secs(10);
+
 /// Resolver that forwards the incoming request to a defined upstream server.
 pub struct ForwardResolver {
     upstreams: Arc<Upstreams>,
     inflight_requests: Inflight<InflightCacheKey, DnsResponseBytes>,
+    edns_udp_payload_size: u16,
+    ecs_mode: EcsMode,
+    selection: UpstreamSelection,
+    option_filter: EdnsOptionFilter,
+    response_hook: Option<UpstreamResponseHook>,
+    truncation: Arc<TruncationCounters>,
 }
 
 impl ForwardResolver {
@@ -76,14 +197,91 @@ impl ForwardResolver {
                         connect_timeout: Duration::from_secs(2),
                         max_tcp_connections: 10,
                         max_idle_tcp_connections: 5,
-                        tcp_ttl: Duration::from_secs(10),
+                        tcp_ttl: TCP_TTL,
+                        // reap at half the TTL, so an idle connection outlives its TTL by at most that.
+                        tcp_reap_interval: TCP_TTL / 2,
+                        tcp_fast_open: false,
+                        udp: UdpSocketOptions {
+                            dont_fragment: true,
+                            ..Default::default()
+                        },
                     },
                 )
                 .await?,
             ),
-            inflight_requests: Inflight::new(),
+            inflight_requests: Inflight::new().with_max_entries(MAX_INFLIGHT_QUERIES),
+            edns_udp_payload_size: DEFAULT_EDNS_UDP_PAYLOAD_SIZE,
+            ecs_mode: EcsMode::default(),
+            selection: UpstreamSelection::default(),
+            option_filter: EdnsOptionFilter::default(),
+            response_hook: None,
+            truncation: Arc::default(),
         })
     }
+
+    /// TCP connection counters of each upstream.
+    pub fn tcp_pool_stats(&self) -> Vec<(SocketAddr, TcpPoolStats)> {
+        self.upstreams.all().iter().map(|u| (u.addr, u.tcp.stats())).collect()
+    }
+
+    /// Response latencies of each upstream.
+    pub fn latency_stats(&self) -> Vec<(SocketAddr, LatencyStats)> {
+        self.upstreams
+            .all()
+            .iter()
+            .map(|u| (u.addr, u.latency.stats()))
+            .collect()
+    }
+
+    /// Truncated UDP responses from upstreams and the TCP fallbacks they caused.
+    pub fn truncation_stats(&self) -> TruncationStats {
+        self.truncation.stats()
+    }
+
+    /// Set the UDP payload size advertised to upstreams in place of the one the client sent.
+    pub fn with_edns_udp_payload_size(mut self, size: u16) -> Self {
+        self.edns_udp_payload_size = size;
+        self
+    }
+
+    /// Set how the first upstream tried for a query is chosen.
+    pub fn with_upstream_selection(mut self, selection: UpstreamSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Set which EDNS options of the client's query are forwarded upstream.
+    pub fn with_edns_option_filter(mut self, filter: EdnsOptionFilter) -> Self {
+        self.option_filter = filter;
+        self
+    }
+
+    /// Open upstream TCP connections with TCP Fast Open, which saves a round trip once an upstream handed out a
+    /// cookie. Only supported on Linux, elsewhere it is ignored.
+    pub fn with_tcp_fast_open(self, enabled: bool) -> Self {
+        self.upstreams.set_tcp_fast_open(enabled);
+        self
+    }
+
+    /// Set how the client subnet option of forwarded queries is set.
+    pub fn with_ecs_mode(mut self, mode: EcsMode) -> Self {
+        self.ecs_mode = mode;
+        self
+    }
+
+    /// Call `hook` with the address and raw bytes of every response an upstream returns, including ones that
+    /// are rejected afterwards, e.g. to log malformed responses. Coalesced queries only trigger it once.
+    pub fn with_response_hook(mut self, hook: impl Fn(SocketAddr, &Bytes) + Send + Sync + 'static) -> Self {
+        self.response_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Count truncated upstream responses and TCP fallbacks in `counters`, e.g. to keep the counts across
+    /// rebuilds of the resolver.
+    pub fn with_truncation_counters(mut self, counters: Arc<TruncationCounters>) -> Self {
+        self.truncation = counters;
+        self
+    }
 }
 
 #[async_trait]
@@ -95,27 +293,54 @@ where
     async fn resolve(&self, ctx: &DnsRequestCtx<G, L>) -> Result<DnsResponse, ResolveError> {
         let query_message = ctx.message().map_err(|e| ResolveError::InvalidRequest(e.to_string()))?;
 
-        if query_message.questions().len() != 1 {
-            return Err(ResolveError::InvalidRequest(format!(
-                "request contains {} questions, expected 1",
-                query_message.questions().len(),
-            )));
+        query_message
+            .validate_query()
+            .map_err(|e| ResolveError::InvalidRequest(e.to_string()))?;
+
+        // only answered from the cache and local resolvers, which run before the forwarder.
+        if !query_message.flags.recursion_desired {
+            return Err(ResolveError::RecursionNotDesired);
+        }
+
+        // a misconfiguration, answer right away instead of failing every query in the upstream loop.
+        if self.upstreams.all().is_empty() {
+            let response = not_ready_response(query_message);
+            let bytes = response.encode().map_err(|e| ResolveError::Other(e.to_string()))?;
+            return Ok(DnsResponse::from_parsed(bytes, response));
         }
 
-        let key = InflightCacheKey::try_from(query_message).map_err(|e| ResolveError::Other(e.to_string()))?;
+        let mut key = InflightCacheKey::try_from(query_message).map_err(|e| ResolveError::Other(e.to_string()))?;
 
         let upstreams = self.upstreams.clone();
 
-        let query = ctx.raw();
+        let client_subnet = self.ecs_mode.client_subnet(query_message, ctx.request_address());
+        if client_subnet.is_some() {
+            // coalesce on the subnet sent upstream, since the answer may depend on it.
+            key.client_subnet = client_subnet.clone();
+        }
+
+        let query = upstream_query(
+            query_message,
+            ctx.raw(),
+            self.edns_udp_payload_size,
+            &self.option_filter,
+            client_subnet,
+        )?;
         let request_type = ctx.request_type();
         let budget = *ctx.budget();
+        let selection = self.selection;
+        let response_hook = self.response_hook.clone();
+        let truncation = self.truncation.clone();
 
         let resp_arc = self
             .inflight_requests
             .get_or_run(key, async move |_| {
-                let (randomized_query, _) = generate_tid(&query);
+                let (randomized_query, _) = generate_tid(query);
 
-                let request = UpstreamResolveRequest::new(request_type, randomized_query, budget, upstreams);
+                let request = UpstreamResolveRequest::new(request_type, randomized_query, budget, upstreams)
+                    .with_selection(selection)
+                    .with_response_hook(response_hook)
+                    .with_truncation_counters(truncation);
 
                 let response = request.resolve().await?;
 
@@ -134,17 +359,86 @@ where
                 }
             })?;
 
-        let response = resp_arc.as_ref().clone().into_custom_response(query_message.id);
+        let mut response = resp_arc.as_ref().clone().into_custom_response(query_message.id);
 
-        let response_message =
+        let mut response_message =
             DnsMessage::decode(&response).map_err(|e| ResolveError::InvalidResponse(e.to_string()))?;
 
         validate_upstream_response(query_message, &response_message)?;
 
+        // records unrelated to the question are never served, so they never reach the cache either.
+        let removed = response_message.strip_out_of_bailiwick_records();
+        if removed > 0 {
+            tracing::debug!(removed, "dropped out-of-bailiwick records from upstream response");
+            response = response_message
+                .encode()
+                .map_err(|e| ResolveError::Other(e.to_string()))?;
+        }
+
         Ok(DnsResponse::from_parsed(response, response_message))
     }
 }
 
+/// Build the query sent upstream, advertising `udp_payload_size` instead of the client's payload size, dropping
+/// the options `option_filter` doesn't forward and replacing the client's subnet option with `client_subnet` if set.
+/// Queries without EDNS are forwarded unchanged, so the response doesn't gain an OPT record the client didn't ask for.
+fn upstream_query(
+    query: &DnsMessage,
+    raw: Bytes,
+    udp_payload_size: u16,
+    option_filter: &EdnsOptionFilter,
+    client_subnet: Option<ClientSubnet>,
+) -> Result<Bytes, ResolveError> {
+    let Some(edns) = query.edns() else {
+        return Ok(raw);
+    };
+
+    if edns.udp_payload_size == udp_payload_size
+        && client_subnet.is_none()
+        && edns.options.iter().all(|opt| option_filter.forwards(opt.code))
+    {
+        return Ok(raw);
+    }
+
+    let mut edns = edns.clone();
+    edns.udp_payload_size = udp_payload_size;
+    edns.options.retain(|opt| option_filter.forwards(opt.code));
+
+    if let Some(client_subnet) = client_subnet {
+        edns.options.retain(|opt| opt.code != EdnsOptionCode::ClientSubnet);
+        edns.options.push(EdnsOption::new(
+            EdnsOptionCode::ClientSubnet,
+            EdnsOptionData::ClientSubnet(client_subnet),
+        ));
+    }
+
+    let mut query = query.clone();
+    query.set_edns(Some(edns));
+    query.encode().map_err(|e| ResolveError::InvalidRequest(e.to_string()))
+}
+
+/// Build the SERVFAIL answering every query while no upstream is configured.
+///
+/// Clients that sent an OPT record also get the `Not Ready` extended DNS error (RFC 8914).
+fn not_ready_response(query: &DnsMessage) -> DnsMessage {
+    let mut response = DnsMessage::response_from_query(query, DnsResponseCode::ServerFailure);
+
+    if let Some(query_edns) = query.edns() {
+        let mut edns = Edns::default();
+        edns.set_do_bit(query_edns.do_bit());
+        edns.options.push(EdnsOption::new(
+            EdnsOptionCode::ExtendedDnsError,
+            EdnsOptionData::ExtendedError {
+                info_code: ExtendedDnsErrorInfoCode::NotReady,
+                extra_text: None,
+            },
+        ));
+        response.set_edns(Some(edns));
+    }
+
+    response
+}
+
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
 struct DnsResponseBytes(Bytes);
 
@@ -154,46 +448,475 @@ impl DnsResponseBytes {
     }
 
     pub fn into_custom_response(self, transaction_id: u16) -> Bytes {
-        let mut bytes = BytesMut::from(&self.0[0..]);
-        // overwrite the transaction id.
-        bytes[0] = (transaction_id >> 8) as u8;
-        bytes[1] = (transaction_id & 0xFF) as u8;
-        bytes.freeze()
+        rewrite_transaction_id(self.0, transaction_id)
     }
 }
 
 /// Modify the transaction ID of the given query to a random value to prevent poisoning attacks.
-fn generate_tid(query: &[u8]) -> (Bytes, u16) {
+fn generate_tid(query: Bytes) -> (Bytes, u16) {
     let mut rng = rand::rng();
 
     let randomized_id = rng.random::<u16>();
 
-    let mut bytes = BytesMut::from(&query[0..]);
-    // overwrite the transaction id.
-    bytes[0] = (randomized_id >> 8) as u8;
-    bytes[1] = (randomized_id & 0xFF) as u8;
-
-    (bytes.freeze(), randomized_id)
+    (rewrite_transaction_id(query, randomized_id), randomized_id)
 }
 
 pub fn validate_upstream_response(request: &DnsMessage, response: &DnsMessage) -> Result<(), ResolveError> {
-    if request.id != response.id {
-        return Err(ResolveError::MalformedResponse("transaction id mismatch".into()));
+    response
+        .validate_response(request)
+        .map_err(|e| ResolveError::MalformedResponse(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use reso_context::RequestType;
+    use reso_dns::{DnsMessageBuilder, DnsQuestion, DnsRecord, EdnsBuilder, message::DnsRecordData};
+
+    use super::*;
+
+    fn query(edns: Option<reso_dns::Edns>) -> DnsMessage {
+        let builder = DnsMessageBuilder::new().with_id(1).add_question(DnsQuestion::new(
+            DomainName::from_ascii("example.com").unwrap(),
+            RecordType::TXT,
+            ClassType::IN,
+        ));
+        match edns {
+            Some(edns) => builder.with_edns(edns).build(),
+            None => builder.build(),
+        }
     }
 
-    if !response.flags.response {
-        return Err(ResolveError::MalformedResponse(
-            "received query instead of response from upstream".into(),
+    fn response(query: &DnsMessage, answers: usize) -> DnsMessage {
+        let mut builder = DnsMessageBuilder::new()
+            .with_id(query.id)
+            .with_questions(query.questions().to_vec());
+        for i in 0..answers {
+            builder = builder.add_answer(DnsRecord::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+                300,
+                DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, i as u8)),
+            ));
+        }
+        if let Some(edns) = query.edns() {
+            builder = builder.with_edns(edns.clone());
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn test_upstream_query_advertises_configured_payload_size() {
+        let query = query(Some(
+            EdnsBuilder::new().with_udp_payload_size(4096).with_do_bit(true).build(),
         ));
+
+        let upstream = upstream_query(&query, query.encode().unwrap(), 1232, &EdnsOptionFilter::All, None).unwrap();
+
+        let edns = DnsMessage::decode(&upstream).unwrap().edns().clone().unwrap();
+        assert_eq!(edns.udp_payload_size, 1232);
+        assert!(edns.do_bit());
     }
 
-    if response.flags.opcode != request.flags.opcode {
-        return Err(ResolveError::MalformedResponse("opcode mismatch".into()));
+    #[test]
+    fn test_upstream_query_without_edns_is_unchanged() {
+        let query = query(None);
+        let raw = query.encode().unwrap();
+
+        let upstream = upstream_query(&query, raw.clone(), 1232, &EdnsOptionFilter::All, None).unwrap();
+
+        assert_eq!(upstream, raw);
     }
 
-    if request.questions() != response.questions() {
-        return Err(ResolveError::MalformedResponse("questions mismatch".into()));
+    /// Option codes of the query forwarded with `filter`, as the upstream would decode them.
+    fn forwarded_options(query: &DnsMessage, filter: EdnsOptionFilter) -> Vec<EdnsOptionCode> {
+        let upstream = upstream_query(query, query.encode().unwrap(), 1232, &filter, None).unwrap();
+        let edns = DnsMessage::decode(&upstream).unwrap().edns().clone().unwrap();
+        edns.options.iter().map(|opt| opt.code).collect()
     }
 
-    Ok(())
+    fn query_with_unknown_option() -> DnsMessage {
+        let mut edns = EdnsBuilder::new().add_nsid(&[]).build();
+        edns.options.push(EdnsOption::new(
+            EdnsOptionCode::Unknown(65001),
+            EdnsOptionData::Raw(vec![1, 2, 3]),
+        ));
+        query(Some(edns))
+    }
+
+    #[test]
+    fn test_option_filter_all_forwards_everything() {
+        assert_eq!(
+            forwarded_options(&query_with_unknown_option(), EdnsOptionFilter::All),
+            vec![EdnsOptionCode::NSID, EdnsOptionCode::Unknown(65001)]
+        );
+    }
+
+    #[test]
+    fn test_option_filter_known_strips_unknown_options() {
+        assert_eq!(
+            forwarded_options(&query_with_unknown_option(), EdnsOptionFilter::Known),
+            vec![EdnsOptionCode::NSID]
+        );
+    }
+
+    #[test]
+    fn test_option_filter_allow_keeps_only_allowlisted_options() {
+        let query = query_with_unknown_option();
+
+        assert_eq!(
+            forwarded_options(&query, EdnsOptionFilter::Allow(vec![EdnsOptionCode::Unknown(65001)])),
+            vec![EdnsOptionCode::Unknown(65001)]
+        );
+        assert!(forwarded_options(&query, EdnsOptionFilter::Allow(vec![])).is_empty());
+    }
+
+    #[test]
+    fn test_option_filter_keeps_subnet_set_by_ecs_mode() {
+        let query = query_with_unknown_option();
+        let client_subnet = EcsMode::Zeroed.client_subnet(&query, "192.0.2.1".parse().unwrap());
+
+        let upstream = upstream_query(
+            &query,
+            query.encode().unwrap(),
+            1232,
+            &EdnsOptionFilter::Allow(vec![]),
+            client_subnet,
+        )
+        .unwrap();
+
+        assert_eq!(
+            query_client_subnet(&DnsMessage::decode(&upstream).unwrap()),
+            Some(&ClientSubnet::new("0.0.0.0".parse().unwrap(), 0))
+        );
+    }
+
+    /// Client subnet option of the query built for `mode`, as the upstream would decode it.
+    fn upstream_subnet(query: &DnsMessage, mode: EcsMode, client: &str) -> Option<ClientSubnet> {
+        let client_subnet = mode.client_subnet(query, client.parse().unwrap());
+        let upstream = upstream_query(
+            query,
+            query.encode().unwrap(),
+            1232,
+            &EdnsOptionFilter::All,
+            client_subnet,
+        )
+        .unwrap();
+        query_client_subnet(&DnsMessage::decode(&upstream).unwrap()).cloned()
+    }
+
+    #[test]
+    fn test_ecs_off_forwards_client_option_unchanged() {
+        assert_eq!(
+            upstream_subnet(&query(Some(EdnsBuilder::new().build())), EcsMode::Off, "192.0.2.130"),
+            None
+        );
+
+        let query = query(Some(
+            EdnsBuilder::new()
+                .add_client_subnet("198.51.100.7".parse().unwrap(), 24)
+                .build(),
+        ));
+        assert_eq!(
+            upstream_subnet(&query, EcsMode::Off, "192.0.2.130"),
+            Some(ClientSubnet::new("198.51.100.7".parse().unwrap(), 24))
+        );
+    }
+
+    #[test]
+    fn test_ecs_zeroed_sends_only_the_family() {
+        let query = query(Some(EdnsBuilder::new().build()));
+
+        let v4 = upstream_subnet(&query, EcsMode::Zeroed, "192.0.2.130").unwrap();
+        assert_eq!((v4.family, v4.source_prefix, v4.address.len()), (1, 0, 0));
+
+        let v6 = upstream_subnet(&query, EcsMode::Zeroed, "2001:db8::1").unwrap();
+        assert_eq!((v6.family, v6.source_prefix, v6.address.len()), (2, 0, 0));
+    }
+
+    #[test]
+    fn test_ecs_forward_sends_truncated_client_address() {
+        let mode = EcsMode::Forward {
+            v4_prefix: 24,
+            v6_prefix: 56,
+        };
+        let query = query(Some(EdnsBuilder::new().build()));
+
+        assert_eq!(
+            upstream_subnet(&query, mode, "192.0.2.130"),
+            Some(ClientSubnet::new("192.0.2.0".parse().unwrap(), 24))
+        );
+        assert_eq!(
+            upstream_subnet(&query, mode, "::ffff:192.0.2.130"),
+            Some(ClientSubnet::new("192.0.2.0".parse().unwrap(), 24))
+        );
+        assert_eq!(
+            upstream_subnet(&query, mode, "2001:db8:1:2:3::1"),
+            Some(ClientSubnet::new("2001:db8:1:2::".parse().unwrap(), 56))
+        );
+    }
+
+    #[test]
+    fn test_ecs_forward_caps_client_supplied_subnet() {
+        let mode = EcsMode::Forward {
+            v4_prefix: 16,
+            v6_prefix: 48,
+        };
+
+        let wide = query(Some(
+            EdnsBuilder::new()
+                .add_client_subnet("198.51.100.7".parse().unwrap(), 24)
+                .build(),
+        ));
+        assert_eq!(
+            upstream_subnet(&wide, mode, "192.0.2.130"),
+            Some(ClientSubnet::new("198.51.0.0".parse().unwrap(), 16))
+        );
+
+        let narrow = query(Some(
+            EdnsBuilder::new()
+                .add_client_subnet("198.51.100.7".parse().unwrap(), 8)
+                .build(),
+        ));
+        assert_eq!(
+            upstream_subnet(&narrow, mode, "192.0.2.130"),
+            Some(ClientSubnet::new("198.0.0.0".parse().unwrap(), 8))
+        );
+    }
+
+    #[test]
+    fn test_ecs_is_not_added_without_edns() {
+        let query = query(None);
+        let mode = EcsMode::Forward {
+            v4_prefix: 24,
+            v6_prefix: 56,
+        };
+
+        assert_eq!(mode.client_subnet(&query, "192.0.2.130".parse().unwrap()), None);
+        assert_eq!(upstream_subnet(&query, EcsMode::Zeroed, "192.0.2.130"), None);
+    }
+
+    #[test]
+    fn test_inflight_key_separates_checking_disabled() {
+        let plain = query(None);
+        let mut checking_disabled = plain.clone();
+        checking_disabled.flags.checking_disabled = true;
+
+        assert_ne!(
+            InflightCacheKey::try_from(&plain).unwrap(),
+            InflightCacheKey::try_from(&checking_disabled).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cd_reaches_upstream_and_ad_reaches_client() {
+        let upstream = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (len, client) = upstream.recv_from(&mut buf).await.unwrap();
+                let query = DnsMessage::decode(&buf[..len]).unwrap();
+                seen_tx.send(query.flags).unwrap();
+
+                let mut response = DnsMessage::response_from_query(&query, DnsResponseCode::NoError);
+                response.flags.authentic_data = true;
+                response.flags.checking_disabled = query.flags.checking_disabled;
+                upstream.send_to(&response.encode().unwrap(), client).await.unwrap();
+            }
+        });
+
+        let resolver = ForwardResolver::new(&[upstream_addr]).await.unwrap();
+        let mut query = query(None);
+        query.flags.checking_disabled = true;
+        let ctx = DnsRequestCtx::new(
+            Duration::from_secs(2),
+            Ipv4Addr::LOCALHOST.into(),
+            RequestType::UDP,
+            query.encode().unwrap(),
+            Arc::new(()),
+            (),
+        );
+
+        let response = DnsResolver::<(), ()>::resolve(&resolver, &ctx).await.unwrap();
+        let response = response.message().unwrap();
+
+        assert!(seen_rx.recv().await.unwrap().checking_disabled);
+        assert!(response.flags.authentic_data);
+        assert!(response.flags.checking_disabled);
+        assert_eq!(response.id, query.id);
+    }
+
+    #[tokio::test]
+    async fn test_response_hook_sees_rejected_response() {
+        let upstream = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        let (sent_tx, mut sent_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        // answers for a different name, which the resolver rejects.
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (len, client) = upstream.recv_from(&mut buf).await.unwrap();
+                let query = DnsMessage::decode(&buf[..len]).unwrap();
+
+                let response = DnsMessageBuilder::new()
+                    .with_id(query.id)
+                    .with_flags(DnsMessage::response_from_query(&query, DnsResponseCode::NoError).flags)
+                    .add_question(DnsQuestion::new(
+                        DomainName::from_ascii("other.example.com").unwrap(),
+                        RecordType::A,
+                        ClassType::IN,
+                    ))
+                    .build()
+                    .encode()
+                    .unwrap();
+                upstream.send_to(&response, client).await.unwrap();
+                sent_tx.send(response).unwrap();
+            }
+        });
+
+        let (hook_tx, mut hook_rx) = tokio::sync::mpsc::unbounded_channel();
+        let resolver = ForwardResolver::new(&[upstream_addr])
+            .await
+            .unwrap()
+            .with_response_hook(move |addr, bytes| hook_tx.send((addr, bytes.clone())).unwrap());
+        let ctx = DnsRequestCtx::new(
+            Duration::from_secs(2),
+            Ipv4Addr::LOCALHOST.into(),
+            RequestType::UDP,
+            query(None).encode().unwrap(),
+            Arc::new(()),
+            (),
+        );
+
+        let result = DnsResolver::<(), ()>::resolve(&resolver, &ctx).await;
+        assert!(matches!(result, Err(ResolveError::MalformedResponse(_))));
+
+        let (addr, bytes) = hook_rx.recv().await.unwrap();
+        assert_eq!(addr, upstream_addr);
+        assert_eq!(bytes, sent_rx.recv().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_no_upstreams_answers_servfail_not_ready() {
+        let resolver = ForwardResolver::new(&[]).await.unwrap();
+        let query = query(Some(EdnsBuilder::new().build()));
+        let ctx = DnsRequestCtx::new(
+            Duration::from_secs(2),
+            Ipv4Addr::LOCALHOST.into(),
+            RequestType::UDP,
+            query.encode().unwrap(),
+            Arc::new(()),
+            (),
+        );
+
+        let Ok(response) = DnsResolver::<(), ()>::resolve(&resolver, &ctx).await else {
+            panic!("expected a response");
+        };
+        let response = response.message().unwrap();
+
+        assert_eq!(response.id, query.id);
+        assert_eq!(response.response_code(), DnsResponseCode::ServerFailure);
+        assert_eq!(response.questions(), query.questions());
+
+        let info_code = response
+            .edns()
+            .as_ref()
+            .unwrap()
+            .options
+            .iter()
+            .find_map(|option| match &option.data {
+                Some(EdnsOptionData::ExtendedError { info_code, .. }) => Some(*info_code),
+                _ => None,
+            });
+        assert_eq!(info_code, Some(ExtendedDnsErrorInfoCode::NotReady));
+    }
+
+    #[tokio::test]
+    async fn test_out_of_bailiwick_answer_is_dropped() {
+        let upstream = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+
+        // answers with the requested record and an injected one for an unrelated name.
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (len, client) = upstream.recv_from(&mut buf).await.unwrap();
+                let query = DnsMessage::decode(&buf[..len]).unwrap();
+
+                let response = DnsMessageBuilder::new()
+                    .with_id(query.id)
+                    .with_flags(DnsMessage::response_from_query(&query, DnsResponseCode::NoError).flags)
+                    .with_questions(query.questions().to_vec())
+                    .with_answers(response(&query, 1).answers().to_vec())
+                    .add_answer(DnsRecord::new(
+                        DomainName::from_ascii("bank.example.org").unwrap(),
+                        RecordType::A,
+                        ClassType::IN,
+                        300,
+                        DnsRecordData::Ipv4(Ipv4Addr::new(203, 0, 113, 66)),
+                    ))
+                    .build();
+                upstream.send_to(&response.encode().unwrap(), client).await.unwrap();
+            }
+        });
+
+        let resolver = ForwardResolver::new(&[upstream_addr]).await.unwrap();
+        let query = query(None);
+        let ctx = DnsRequestCtx::new(
+            Duration::from_secs(2),
+            Ipv4Addr::LOCALHOST.into(),
+            RequestType::UDP,
+            query.encode().unwrap(),
+            Arc::new(()),
+            (),
+        );
+
+        let Ok(response) = DnsResolver::<(), ()>::resolve(&resolver, &ctx).await else {
+            panic!("expected a response");
+        };
+        let message = response.message().unwrap();
+
+        assert_eq!(message.answers().len(), 1);
+        assert_eq!(message.answers()[0].name, query.questions()[0].qname);
+        // the served bytes carry the filtered message too.
+        assert_eq!(
+            DnsMessage::decode(&response.bytes()).unwrap().answers(),
+            message.answers()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_without_recursion_desired_is_not_forwarded() {
+        let upstream = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver = ForwardResolver::new(&[upstream.local_addr().unwrap()]).await.unwrap();
+
+        let mut query = query(None);
+        query.flags.recursion_desired = false;
+        let ctx = DnsRequestCtx::new(
+            Duration::from_secs(2),
+            Ipv4Addr::LOCALHOST.into(),
+            RequestType::UDP,
+            query.encode().unwrap(),
+            Arc::new(()),
+            (),
+        );
+
+        let result = DnsResolver::<(), ()>::resolve(&resolver, &ctx).await;
+        let Err(error) = result else {
+            panic!("expected the query to be refused");
+        };
+        assert!(matches!(error, ResolveError::RecursionNotDesired));
+        assert_eq!(error.response_code(), DnsResponseCode::Refused);
+        assert_eq!(error.extended_error(), Some(ExtendedDnsErrorInfoCode::NotAuthorative));
+
+        let mut buf = [0u8; 512];
+        let received = tokio::time::timeout(Duration::from_millis(100), upstream.recv_from(&mut buf)).await;
+        assert!(received.is_err(), "the query reached the upstream");
+    }
 }
@@ -1,13 +1,18 @@
 use std::{
     collections::VecDeque,
+    io,
     net::SocketAddr,
-    sync::{Arc, Mutex},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
 };
 
 use bytes::{Bytes, BytesMut};
+use socket2::SockRef;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
+    net::{TcpSocket, TcpStream},
     sync::OwnedSemaphorePermit,
     time::{Duration, Instant, timeout_at},
 };
@@ -23,10 +28,33 @@ pub(crate) struct TcpPool {
     pub addr: SocketAddr,
     /// Upstream limits
     pub limits: Limits,
+    /// Whether new connections use TCP Fast Open, starts out as `limits.tcp_fast_open`.
+    tcp_fast_open: AtomicBool,
     /// Idle connections in insertion order.
     idle: Mutex<VecDeque<TcpConn>>,
     /// Total connections (including in-use and connecting)
     connections: Arc<Semaphore>,
+    /// Connections opened.
+    created: AtomicU64,
+    /// Checkouts served by an idle connection.
+    reused: AtomicU64,
+    /// Idle connections dropped because they expired or were closed by the upstream.
+    reaped: AtomicU64,
+}
+
+/// Snapshot of the connection counters of a TCP pool.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TcpPoolStats {
+    /// Connections opened since the pool was created.
+    pub created: u64,
+    /// Checkouts served by an idle connection.
+    pub reused: u64,
+    /// Idle connections dropped because they expired or were closed by the upstream.
+    pub reaped: u64,
+    /// Connections currently idle in the pool.
+    pub idle: usize,
+    /// Connections currently checked out or connecting.
+    pub in_use: usize,
 }
 
 impl TcpPool {
@@ -34,11 +62,47 @@ impl TcpPool {
         Arc::new(Self {
             addr,
             limits,
+            tcp_fast_open: AtomicBool::new(limits.tcp_fast_open),
             idle: Mutex::new(VecDeque::new()),
             connections: Arc::new(Semaphore::new(limits.max_tcp_connections)),
+            created: AtomicU64::new(0),
+            reused: AtomicU64::new(0),
+            reaped: AtomicU64::new(0),
         })
     }
 
+    /// Set whether connections opened from now on use TCP Fast Open.
+    pub fn set_tcp_fast_open(&self, enabled: bool) {
+        self.tcp_fast_open.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Current connection counters.
+    pub fn stats(&self) -> TcpPoolStats {
+        let idle = self.idle.lock().unwrap_or_else(|e| e.into_inner()).len();
+        // idle connections hold a permit as well.
+        let open = self.limits.max_tcp_connections - self.connections.available_permits();
+
+        TcpPoolStats {
+            created: self.created.load(Ordering::Relaxed),
+            reused: self.reused.load(Ordering::Relaxed),
+            reaped: self.reaped.load(Ordering::Relaxed),
+            idle,
+            in_use: open.saturating_sub(idle),
+        }
+    }
+
+    /// Drop idle connections that expired before `now`, returning how many were dropped.
+    fn reap(&self, now: Instant) -> usize {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        let before = idle.len();
+        idle.retain(|c| c.ttl > now);
+        let dropped = before - idle.len();
+        drop(idle);
+
+        self.reaped.fetch_add(dropped as u64, Ordering::Relaxed);
+        dropped
+    }
+
     /// Start a background task that reaps expired idle tcp connections.
     pub fn start_reaper(self: Arc<Self>, interval: Duration) {
         // Use a weak reference to avoid keeping the pool alive if it is dropped.
@@ -51,12 +115,7 @@ impl TcpPool {
                     Some(pool) => pool,
                     None => return,
                 };
-                let now = Instant::now();
-                let mut idle = this.idle.lock().unwrap_or_else(|e| e.into_inner());
-                let before = idle.len();
-                idle.retain(|c| c.ttl > now);
-                let dropped = before - idle.len();
-                drop(idle);
+                let dropped = this.reap(Instant::now());
                 if dropped > 0 {
                     tracing::debug!("reaper dropped {} expired tcp conns to {}", dropped, this.addr);
                 }
@@ -70,8 +129,10 @@ impl TcpPool {
         let now = Instant::now();
         while let Some(conn) = idle.pop_back() {
             if conn.ttl > now && conn.is_alive() {
+                self.reused.fetch_add(1, Ordering::Relaxed);
                 return Some(conn);
             }
+            self.reaped.fetch_add(1, Ordering::Relaxed);
             tracing::debug!(upstream = %self.addr, "discarding closed idle tcp connection");
         }
         None
@@ -98,25 +159,30 @@ impl TcpPool {
 
         tracing::debug!(upstream = %self.addr, "opening new tcp connection");
 
-        TcpConn::connect(
+        let conn = TcpConn::connect(
             self.addr,
             deadline,
             self.limits.connect_timeout,
+            self.tcp_fast_open.load(Ordering::Relaxed),
             permit,
             Instant::now() + self.limits.tcp_ttl,
         )
-        .await
+        .await?;
+
+        self.created.fetch_add(1, Ordering::Relaxed);
+        Ok(conn)
     }
 
     /// Attempt to put back a connection to the pool.
-    pub fn put_back(&self, conn: TcpConn, healthy: bool) {
-        if healthy {
-            let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
-            if idle.len() < self.limits.max_idle_tcp_connections {
-                idle.push_back(conn);
-            } else {
-                tracing::trace!(upstream = %self.addr, "idle pool full, dropping connection");
-            }
+    ///
+    /// Only connections that completed an exchange may be put back, a connection that failed a read or write
+    /// is in an unknown state and must be dropped instead.
+    pub fn put_back(&self, conn: TcpConn) {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        if idle.len() < self.limits.max_idle_tcp_connections {
+            idle.push_back(conn);
+        } else {
+            tracing::trace!(upstream = %self.addr, "idle pool full, dropping connection");
         }
     }
 }
@@ -138,18 +204,33 @@ pub struct TcpConn {
 impl TcpConn {
     /// Establish a new TCP connection to the given address with a timeout and a permit.
     /// The effective timeout is `min(now + connect_timeout, deadline)`.
+    ///
+    /// With `fast_open`, the connection is opened with TCP Fast Open where the OS supports it.
     async fn connect(
         addr: SocketAddr,
         deadline: Instant,
         connect_timeout: Duration,
+        fast_open: bool,
         _permit: OwnedSemaphorePermit,
         ttl: Instant,
     ) -> Result<Self, UpstreamError> {
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()
+        } else {
+            TcpSocket::new_v6()
+        }
+        .map_err(UpstreamError::SendError)?;
+
+        if fast_open && let Err(e) = set_fast_open_connect(SockRef::from(&socket)) {
+            // not fatal, the connection is opened with a regular handshake.
+            tracing::debug!(upstream = %addr, error = %e, "failed to enable TCP fast open");
+        }
+
         // TCP connect can take a long time if the server is unresponsive
         // so we apply the timeout to the connect operation itself rather than the whole get_or_connect
 
         let effective_deadline = (Instant::now() + connect_timeout).min(deadline);
-        let s = timeout_at(effective_deadline, TcpStream::connect(addr))
+        let s = timeout_at(effective_deadline, socket.connect(addr))
             .await
             .map_err(|_| UpstreamError::SendTimeout)?
             .map_err(UpstreamError::SendError)?;
@@ -225,3 +306,212 @@ impl TcpConn {
         Ok(resp)
     }
 }
+
+/// Let `connect` return before the handshake, so the first write is sent in the SYN when the upstream's fast
+/// open cookie is known. Without a cookie, one is requested and the handshake is a regular one.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_fast_open_connect(socket: SockRef<'_>) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let value: libc::c_int = 1;
+    // SAFETY: the fd is a valid socket for the lifetime of `socket`, and `value` is a c_int as the option expects.
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_FASTOPEN_CONNECT,
+            (&value as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_fast_open_connect(_socket: SockRef<'_>) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::forwarder::udp::UdpSocketOptions;
+
+    fn test_limits() -> Limits {
+        Limits {
+            max_tcp_connections: 4,
+            max_idle_tcp_connections: 2,
+            connect_timeout: Duration::from_secs(1),
+            tcp_ttl: Duration::from_secs(30),
+            tcp_reap_interval: Duration::from_secs(15),
+            tcp_fast_open: false,
+            udp: UdpSocketOptions::default(),
+        }
+    }
+
+    /// Accept connections and keep them open, so they stay alive in the pool.
+    async fn spawn_listener() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut accepted = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.push(stream);
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn counts_created_reused_and_reaped_connections() {
+        let pool = TcpPool::new(spawn_listener().await, test_limits());
+        let deadline = Instant::now() + Duration::from_secs(1);
+
+        let first = pool.get_or_connect(deadline).await.unwrap();
+        let second = pool.get_or_connect(deadline).await.unwrap();
+        assert_eq!(
+            pool.stats(),
+            TcpPoolStats {
+                created: 2,
+                in_use: 2,
+                ..Default::default()
+            }
+        );
+
+        pool.put_back(first);
+        pool.put_back(second);
+        assert_eq!(pool.stats().idle, 2);
+        assert_eq!(pool.stats().in_use, 0);
+
+        let reused = pool.get_or_connect(deadline).await.unwrap();
+        let stats = pool.stats();
+        assert_eq!((stats.created, stats.reused, stats.idle, stats.in_use), (2, 1, 1, 1));
+
+        // a failed connection is dropped instead of returned to the pool.
+        drop(reused);
+        assert_eq!(pool.stats().in_use, 0);
+
+        assert_eq!(pool.reap(Instant::now() + Duration::from_secs(31)), 1);
+        assert_eq!(
+            pool.stats(),
+            TcpPoolStats {
+                created: 2,
+                reused: 1,
+                reaped: 1,
+                idle: 0,
+                in_use: 0,
+            }
+        );
+    }
+
+    /// Whether `TCP_FASTOPEN_CONNECT` is set on the connection's socket, `None` if the kernel doesn't know it.
+    #[cfg(target_os = "linux")]
+    fn fast_open_connect(conn: &TcpConn) -> Option<bool> {
+        use std::os::fd::AsRawFd;
+
+        let mut value: libc::c_int = -1;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: the fd is a valid socket and `value` and `len` describe a c_int sized buffer.
+        let res = unsafe {
+            libc::getsockopt(
+                conn.stream.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_FASTOPEN_CONNECT,
+                (&mut value as *mut libc::c_int).cast(),
+                &mut len,
+            )
+        };
+        (res == 0).then_some(value != 0)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn fast_open_connection_is_established() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).await.unwrap();
+            let mut query = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut query).await.unwrap();
+            stream.write_all(&len).await.unwrap();
+            stream.write_all(&query).await.unwrap();
+        });
+
+        let limits = Limits {
+            tcp_fast_open: true,
+            ..test_limits()
+        };
+        let pool = TcpPool::new(addr, limits);
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let mut conn = pool.get_or_connect(deadline).await.unwrap();
+
+        // kernels without TCP_FASTOPEN_CONNECT fall back to a regular handshake.
+        let Some(enabled) = fast_open_connect(&conn) else {
+            return;
+        };
+        assert!(enabled);
+
+        // the query goes out with the handshake and is echoed back.
+        let query = [0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(conn.send_and_receive(&query, deadline).await.unwrap(), &query[..]);
+        server.await.unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn fast_open_can_be_toggled_for_new_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _server = tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                streams.push(stream);
+            }
+        });
+
+        let pool = TcpPool::new(addr, test_limits());
+        let deadline = Instant::now() + Duration::from_secs(1);
+
+        let conn = pool.get_or_connect(deadline).await.unwrap();
+        let Some(enabled) = fast_open_connect(&conn) else {
+            return;
+        };
+        assert!(!enabled);
+
+        pool.set_tcp_fast_open(true);
+        let conn = pool.get_or_connect(deadline).await.unwrap();
+        assert_eq!(fast_open_connect(&conn), Some(true));
+    }
+
+    #[tokio::test]
+    async fn reaper_drops_expired_idle_connections() {
+        let limits = Limits {
+            tcp_ttl: Duration::from_millis(50),
+            tcp_reap_interval: Duration::from_millis(20),
+            ..test_limits()
+        };
+        let pool = TcpPool::new(spawn_listener().await, limits);
+        pool.clone().start_reaper(limits.tcp_reap_interval);
+
+        let conn = pool
+            .get_or_connect(Instant::now() + Duration::from_secs(1))
+            .await
+            .unwrap();
+        pool.put_back(conn);
+        assert_eq!(pool.stats().idle, 1);
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let stats = pool.stats();
+        assert_eq!(stats.idle, 0);
+        assert_eq!(stats.reaped, 1);
+    }
+}
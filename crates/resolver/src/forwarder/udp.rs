@@ -1,4 +1,5 @@
 use std::{
+    io,
     net::SocketAddr,
     sync::{
         Arc,
@@ -9,6 +10,7 @@ use std::{
 use bytes::Bytes;
 use dashmap::DashMap;
 use reso_dns::helpers;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
 use tokio::{
     net::UdpSocket,
     sync::{oneshot, watch},
@@ -17,6 +19,18 @@ use tokio::{
 
 use crate::forwarder::upstream::UpstreamError;
 
+/// Options applied to the UDP socket of an upstream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UdpSocketOptions {
+    /// Set the don't fragment bit, so datagrams too large for the path fail instead of being fragmented
+    /// (DNS Flag Day 2020). Only supported on Linux, elsewhere it is ignored.
+    pub dont_fragment: bool,
+    /// Size of the receive buffer, the OS default if `None`.
+    pub recv_buffer_size: Option<usize>,
+    /// Size of the send buffer, the OS default if `None`.
+    pub send_buffer_size: Option<usize>,
+}
+
 struct Pending(oneshot::Sender<Bytes>);
 
 /// A multiplexer that sends DNS queries and receives responses over a single
@@ -33,14 +47,14 @@ pub struct UpstreamUdpMux {
 }
 
 impl UpstreamUdpMux {
-    pub async fn new(upstream_addr: SocketAddr) -> Result<Self, std::io::Error> {
+    pub async fn new(upstream_addr: SocketAddr, options: UdpSocketOptions) -> Result<Self, std::io::Error> {
         let bind_addr = if upstream_addr.is_ipv4() {
             SocketAddr::from(([0, 0, 0, 0], 0))
         } else {
             SocketAddr::from(([0u16; 8], 0))
         };
 
-        let socket = Arc::new(UdpSocket::bind(bind_addr).await?);
+        let socket = Arc::new(bind_socket(bind_addr, options)?);
         socket.connect(upstream_addr).await?;
 
         let pending = Arc::new(DashMap::<u16, Pending>::new());
@@ -109,6 +123,62 @@ impl UpstreamUdpMux {
     }
 }
 
+/// Bind a non-blocking UDP socket to `bind_addr` with `options` applied.
+fn bind_socket(bind_addr: SocketAddr, options: UdpSocketOptions) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(bind_addr), Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_nonblocking(true)?;
+
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if options.dont_fragment
+        && let Err(e) = set_dont_fragment(SockRef::from(&socket), bind_addr.is_ipv6())
+    {
+        // not fatal, the socket still works with fragmentation.
+        tracing::debug!(error = %e, "failed to set the don't fragment bit");
+    }
+
+    socket.bind(&bind_addr.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Set the don't fragment bit by always doing path MTU discovery, so sending a datagram larger than the known
+/// path MTU fails with `EMSGSIZE`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_dont_fragment(socket: SockRef<'_>, ipv6: bool) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let (level, name, value) = if ipv6 {
+        (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, libc::IPV6_PMTUDISC_DO)
+    } else {
+        (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_DO)
+    };
+
+    // SAFETY: the fd is a valid socket for the lifetime of `socket`, and `value` is a c_int as the option expects.
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            (&value as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_dont_fragment(_socket: SockRef<'_>, _ipv6: bool) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
 /// Background task that reads responses from the socket and dispatches them
 /// to the corresponding pending callers.
 async fn recv_loop(
@@ -173,3 +243,72 @@ async fn recv_loop(
     // Cancel all inflight callers so they fail immediately rather than waiting until their individual deadlines expire.
     pending.retain(|_, _| false);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Path MTU discovery mode of the socket, `IP_PMTUDISC_DO` when the don't fragment bit is set.
+    #[cfg(target_os = "linux")]
+    fn mtu_discover(socket: &UdpSocket, ipv6: bool) -> libc::c_int {
+        use std::os::fd::AsRawFd;
+
+        let (level, name) = if ipv6 {
+            (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER)
+        } else {
+            (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER)
+        };
+
+        let mut value: libc::c_int = -1;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: the fd is a valid socket and `value` and `len` describe a c_int sized buffer.
+        let res = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                (&mut value as *mut libc::c_int).cast(),
+                &mut len,
+            )
+        };
+        assert_eq!(res, 0, "getsockopt failed: {}", io::Error::last_os_error());
+        value
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn dont_fragment_is_applied() {
+        let options = UdpSocketOptions {
+            dont_fragment: true,
+            ..Default::default()
+        };
+
+        let mux = UpstreamUdpMux::new("127.0.0.1:53".parse().unwrap(), options)
+            .await
+            .unwrap();
+        assert_eq!(mtu_discover(&mux.socket, false), libc::IP_PMTUDISC_DO);
+
+        let mux = UpstreamUdpMux::new("127.0.0.1:53".parse().unwrap(), UdpSocketOptions::default())
+            .await
+            .unwrap();
+        assert_ne!(mtu_discover(&mux.socket, false), libc::IP_PMTUDISC_DO);
+    }
+
+    #[tokio::test]
+    async fn buffer_sizes_are_applied() {
+        let options = UdpSocketOptions {
+            recv_buffer_size: Some(64 * 1024),
+            send_buffer_size: Some(32 * 1024),
+            ..Default::default()
+        };
+
+        let mux = UpstreamUdpMux::new("127.0.0.1:53".parse().unwrap(), options)
+            .await
+            .unwrap();
+        let socket = SockRef::from(&*mux.socket);
+
+        // the kernel may round the sizes up, e.g. Linux doubles them for bookkeeping.
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert!(socket.send_buffer_size().unwrap() >= 32 * 1024);
+    }
+}
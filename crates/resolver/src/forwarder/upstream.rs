@@ -8,10 +8,14 @@ use std::{
 };
 
 use arc_swap::ArcSwap;
+use rand::RngExt;
 
-use crate::forwarder::udp::UpstreamUdpMux;
+use crate::forwarder::udp::{UdpSocketOptions, UpstreamUdpMux};
 
-use super::tcp::TcpPool;
+use super::{
+    latency::{LatencyEwma, LatencyHistogram},
+    tcp::TcpPool,
+};
 
 /// Limits for upstream connections.
 #[derive(Clone, Copy, Debug)]
@@ -24,8 +28,29 @@ pub struct Limits {
     pub connect_timeout: Duration,
     /// TCP connection time-to-live
     pub tcp_ttl: Duration,
+    /// How often expired idle TCP connections are dropped
+    pub tcp_reap_interval: Duration,
+    /// Open TCP connections with TCP Fast Open, which sends the first query in the SYN once the upstream handed
+    /// out a cookie. Only supported on Linux, elsewhere it is ignored.
+    pub tcp_fast_open: bool,
+    /// Options of the UDP socket
+    pub udp: UdpSocketOptions,
+}
+
+/// How the first upstream tried for a request is chosen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UpstreamSelection {
+    /// Cycle through the upstreams in order.
+    #[default]
+    RoundRobin,
+    /// Pick upstreams at random, weighted inversely to their recent average latency, so faster upstreams get
+    /// more traffic while slower ones are still probed.
+    Latency,
 }
 
+/// Latency assumed for weighting when an upstream answers faster, so one very fast sample can't starve others.
+const MIN_WEIGHTED_LATENCY: Duration = Duration::from_micros(100);
+
 /// List of upstream servers.
 pub struct Upstreams {
     /// Upstream pools (1 per upstream server)
@@ -68,13 +93,13 @@ impl Upstreams {
         Ok(Arc::into_inner(upstreams).expect("no other references at construction"))
     }
 
-    pub fn iter(&self) -> Option<UpstreamIter> {
+    /// Iterate over the healthy upstreams, starting at the one chosen by `selection`.
+    pub fn iter(&self, selection: UpstreamSelection) -> Option<UpstreamIter> {
         let upstreams = self.healthy_cache.load_full();
-        let n = upstreams.len();
-        if n == 0 {
+        if upstreams.is_empty() {
             return None;
         }
-        let starting_index = self.rr.fetch_add(1, Ordering::Relaxed) % n;
+        let starting_index = self.pick_index(&upstreams, selection);
 
         Some(UpstreamIter {
             upstreams,
@@ -83,6 +108,53 @@ impl Upstreams {
         })
     }
 
+    /// Index into `upstreams` of the upstream to try first.
+    fn pick_index(&self, upstreams: &[Arc<Upstream>], selection: UpstreamSelection) -> usize {
+        let round_robin = || self.rr.fetch_add(1, Ordering::Relaxed) % upstreams.len();
+
+        if selection == UpstreamSelection::RoundRobin {
+            return round_robin();
+        }
+
+        let latencies: Vec<Option<Duration>> = upstreams
+            .iter()
+            .map(|u| u.recent_latency.get().map(|l| l.max(MIN_WEIGHTED_LATENCY)))
+            .collect();
+
+        // nothing to weight by yet.
+        let Some(fastest) = latencies.iter().flatten().min().copied() else {
+            return round_robin();
+        };
+
+        // upstreams without samples are weighted like the fastest one, so they get probed.
+        let weights: Vec<f64> = latencies
+            .iter()
+            .map(|latency| 1.0 / latency.unwrap_or(fastest).as_secs_f64())
+            .collect();
+
+        let mut target = rand::rng().random::<f64>() * weights.iter().sum::<f64>();
+        for (i, weight) in weights.iter().enumerate() {
+            if target < *weight {
+                return i;
+            }
+            target -= weight;
+        }
+
+        upstreams.len() - 1
+    }
+
+    /// Set whether TCP connections opened from now on use TCP Fast Open.
+    pub fn set_tcp_fast_open(&self, enabled: bool) {
+        for upstream in self.list.iter() {
+            upstream.tcp.set_tcp_fast_open(enabled);
+        }
+    }
+
+    /// All upstreams, healthy or not.
+    pub fn all(&self) -> &[Arc<Upstream>] {
+        &self.list
+    }
+
     pub fn rebuild_healthy_cache(&self) {
         self.healthy_cache.store(Arc::new(Self::compute_healthy(&self.list)));
     }
@@ -180,21 +252,31 @@ pub struct Upstream {
     pub tcp: Arc<TcpPool>,
     /// Health status of the upstream, used to determine if it should be skipped for new requests.
     pub health: UpstreamHealth,
+    /// Latencies of successful responses from this upstream.
+    pub latency: LatencyHistogram,
+    /// Moving average of the latencies of recent attempts, with timeouts counted as the time waited. Used to
+    /// pick upstreams by latency.
+    pub recent_latency: LatencyEwma,
     /// Flag to prevent concurrent UDP reconnect attempts.
     udp_reconnecting: AtomicBool,
+    /// Options the UDP socket is created with, reused on reconnect.
+    udp_options: UdpSocketOptions,
 }
 
 impl Upstream {
     pub async fn new(addr: SocketAddr, limits: Limits) -> Result<Self, std::io::Error> {
         let tcp = TcpPool::new(addr, limits);
-        tcp.clone().start_reaper(limits.tcp_ttl);
+        tcp.clone().start_reaper(limits.tcp_reap_interval);
 
         Ok(Self {
             addr,
             tcp,
-            udp: ArcSwap::from_pointee(UpstreamUdpMux::new(addr).await?),
+            udp: ArcSwap::from_pointee(UpstreamUdpMux::new(addr, limits.udp).await?),
             health: UpstreamHealth::new(),
+            latency: LatencyHistogram::default(),
+            recent_latency: LatencyEwma::default(),
             udp_reconnecting: AtomicBool::new(false),
+            udp_options: limits.udp,
         })
     }
 
@@ -222,7 +304,7 @@ impl Upstream {
 
             loop {
                 tokio::time::sleep(backoff).await;
-                match UpstreamUdpMux::new(self.addr).await {
+                match UpstreamUdpMux::new(self.addr, self.udp_options).await {
                     Ok(mux) => {
                         self.udp.store(Arc::new(mux));
                         self.udp_reconnecting.store(false, Ordering::Release);
@@ -282,6 +364,9 @@ mod tests {
             max_idle_tcp_connections: 5,
             connect_timeout: Duration::from_secs(5),
             tcp_ttl: Duration::from_secs(30),
+            tcp_reap_interval: Duration::from_secs(15),
+            tcp_fast_open: false,
+            udp: UdpSocketOptions::default(),
         }
     }
 
@@ -290,8 +375,8 @@ mod tests {
         let addrs: Vec<SocketAddr> = vec!["127.0.0.1:5353".parse().unwrap(), "127.0.0.2:5353".parse().unwrap()];
         let upstreams = Upstreams::new(&addrs, test_limits()).await.unwrap();
 
-        let first = upstreams.iter().unwrap().next().unwrap();
-        let second = upstreams.iter().unwrap().next().unwrap();
+        let first = upstreams.iter(UpstreamSelection::RoundRobin).unwrap().next().unwrap();
+        let second = upstreams.iter(UpstreamSelection::RoundRobin).unwrap().next().unwrap();
 
         assert_ne!(first.addr, second.addr);
     }
@@ -307,7 +392,7 @@ mod tests {
         }
         upstreams.rebuild_healthy_cache();
 
-        let results: Vec<_> = upstreams.iter().unwrap().collect();
+        let results: Vec<_> = upstreams.iter(UpstreamSelection::RoundRobin).unwrap().collect();
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].addr, addrs[1]);
     }
@@ -324,7 +409,70 @@ mod tests {
         }
         upstreams.rebuild_healthy_cache();
 
-        let results: Vec<_> = upstreams.iter().unwrap().collect();
+        let results: Vec<_> = upstreams.iter(UpstreamSelection::RoundRobin).unwrap().collect();
         assert_eq!(results.len(), 2);
     }
+
+    #[tokio::test]
+    async fn latency_selection_prefers_faster_upstream() {
+        let addrs: Vec<SocketAddr> = vec!["127.0.0.1:5353".parse().unwrap(), "127.0.0.2:5353".parse().unwrap()];
+        let upstreams = Upstreams::new(&addrs, test_limits()).await.unwrap();
+
+        for _ in 0..10 {
+            upstreams.list[0].recent_latency.record(Duration::from_millis(10));
+            upstreams.list[1].recent_latency.record(Duration::from_millis(100));
+        }
+
+        let mut picks = [0usize; 2];
+        for _ in 0..2000 {
+            let first = upstreams.iter(UpstreamSelection::Latency).unwrap().next().unwrap();
+            picks[if first.addr == addrs[0] { 0 } else { 1 }] += 1;
+        }
+
+        // roughly 10:1, the slower upstream is still probed.
+        assert!(picks[0] > picks[1] * 4, "unexpected picks {picks:?}");
+        assert!(picks[1] > 0, "slower upstream was never picked");
+    }
+
+    #[tokio::test]
+    async fn latency_selection_probes_upstreams_without_samples() {
+        let addrs: Vec<SocketAddr> = vec!["127.0.0.1:5353".parse().unwrap(), "127.0.0.2:5353".parse().unwrap()];
+        let upstreams = Upstreams::new(&addrs, test_limits()).await.unwrap();
+
+        // without samples it falls back to round robin.
+        let first = upstreams.iter(UpstreamSelection::Latency).unwrap().next().unwrap();
+        let second = upstreams.iter(UpstreamSelection::Latency).unwrap().next().unwrap();
+        assert_ne!(first.addr, second.addr);
+
+        upstreams.list[0].recent_latency.record(Duration::from_millis(10));
+        let picked_new = (0..200)
+            .filter(|_| upstreams.iter(UpstreamSelection::Latency).unwrap().next().unwrap().addr == addrs[1])
+            .count();
+        assert!(picked_new > 0);
+    }
+
+    #[tokio::test]
+    async fn latency_selection_moves_away_from_upstream_that_became_slow() {
+        let addrs: Vec<SocketAddr> = vec!["127.0.0.1:5353".parse().unwrap(), "127.0.0.2:5353".parse().unwrap()];
+        let upstreams = Upstreams::new(&addrs, test_limits()).await.unwrap();
+
+        // a long history of the first upstream being fast.
+        for _ in 0..1000 {
+            upstreams.list[0].recent_latency.record(Duration::from_millis(10));
+        }
+        upstreams.list[1].recent_latency.record(Duration::from_millis(100));
+
+        // then it slows down and times out.
+        for _ in 0..30 {
+            upstreams.list[0].recent_latency.record(Duration::from_millis(500));
+        }
+
+        let mut picks = [0usize; 2];
+        for _ in 0..2000 {
+            let first = upstreams.iter(UpstreamSelection::Latency).unwrap().next().unwrap();
+            picks[if first.addr == addrs[0] { 0 } else { 1 }] += 1;
+        }
+
+        assert!(picks[1] > picks[0] * 2, "unexpected picks {picks:?}");
+    }
 }
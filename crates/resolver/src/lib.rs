@@ -1,6 +1,6 @@
 use async_trait::async_trait;
 use reso_context::{DnsRequestCtx, DnsResponse, ErrorType};
-use reso_dns::DnsResponseCode;
+use reso_dns::{DnsResponseCode, message::ExtendedDnsErrorInfoCode};
 use thiserror::Error;
 
 /// Trait for DNS resolvers that can resolve DNS requests.
@@ -12,7 +12,7 @@ pub trait DnsResolver<G: Send + Sync, L> {
 pub type DynResolver<G, L> = dyn DnsResolver<G, L> + Send + Sync;
 
 /// Error type for DNS resolvers
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum ResolveError {
     #[error("request timed out")]
     Timeout,
@@ -26,6 +26,18 @@ pub enum ResolveError {
     #[error("malformed response: {0}")]
     MalformedResponse(String),
 
+    /// The upstream answered with an error response code.
+    #[error("upstream responded with {0:?}")]
+    Upstream(DnsResponseCode),
+
+    /// No upstream gave a usable answer.
+    #[error("all upstreams failed")]
+    NoReachableUpstream,
+
+    /// The query has RD unset, so it may only be answered from what is known locally.
+    #[error("recursion not desired")]
+    RecursionNotDesired,
+
     #[error("{0}")]
     Other(String),
 }
@@ -37,19 +49,40 @@ impl ResolveError {
             ResolveError::InvalidRequest(_) => DnsResponseCode::Refused,
             ResolveError::InvalidResponse(_) => DnsResponseCode::ServerFailure,
             ResolveError::MalformedResponse(_) => DnsResponseCode::ServerFailure,
+            ResolveError::Upstream(code) => *code,
+            ResolveError::NoReachableUpstream => DnsResponseCode::ServerFailure,
+            ResolveError::RecursionNotDesired => DnsResponseCode::Refused,
             ResolveError::Other(_) => DnsResponseCode::ServerFailure,
         }
     }
 
+    /// Extended DNS error (RFC 8914) describing the cause to the client, if there is a fitting one.
+    pub fn extended_error(&self) -> Option<ExtendedDnsErrorInfoCode> {
+        match self {
+            ResolveError::NoReachableUpstream => Some(ExtendedDnsErrorInfoCode::NoReachableAuthority),
+            // RFC 8914 section 4.21.
+            ResolveError::RecursionNotDesired => Some(ExtendedDnsErrorInfoCode::NotAuthorative),
+            _ => None,
+        }
+    }
+
     pub fn error_type(&self) -> ErrorType {
         match self {
             Self::Timeout => ErrorType::Timeout,
             Self::InvalidRequest(_) => ErrorType::InvalidRequest,
             Self::InvalidResponse(_) => ErrorType::InvalidResponse,
             Self::MalformedResponse(_) => ErrorType::MalformedResponse,
-            Self::Other(_) => ErrorType::Other,
+            Self::Upstream(_) | Self::NoReachableUpstream | Self::RecursionNotDesired | Self::Other(_) => {
+                ErrorType::Other
+            }
         }
     }
 }
 
+pub mod chain;
 pub mod forwarder;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
+pub mod ptr;
+pub mod truncation;
+pub mod zone;
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use reso_context::{DnsRequestCtx, DnsResponse};
+use reso_dns::{DnsMessage, DnsResponseCode, RecordType, domain_name::DomainName};
+
+use crate::{DnsResolver, ResolveError};
+
+/// Resolver for tests that answers from a fixed set of responses.
+///
+/// Queries without a configured response are answered with SERVFAIL.
+#[derive(Debug, Default)]
+pub struct MockResolver {
+    responses: HashMap<(DomainName, RecordType), DnsMessage>,
+    error: Option<ResolveError>,
+}
+
+impl MockResolver {
+    /// Create a mock resolver without any responses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer queries for `qname` and `qtype` with `response`.
+    pub fn with_response(mut self, qname: DomainName, qtype: RecordType, response: DnsMessage) -> Self {
+        self.responses.insert((qname, qtype), response);
+        self
+    }
+
+    /// Fail every query with `error`.
+    pub fn with_error(mut self, error: ResolveError) -> Self {
+        self.error = Some(error);
+        self
+    }
+}
+
+#[async_trait]
+impl<G, L> DnsResolver<G, L> for MockResolver
+where
+    G: Send + Sync + 'static,
+    L: Send + Sync,
+{
+    async fn resolve(&self, ctx: &DnsRequestCtx<G, L>) -> Result<DnsResponse, ResolveError> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+
+        let query = ctx.message().map_err(|e| ResolveError::InvalidRequest(e.to_string()))?;
+
+        let response = query
+            .questions()
+            .first()
+            .and_then(|q| self.responses.get(&(q.qname.clone(), q.qtype)))
+            .map(|response| {
+                let mut response = response.clone();
+                response.id = query.id;
+                response
+            })
+            .unwrap_or_else(|| DnsMessage::response_from_query(query, DnsResponseCode::ServerFailure));
+
+        let bytes = response.encode().map_err(|e| ResolveError::Other(e.to_string()))?;
+
+        Ok(DnsResponse::from_parsed(bytes, response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+
+    use reso_context::RequestType;
+    use reso_dns::{ClassType, DnsMessageBuilder, DnsQuestion, DnsRecord, message::DnsRecordData};
+
+    use super::*;
+
+    fn ctx(qname: &str, id: u16) -> DnsRequestCtx<(), ()> {
+        let query = DnsMessageBuilder::new()
+            .with_id(id)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii(qname).unwrap(),
+                RecordType::A,
+                ClassType::IN,
+            ))
+            .build()
+            .encode()
+            .unwrap();
+
+        DnsRequestCtx::new(
+            Duration::from_secs(1),
+            Ipv4Addr::LOCALHOST.into(),
+            RequestType::UDP,
+            query,
+            Arc::new(()),
+            (),
+        )
+    }
+
+    fn resolver() -> MockResolver {
+        let name = DomainName::from_ascii("example.com").unwrap();
+        let response = DnsMessageBuilder::new()
+            .with_response(DnsResponseCode::NoError)
+            .add_answer(DnsRecord::new(
+                name.clone(),
+                RecordType::A,
+                ClassType::IN,
+                300,
+                DnsRecordData::Ipv4(Ipv4Addr::new(93, 184, 216, 34)),
+            ))
+            .build();
+
+        MockResolver::new().with_response(name, RecordType::A, response)
+    }
+
+    #[tokio::test]
+    async fn returns_configured_response() {
+        let response = resolver().resolve(&ctx("example.com", 7)).await.unwrap();
+        let message = response.message().unwrap();
+
+        assert_eq!(message.id, 7);
+        assert_eq!(message.response_code(), DnsResponseCode::NoError);
+        assert_eq!(
+            message.answers()[0].data,
+            DnsRecordData::Ipv4(Ipv4Addr::new(93, 184, 216, 34))
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_query_gets_servfail() {
+        let response = resolver().resolve(&ctx("other.example", 7)).await.unwrap();
+        let message = response.message().unwrap();
+
+        assert_eq!(message.id, 7);
+        assert_eq!(message.response_code(), DnsResponseCode::ServerFailure);
+        assert!(message.answers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn returns_canned_error() {
+        let resolver = resolver().with_error(ResolveError::Timeout);
+
+        let result = DnsResolver::<(), ()>::resolve(&resolver, &ctx("example.com", 7)).await;
+
+        assert!(matches!(result, Err(ResolveError::Timeout)));
+    }
+}
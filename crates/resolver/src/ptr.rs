@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use anyhow::{Context, bail};
+use async_trait::async_trait;
+use reso_context::{DnsRequestCtx, DnsResponse};
+use reso_dns::{DnsMessage, DnsRecord, DnsResponseCode, RecordType, domain_name::DomainName, message::DnsRecordData};
+
+use crate::{DnsResolver, ResolveError};
+
+/// TTL of synthesized PTR records.
+const LOCAL_PTR_TTL: u32 = 300;
+
+/// An IP network in CIDR notation, e.g. `10.0.0.0/8` or `fd00::/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpPrefix {
+    addr: IpAddr,
+    len: u8,
+}
+
+impl IpPrefix {
+    /// Whether `ip` is inside this network.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.len as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.len as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpPrefix {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (addr, len) = s.split_once('/').context("prefix is missing a length")?;
+        let addr: IpAddr = addr.parse().with_context(|| format!("invalid address in prefix {s}"))?;
+        let len: u8 = len.parse().with_context(|| format!("invalid length in prefix {s}"))?;
+
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if len > max {
+            bail!("prefix length {len} exceeds {max} in {s}");
+        }
+
+        Ok(Self { addr, len })
+    }
+}
+
+/// Resolver that answers PTR queries for addresses in local networks.
+///
+/// Mapped addresses are answered with their name. Unmapped addresses are answered with a generated
+/// `host-<address>.<domain>` name if a domain is set, and NXDOMAIN otherwise, so local addresses never reach
+/// an upstream. Queries for anything else fail, so a chain can fall through to the next resolver.
+#[derive(Debug, Default)]
+pub struct LocalPtrResolver {
+    prefixes: Vec<IpPrefix>,
+    names: HashMap<IpAddr, DomainName>,
+    domain: Option<DomainName>,
+}
+
+impl LocalPtrResolver {
+    /// Create a resolver for the given local networks and address to name map.
+    pub fn new(prefixes: Vec<IpPrefix>, names: HashMap<IpAddr, DomainName>) -> Self {
+        Self {
+            prefixes,
+            names,
+            domain: None,
+        }
+    }
+
+    /// Generate names below `domain` for local addresses that are not mapped.
+    pub fn with_domain(mut self, domain: DomainName) -> Self {
+        self.domain = Some(domain);
+        self
+    }
+
+    /// Build the answer for a query, or `None` if it is not a PTR query for a local address.
+    fn answer(&self, query: &DnsMessage) -> Option<DnsMessage> {
+        let question = query.questions().first()?;
+        if question.qtype != RecordType::PTR {
+            return None;
+        }
+
+        let ip = ip_from_reverse_name(&question.qname)?;
+        if !self.prefixes.iter().any(|prefix| prefix.contains(&ip)) {
+            return None;
+        }
+
+        let name = match (self.names.get(&ip), &self.domain) {
+            (Some(name), _) => Some(name.clone()),
+            (None, Some(domain)) => generated_name(&ip, domain),
+            (None, None) => None,
+        };
+
+        let response_code = if name.is_some() {
+            DnsResponseCode::NoError
+        } else {
+            DnsResponseCode::NxDomain
+        };
+
+        let answers = name
+            .into_iter()
+            .map(|name| {
+                DnsRecord::new(
+                    question.qname.clone(),
+                    RecordType::PTR,
+                    question.qclass,
+                    LOCAL_PTR_TTL,
+                    DnsRecordData::DomainName(name),
+                )
+            })
+            .collect();
+
+        let mut flags = DnsMessage::response_from_query(query, response_code).flags;
+        flags.authorative_answer = true;
+
+        let mut response = DnsMessage::new(query.id, flags, query.questions().to_vec(), answers, vec![], vec![]);
+        response.set_response_code(response_code);
+        Some(response)
+    }
+}
+
+/// Parse the address out of a full `in-addr.arpa` or `ip6.arpa` name.
+fn ip_from_reverse_name(name: &DomainName) -> Option<IpAddr> {
+    let labels: Vec<&[u8]> = name.label_iter().collect();
+
+    match labels.as_slice() {
+        [octets @ .., b"in-addr", b"arpa"] if octets.len() == 4 => {
+            let mut ip = [0u8; 4];
+            for (byte, label) in ip.iter_mut().zip(octets.iter().rev()) {
+                *byte = std::str::from_utf8(label).ok()?.parse().ok()?;
+            }
+            Some(IpAddr::V4(Ipv4Addr::from(ip)))
+        }
+        [nibbles @ .., b"ip6", b"arpa"] if nibbles.len() == 32 => {
+            let mut ip = 0u128;
+            for label in nibbles.iter().rev() {
+                let [nibble] = label else {
+                    return None;
+                };
+                ip = (ip << 4) | (*nibble as char).to_digit(16)? as u128;
+            }
+            Some(IpAddr::V6(Ipv6Addr::from(ip)))
+        }
+        _ => None,
+    }
+}
+
+/// Name for an unmapped address, e.g. `host-10-0-0-5.lan` for `10.0.0.5`.
+fn generated_name(ip: &IpAddr, domain: &DomainName) -> Option<DomainName> {
+    let host = ip.to_string().replace(['.', ':'], "-");
+    DomainName::from_ascii(format!("host-{}.{}", host, domain)).ok()
+}
+
+#[async_trait]
+impl<G, L> DnsResolver<G, L> for LocalPtrResolver
+where
+    G: Send + Sync + 'static,
+    L: Send + Sync,
+{
+    async fn resolve(&self, ctx: &DnsRequestCtx<G, L>) -> Result<DnsResponse, ResolveError> {
+        let query = ctx.message().map_err(|e| ResolveError::InvalidRequest(e.to_string()))?;
+
+        let response = self.answer(query).ok_or_else(|| {
+            let qname = query
+                .questions()
+                .first()
+                .map(|q| q.qname.to_string())
+                .unwrap_or_default();
+            ResolveError::InvalidRequest(format!("{} is not a local reverse name", qname))
+        })?;
+
+        let bytes = response.encode().map_err(|e| ResolveError::Other(e.to_string()))?;
+
+        Ok(DnsResponse::from_parsed(bytes, response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reso_dns::{ClassType, DnsMessageBuilder, DnsQuestion};
+
+    use super::*;
+
+    fn query(name: &str) -> DnsMessage {
+        DnsMessageBuilder::new()
+            .with_id(5)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii(name).unwrap(),
+                RecordType::PTR,
+                ClassType::IN,
+            ))
+            .build()
+    }
+
+    fn resolver() -> LocalPtrResolver {
+        LocalPtrResolver::new(
+            vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()],
+            HashMap::from([
+                ("10.0.0.5".parse().unwrap(), DomainName::from_ascii("nas.lan").unwrap()),
+                (
+                    "fd00::1".parse().unwrap(),
+                    DomainName::from_ascii("router.lan").unwrap(),
+                ),
+            ]),
+        )
+    }
+
+    fn ptr_target(response: &DnsMessage) -> &DnsRecordData {
+        &response.answers()[0].data
+    }
+
+    #[test]
+    fn parses_prefixes() {
+        let prefix: IpPrefix = "192.168.1.0/24".parse().unwrap();
+        assert!(prefix.contains(&"192.168.1.200".parse().unwrap()));
+        assert!(!prefix.contains(&"192.168.2.1".parse().unwrap()));
+        assert!(!prefix.contains(&"::1".parse().unwrap()));
+
+        let all: IpPrefix = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(&"8.8.8.8".parse().unwrap()));
+
+        assert!("10.0.0.0".parse::<IpPrefix>().is_err());
+        assert!("10.0.0.0/33".parse::<IpPrefix>().is_err());
+        assert!("fd00::/129".parse::<IpPrefix>().is_err());
+    }
+
+    #[test]
+    fn answers_mapped_local_address() {
+        let response = resolver().answer(&query("5.0.0.10.in-addr.arpa")).unwrap();
+
+        assert_eq!(response.id, 5);
+        assert!(response.flags.authorative_answer);
+        assert_eq!(response.response_code(), DnsResponseCode::NoError);
+        assert_eq!(
+            ptr_target(&response),
+            &DnsRecordData::DomainName(DomainName::from_ascii("nas.lan").unwrap())
+        );
+
+        let v6 = "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.d.f.ip6.arpa";
+        let response = resolver().answer(&query(v6)).unwrap();
+        assert_eq!(
+            ptr_target(&response),
+            &DnsRecordData::DomainName(DomainName::from_ascii("router.lan").unwrap())
+        );
+    }
+
+    #[test]
+    fn unmapped_local_address_is_nxdomain() {
+        let response = resolver().answer(&query("6.0.0.10.in-addr.arpa")).unwrap();
+
+        assert_eq!(response.response_code(), DnsResponseCode::NxDomain);
+        assert!(response.answers().is_empty());
+    }
+
+    #[test]
+    fn unmapped_local_address_gets_generated_name_with_domain() {
+        let resolver = resolver().with_domain(DomainName::from_ascii("lan").unwrap());
+
+        let response = resolver.answer(&query("6.0.0.10.in-addr.arpa")).unwrap();
+
+        assert_eq!(response.response_code(), DnsResponseCode::NoError);
+        assert_eq!(
+            ptr_target(&response),
+            &DnsRecordData::DomainName(DomainName::from_ascii("host-10-0-0-6.lan").unwrap())
+        );
+    }
+
+    #[test]
+    fn public_and_partial_names_fall_through() {
+        assert!(resolver().answer(&query("8.8.8.8.in-addr.arpa")).is_none());
+        assert!(resolver().answer(&query("10.in-addr.arpa")).is_none());
+        assert!(resolver().answer(&query("x.0.0.10.in-addr.arpa")).is_none());
+        assert!(resolver().answer(&query("example.com")).is_none());
+    }
+}
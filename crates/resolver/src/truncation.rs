@@ -0,0 +1,38 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Snapshot of [`TruncationCounters`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TruncationStats {
+    /// UDP responses that had TC set.
+    pub truncated: u64,
+    /// Queries retried over TCP after a truncated UDP response.
+    pub tcp_fallbacks: u64,
+}
+
+/// Counters of truncated UDP responses and the TCP retries they lead to.
+///
+/// Shared behind an `Arc`, so the counts outlive the resolver or server that updates them.
+#[derive(Debug, Default)]
+pub struct TruncationCounters {
+    truncated: AtomicU64,
+    tcp_fallbacks: AtomicU64,
+}
+
+impl TruncationCounters {
+    /// Count a UDP response with TC set.
+    pub fn record_truncated(&self) {
+        self.truncated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a query retried over TCP.
+    pub fn record_tcp_fallback(&self) {
+        self.tcp_fallbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> TruncationStats {
+        TruncationStats {
+            truncated: self.truncated.load(Ordering::Relaxed),
+            tcp_fallbacks: self.tcp_fallbacks.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,501 @@
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+};
+
+use anyhow::{Context, bail};
+use async_trait::async_trait;
+use reso_context::{DnsRequestCtx, DnsResponse};
+use reso_dns::{
+    ClassType, DnsMessage, DnsRecord, DnsResponseCode, RecordType, domain_name::DomainName, message::DnsRecordData,
+};
+
+use crate::{DnsResolver, ResolveError};
+
+/// TTL used for records when the zone has no `$TTL` directive.
+const DEFAULT_TTL: u32 = 3600;
+
+/// Records parsed from a zone file.
+#[derive(Debug, Clone, Default)]
+pub struct Zone {
+    /// Name of the zone, taken from the SOA record or the first `$ORIGIN`.
+    apex: Option<DomainName>,
+    records: Vec<DnsRecord>,
+}
+
+impl Zone {
+    /// Parse a zone file in the RFC 1035 master file format.
+    ///
+    /// Supports the `$ORIGIN` and `$TTL` directives, parentheses and comments, and A, AAAA, CNAME, NS, PTR, MX,
+    /// SRV, TXT and SOA records.
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let mut zone = Zone::default();
+        let mut origin: Option<DomainName> = None;
+        let mut default_ttl = DEFAULT_TTL;
+        let mut owner: Option<DomainName> = None;
+
+        for (line_number, line, inherits_owner) in logical_lines(input)? {
+            let tokens = tokenize(&line).with_context(|| format!("line {}", line_number))?;
+            let Some(first) = tokens.first() else {
+                continue;
+            };
+
+            match first.to_ascii_uppercase().as_str() {
+                "$ORIGIN" => {
+                    let name = tokens.get(1).context("missing $ORIGIN name")?;
+                    let name = parse_name(name, origin.as_ref()).with_context(|| format!("line {}", line_number))?;
+                    zone.apex.get_or_insert_with(|| name.clone());
+                    origin = Some(name);
+                    continue;
+                }
+                "$TTL" => {
+                    default_ttl = tokens
+                        .get(1)
+                        .and_then(|t| t.parse().ok())
+                        .with_context(|| format!("line {}: invalid $TTL", line_number))?;
+                    continue;
+                }
+                directive if directive.starts_with('$') => {
+                    bail!("line {}: unsupported directive {}", line_number, first)
+                }
+                _ => {}
+            }
+
+            let mut rest = tokens.as_slice();
+            if !inherits_owner {
+                owner = Some(parse_name(&rest[0], origin.as_ref()).with_context(|| format!("line {}", line_number))?);
+                rest = &rest[1..];
+            }
+            let owner = owner
+                .clone()
+                .with_context(|| format!("line {}: record without an owner name", line_number))?;
+
+            let mut ttl = default_ttl;
+            while let Some(token) = rest.first() {
+                if let Ok(value) = token.parse() {
+                    ttl = value;
+                } else if token.eq_ignore_ascii_case("IN") {
+                } else {
+                    break;
+                }
+                rest = &rest[1..];
+            }
+
+            let (record_type, rdata) = rest
+                .split_first()
+                .with_context(|| format!("line {}: missing record type", line_number))?;
+            let record_type = record_type
+                .parse::<RecordType>()
+                .map_err(|_| anyhow::anyhow!("line {}: unknown record type {}", line_number, record_type))?;
+            let data = parse_rdata(record_type, rdata, origin.as_ref())
+                .with_context(|| format!("line {}: invalid {:?} record", line_number, record_type))?;
+
+            if record_type == RecordType::SOA {
+                zone.apex = Some(owner.clone());
+            }
+
+            zone.records
+                .push(DnsRecord::new(owner, record_type, ClassType::IN, ttl, data));
+        }
+
+        Ok(zone)
+    }
+}
+
+/// Join the lines of a zone file that are grouped by parentheses and strip comments.
+///
+/// Returns the line number each entry starts at, the entry, and whether it starts with whitespace, in which case it
+/// uses the owner name of the previous record.
+fn logical_lines(input: &str) -> anyhow::Result<Vec<(usize, String, bool)>> {
+    let mut lines = Vec::new();
+    let mut current: Option<(usize, String, bool)> = None;
+    let mut depth = 0usize;
+
+    for (index, raw) in input.lines().enumerate() {
+        let mut line = String::with_capacity(raw.len());
+        let mut quoted = false;
+        let mut escaped = false;
+
+        for c in raw.chars() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = !quoted,
+                ';' if !quoted => break,
+                '(' if !quoted => {
+                    depth += 1;
+                    line.push(' ');
+                    continue;
+                }
+                ')' if !quoted => {
+                    depth = depth
+                        .checked_sub(1)
+                        .with_context(|| format!("line {}: unbalanced parentheses", index + 1))?;
+                    line.push(' ');
+                    continue;
+                }
+                _ => {}
+            }
+            line.push(c);
+        }
+
+        let entry = current.get_or_insert_with(|| {
+            let inherits_owner = raw.starts_with(char::is_whitespace);
+            (index + 1, String::new(), inherits_owner)
+        });
+        entry.1.push(' ');
+        entry.1.push_str(&line);
+
+        if depth == 0
+            && let Some(entry) = current.take()
+            && !entry.1.trim().is_empty()
+        {
+            lines.push(entry);
+        }
+    }
+
+    if depth != 0 {
+        bail!("unbalanced parentheses at end of file");
+    }
+
+    Ok(lines)
+}
+
+/// Split an entry on whitespace, keeping quoted strings together with their quotes.
+fn tokenize(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut token = String::new();
+        if c == '"' {
+            token.push(chars.next().unwrap_or_default());
+            loop {
+                match chars.next() {
+                    Some('\\') => token.extend(chars.next()),
+                    Some('"') => {
+                        token.push('"');
+                        break;
+                    }
+                    Some(c) => token.push(c),
+                    None => bail!("unterminated quoted string"),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+        }
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Parse a possibly relative name against the current origin.
+fn parse_name(token: &str, origin: Option<&DomainName>) -> anyhow::Result<DomainName> {
+    if token.ends_with('.') {
+        return Ok(DomainName::from_ascii(token)?);
+    }
+
+    let origin = origin.with_context(|| format!("relative name {} without $ORIGIN", token))?;
+    if token == "@" {
+        return Ok(origin.clone());
+    }
+    if origin.is_root() {
+        Ok(DomainName::from_ascii(token)?)
+    } else {
+        Ok(DomainName::from_ascii(format!("{}.{}", token, origin))?)
+    }
+}
+
+fn parse_rdata(
+    record_type: RecordType,
+    rdata: &[String],
+    origin: Option<&DomainName>,
+) -> anyhow::Result<DnsRecordData> {
+    let field = |i: usize| rdata.get(i).map(String::as_str).context("missing field");
+    let number = |i: usize| -> anyhow::Result<u32> { Ok(field(i)?.parse()?) };
+    let short = |i: usize| -> anyhow::Result<u16> { Ok(field(i)?.parse()?) };
+    let name = |i: usize| parse_name(field(i)?, origin);
+
+    Ok(match record_type {
+        RecordType::A => DnsRecordData::Ipv4(field(0)?.parse::<Ipv4Addr>()?),
+        RecordType::AAAA => DnsRecordData::Ipv6(field(0)?.parse::<Ipv6Addr>()?),
+        RecordType::CNAME | RecordType::NS | RecordType::PTR => DnsRecordData::DomainName(name(0)?),
+        RecordType::MX => DnsRecordData::MX {
+            priority: short(0)?,
+            host: name(1)?,
+        },
+        RecordType::SRV => DnsRecordData::SRV {
+            priority: short(0)?,
+            weight: short(1)?,
+            port: short(2)?,
+            target: name(3)?,
+        },
+        RecordType::TXT => {
+            if rdata.is_empty() {
+                bail!("missing text");
+            }
+            let chunks = rdata
+                .iter()
+                .map(|t| {
+                    let t = t.strip_prefix('"').and_then(|t| t.strip_suffix('"')).unwrap_or(t);
+                    Box::from(t)
+                })
+                .collect();
+            DnsRecordData::Text(chunks)
+        }
+        RecordType::SOA => DnsRecordData::SOA {
+            mname: name(0)?,
+            rname: name(1)?,
+            serial: number(2)?,
+            refresh: number(3)?,
+            retry: number(4)?,
+            expire: number(5)?,
+            minimum: number(6)?,
+        },
+        _ => bail!("unsupported record type"),
+    })
+}
+
+/// Resolver that answers authoritatively from zone files.
+///
+/// Queries outside of the loaded zones fail, so a chain can fall through to the next resolver.
+#[derive(Debug, Default)]
+pub struct StaticResolver {
+    records: HashMap<DomainName, Vec<DnsRecord>>,
+    /// Apex of each zone with its SOA record, if the zone has one.
+    apexes: Vec<(DomainName, Option<DnsRecord>)>,
+}
+
+impl StaticResolver {
+    /// Create a resolver from parsed zones.
+    pub fn new(zones: Vec<Zone>) -> Self {
+        let mut resolver = Self::default();
+
+        for zone in zones {
+            if let Some(apex) = zone.apex {
+                let soa = zone
+                    .records
+                    .iter()
+                    .find(|r| r.record_type == RecordType::SOA && r.name == apex)
+                    .cloned();
+                resolver.apexes.push((apex, soa));
+            }
+            for record in zone.records {
+                resolver.records.entry(record.name.clone()).or_default().push(record);
+            }
+        }
+
+        resolver
+    }
+
+    /// Load and parse zone files.
+    pub fn load(paths: &[PathBuf]) -> anyhow::Result<Self> {
+        let zones = paths
+            .iter()
+            .map(|path| {
+                let input = std::fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+                Zone::parse(&input).with_context(|| format!("failed to parse {:?}", path))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self::new(zones))
+    }
+
+    /// Build the authoritative answer for a query, or `None` if the name is not in any zone.
+    fn answer(&self, query: &DnsMessage) -> Option<DnsMessage> {
+        let question = query.questions().first()?;
+        let qname = &question.qname;
+
+        // The most specific zone containing the name.
+        let zone = self
+            .apexes
+            .iter()
+            .filter(|(apex, _)| qname.is_subdomain_of(apex))
+            .max_by_key(|(apex, _)| apex.label_count());
+
+        let records = self.records.get(qname);
+        if records.is_none() && zone.is_none() {
+            return None;
+        }
+
+        let mut answers: Vec<DnsRecord> = records
+            .into_iter()
+            .flatten()
+            .filter(|r| r.record_type == question.qtype || question.qtype == RecordType::ANY)
+            .cloned()
+            .collect();
+
+        if answers.is_empty() {
+            answers = records
+                .into_iter()
+                .flatten()
+                .filter(|r| r.record_type == RecordType::CNAME)
+                .cloned()
+                .collect();
+        }
+
+        // A name exists if it owns records or has descendants that do (an empty non-terminal).
+        let exists = records.is_some() || self.records.keys().any(|name| name.is_subdomain_of(qname));
+        let response_code = if exists {
+            DnsResponseCode::NoError
+        } else {
+            DnsResponseCode::NxDomain
+        };
+
+        let authority = match zone {
+            Some((_, Some(soa))) if answers.is_empty() => vec![soa.clone()],
+            _ => vec![],
+        };
+
+        let mut flags = DnsMessage::response_from_query(query, response_code).flags;
+        flags.authorative_answer = true;
+
+        let mut response = DnsMessage::new(query.id, flags, query.questions().to_vec(), answers, authority, vec![]);
+        response.set_response_code(response_code);
+        Some(response)
+    }
+}
+
+#[async_trait]
+impl<G, L> DnsResolver<G, L> for StaticResolver
+where
+    G: Send + Sync + 'static,
+    L: Send + Sync,
+{
+    async fn resolve(&self, ctx: &DnsRequestCtx<G, L>) -> Result<DnsResponse, ResolveError> {
+        let query = ctx.message().map_err(|e| ResolveError::InvalidRequest(e.to_string()))?;
+
+        let response = self.answer(query).ok_or_else(|| {
+            let qname = query
+                .questions()
+                .first()
+                .map(|q| q.qname.to_string())
+                .unwrap_or_default();
+            ResolveError::InvalidRequest(format!("{} is not in a static zone", qname))
+        })?;
+
+        let bytes = response.encode().map_err(|e| ResolveError::Other(e.to_string()))?;
+
+        Ok(DnsResponse::from_parsed(bytes, response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reso_dns::{DnsMessageBuilder, DnsQuestion};
+
+    use super::*;
+
+    const ZONE: &str = r#"
+$ORIGIN example.com.
+$TTL 300
+@   IN  SOA ns1 hostmaster (
+        2024010101 ; serial
+        7200 3600 1209600 300 )
+    IN  NS  ns1
+ns1     A   192.0.2.53
+www 60  IN  A   192.0.2.1
+        IN  AAAA 2001:db8::1
+alias   CNAME www
+mail    MX  10 mx.example.net.
+txt     TXT "v=spf1 -all" "semi;colon"
+_sip._udp.deep  SRV 10 60 5060 sip
+"#;
+
+    fn query(name: &str, qtype: RecordType) -> DnsMessage {
+        DnsMessageBuilder::new()
+            .with_id(9)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii(name).unwrap(),
+                qtype,
+                ClassType::IN,
+            ))
+            .build()
+    }
+
+    fn resolver() -> StaticResolver {
+        StaticResolver::new(vec![Zone::parse(ZONE).unwrap()])
+    }
+
+    #[test]
+    fn parses_zone_file() {
+        let zone = Zone::parse(ZONE).unwrap();
+
+        assert_eq!(zone.apex, Some(DomainName::from_ascii("example.com").unwrap()));
+        assert_eq!(zone.records.len(), 9);
+
+        let soa = &zone.records[0];
+        assert_eq!(soa.ttl, 300);
+        assert_eq!(
+            soa.data.to_string(),
+            "ns1.example.com. hostmaster.example.com. 2024010101 7200 3600 1209600 300"
+        );
+
+        // the AAAA record inherits the owner of the line above it, but not its TTL.
+        let aaaa = &zone.records[4];
+        assert_eq!(&*aaaa.name, "www.example.com");
+        assert_eq!(aaaa.ttl, 300);
+        assert_eq!(zone.records[3].ttl, 60);
+
+        assert_eq!(zone.records[7].data.to_string(), "\"v=spf1 -all\" \"semi;colon\"");
+    }
+
+    #[test]
+    fn rejects_invalid_zone_files() {
+        assert!(Zone::parse("www A 192.0.2.1").is_err());
+        assert!(Zone::parse("www.example.com. A not-an-address").is_err());
+        assert!(Zone::parse("www.example.com. HINFO x86 linux").is_err());
+        assert!(Zone::parse("@ SOA ( ns1.example.com. host.example.com. 1 2 3 4 5").is_err());
+    }
+
+    #[test]
+    fn answers_from_zone() {
+        let response = resolver().answer(&query("www.example.com", RecordType::A)).unwrap();
+
+        assert_eq!(response.id, 9);
+        assert!(response.flags.authorative_answer);
+        assert_eq!(response.response_code(), DnsResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers()[0].data,
+            DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1))
+        );
+
+        let response = resolver().answer(&query("alias.example.com", RecordType::A)).unwrap();
+        assert_eq!(response.answers()[0].record_type(), RecordType::CNAME);
+    }
+
+    #[test]
+    fn negative_answers_carry_soa() {
+        let nodata = resolver().answer(&query("www.example.com", RecordType::MX)).unwrap();
+        assert_eq!(nodata.response_code(), DnsResponseCode::NoError);
+        assert!(nodata.answers().is_empty());
+        assert_eq!(nodata.authority_records()[0].record_type(), RecordType::SOA);
+
+        let nxdomain = resolver().answer(&query("missing.example.com", RecordType::A)).unwrap();
+        assert_eq!(nxdomain.response_code(), DnsResponseCode::NxDomain);
+        assert_eq!(nxdomain.authority_records()[0].record_type(), RecordType::SOA);
+
+        // an empty non-terminal exists, even though it owns no records.
+        let ent = resolver().answer(&query("deep.example.com", RecordType::A)).unwrap();
+        assert_eq!(ent.response_code(), DnsResponseCode::NoError);
+    }
+
+    #[test]
+    fn names_outside_zones_are_not_answered() {
+        assert!(resolver().answer(&query("example.org", RecordType::A)).is_none());
+    }
+}
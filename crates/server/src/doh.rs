@@ -1,6 +1,13 @@
 use std::{fs, io};
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
 
 use arc_swap::ArcSwap;
 use base64::{Engine, engine::GeneralPurpose};
@@ -8,15 +15,18 @@ use bytes::Bytes;
 use http_body_util::{BodyExt, Full};
 use hyper::server::conn::http2;
 use hyper::{Method, Request, Response, body::Incoming, server::conn::http1, service::service_fn};
-use hyper_util::rt::TokioIo;
+use hyper_util::rt::{TokioIo, TokioTimer};
 use reso_context::{DnsRequestCtx, RequestType};
-use reso_dns::{DnsMessage, DnsMessageBuilder};
-use rustls::ServerConfig;
+use reso_dns::DnsMessage;
+use rustls::ServerConfig as TlsServerConfig;
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
 use tokio_rustls::TlsAcceptor;
 
-use crate::{ServerError, ServerState, handle_request};
+use crate::json::{DNS_JSON_CONTENT_TYPE, DnsJsonResponse, build_json_query};
+use crate::{ServerConfig, ServerError, ServerState, error_response, handle_request};
 
 type Req = Request<Incoming>;
 type Res = Response<Full<Bytes>>;
@@ -57,6 +67,7 @@ pub async fn run_doh<G, L>(
     config: DohConfig,
     bind_addr: SocketAddr,
     state: Arc<ArcSwap<ServerState<G, L>>>,
+    server_config: &ServerConfig,
 ) -> anyhow::Result<()>
 where
     G: Send + Sync + 'static,
@@ -70,14 +81,17 @@ where
     let addr = SocketAddr::from((bind_addr.ip(), config.port));
     let listener = TcpListener::bind(addr).await?;
 
-    let mut server_config = ServerConfig::builder()
+    let mut tls_config = TlsServerConfig::builder()
         .with_no_client_auth()
         .with_single_cert(certs, key)
         .map_err(|e| error(e.to_string()))?;
 
-    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
 
-    let tls_acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let tls_acceptor = TlsAcceptor::from(Arc::new(tls_config));
+
+    // bounds the number of concurrently processed requests, requests beyond the limit get a 503.
+    let permits = Arc::new(Semaphore::new(server_config.max_concurrent_requests));
 
     tracing::info!("DOH listening on {}", addr);
 
@@ -99,25 +113,118 @@ where
         let io = TokioIo::new(tls_stream);
 
         let state = state.load_full();
+        let permits = permits.clone();
+        let idle_timeout = server_config.tcp_idle_timeout;
+        let request_timeout = server_config.doh_timeout;
 
         tokio::task::spawn(async move {
-            let svc = service_fn(move |req: Req| handle_req(req, client, state.clone()));
+            let activity = Arc::new(ConnectionActivity::new());
+            let svc_activity = activity.clone();
+            let svc = service_fn(move |req: Req| {
+                let request = svc_activity.begin();
+                let response = handle_req(req, client, state.clone(), permits.clone(), request_timeout);
+                async move {
+                    let _request = request;
+                    response.await
+                }
+            });
 
             if http2 {
-                // HTTP/2
-                if let Err(e) = http2::Builder::new(TokioExecutor).serve_connection(io, svc).await {
-                    tracing::error!("h2 conn error: {e}");
+                // HTTP/2, hyper has no idle timeout for it so the connection is shut down once it is idle.
+                let conn = http2::Builder::new(TokioExecutor)
+                    .timer(TokioTimer::new())
+                    .serve_connection(io, svc);
+                tokio::pin!(conn);
+
+                let mut shutting_down = false;
+                loop {
+                    tokio::select! {
+                        result = conn.as_mut() => {
+                            if let Err(e) = result {
+                                tracing::error!("h2 conn error: {e}");
+                            }
+                            break;
+                        }
+                        _ = activity.idle(idle_timeout), if !shutting_down => {
+                            shutting_down = true;
+                            conn.as_mut().graceful_shutdown();
+                        }
+                    }
                 }
             } else {
                 // HTTP/1.1
-                if let Err(e) = http1::Builder::new().serve_connection(io, svc).await {
+                if let Err(e) = http1::Builder::new()
+                    .timer(TokioTimer::new())
+                    .header_read_timeout(idle_timeout)
+                    .serve_connection(io, svc)
+                    .await
+                {
                     tracing::error!("h1 conn error: {e}");
                 }
             }
         });
     }
 }
-async fn handle_req<G, L>(req: Req, addr: SocketAddr, state: Arc<ServerState<G, L>>) -> anyhow::Result<Res>
+
+/// Requests in flight on a connection and when the last one finished, to close connections that sit idle.
+struct ConnectionActivity {
+    opened: Instant,
+    in_flight: AtomicUsize,
+    /// Microseconds after `opened` at which the last request finished.
+    last_finished_us: AtomicU64,
+}
+
+/// A request in flight on a connection, finished when dropped.
+struct ActiveRequest(Arc<ConnectionActivity>);
+
+impl ConnectionActivity {
+    fn new() -> Self {
+        Self {
+            opened: Instant::now(),
+            in_flight: AtomicUsize::new(0),
+            last_finished_us: AtomicU64::new(0),
+        }
+    }
+
+    /// Mark a request as in flight until the returned guard is dropped.
+    fn begin(self: &Arc<Self>) -> ActiveRequest {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        ActiveRequest(self.clone())
+    }
+
+    /// Resolve once no request has been in flight for `timeout`.
+    async fn idle(&self, timeout: Duration) {
+        loop {
+            let deadline = if self.in_flight.load(Ordering::Relaxed) > 0 {
+                Instant::now() + timeout
+            } else {
+                self.opened + Duration::from_micros(self.last_finished_us.load(Ordering::Relaxed)) + timeout
+            };
+
+            if deadline <= Instant::now() && self.in_flight.load(Ordering::Relaxed) == 0 {
+                return;
+            }
+
+            tokio::time::sleep_until(deadline).await;
+        }
+    }
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        let elapsed = self.0.opened.elapsed().as_micros() as u64;
+        self.0.last_finished_us.fetch_max(elapsed, Ordering::Relaxed);
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+async fn handle_req<G, L>(
+    req: Req,
+    addr: SocketAddr,
+    state: Arc<ServerState<G, L>>,
+    permits: Arc<Semaphore>,
+    request_timeout: Option<Duration>,
+) -> anyhow::Result<Res>
 where
     G: Send + Sync + 'static,
     L: Send + Sync + Default + 'static,
@@ -126,31 +233,36 @@ where
         return Ok(Response::builder().status(404).body(Full::new(Bytes::new()))?);
     }
 
+    let Ok(_permit) = permits.try_acquire() else {
+        tracing::debug!(client = %addr.ip(), transport = ?RequestType::DOH, "request limit reached, rejecting request");
+        return Ok(Response::builder().status(503).body(Full::new(Bytes::new()))?);
+    };
+
     const MAX_RECV_SIZE: usize = 1232;
 
-    let bytes = match *req.method() {
+    let (bytes, format) = match *req.method() {
         Method::GET => match extract_bytes_from_get(req).await {
             Ok(b) => b,
             Err(e) => {
-                tracing::error!("failed to handle DOH GET request: {e:?}");
+                tracing::error!(client = %addr.ip(), transport = ?RequestType::DOH, error = ?e, "failed to handle GET request");
                 return Ok(Response::builder().status(400).body(Full::new(Bytes::new()))?);
             }
         },
         Method::POST => match extract_bytes_from_post(req, MAX_RECV_SIZE).await {
-            Ok(b) => b,
+            Ok(b) => (b, ResponseFormat::Wire),
             Err(e) => {
-                tracing::error!("failed to handle DOH POST request: {e:?}");
+                tracing::error!(client = %addr.ip(), transport = ?RequestType::DOH, error = ?e, "failed to handle POST request");
                 return Ok(Response::builder().status(400).body(Full::new(Bytes::new()))?);
             }
         },
         _ => {
-            tracing::error!("unsupported DOH method: {}", req.method());
+            tracing::error!(client = %addr.ip(), transport = ?RequestType::DOH, method = %req.method(), "unsupported method");
             return Ok(Response::builder().status(405).body(Full::new(Bytes::new()))?);
         }
     };
 
     let mut ctx = DnsRequestCtx::new(
-        state.timeout,
+        request_timeout.unwrap_or(state.timeout),
         addr.ip(),
         RequestType::DOH,
         bytes,
@@ -161,16 +273,26 @@ where
     let response = handle_request(&mut ctx, state.clone()).await;
 
     match response {
-        Ok(resp) => Ok(Response::builder()
-            .status(200)
-            .header("Content-Type", "application/dns-message")
-            .body(Full::new(resp.bytes()))?),
+        Ok(resp) => match format {
+            ResponseFormat::Wire => Ok(Response::builder()
+                .status(200)
+                .header("Content-Type", "application/dns-message")
+                .body(Full::new(resp.bytes()))?),
+            ResponseFormat::Json => json_response(resp.message()?),
+        },
+        Err(ServerError::Ignored) => Ok(Response::builder().status(400).body(Full::new(Bytes::new()))?),
         Err(e) => {
             let resp = match ctx.message() {
-                Ok(m) => Response::builder()
-                    .status(200)
-                    .header("Content-Type", "application/dns-message")
-                    .body(Full::new(create_error_message(m, &e)?))?,
+                Ok(m) => {
+                    let error_message = create_error_message(m, &e);
+                    match format {
+                        ResponseFormat::Wire => Response::builder()
+                            .status(200)
+                            .header("Content-Type", "application/dns-message")
+                            .body(Full::new(error_message.encode()?))?,
+                        ResponseFormat::Json => json_response(&error_message)?,
+                    }
+                }
                 Err(_) => Response::builder().status(500).body(Full::new(Bytes::new()))?,
             };
 
@@ -179,7 +301,24 @@ where
     }
 }
 
-async fn extract_bytes_from_get(req: Req) -> anyhow::Result<Bytes> {
+/// Format of the DoH response body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResponseFormat {
+    /// `application/dns-message`
+    Wire,
+    /// `application/dns-json`
+    Json,
+}
+
+fn json_response(message: &DnsMessage) -> anyhow::Result<Res> {
+    let body = serde_json::to_vec(&DnsJsonResponse::from(message))?;
+    Ok(Response::builder()
+        .status(200)
+        .header("Content-Type", DNS_JSON_CONTENT_TYPE)
+        .body(Full::new(Bytes::from(body)))?)
+}
+
+async fn extract_bytes_from_get(req: Req) -> anyhow::Result<(Bytes, ResponseFormat)> {
     let query_pairs = req.uri().query().map(|v| {
         url::form_urlencoded::parse(v.as_bytes())
             .into_owned()
@@ -190,11 +329,16 @@ async fn extract_bytes_from_get(req: Req) -> anyhow::Result<Bytes> {
         let doh_param = pairs.iter().find(|(k, _)| k == "dns");
         if let Some((_, v)) = doh_param {
             let decoded = BASE64_ENGINE.decode(v)?;
-            return Ok(Bytes::from(decoded));
+            return Ok((Bytes::from(decoded), ResponseFormat::Wire));
+        }
+
+        // JSON format queries are identified by the `name` parameter.
+        if pairs.iter().any(|(k, _)| k == "name") {
+            return Ok((build_json_query(&pairs)?, ResponseFormat::Json));
         }
     }
 
-    Err(anyhow::anyhow!("no 'dns' query parameter found"))
+    Err(anyhow::anyhow!("no 'dns' or 'name' query parameter found"))
 }
 
 async fn extract_bytes_from_post(req: Req, max_size: usize) -> anyhow::Result<Bytes> {
@@ -273,12 +417,33 @@ fn error(err: String) -> io::Error {
     io::Error::other(err)
 }
 
-fn create_error_message(message: &DnsMessage, error: &ServerError) -> anyhow::Result<Bytes> {
-    let payload = DnsMessageBuilder::new()
-        .with_id(message.id)
-        .with_questions(message.questions().to_vec())
-        .with_response(error.response_code())
-        .build()
-        .encode()?;
-    Ok(payload)
+fn create_error_message(message: &DnsMessage, error: &ServerError) -> DnsMessage {
+    error_response(message, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connection_is_idle_after_timeout_without_requests() {
+        let activity = Arc::new(ConnectionActivity::new());
+        let timeout = Duration::from_millis(100);
+
+        let started = Instant::now();
+        let request = activity.begin();
+        // a request in flight keeps the connection busy.
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), activity.idle(timeout))
+                .await
+                .is_err()
+        );
+
+        drop(request);
+        let finished = Instant::now();
+        activity.idle(timeout).await;
+
+        assert!(finished.elapsed() >= timeout - Duration::from_millis(1));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
 }
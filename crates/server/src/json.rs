@@ -0,0 +1,288 @@
+use std::net::IpAddr;
+
+use bytes::Bytes;
+use reso_dns::{
+    ClassType, DnsFlags, DnsMessage, DnsMessageBuilder, DnsQuestion, DnsRecord, Edns, EdnsOption, RecordType,
+    domain_name::DomainName,
+    message::{ClientSubnet, EdnsOptionCode, EdnsOptionData},
+};
+use serde::Serialize;
+
+/// Content type of the JSON DoH format.
+pub const DNS_JSON_CONTENT_TYPE: &str = "application/dns-json";
+
+/// JSON representation of a DNS response, compatible with the `application/dns-json` format.
+#[derive(Debug, Serialize)]
+pub struct DnsJsonResponse {
+    #[serde(rename = "Status")]
+    pub status: u16,
+    #[serde(rename = "TC")]
+    pub truncated: bool,
+    #[serde(rename = "RD")]
+    pub recursion_desired: bool,
+    #[serde(rename = "RA")]
+    pub recursion_available: bool,
+    #[serde(rename = "AD")]
+    pub authentic_data: bool,
+    #[serde(rename = "CD")]
+    pub checking_disabled: bool,
+    #[serde(rename = "Question")]
+    pub question: Vec<DnsJsonQuestion>,
+    #[serde(rename = "Answer", skip_serializing_if = "Vec::is_empty")]
+    pub answer: Vec<DnsJsonRecord>,
+    #[serde(rename = "Authority", skip_serializing_if = "Vec::is_empty")]
+    pub authority: Vec<DnsJsonRecord>,
+    #[serde(rename = "Comment", skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edns_client_subnet: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DnsJsonQuestion {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub qtype: u16,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DnsJsonRecord {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub rtype: u16,
+    #[serde(rename = "TTL")]
+    pub ttl: u32,
+    pub data: String,
+}
+
+impl From<&DnsMessage> for DnsJsonResponse {
+    fn from(message: &DnsMessage) -> Self {
+        let options = message
+            .edns()
+            .as_ref()
+            .map(|e| e.options.as_slice())
+            .unwrap_or_default();
+
+        let comment = options.iter().find_map(|opt| match &opt.data {
+            Some(EdnsOptionData::ExtendedError { info_code, extra_text }) => Some(match extra_text {
+                Some(text) => format!("EDE({}): {:?} ({})", info_code.to_u16(), info_code, text),
+                None => format!("EDE({}): {:?}", info_code.to_u16(), info_code),
+            }),
+            _ => None,
+        });
+
+        let edns_client_subnet = options.iter().find_map(|opt| match &opt.data {
+            Some(EdnsOptionData::ClientSubnet(cs)) => Some(format_client_subnet(cs)),
+            _ => None,
+        });
+
+        Self {
+            status: message.response_code().to_u16(),
+            truncated: message.flags.truncated,
+            recursion_desired: message.flags.recursion_desired,
+            recursion_available: message.flags.recursion_available,
+            authentic_data: message.flags.authentic_data,
+            checking_disabled: message.flags.checking_disabled,
+            question: message
+                .questions()
+                .iter()
+                .map(|q| DnsJsonQuestion {
+                    name: q.qname.to_fqdn(),
+                    qtype: q.qtype.to_u16(),
+                })
+                .collect(),
+            answer: message.answers().iter().map(DnsJsonRecord::from).collect(),
+            authority: message.authority_records().iter().map(DnsJsonRecord::from).collect(),
+            comment,
+            edns_client_subnet,
+        }
+    }
+}
+
+impl From<&DnsRecord> for DnsJsonRecord {
+    fn from(record: &DnsRecord) -> Self {
+        Self {
+            name: record.name.to_fqdn(),
+            rtype: record.record_type.to_u16(),
+            ttl: record.ttl,
+            data: record.data.to_string(),
+        }
+    }
+}
+
+/// Build a DNS query from the JSON DoH query parameters (`name`, `type`, `cd`, `do` and `edns_client_subnet`).
+pub fn build_json_query(pairs: &[(String, String)]) -> anyhow::Result<Bytes> {
+    let param = |key: &str| pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+    let flag = |key: &str| matches!(param(key), Some("1" | "true"));
+
+    let name = param("name").ok_or_else(|| anyhow::anyhow!("no 'name' query parameter found"))?;
+    let qname = DomainName::from_user(name)?;
+    let qtype = match param("type") {
+        Some(t) => t.parse::<RecordType>()?,
+        None => RecordType::A,
+    };
+
+    let mut flags = DnsFlags::default();
+    flags.recursion_desired = true;
+    flags.checking_disabled = flag("cd");
+
+    let mut builder = DnsMessageBuilder::new()
+        .with_flags(flags)
+        .add_question(DnsQuestion::new(qname, qtype, ClassType::IN));
+
+    let client_subnet = param("edns_client_subnet").map(parse_client_subnet).transpose()?;
+
+    if flag("do") || client_subnet.is_some() {
+        let mut edns = Edns::default();
+        edns.set_do_bit(flag("do"));
+        if let Some(cs) = client_subnet {
+            edns.options.push(EdnsOption::new(
+                EdnsOptionCode::ClientSubnet,
+                EdnsOptionData::ClientSubnet(cs),
+            ));
+        }
+        builder = builder.with_edns(edns);
+    }
+
+    Ok(builder.build().encode()?)
+}
+
+/// Parse a subnet in `address[/prefix]` notation into an ECS option.
+fn parse_client_subnet(value: &str) -> anyhow::Result<ClientSubnet> {
+    let (addr, prefix) = match value.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>()?)),
+        None => (value, None),
+    };
+
+    let addr = addr.parse::<IpAddr>()?;
+    let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+
+    let source_prefix = prefix.unwrap_or(max_prefix);
+    if source_prefix > max_prefix {
+        anyhow::bail!("subnet prefix {source_prefix} exceeds {max_prefix}");
+    }
+
+    Ok(ClientSubnet::new(addr, source_prefix))
+}
+
+fn format_client_subnet(cs: &ClientSubnet) -> String {
+    let addr = match cs.family {
+        1 => {
+            let mut octets = [0u8; 4];
+            let len = cs.address.len().min(4);
+            octets[..len].copy_from_slice(&cs.address[..len]);
+            IpAddr::from(octets)
+        }
+        _ => {
+            let mut octets = [0u8; 16];
+            let len = cs.address.len().min(16);
+            octets[..len].copy_from_slice(&cs.address[..len]);
+            IpAddr::from(octets)
+        }
+    };
+    format!("{}/{}", addr, cs.scope_prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use reso_dns::{DnsResponseCode, message::DnsRecordData, message::ExtendedDnsErrorInfoCode};
+
+    use super::*;
+
+    fn question() -> DnsQuestion {
+        DnsQuestion::new(
+            DomainName::from_ascii("example.com").unwrap(),
+            RecordType::A,
+            ClassType::IN,
+        )
+    }
+
+    #[test]
+    fn test_flag_mapping() {
+        let flags = DnsFlags::new(true, Default::default(), false, true, true, true, true, false);
+        let message = DnsMessageBuilder::new()
+            .with_flags(flags)
+            .add_question(question())
+            .add_answer(DnsRecord::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+                300,
+                DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1)),
+            ))
+            .with_response(DnsResponseCode::NoError)
+            .build();
+
+        let json = serde_json::to_value(DnsJsonResponse::from(&message)).unwrap();
+
+        assert_eq!(json["Status"], 0);
+        assert_eq!(json["TC"], true);
+        assert_eq!(json["RD"], true);
+        assert_eq!(json["RA"], true);
+        assert_eq!(json["AD"], true);
+        assert_eq!(json["CD"], false);
+        assert_eq!(json["Question"][0]["name"], "example.com.");
+        assert_eq!(json["Question"][0]["type"], 1);
+        assert_eq!(json["Answer"][0]["TTL"], 300);
+        assert_eq!(json["Answer"][0]["data"], "192.0.2.1");
+        assert!(json.get("Comment").is_none());
+    }
+
+    #[test]
+    fn test_blocked_response_surfaces_ede() {
+        let mut edns = Edns::default();
+        edns.options.push(EdnsOption::new(
+            EdnsOptionCode::ExtendedDnsError,
+            EdnsOptionData::ExtendedError {
+                info_code: ExtendedDnsErrorInfoCode::Blocked,
+                extra_text: Some("blocked by reso".to_string()),
+            },
+        ));
+        let message = DnsMessageBuilder::new()
+            .add_question(question())
+            .with_edns(edns)
+            .with_response(DnsResponseCode::NxDomain)
+            .build();
+
+        let json = serde_json::to_value(DnsJsonResponse::from(&message)).unwrap();
+
+        assert_eq!(json["Status"], 3);
+        assert_eq!(json["Comment"], "EDE(15): Blocked (blocked by reso)");
+        assert!(json.get("Answer").is_none());
+    }
+
+    #[test]
+    fn test_build_json_query() {
+        let pairs = vec![
+            ("name".to_string(), "example.com".to_string()),
+            ("type".to_string(), "AAAA".to_string()),
+            ("edns_client_subnet".to_string(), "192.0.2.77/24".to_string()),
+        ];
+
+        let message = DnsMessage::decode(&build_json_query(&pairs).unwrap()).unwrap();
+
+        assert!(message.flags.recursion_desired);
+        assert_eq!(message.questions()[0].qtype, RecordType::AAAA);
+        let cs = message
+            .edns()
+            .as_ref()
+            .and_then(|e| e.options.first())
+            .and_then(|o| o.data.clone());
+        assert_eq!(
+            cs,
+            Some(EdnsOptionData::ClientSubnet(ClientSubnet {
+                family: 1,
+                source_prefix: 24,
+                scope_prefix: 0,
+                address: vec![192, 0, 2],
+            }))
+        );
+    }
+
+    #[test]
+    fn test_build_json_query_requires_name() {
+        assert!(build_json_query(&[("type".to_string(), "A".to_string())]).is_err());
+    }
+}
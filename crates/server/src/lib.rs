@@ -3,20 +3,33 @@ use std::{fmt, net::SocketAddr, sync::Arc, time::Duration};
 use arc_swap::ArcSwap;
 use doh::run_doh;
 use reso_context::{DnsMiddleware, DnsRequestCtx, DnsResponse, ErrorType};
-use reso_dns::DnsResponseCode;
-use reso_resolver::{DynResolver, ResolveError};
+use reso_dns::{
+    DnsMessage, DnsOpcode, DnsResponseCode, EdnsOption,
+    helpers::{is_response, restore_question_case},
+    message::{EdnsOptionCode, EdnsOptionData, ExtendedDnsErrorInfoCode},
+};
+use reso_resolver::{
+    DynResolver, ResolveError,
+    truncation::{TruncationCounters, TruncationStats},
+};
 use tcp::run_tcp;
-use udp::run_udp;
+use tokio::net::UdpSocket;
+use udp::{run_udp, serve_udp_socket};
 
 use crate::doh::DohConfig;
 
 mod doh;
+mod json;
 mod tcp;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 mod udp;
 
 pub enum ServerError {
     ResolveError(ResolveError),
     MiddlewareError(anyhow::Error),
+    /// The request must not be answered at all, e.g. because it is itself a response.
+    Ignored,
 }
 
 impl ServerError {
@@ -25,6 +38,15 @@ impl ServerError {
         match self {
             ServerError::ResolveError(e) => e.response_code(),
             ServerError::MiddlewareError(_) => DnsResponseCode::ServerFailure,
+            ServerError::Ignored => DnsResponseCode::FormatError,
+        }
+    }
+
+    /// Get the extended DNS error describing this error, if any.
+    pub fn extended_error(&self) -> Option<ExtendedDnsErrorInfoCode> {
+        match self {
+            ServerError::ResolveError(e) => e.extended_error(),
+            ServerError::MiddlewareError(_) | ServerError::Ignored => None,
         }
     }
 
@@ -32,7 +54,7 @@ impl ServerError {
     pub fn error_type(&self) -> ErrorType {
         match self {
             ServerError::ResolveError(e) => e.error_type(),
-            ServerError::MiddlewareError(_) => ErrorType::Other,
+            ServerError::MiddlewareError(_) | ServerError::Ignored => ErrorType::Other,
         }
     }
 }
@@ -42,12 +64,96 @@ impl fmt::Display for ServerError {
         match self {
             ServerError::ResolveError(e) => write!(f, "{}", e),
             ServerError::MiddlewareError(e) => write!(f, "{}", e),
+            ServerError::Ignored => write!(f, "request ignored"),
         }
     }
 }
 
 pub type ServerMiddlewares<G, L> = Arc<Vec<Arc<dyn DnsMiddleware<G, L> + 'static>>>;
 
+/// Smallest UDP receive size, every DNS client must be able to use 512 byte messages (RFC 1035).
+pub const MIN_RECV_SIZE: u16 = 512;
+
+/// Default UDP receive size, the EDNS buffer size recommended by DNS Flag Day 2020.
+pub const DEFAULT_RECV_SIZE: u16 = 1232;
+
+/// Transport-level configuration for the DNS server.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// Maximum number of requests processed concurrently per transport.
+    /// Requests beyond this limit are shed instead of queued.
+    pub max_concurrent_requests: usize,
+    /// Maximum number of open inbound TCP connections. Further connections wait in the listen backlog until one
+    /// closes, so a connection flood can't exhaust tasks and sockets.
+    pub max_tcp_connections: usize,
+    /// How long an inbound TCP or DoH connection may sit idle between queries before it is closed.
+    pub tcp_idle_timeout: Duration,
+    /// Maximum time to read a single TCP message once its length prefix has been received.
+    pub tcp_read_timeout: Duration,
+    /// Request timeout for UDP queries, falls back to the state's `timeout` when unset.
+    pub udp_timeout: Option<Duration>,
+    /// Request timeout for TCP queries, falls back to the state's `timeout` when unset.
+    pub tcp_timeout: Option<Duration>,
+    /// Request timeout for DoH queries, falls back to the state's `timeout` when unset.
+    pub doh_timeout: Option<Duration>,
+    /// Size of the UDP receive buffer, also advertised to clients as the EDNS UDP payload size.
+    pub recv_size: u16,
+    /// Largest UDP response sent to any client, whatever EDNS buffer size it advertises. Larger responses are
+    /// truncated so clients retry over TCP, which limits how much the server can amplify spoofed queries.
+    pub max_udp_response: Option<u16>,
+}
+
+impl ServerConfig {
+    /// Check that the config can be served.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_concurrent_requests == 0 {
+            anyhow::bail!("max_concurrent_requests must be at least 1");
+        }
+
+        if self.max_tcp_connections == 0 {
+            anyhow::bail!("max_tcp_connections must be at least 1");
+        }
+
+        if self.recv_size < MIN_RECV_SIZE {
+            anyhow::bail!(
+                "recv_size must be between {} and {}, got {}",
+                MIN_RECV_SIZE,
+                u16::MAX,
+                self.recv_size
+            );
+        }
+
+        if let Some(max) = self.max_udp_response
+            && max < MIN_RECV_SIZE
+        {
+            anyhow::bail!(
+                "max_udp_response must be between {} and {}, got {}",
+                MIN_RECV_SIZE,
+                u16::MAX,
+                max
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_requests: 4096,
+            max_tcp_connections: 1024,
+            tcp_idle_timeout: Duration::from_secs(10),
+            tcp_read_timeout: Duration::from_secs(2),
+            udp_timeout: None,
+            tcp_timeout: None,
+            doh_timeout: None,
+            recv_size: DEFAULT_RECV_SIZE,
+            max_udp_response: None,
+        }
+    }
+}
+
 pub struct ServerState<G, L> {
     pub resolver: Arc<DynResolver<G, L>>,
     pub middlewares: ServerMiddlewares<G, L>,
@@ -58,26 +164,48 @@ pub struct ServerState<G, L> {
 /// DNS Server
 pub struct DnsServer<G, L> {
     state: Arc<ArcSwap<ServerState<G, L>>>,
+    config: ServerConfig,
+    truncation: Arc<TruncationCounters>,
 }
 
 impl<L: Default + Send + Sync + 'static, G: Send + Sync + 'static> DnsServer<G, L> {
-    pub fn new(state: ServerState<G, L>) -> Self {
-        Self {
+    pub fn new(state: ServerState<G, L>, config: ServerConfig) -> anyhow::Result<Self> {
+        config.validate()?;
+
+        Ok(Self {
             state: Arc::new(ArcSwap::new(state.into())),
-        }
+            config,
+            truncation: Arc::default(),
+        })
+    }
+
+    /// Count the UDP responses sent with TC set in `counters`.
+    pub fn with_truncation_counters(mut self, counters: Arc<TruncationCounters>) -> Self {
+        self.truncation = counters;
+        self
+    }
+
+    /// UDP responses sent with TC set. The server never retries over TCP itself, so `tcp_fallbacks` stays 0.
+    pub fn truncation_stats(&self) -> TruncationStats {
+        self.truncation.stats()
     }
 
     pub fn swap_state(&self, new_state: ServerState<G, L>) {
         self.state.swap(new_state.into());
     }
 
+    /// The resolver of the current state.
+    pub fn resolver(&self) -> Arc<DynResolver<G, L>> {
+        self.state.load().resolver.clone()
+    }
+
     /// Serve the server over TCP.
     pub async fn serve_tcp(
         &self,
         bind_addr: SocketAddr,
         shutdown: tokio_util::sync::CancellationToken,
     ) -> anyhow::Result<()> {
-        run_tcp(bind_addr, self.state.clone(), shutdown).await
+        run_tcp(bind_addr, self.state.clone(), &self.config, shutdown).await
     }
 
     /// Serve the server over UDP.
@@ -86,12 +214,35 @@ impl<L: Default + Send + Sync + 'static, G: Send + Sync + 'static> DnsServer<G,
         bind_addr: SocketAddr,
         shutdown: tokio_util::sync::CancellationToken,
     ) -> anyhow::Result<()> {
-        run_udp(bind_addr, self.state.clone(), shutdown).await
+        run_udp(
+            bind_addr,
+            self.state.clone(),
+            &self.config,
+            self.truncation.clone(),
+            shutdown,
+        )
+        .await
+    }
+
+    /// Serve the server over UDP on an already bound socket.
+    pub async fn serve_udp_socket(
+        &self,
+        socket: UdpSocket,
+        shutdown: tokio_util::sync::CancellationToken,
+    ) -> anyhow::Result<()> {
+        serve_udp_socket(
+            socket,
+            self.state.clone(),
+            &self.config,
+            self.truncation.clone(),
+            shutdown,
+        )
+        .await
     }
 
     /// Serve the server over DOH.
     pub async fn serve_doh(&self, bind_addr: SocketAddr, config: DohConfig) -> anyhow::Result<()> {
-        run_doh(config, bind_addr, self.state.clone()).await
+        run_doh(config, bind_addr, self.state.clone(), &self.config).await
     }
 }
 
@@ -100,6 +251,30 @@ pub async fn handle_request<G, L>(
     ctx: &mut DnsRequestCtx<G, L>,
     state: Arc<ServerState<G, L>>,
 ) -> Result<DnsResponse, ServerError>
+where
+    G: Send + Sync + 'static,
+    L: Send + Sync,
+{
+    // names are decoded in lower case, so responses built or re-encoded from a message, and responses shared
+    // with other clients, may not spell the question the way the client did.
+    let result = process_request(ctx, state)
+        .await
+        .map(|response| response.map_bytes(|bytes| restore_question_case(bytes, &ctx.raw())));
+    log_request(ctx, &result);
+    result
+}
+
+/// Answer a request with the middlewares and the resolver.
+///
+/// Messages with the QR flag set fail with [`ServerError::Ignored`] and must not be answered.
+///
+/// DNS UPDATE refusals, answers to malformed queries and BADVERS answers are returned before any middleware runs:
+/// middlewares expect a decodable standard query, and e.g. the cache would store the error answer for the name.
+/// These answers are still logged by `handle_request`, but middleware hooks such as query metrics don't see them.
+async fn process_request<G, L>(
+    ctx: &mut DnsRequestCtx<G, L>,
+    state: Arc<ServerState<G, L>>,
+) -> Result<DnsResponse, ServerError>
 where
     G: Send + Sync + 'static,
     L: Send + Sync,
@@ -108,6 +283,24 @@ where
         resolver, middlewares, ..
     } = &*state;
 
+    // answering a response, even with an error, lets two servers or a spoofed source bounce messages back and
+    // forth, so responses are dropped.
+    if is_response(&ctx.raw()) == Some(true) {
+        return Err(ServerError::Ignored);
+    }
+
+    if let Some(response) = update_response(ctx)? {
+        return Ok(response);
+    }
+
+    if let Some(response) = invalid_query_response(ctx)? {
+        return Ok(response);
+    }
+
+    if let Some(response) = unsupported_edns_version_response(ctx)? {
+        return Ok(response);
+    }
+
     for (i, middleware) in state.middlewares.iter().enumerate() {
         match middleware.on_query(ctx).await {
             Ok(Some(response)) => {
@@ -152,6 +345,203 @@ where
     }
 }
 
+/// Emit a structured event describing how a request was handled.
+fn log_request<G, L>(ctx: &DnsRequestCtx<G, L>, result: &Result<DnsResponse, ServerError>) {
+    // parsing the response for its rcode is not free, skip it when nobody listens.
+    if !tracing::enabled!(tracing::Level::DEBUG) {
+        return;
+    }
+
+    let question = ctx.message().ok().and_then(|m| m.questions().first());
+    let qname = question.map(|q| q.qname.as_str());
+    let qtype = question.map(|q| tracing::field::debug(q.qtype));
+    let duration_ms = ctx.budget().elapsed().as_millis() as u64;
+
+    match result {
+        Ok(response) => {
+            let rcode = response
+                .message()
+                .ok()
+                .map(|m| tracing::field::debug(m.response_code()));
+            tracing::debug!(
+                client = %ctx.request_address(),
+                transport = ?ctx.request_type(),
+                qname,
+                qtype,
+                rcode,
+                duration_ms,
+                "query answered"
+            );
+        }
+        Err(ServerError::Ignored) => {
+            tracing::debug!(
+                client = %ctx.request_address(),
+                transport = ?ctx.request_type(),
+                qname,
+                qtype,
+                duration_ms,
+                "query ignored"
+            );
+        }
+        Err(e) => {
+            tracing::debug!(
+                client = %ctx.request_address(),
+                transport = ?ctx.request_type(),
+                qname,
+                qtype,
+                rcode = ?e.response_code(),
+                duration_ms,
+                error = %e,
+                "query failed"
+            );
+        }
+    }
+}
+
+/// Build the response for a query that failed with `error`.
+///
+/// Clients that sent an OPT record also get the extended DNS error (RFC 8914) for the error, if it has one.
+pub(crate) fn error_response(query: &DnsMessage, error: &ServerError) -> DnsMessage {
+    let mut response = DnsMessage::response_from_query(query, error.response_code());
+
+    if let Some(query_edns) = query.edns()
+        && let Some(info_code) = error.extended_error()
+    {
+        let mut edns = response.edns().clone().unwrap_or_default();
+        edns.set_do_bit(query_edns.do_bit());
+        edns.options.push(EdnsOption::new(
+            EdnsOptionCode::ExtendedDnsError,
+            EdnsOptionData::ExtendedError {
+                info_code,
+                extra_text: None,
+            },
+        ));
+        response.set_edns(Some(edns));
+    }
+
+    response
+}
+
+/// Build a FORMERR or NOTIMP response if the message is not a standard query with a single question.
+fn invalid_query_response<G, L>(ctx: &DnsRequestCtx<G, L>) -> Result<Option<DnsResponse>, ServerError> {
+    let Ok(message) = ctx.message() else {
+        return Ok(None);
+    };
+
+    match message.validate_query() {
+        Ok(()) => Ok(None),
+        Err(e) => {
+            let response = DnsMessage::response_from_query(message, e.response_code());
+            let bytes = response.encode().map_err(|e| ServerError::MiddlewareError(e.into()))?;
+            Ok(Some(DnsResponse::from_parsed(bytes, response)))
+        }
+    }
+}
+
+/// Build a REFUSED response if the request is a DNS UPDATE (RFC 2136), so it is never forwarded. It is answered
+/// before the middlewares run, see `process_request`.
+///
+/// The opcode is read from the header first, so only updates are decoded. Of those only the header and zone
+/// section are decoded, as the other sections of an update may not decode as a query.
+fn update_response<G, L>(ctx: &DnsRequestCtx<G, L>) -> Result<Option<DnsResponse>, ServerError> {
+    let raw = ctx.raw();
+    // the opcode is the 4 bits after the QR bit in the third header byte.
+    if raw.len() < 3 || (raw[2] >> 3) & 0x0F != DnsOpcode::Update as u8 {
+        return Ok(None);
+    }
+
+    let Ok(message) = DnsMessage::decode_questions(&raw) else {
+        return Ok(None);
+    };
+
+    if message.flags.opcode != DnsOpcode::Update || message.flags.response {
+        return Ok(None);
+    }
+
+    let response = DnsMessage::response_from_query(&message, DnsResponseCode::Refused);
+    let bytes = response.encode().map_err(|e| ServerError::MiddlewareError(e.into()))?;
+    Ok(Some(DnsResponse::from_parsed(bytes, response)))
+}
+
+/// Build a BADVERS response if the query uses an EDNS version other than 0 (RFC 6891 section 6.1.3). It is answered
+/// before the middlewares run, see `process_request`.
+fn unsupported_edns_version_response<G, L>(ctx: &DnsRequestCtx<G, L>) -> Result<Option<DnsResponse>, ServerError> {
+    let Ok(message) = ctx.message() else {
+        return Ok(None);
+    };
+
+    match message.edns() {
+        Some(edns) if edns.version != 0 => {
+            let response = DnsMessage::response_from_query(message, DnsResponseCode::BADVERS);
+            let bytes = response.encode().map_err(|e| ServerError::MiddlewareError(e.into()))?;
+            Ok(Some(DnsResponse::from_parsed(bytes, response)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Advertise `recv_size` as the UDP payload size of a response that carries an OPT record.
+///
+/// A re-encoded response gets the question case of `query` back.
+fn advertise_recv_size(response: DnsResponse, query: &[u8], recv_size: u16) -> DnsResponse {
+    let Ok(message) = response.message() else {
+        return response;
+    };
+
+    match message.edns() {
+        Some(edns) if edns.udp_payload_size != recv_size => {
+            let mut edns = edns.clone();
+            edns.udp_payload_size = recv_size;
+
+            let mut message = message.clone();
+            message.set_edns(Some(edns));
+            match message.encode() {
+                Ok(bytes) => DnsResponse::from_parsed(restore_question_case(bytes, query), message),
+                Err(_) => response,
+            }
+        }
+        _ => response,
+    }
+}
+
+/// Replace a UDP response that does not fit the client's buffer with an empty one that has TC set.
+///
+/// The client's buffer is the EDNS payload size of its query, capped at `recv_size`, or 512 bytes without EDNS.
+/// `max_response` caps it further. Clients retry truncated responses over TCP. The truncated response gets the
+/// question case of `raw_query` back.
+fn truncate_udp_response(
+    response: DnsResponse,
+    raw_query: &[u8],
+    query: Option<&DnsMessage>,
+    recv_size: u16,
+    max_response: Option<u16>,
+) -> DnsResponse {
+    let buffer = match query.and_then(|q| q.edns().as_ref()) {
+        Some(edns) => edns.udp_payload_size.clamp(MIN_RECV_SIZE, recv_size),
+        None => MIN_RECV_SIZE,
+    };
+    let limit = max_response.map_or(buffer, |max| buffer.min(max));
+
+    if response.bytes().len() <= limit as usize {
+        return response;
+    }
+
+    let Ok(message) = response.message() else {
+        return response;
+    };
+
+    let mut flags = message.flags;
+    flags.truncated = true;
+
+    let mut truncated = DnsMessage::new(message.id, flags, message.questions().to_vec(), vec![], vec![], vec![]);
+    truncated.set_edns(message.edns().clone());
+
+    match truncated.encode() {
+        Ok(bytes) => DnsResponse::from_parsed(restore_question_case(bytes, raw_query), truncated),
+        Err(_) => response,
+    }
+}
+
 /// Notify middlewares that an error occurred, in reverse order.
 async fn notify_error<G, L>(
     ctx: &mut DnsRequestCtx<G, L>,
@@ -167,3 +557,147 @@ async fn notify_error<G, L>(
         middleware.on_error(ctx, &error_type, &message).await;
     }
 }
+
+#[cfg(test)]
+use async_trait::async_trait;
+
+#[cfg(test)]
+pub(crate) struct DelayedResolver {
+    pub delay: Duration,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl reso_resolver::DnsResolver<(), ()> for DelayedResolver {
+    async fn resolve(&self, ctx: &DnsRequestCtx<(), ()>) -> Result<DnsResponse, ResolveError> {
+        tokio::time::sleep(self.delay).await;
+        let query = ctx.message().map_err(|e| ResolveError::InvalidRequest(e.to_string()))?;
+        let bytes = reso_dns::DnsMessage::response_from_query(query, DnsResponseCode::NoError)
+            .encode()
+            .map_err(|e| ResolveError::Other(e.to_string()))?;
+        Ok(DnsResponse::from_bytes(bytes))
+    }
+}
+
+/// Resolver that records the request timeout it was given.
+#[cfg(test)]
+#[derive(Default, Clone)]
+pub(crate) struct BudgetResolver {
+    pub timeout: Arc<std::sync::Mutex<Option<Duration>>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl reso_resolver::DnsResolver<(), ()> for BudgetResolver {
+    async fn resolve(&self, ctx: &DnsRequestCtx<(), ()>) -> Result<DnsResponse, ResolveError> {
+        let budget = ctx.budget();
+        *self.timeout.lock().unwrap() = Some(budget.remaining().unwrap_or_default() + budget.elapsed());
+        DelayedResolver { delay: Duration::ZERO }.resolve(ctx).await
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn assert_timeout_close(actual: Option<Duration>, expected: Duration) {
+    let actual = actual.expect("resolver should have been called");
+    assert!(
+        actual >= expected && actual < expected + Duration::from_millis(100),
+        "expected a timeout of {expected:?}, got {actual:?}"
+    );
+}
+
+#[cfg(test)]
+pub(crate) fn test_state(
+    resolver: impl reso_resolver::DnsResolver<(), ()> + Send + Sync + 'static,
+) -> Arc<ArcSwap<ServerState<(), ()>>> {
+    Arc::new(ArcSwap::from_pointee(ServerState {
+        resolver: Arc::new(resolver),
+        middlewares: Arc::new(vec![]),
+        global: Arc::new(()),
+        timeout: Duration::from_secs(2),
+    }))
+}
+
+/// A query repeating the question of `test_query` `count` times.
+#[cfg(test)]
+pub(crate) fn test_query_with_questions(id: u16, count: usize) -> bytes::Bytes {
+    let question = DnsMessage::decode(&test_query(id)).unwrap().questions()[0].clone();
+    reso_dns::DnsMessageBuilder::new()
+        .with_id(id)
+        .with_questions(vec![question; count])
+        .build()
+        .encode()
+        .unwrap()
+}
+
+#[cfg(test)]
+pub(crate) fn test_query(id: u16) -> bytes::Bytes {
+    reso_dns::DnsMessageBuilder::new()
+        .with_id(id)
+        .add_question(reso_dns::DnsQuestion::new(
+            reso_dns::domain_name::DomainName::from_ascii("example.com").unwrap(),
+            reso_dns::RecordType::A,
+            reso_dns::ClassType::IN,
+        ))
+        .build()
+        .encode()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use reso_dns::Edns;
+
+    use super::*;
+
+    #[test]
+    fn test_recv_size_defaults_and_is_validated() {
+        let config = ServerConfig::default();
+        assert_eq!(config.recv_size, 1232);
+        assert!(config.validate().is_ok());
+
+        for recv_size in [MIN_RECV_SIZE, u16::MAX] {
+            assert!(
+                ServerConfig {
+                    recv_size,
+                    ..Default::default()
+                }
+                .validate()
+                .is_ok()
+            );
+        }
+        for recv_size in [0, MIN_RECV_SIZE - 1] {
+            assert!(
+                ServerConfig {
+                    recv_size,
+                    ..Default::default()
+                }
+                .validate()
+                .is_err()
+            );
+        }
+
+        assert!(
+            ServerConfig {
+                max_udp_response: Some(MIN_RECV_SIZE - 1),
+                ..Default::default()
+            }
+            .validate()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_advertises_recv_size_in_edns_responses() {
+        let query = DnsMessage::decode(&test_query(1)).unwrap();
+        let mut with_edns = DnsMessage::response_from_query(&query, DnsResponseCode::NoError);
+        with_edns.set_edns(Some(Edns::default()));
+        let without_edns = DnsMessage::response_from_query(&query, DnsResponseCode::NoError);
+
+        let response = advertise_recv_size(DnsResponse::from_bytes(with_edns.encode().unwrap()), &[], 4096);
+        let decoded = DnsMessage::decode(&response.bytes()).unwrap();
+        assert_eq!(decoded.edns().as_ref().unwrap().udp_payload_size, 4096);
+
+        let response = advertise_recv_size(DnsResponse::from_bytes(without_edns.encode().unwrap()), &[], 4096);
+        assert!(DnsMessage::decode(&response.bytes()).unwrap().edns().is_none());
+    }
+}
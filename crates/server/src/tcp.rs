@@ -1,17 +1,21 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use anyhow::Context;
 use arc_swap::ArcSwap;
 use bytes::Bytes;
-use reso_context::{DnsRequestCtx, RequestType};
-use reso_dns::{DnsMessage, DnsMessageBuilder};
+use reso_context::{DnsRequestCtx, DnsResponse, RequestType};
+use reso_dns::{
+    DnsMessage, DnsResponseCode, Edns,
+    message::{EdnsOption, EdnsOptionCode, EdnsOptionData},
+};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
+    sync::{OwnedSemaphorePermit, Semaphore},
     task::JoinSet,
 };
 
-use crate::{ServerError, ServerState, handle_request};
+use crate::{ServerConfig, ServerError, ServerState, error_response, handle_request};
 
 /// Max DNS message size.
 const MAX_MESSAGE_SIZE: usize = 65535;
@@ -23,6 +27,7 @@ const MAX_QUERIES_PER_CONNECTION: usize = 100;
 pub async fn run_tcp<G, L>(
     bind_addr: SocketAddr,
     state: Arc<ArcSwap<ServerState<G, L>>>,
+    config: &ServerConfig,
     shutdown: tokio_util::sync::CancellationToken,
 ) -> anyhow::Result<()>
 where
@@ -32,6 +37,26 @@ where
     let listener = TcpListener::bind(bind_addr).await?;
     tracing::info!("TCP listening on {}", bind_addr);
 
+    serve_tcp_listener(listener, state, config, shutdown).await
+}
+
+/// Serve DNS requests on an already bound TCP listener.
+async fn serve_tcp_listener<G, L>(
+    listener: TcpListener,
+    state: Arc<ArcSwap<ServerState<G, L>>>,
+    config: &ServerConfig,
+    shutdown: tokio_util::sync::CancellationToken,
+) -> anyhow::Result<()>
+where
+    L: Default + Send + Sync + 'static,
+    G: Send + Sync + 'static,
+{
+    // bounds the number of concurrently processed requests, requests beyond the limit are refused.
+    let permits = Arc::new(Semaphore::new(config.max_concurrent_requests));
+
+    // bounds the number of open connections, each holds a permit until it is closed.
+    let connections = Arc::new(Semaphore::new(config.max_tcp_connections));
+
     // we keep track of the inflight requests so that we can wait for them to finish before shutting down the server.
     let mut inflight = JoinSet::new();
 
@@ -42,12 +67,17 @@ where
                     tracing::warn!("TCP inflight task failed: {}", err);
                 }
             }
-            result = listener.accept() => {
-                let (mut stream, client) = result?;
+            result = accept_with_permit(&listener, &connections) => {
+                let (mut stream, client, connection_permit) = result?;
                 let state = state.clone();
                 let shutdown = shutdown.clone();
+                let permits = permits.clone();
+                let idle_timeout = config.tcp_idle_timeout;
+                let read_timeout = config.tcp_read_timeout;
+                let request_timeout = config.tcp_timeout;
 
                 inflight.spawn(async move {
+                    let _connection_permit = connection_permit;
                     let mut len_buf = [0u8; 2];
                     let mut buf = Vec::new();
                     let mut query_count = 0;
@@ -62,12 +92,16 @@ where
 
                         let len_res = tokio::select! {
                             _ = shutdown.cancelled() => return,
+                            _ = tokio::time::sleep(idle_timeout) => {
+                                tracing::debug!(client = %client.ip(), transport = ?RequestType::TCP, ?idle_timeout, "connection idle, closing connection");
+                                return;
+                            }
                             res = stream.read_exact(&mut len_buf) => res,
                         };
 
                         if let Err(e) = len_res {
                             if e.kind() != std::io::ErrorKind::UnexpectedEof {
-                                tracing::debug!("failed to read length from client {}: {}", client, e);
+                                tracing::debug!(client = %client.ip(), transport = ?RequestType::TCP, error = %e, "failed to read message length");
                             }
                             return;
                         }
@@ -83,21 +117,35 @@ where
 
                         let body_res = tokio::select! {
                             _ = shutdown.cancelled() => return,
+                            _ = tokio::time::sleep(read_timeout) => {
+                                tracing::debug!(client = %client.ip(), transport = ?RequestType::TCP, "timed out reading message body, closing connection");
+                                return;
+                            }
                            res = stream.read_exact(&mut buf) => res,
                         };
 
                         if let Err(e) = body_res {
                             if e.kind() != std::io::ErrorKind::UnexpectedEof {
-                                tracing::debug!("failed to read body from client {}: {}", client, e);
+                                tracing::debug!(client = %client.ip(), transport = ?RequestType::TCP, error = %e, "failed to read message body");
                             }
                             return;
                         }
 
                         let bytes = Bytes::copy_from_slice(&buf);
+
+                        let Ok(_permit) = permits.try_acquire() else {
+                            tracing::debug!(client = %client.ip(), transport = ?RequestType::TCP, "request limit reached, refusing request");
+                            if let Ok(message) = DnsMessage::decode(&bytes) && let Err(e) = write_tcp_error_response(&message, &mut stream, DnsResponseCode::Refused).await {
+                                tracing::debug!(client = %client.ip(), transport = ?RequestType::TCP, error = ?e, "failed to write refused response");
+                                return;
+                            }
+                            continue;
+                        };
+
                         let current_state = state.load_full();
 
                         let mut ctx = DnsRequestCtx::new(
-                            current_state.timeout,
+                            request_timeout.unwrap_or(current_state.timeout),
                             client.ip(),
                             RequestType::TCP,
                             bytes,
@@ -106,15 +154,32 @@ where
                         );
 
                         match handle_request(&mut ctx, current_state).await {
+                            Ok(resp) if resp.bytes().len() > u16::MAX as usize => {
+                                tracing::warn!(client = %client.ip(), transport = ?RequestType::TCP, len = resp.bytes().len(), "response does not fit in a TCP frame, answering with SERVFAIL");
+                                if let Ok(message) = ctx.message() && let Err(e) = write_tcp_error_response(message, &mut stream, DnsResponseCode::ServerFailure).await {
+                                    tracing::debug!(client = %client.ip(), transport = ?RequestType::TCP, error = ?e, "failed to write error response");
+                                    return;
+                                }
+                                continue;
+                            }
                             Ok(resp) => {
-                                if let Err(e) = write_tcp_response(&mut stream, &resp.bytes()).await {
-                                    tracing::debug!("failed to write tcp response to client: {:?}", e);
+                                let bytes = match with_keepalive(&ctx, &resp, idle_timeout) {
+                                    Ok(Some(bytes)) => bytes,
+                                    Ok(None) => resp.bytes(),
+                                    Err(e) => {
+                                        tracing::debug!(client = %client.ip(), transport = ?RequestType::TCP, error = ?e, "failed to add keepalive option");
+                                        resp.bytes()
+                                    }
+                                };
+                                if let Err(e) = write_tcp_response(&mut stream, &bytes).await {
+                                    tracing::debug!(client = %client.ip(), transport = ?RequestType::TCP, error = ?e, "failed to write response");
                                     return;
                                 }
                             }
+                            Err(ServerError::Ignored) => continue,
                             Err(e) => {
                                 if let Ok(message) = ctx.message() && let Err(e) = write_tcp_server_error_response(message, &mut stream, &e).await {
-                                    tracing::debug!("failed to write tcp server response to client: {:?}", e);
+                                    tracing::debug!(client = %client.ip(), transport = ?RequestType::TCP, error = ?e, "failed to write error response");
                                     return;
                                 }
                                 continue;
@@ -142,6 +207,56 @@ where
     Ok(())
 }
 
+/// Encode the response with an EDNS TCP keepalive option advertising `idle_timeout` (RFC 7828), or `None` if the
+/// query did not ask for one.
+///
+/// Servers must not send the option unsolicited, a keepalive option already in the response, e.g. from an
+/// upstream, is replaced as it describes the upstream connection.
+fn with_keepalive<G, L>(
+    ctx: &DnsRequestCtx<G, L>,
+    response: &DnsResponse,
+    idle_timeout: Duration,
+) -> anyhow::Result<Option<Bytes>> {
+    let query = ctx.message()?;
+    let requested = query
+        .edns()
+        .as_ref()
+        .is_some_and(|edns| edns.options.iter().any(|o| o.code == EdnsOptionCode::TcpKeepAlive));
+    if !requested {
+        return Ok(None);
+    }
+
+    let mut message = response.message()?.clone();
+    let mut edns = message.edns().clone().unwrap_or_else(|| {
+        let mut edns = Edns::default();
+        edns.set_do_bit(query.edns().as_ref().is_some_and(Edns::do_bit));
+        edns
+    });
+    edns.options.retain(|o| o.code != EdnsOptionCode::TcpKeepAlive);
+    edns.options.push(EdnsOption::new(
+        EdnsOptionCode::TcpKeepAlive,
+        EdnsOptionData::Timeout(keepalive_timeout(idle_timeout)),
+    ));
+    message.set_edns(Some(edns));
+
+    Ok(Some(message.encode()?))
+}
+
+/// Idle timeout in the 100 millisecond units of the keepalive option.
+fn keepalive_timeout(idle_timeout: Duration) -> u16 {
+    u16::try_from(idle_timeout.as_millis() / 100).unwrap_or(u16::MAX)
+}
+
+/// Accept a connection once fewer than the maximum number of connections are open.
+async fn accept_with_permit(
+    listener: &TcpListener,
+    connections: &Arc<Semaphore>,
+) -> anyhow::Result<(TcpStream, SocketAddr, OwnedSemaphorePermit)> {
+    let permit = connections.clone().acquire_owned().await?;
+    let (stream, client) = listener.accept().await?;
+    Ok((stream, client, permit))
+}
+
 /// Write a DNS friendly response to a TCP stream.
 async fn write_tcp_response(stream: &mut tokio::net::TcpStream, response: &Bytes) -> anyhow::Result<()> {
     let len = u16::try_from(response.len()).context("DNS payload exceeds 65535 bytes")?;
@@ -150,19 +265,324 @@ async fn write_tcp_response(stream: &mut tokio::net::TcpStream, response: &Bytes
     Ok(())
 }
 
+/// Write a DNS message indicating an error over TCP.
+async fn write_tcp_error_response(
+    message: &DnsMessage,
+    stream: &mut TcpStream,
+    response_code: DnsResponseCode,
+) -> anyhow::Result<()> {
+    let bytes = DnsMessage::response_from_query(message, response_code).encode()?;
+    write_tcp_response(stream, &bytes).await?;
+
+    Ok(())
+}
+
 /// Write a DNS message indicating a server error over TCP.
 async fn write_tcp_server_error_response(
     message: &DnsMessage,
     stream: &mut TcpStream,
     error: &ServerError,
 ) -> anyhow::Result<()> {
-    let bytes = DnsMessageBuilder::new()
-        .with_id(message.id)
-        .with_questions(message.questions().to_vec())
-        .with_response(error.response_code())
-        .build()
-        .encode()?;
+    let bytes = error_response(message, error).encode()?;
     write_tcp_response(stream, &bytes).await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        BudgetResolver, DelayedResolver, assert_timeout_close, test_query, test_query_with_questions, test_state,
+    };
+
+    async fn send_query(stream: &mut TcpStream, id: u16) {
+        write_tcp_response(stream, &test_query(id)).await.unwrap();
+    }
+
+    async fn read_response(stream: &mut TcpStream) -> DnsMessage {
+        let len = stream.read_u16().await.unwrap() as usize;
+        let mut buf = vec![0u8; len];
+        stream.read_exact(&mut buf).await.unwrap();
+        DnsMessage::decode(&buf).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_excess_requests_are_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let state = test_state(DelayedResolver {
+            delay: Duration::from_millis(300),
+        });
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let config = ServerConfig {
+                max_concurrent_requests: 1,
+                ..Default::default()
+            };
+            serve_tcp_listener(listener, state, &config, server_shutdown).await
+        });
+
+        let mut first = TcpStream::connect(server_addr).await.unwrap();
+        send_query(&mut first, 1).await;
+
+        // give the server time to start processing the first request.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let mut second = TcpStream::connect(server_addr).await.unwrap();
+        send_query(&mut second, 2).await;
+
+        let refused = tokio::time::timeout(Duration::from_millis(200), read_response(&mut second))
+            .await
+            .expect("excess request should be refused without waiting");
+        assert_eq!(refused.id, 2);
+        assert_eq!(refused.response_code(), DnsResponseCode::Refused);
+
+        let answered = read_response(&mut first).await;
+        assert_eq!(answered.id, 1);
+        assert_eq!(answered.response_code(), DnsResponseCode::NoError);
+
+        shutdown.cancel();
+    }
+
+    /// Resolver that answers with a payload too large for a TCP length prefix.
+    struct OversizedResolver;
+
+    #[async_trait::async_trait]
+    impl reso_resolver::DnsResolver<(), ()> for OversizedResolver {
+        async fn resolve(
+            &self,
+            ctx: &DnsRequestCtx<(), ()>,
+        ) -> Result<reso_context::DnsResponse, reso_resolver::ResolveError> {
+            let query = ctx.message().unwrap();
+            let mut bytes = DnsMessage::response_from_query(query, DnsResponseCode::NoError)
+                .encode()
+                .unwrap()
+                .to_vec();
+            bytes.resize(u16::MAX as usize + 1, 0);
+            Ok(reso_context::DnsResponse::from_bytes(bytes.into()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_excess_connections_wait_for_a_free_slot() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let state = test_state(DelayedResolver {
+            delay: Duration::from_millis(0),
+        });
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let config = ServerConfig {
+                max_tcp_connections: 1,
+                ..Default::default()
+            };
+            serve_tcp_listener(listener, state, &config, server_shutdown).await
+        });
+
+        let mut first = TcpStream::connect(server_addr).await.unwrap();
+        send_query(&mut first, 1).await;
+        assert_eq!(read_response(&mut first).await.id, 1);
+
+        let mut second = TcpStream::connect(server_addr).await.unwrap();
+        send_query(&mut second, 2).await;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), read_response(&mut second))
+                .await
+                .is_err(),
+            "second connection should not be served while the first is open"
+        );
+
+        drop(first);
+
+        let answered = tokio::time::timeout(Duration::from_secs(2), read_response(&mut second))
+            .await
+            .expect("second connection should be served once the first closes");
+        assert_eq!(answered.id, 2);
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_oversized_response_is_answered_with_servfail() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let state = test_state(OversizedResolver);
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(
+            async move { serve_tcp_listener(listener, state, &ServerConfig::default(), server_shutdown).await },
+        );
+
+        let mut stream = TcpStream::connect(server_addr).await.unwrap();
+        send_query(&mut stream, 1).await;
+
+        let response = read_response(&mut stream).await;
+        assert_eq!(response.id, 1);
+        assert_eq!(response.response_code(), DnsResponseCode::ServerFailure);
+
+        // the connection stays usable after the oversized response.
+        send_query(&mut stream, 2).await;
+        assert_eq!(read_response(&mut stream).await.id, 2);
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_write_rejects_oversized_payload() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let payload = Bytes::from(vec![0u8; u16::MAX as usize + 1]);
+
+        assert!(write_tcp_response(&mut stream, &payload).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_timeout_overrides_state_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let resolver = BudgetResolver::default();
+        let state = test_state(resolver.clone());
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let config = ServerConfig {
+                udp_timeout: Some(Duration::from_secs(5)),
+                tcp_timeout: Some(Duration::from_millis(700)),
+                ..Default::default()
+            };
+            serve_tcp_listener(listener, state, &config, server_shutdown).await
+        });
+
+        let mut stream = TcpStream::connect(server_addr).await.unwrap();
+        send_query(&mut stream, 1).await;
+        assert_eq!(read_response(&mut stream).await.id, 1);
+
+        assert_timeout_close(*resolver.timeout.lock().unwrap(), Duration::from_millis(700));
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_idle_connection_is_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let state = test_state(DelayedResolver { delay: Duration::ZERO });
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let config = ServerConfig {
+                tcp_idle_timeout: Duration::from_millis(100),
+                ..Default::default()
+            };
+            serve_tcp_listener(listener, state, &config, server_shutdown).await
+        });
+
+        let mut stream = TcpStream::connect(server_addr).await.unwrap();
+        send_query(&mut stream, 1).await;
+        assert_eq!(read_response(&mut stream).await.id, 1);
+
+        // stay idle past the timeout, the server should close the connection.
+        let mut buf = [0u8; 1];
+        let read = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf))
+            .await
+            .expect("idle connection should be closed by the server");
+        assert_eq!(read.unwrap(), 0);
+
+        shutdown.cancel();
+    }
+
+    fn keepalive_query(id: u16) -> Bytes {
+        let mut query = DnsMessage::decode(&test_query(id)).unwrap();
+        let mut edns = Edns::default();
+        edns.options.push(EdnsOption {
+            code: EdnsOptionCode::TcpKeepAlive,
+            data: None,
+        });
+        query.set_edns(Some(edns));
+        query.encode().unwrap()
+    }
+
+    fn keepalive(response: &DnsMessage) -> Option<&EdnsOptionData> {
+        response
+            .edns()
+            .as_ref()?
+            .options
+            .iter()
+            .find(|o| o.code == EdnsOptionCode::TcpKeepAlive)?
+            .data()
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_advertises_idle_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let state = test_state(DelayedResolver { delay: Duration::ZERO });
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let config = ServerConfig {
+                tcp_idle_timeout: Duration::from_secs(30),
+                ..Default::default()
+            };
+            serve_tcp_listener(listener, state, &config, server_shutdown).await
+        });
+
+        let mut stream = TcpStream::connect(server_addr).await.unwrap();
+        write_tcp_response(&mut stream, &keepalive_query(1)).await.unwrap();
+
+        let response = read_response(&mut stream).await;
+        assert_eq!(response.id, 1);
+        assert_eq!(keepalive(&response), Some(&EdnsOptionData::Timeout(300)));
+
+        // the option is only sent to clients that asked for it.
+        send_query(&mut stream, 2).await;
+        let response = read_response(&mut stream).await;
+        assert_eq!(response.id, 2);
+        assert!(response.edns().is_none());
+
+        shutdown.cancel();
+    }
+
+    #[test]
+    fn test_keepalive_timeout_saturates() {
+        assert_eq!(keepalive_timeout(Duration::from_millis(2_550)), 25);
+        assert_eq!(keepalive_timeout(Duration::from_secs(24 * 3600)), u16::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_question_count_gets_formerr() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let state = test_state(DelayedResolver { delay: Duration::ZERO });
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(
+            async move { serve_tcp_listener(listener, state, &ServerConfig::default(), server_shutdown).await },
+        );
+
+        let mut stream = TcpStream::connect(server_addr).await.unwrap();
+        for (id, count) in [(7, 0), (8, 2)] {
+            write_tcp_response(&mut stream, &test_query_with_questions(id, count))
+                .await
+                .unwrap();
+
+            let response = read_response(&mut stream).await;
+            assert_eq!(response.id, id);
+            assert_eq!(response.response_code(), DnsResponseCode::FormatError);
+        }
+
+        shutdown.cancel();
+    }
+}
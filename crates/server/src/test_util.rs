@@ -0,0 +1,133 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use reso_dns::DnsMessage;
+use reso_resolver::{
+    mock::MockResolver,
+    truncation::{TruncationCounters, TruncationStats},
+};
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+
+use crate::{DnsServer, ServerConfig, ServerState};
+
+/// How long [`TestServer::query`] waits for a response.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A [`DnsServer`] serving UDP on an ephemeral localhost port, for black-box tests.
+///
+/// The server is shut down when this is dropped.
+pub struct TestServer {
+    addr: SocketAddr,
+    shutdown: CancellationToken,
+    truncation: Arc<TruncationCounters>,
+}
+
+impl TestServer {
+    /// Start a server without middlewares that resolves through `resolver`.
+    pub async fn start(resolver: MockResolver) -> anyhow::Result<Self> {
+        let state = ServerState {
+            resolver: Arc::new(resolver),
+            middlewares: Arc::new(vec![]),
+            global: Arc::new(()),
+            timeout: QUERY_TIMEOUT,
+        };
+
+        Self::start_with(state, ServerConfig::default()).await
+    }
+
+    /// Start a server with the given state and config.
+    pub async fn start_with(state: ServerState<(), ()>, config: ServerConfig) -> anyhow::Result<Self> {
+        let truncation = Arc::new(TruncationCounters::default());
+        let server = DnsServer::new(state, config)?.with_truncation_counters(truncation.clone());
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = socket.local_addr()?;
+        let shutdown = CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(e) = server.serve_udp_socket(socket, server_shutdown).await {
+                tracing::error!("test server failed: {}", e);
+            }
+        });
+
+        Ok(Self {
+            addr,
+            shutdown,
+            truncation,
+        })
+    }
+
+    /// Address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// UDP responses the server sent with TC set.
+    pub fn truncation_stats(&self) -> TruncationStats {
+        self.truncation.stats()
+    }
+
+    /// Send `query` from a fresh socket and decode the response.
+    pub async fn query(&self, query: &[u8]) -> anyhow::Result<DnsMessage> {
+        Ok(DnsMessage::decode(&self.query_raw(query).await?)?)
+    }
+
+    /// Send `query` from a fresh socket and return the response as received.
+    pub async fn query_raw(&self, query: &[u8]) -> anyhow::Result<Bytes> {
+        let client = UdpSocket::bind("127.0.0.1:0").await?;
+        client.send_to(query, self.addr).await?;
+
+        let mut buf = vec![0u8; u16::MAX as usize];
+        let (len, _) = tokio::time::timeout(QUERY_TIMEOUT, client.recv_from(&mut buf)).await??;
+        buf.truncate(len);
+
+        Ok(Bytes::from(buf))
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use reso_dns::{
+        ClassType, DnsMessageBuilder, DnsQuestion, DnsRecord, DnsResponseCode, RecordType, domain_name::DomainName,
+        message::DnsRecordData,
+    };
+
+    use super::*;
+    use crate::test_query;
+
+    #[tokio::test]
+    async fn test_resolves_through_harness() {
+        let name = DomainName::from_ascii("example.com").unwrap();
+        let mut response = DnsMessageBuilder::new()
+            .with_response(DnsResponseCode::NoError)
+            .add_question(DnsQuestion::new(name.clone(), RecordType::A, ClassType::IN))
+            .add_answer(DnsRecord::new(
+                name.clone(),
+                RecordType::A,
+                ClassType::IN,
+                300,
+                DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1)),
+            ))
+            .build();
+        response.flags.response = true;
+
+        let server = TestServer::start(MockResolver::new().with_response(name, RecordType::A, response.clone()))
+            .await
+            .unwrap();
+
+        let answer = server.query(&test_query(21)).await.unwrap();
+
+        assert_eq!(answer.id, 21);
+        assert_eq!(answer.response_code(), DnsResponseCode::NoError);
+        assert_eq!(answer.answers(), response.answers());
+    }
+}
@@ -3,28 +3,54 @@ use std::{net::SocketAddr, sync::Arc};
 use arc_swap::ArcSwap;
 use bytes::Bytes;
 use reso_context::{DnsRequestCtx, RequestType};
-use reso_dns::{DnsMessage, DnsMessageBuilder};
-use tokio::{net::UdpSocket, task::JoinSet};
+use reso_dns::{DnsMessage, helpers};
+use reso_resolver::truncation::TruncationCounters;
+use tokio::{net::UdpSocket, sync::Semaphore, task::JoinSet};
 
-use crate::{ServerError, ServerState, handle_request};
+use crate::{
+    ServerConfig, ServerError, ServerState, advertise_recv_size, error_response, handle_request, truncate_udp_response,
+};
 
 /// Run the DNS server over UDP.
 pub async fn run_udp<G, L>(
     bind_addr: SocketAddr,
     state: Arc<ArcSwap<ServerState<G, L>>>,
+    config: &ServerConfig,
+    truncation: Arc<TruncationCounters>,
     shutdown: tokio_util::sync::CancellationToken,
 ) -> anyhow::Result<()>
 where
     L: Default + Send + Sync + 'static,
     G: Send + Sync + 'static,
 {
-    const RECV_SIZE: usize = 1232;
-
-    let socket = Arc::new(UdpSocket::bind(bind_addr).await?);
-    let mut buffer = vec![0; RECV_SIZE];
+    let socket = UdpSocket::bind(bind_addr).await?;
 
     tracing::info!("UDP listening on {}", bind_addr);
 
+    serve_udp_socket(socket, state, config, truncation, shutdown).await
+}
+
+/// Serve DNS requests on an already bound UDP socket.
+pub(crate) async fn serve_udp_socket<G, L>(
+    socket: UdpSocket,
+    state: Arc<ArcSwap<ServerState<G, L>>>,
+    config: &ServerConfig,
+    truncation: Arc<TruncationCounters>,
+    shutdown: tokio_util::sync::CancellationToken,
+) -> anyhow::Result<()>
+where
+    L: Default + Send + Sync + 'static,
+    G: Send + Sync + 'static,
+{
+    let recv_size = config.recv_size;
+    let max_udp_response = config.max_udp_response;
+
+    let socket = Arc::new(socket);
+    let mut buffer = vec![0; recv_size as usize];
+
+    // bounds the number of concurrently processed requests, datagrams beyond the limit are dropped.
+    let permits = Arc::new(Semaphore::new(config.max_concurrent_requests));
+
     // we keep track of the inflight requests so that we can wait for them to finish before shutting down the server.
     let mut inflight = JoinSet::new();
 
@@ -37,24 +63,40 @@ where
             }
             result = socket.recv_from(&mut buffer[..]) => {
                 let (len, client) = result?;
+
+                let Ok(permit) = permits.clone().try_acquire_owned() else {
+                    tracing::debug!(client = %client.ip(), transport = ?RequestType::UDP, "request limit reached, dropping request");
+                    continue;
+                };
+
                 let raw = Bytes::copy_from_slice(&buffer[..len]);
                 let sock = socket.clone();
+                let truncation = truncation.clone();
 
                 let state = state.load_full();
                 let global = state.global.clone();
+                let timeout = config.udp_timeout.unwrap_or(state.timeout);
 
                 inflight.spawn(async move {
-                    let mut ctx = DnsRequestCtx::new(state.timeout, client.ip(), RequestType::UDP, raw, global, L::default());
+                    let _permit = permit;
+                    let mut ctx = DnsRequestCtx::new(timeout, client.ip(), RequestType::UDP, raw, global, L::default());
 
                     match handle_request(&mut ctx, state).await {
                         Ok(resp) => {
+                            let raw = ctx.raw();
+                            let resp = advertise_recv_size(resp, &raw, recv_size);
+                            let resp = truncate_udp_response(resp, &raw, ctx.message().ok(), recv_size, max_udp_response);
+                            if helpers::is_truncated(&resp.bytes()) == Some(true) {
+                                truncation.record_truncated();
+                            }
                             let _ = sock.send_to(&resp.bytes(), client).await;
                         },
+                        Err(ServerError::Ignored) => {}
                         Err(e) => {
                             if let Ok(message) = ctx.message() {
                                 let res = write_udp_server_error_response(message, &sock, &client, &e).await;
                                 if let Err(err) = res {
-                                    tracing::warn!("failed to write error response to client {}: {}", client, err);
+                                    tracing::warn!(client = %client.ip(), transport = ?RequestType::UDP, error = %err, "failed to write error response");
                                 }
                             }
                         }
@@ -87,14 +129,587 @@ async fn write_udp_server_error_response(
     client: &SocketAddr,
     error: &ServerError,
 ) -> anyhow::Result<()> {
-    let bytes = DnsMessageBuilder::new()
-        .with_id(message.id)
-        .with_questions(message.questions().to_vec())
-        .with_response(error.response_code())
-        .build()
-        .encode()?;
+    let bytes = error_response(message, error).encode()?;
 
     socket.send_to(&bytes, client).await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, time::Duration};
+
+    use reso_dns::{
+        ClassType, DnsMessageBuilder, DnsOpcode, DnsQuestion, DnsRecord, DnsResponseCode, Edns, RecordType,
+        domain_name::DomainName,
+        message::{DnsRecordData, EdnsOptionData, ExtendedDnsErrorInfoCode},
+    };
+    use reso_resolver::mock::MockResolver;
+
+    use super::*;
+    use crate::{
+        BudgetResolver, DelayedResolver, assert_timeout_close, test_query, test_query_with_questions, test_state,
+        test_util::TestServer,
+    };
+
+    #[tokio::test]
+    async fn test_excess_requests_are_dropped() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let state = test_state(DelayedResolver {
+            delay: Duration::from_millis(300),
+        });
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let config = ServerConfig {
+                max_concurrent_requests: 1,
+                ..Default::default()
+            };
+            serve_udp_socket(socket, state, &config, Default::default(), server_shutdown).await
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(&test_query(1), server_addr).await.unwrap();
+        client.send_to(&test_query(2), server_addr).await.unwrap();
+
+        let mut buf = [0u8; 512];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), client.recv_from(&mut buf))
+            .await
+            .expect("first request should be answered")
+            .unwrap();
+        assert_eq!(DnsMessage::decode(&buf[..len]).unwrap().id, 1);
+
+        // the second request was shed while the first one held the only permit.
+        let second = tokio::time::timeout(Duration::from_millis(600), client.recv_from(&mut buf)).await;
+        assert!(second.is_err());
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_query_event_has_structured_fields() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let state = test_state(DelayedResolver { delay: Duration::ZERO });
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            serve_udp_socket(
+                socket,
+                state,
+                &ServerConfig::default(),
+                Default::default(),
+                server_shutdown,
+            )
+            .await
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(&test_query(1), server_addr).await.unwrap();
+
+        let mut buf = [0u8; 512];
+        tokio::time::timeout(Duration::from_secs(2), client.recv_from(&mut buf))
+            .await
+            .expect("query should be answered")
+            .unwrap();
+        shutdown.cancel();
+
+        assert!(logs_contain("query answered"));
+        assert!(logs_contain("client=127.0.0.1"));
+        assert!(logs_contain("transport=UDP"));
+        assert!(logs_contain("qname=\"example.com\""));
+        assert!(logs_contain("qtype=A"));
+        assert!(logs_contain("rcode=NoError"));
+        assert!(logs_contain("duration_ms="));
+    }
+
+    async fn udp_request_timeout(config: ServerConfig) -> Option<Duration> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let resolver = BudgetResolver::default();
+        let state = test_state(resolver.clone());
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(
+            async move { serve_udp_socket(socket, state, &config, Default::default(), server_shutdown).await },
+        );
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(&test_query(1), server_addr).await.unwrap();
+
+        let mut buf = [0u8; 512];
+        tokio::time::timeout(Duration::from_secs(2), client.recv_from(&mut buf))
+            .await
+            .expect("query should be answered")
+            .unwrap();
+
+        shutdown.cancel();
+        *resolver.timeout.lock().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_udp_timeout_overrides_state_timeout() {
+        let config = ServerConfig {
+            udp_timeout: Some(Duration::from_millis(500)),
+            tcp_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        assert_timeout_close(udp_request_timeout(config).await, Duration::from_millis(500));
+
+        // falls back to the state timeout of the test state.
+        assert_timeout_close(
+            udp_request_timeout(ServerConfig::default()).await,
+            Duration::from_secs(2),
+        );
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_unsupported_edns_version_gets_badvers() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let state = test_state(DelayedResolver { delay: Duration::ZERO });
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            serve_udp_socket(
+                socket,
+                state,
+                &ServerConfig::default(),
+                Default::default(),
+                server_shutdown,
+            )
+            .await
+        });
+
+        let mut query = DnsMessage::decode(&test_query(7)).unwrap();
+        let mut edns = Edns::default();
+        edns.version = 1;
+        query.set_edns(Some(edns));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(&query.encode().unwrap(), server_addr).await.unwrap();
+
+        let mut buf = [0u8; 512];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), client.recv_from(&mut buf))
+            .await
+            .expect("query should be answered")
+            .unwrap();
+        let response = DnsMessage::decode(&buf[..len]).unwrap();
+
+        assert_eq!(response.id, 7);
+        assert_eq!(response.response_code(), DnsResponseCode::BADVERS);
+        assert_eq!(response.questions(), query.questions());
+        let edns = response.edns().as_ref().expect("BADVERS requires an OPT record");
+        assert_eq!(edns.version, 0);
+        assert!(edns.options.is_empty());
+
+        // answered before the middlewares run, but still logged.
+        assert!(logs_contain("query answered"));
+        assert!(logs_contain("rcode=BADVERS"));
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_question_count_gets_formerr() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let state = test_state(DelayedResolver { delay: Duration::ZERO });
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            serve_udp_socket(
+                socket,
+                state,
+                &ServerConfig::default(),
+                Default::default(),
+                server_shutdown,
+            )
+            .await
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for (id, count) in [(7, 0), (8, 2)] {
+            client
+                .send_to(&test_query_with_questions(id, count), server_addr)
+                .await
+                .unwrap();
+
+            let mut buf = [0u8; 512];
+            let (len, _) = tokio::time::timeout(Duration::from_secs(2), client.recv_from(&mut buf))
+                .await
+                .expect("query should be answered")
+                .unwrap();
+            let response = DnsMessage::decode(&buf[..len]).unwrap();
+
+            assert_eq!(response.id, id);
+            assert_eq!(response.response_code(), DnsResponseCode::FormatError);
+        }
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_opcode_gets_notimp() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let state = test_state(DelayedResolver { delay: Duration::ZERO });
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            serve_udp_socket(
+                socket,
+                state,
+                &ServerConfig::default(),
+                Default::default(),
+                server_shutdown,
+            )
+            .await
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for (id, opcode) in [(7, DnsOpcode::Status), (8, DnsOpcode::Notify)] {
+            let mut query = DnsMessage::decode(&test_query(id)).unwrap();
+            query.flags.opcode = opcode;
+            client.send_to(&query.encode().unwrap(), server_addr).await.unwrap();
+
+            let mut buf = [0u8; 512];
+            let (len, _) = tokio::time::timeout(Duration::from_secs(2), client.recv_from(&mut buf))
+                .await
+                .expect("query should be answered")
+                .unwrap();
+            let response = DnsMessage::decode(&buf[..len]).unwrap();
+
+            assert_eq!(response.id, id);
+            assert_eq!(response.flags.opcode, opcode);
+            assert_eq!(response.response_code(), DnsResponseCode::NotImp);
+            assert_eq!(response.questions(), query.questions());
+        }
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_response_is_dropped_without_answer() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let resolver = BudgetResolver::default();
+        let state = test_state(resolver.clone());
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            serve_udp_socket(
+                socket,
+                state,
+                &ServerConfig::default(),
+                Default::default(),
+                server_shutdown,
+            )
+            .await
+        });
+
+        // a FORMERR reply to a spoofed source would otherwise be answered by that source's server again.
+        let mut response = test_query(1).to_vec();
+        response[2] |= 0x80;
+        response[3] |= 0x01;
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(&response, server_addr).await.unwrap();
+
+        let mut buf = [0u8; 512];
+        let answer = tokio::time::timeout(Duration::from_millis(300), client.recv_from(&mut buf)).await;
+        assert!(answer.is_err(), "a response should not be answered");
+        assert!(
+            resolver.timeout.lock().unwrap().is_none(),
+            "a response should not be resolved"
+        );
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_update_is_refused_without_forwarding() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let resolver = BudgetResolver::default();
+        let state = test_state(resolver.clone());
+        let shutdown = tokio_util::sync::CancellationToken::new();
+
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            serve_udp_socket(
+                socket,
+                state,
+                &ServerConfig::default(),
+                Default::default(),
+                server_shutdown,
+            )
+            .await
+        });
+
+        #[rustfmt::skip]
+        let update = [
+            0x00, 0x0A, // id
+            0x28, 0x00, // opcode 5 (UPDATE)
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, // ZOCOUNT 1, PRCOUNT 0, UPCOUNT 1, ADCOUNT 0
+            // Zone: example.com SOA IN
+            7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, 0x00, 0x06, 0x00, 0x01,
+            // Update: delete the A RRset of example.com
+            0xC0, 0x0C, 0x00, 0x01, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(&update, server_addr).await.unwrap();
+
+        let mut buf = [0u8; 512];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), client.recv_from(&mut buf))
+            .await
+            .expect("update should be answered")
+            .unwrap();
+        let response = DnsMessage::decode(&buf[..len]).unwrap();
+
+        assert_eq!(response.id, 10);
+        assert_eq!(response.flags.opcode, DnsOpcode::Update);
+        assert_eq!(response.response_code(), DnsResponseCode::Refused);
+        assert!(
+            resolver.timeout.lock().unwrap().is_none(),
+            "update should not be forwarded"
+        );
+
+        // answered before the middlewares run, but still logged.
+        assert!(logs_contain("query answered"));
+        assert!(logs_contain("rcode=Refused"));
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_oversized_response_is_truncated() {
+        let name = DomainName::from_ascii("example.com").unwrap();
+        let answers = (0..64)
+            .map(|i| {
+                DnsRecord::new(
+                    name.clone(),
+                    RecordType::A,
+                    ClassType::IN,
+                    300,
+                    DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, i)),
+                )
+            })
+            .collect();
+        let mut response = DnsMessageBuilder::new()
+            .with_response(DnsResponseCode::NoError)
+            .add_question(DnsQuestion::new(name.clone(), RecordType::A, ClassType::IN))
+            .with_answers(answers)
+            .build();
+        response.flags.response = true;
+        let server = TestServer::start(MockResolver::new().with_response(name, RecordType::A, response))
+            .await
+            .unwrap();
+
+        let query = DnsMessage::decode(&test_query(11)).unwrap();
+        let truncated = server.query(&test_query(11)).await.unwrap();
+
+        assert!(truncated.flags.truncated);
+        assert_eq!(truncated.id, 11);
+        assert_eq!(truncated.response_code(), DnsResponseCode::NoError);
+        assert_eq!(truncated.questions(), query.questions());
+        assert!(truncated.answers().is_empty());
+
+        let mut with_edns = query.clone();
+        with_edns.set_edns(Some(Edns::default()));
+        let full = server.query(&with_edns.encode().unwrap()).await.unwrap();
+
+        assert!(!full.flags.truncated);
+        assert_eq!(full.answers().len(), 64);
+
+        // only the truncated response is counted.
+        assert_eq!(server.truncation_stats().truncated, 1);
+        assert_eq!(server.truncation_stats().tcp_fallbacks, 0);
+    }
+
+    #[tokio::test]
+    async fn test_response_over_max_udp_response_is_truncated() {
+        let name = DomainName::from_ascii("example.com").unwrap();
+        let answers = (0..64)
+            .map(|i| {
+                DnsRecord::new(
+                    name.clone(),
+                    RecordType::A,
+                    ClassType::IN,
+                    300,
+                    DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, i)),
+                )
+            })
+            .collect();
+        let mut response = DnsMessageBuilder::new()
+            .with_response(DnsResponseCode::NoError)
+            .add_question(DnsQuestion::new(name.clone(), RecordType::A, ClassType::IN))
+            .with_answers(answers)
+            .build();
+        response.flags.response = true;
+        let state = ServerState {
+            resolver: Arc::new(MockResolver::new().with_response(name, RecordType::A, response)),
+            middlewares: Arc::new(vec![]),
+            global: Arc::new(()),
+            timeout: Duration::from_secs(2),
+        };
+        let config = ServerConfig {
+            recv_size: 4096,
+            max_udp_response: Some(512),
+            ..Default::default()
+        };
+        let server = TestServer::start_with(state, config).await.unwrap();
+
+        // the client's buffer fits the whole response, the server's cap doesn't.
+        let mut query = DnsMessage::decode(&test_query(14)).unwrap();
+        let mut edns = Edns::default();
+        edns.udp_payload_size = 4096;
+        query.set_edns(Some(edns));
+        let truncated = server.query(&query.encode().unwrap()).await.unwrap();
+
+        assert!(truncated.flags.truncated);
+        assert_eq!(truncated.id, 14);
+        assert_eq!(truncated.questions(), query.questions());
+        assert!(truncated.answers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_upstreams_get_servfail_with_ede() {
+        let server =
+            TestServer::start(MockResolver::new().with_error(reso_resolver::ResolveError::NoReachableUpstream))
+                .await
+                .unwrap();
+
+        let mut query = DnsMessage::decode(&test_query(12)).unwrap();
+        query.set_edns(Some(Edns::default()));
+        let response = server.query(&query.encode().unwrap()).await.unwrap();
+
+        assert_eq!(response.id, 12);
+        assert_eq!(response.response_code(), DnsResponseCode::ServerFailure);
+        let options = &response.edns().as_ref().expect("query had an OPT record").options;
+        assert!(options.iter().any(|o| matches!(
+            o.data,
+            Some(EdnsOptionData::ExtendedError {
+                info_code: ExtendedDnsErrorInfoCode::NoReachableAuthority,
+                ..
+            })
+        )));
+
+        // clients without EDNS get a plain SERVFAIL.
+        let response = server.query(&test_query(13)).await.unwrap();
+        assert_eq!(response.response_code(), DnsResponseCode::ServerFailure);
+        assert!(response.edns().is_none());
+    }
+
+    /// Answers every query with one A record, like a cache hit.
+    struct CachedAnswer;
+
+    #[async_trait::async_trait]
+    impl reso_context::DnsMiddleware<(), ()> for CachedAnswer {
+        async fn on_query(&self, ctx: &mut DnsRequestCtx<(), ()>) -> anyhow::Result<Option<reso_context::DnsResponse>> {
+            let query = ctx.message()?;
+            let mut builder = DnsMessageBuilder::new()
+                .with_id(query.id)
+                .with_flags(DnsMessage::response_from_query(query, DnsResponseCode::NoError).flags)
+                .with_questions(query.questions().to_vec())
+                .add_answer(DnsRecord::new(
+                    query.questions()[0].qname.clone(),
+                    RecordType::A,
+                    ClassType::IN,
+                    300,
+                    DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1)),
+                ));
+            if let Some(edns) = query.edns() {
+                builder = builder.with_edns(edns.clone());
+            }
+            let response = builder.build();
+            let bytes = response.encode()?;
+            Ok(Some(reso_context::DnsResponse::from_parsed(bytes, response)))
+        }
+    }
+
+    /// Re-encodes every response from its message, like middlewares that edit responses.
+    struct Reencode;
+
+    #[async_trait::async_trait]
+    impl reso_context::DnsMiddleware<(), ()> for Reencode {
+        async fn on_response(
+            &self,
+            _ctx: &mut DnsRequestCtx<(), ()>,
+            response: &mut reso_context::DnsResponse,
+        ) -> anyhow::Result<()> {
+            let message = response.message()?.clone();
+            *response = reso_context::DnsResponse::from_parsed(message.encode()?, message);
+            Ok(())
+        }
+    }
+
+    /// A query for `example.com` spelled `ExAmPlE.CoM`.
+    fn mixed_case_query(id: u16, edns: bool) -> Vec<u8> {
+        let mut query = DnsMessage::decode(&test_query(id)).unwrap();
+        if edns {
+            // differs from the receive size the server advertises.
+            let mut edns = Edns::default();
+            edns.udp_payload_size = 4096;
+            query.set_edns(Some(edns));
+        }
+        let mut raw = query.encode().unwrap().to_vec();
+        raw[12..12 + MIXED_CASE_QNAME.len()].copy_from_slice(MIXED_CASE_QNAME);
+        raw
+    }
+
+    const MIXED_CASE_QNAME: &[u8] = b"\x07ExAmPlE\x03CoM\x00";
+
+    #[tokio::test]
+    async fn test_cached_and_reencoded_response_uses_the_case_of_the_query() {
+        let state = ServerState {
+            resolver: Arc::new(MockResolver::new()),
+            middlewares: Arc::new(vec![Arc::new(Reencode), Arc::new(CachedAnswer)]),
+            global: Arc::new(()),
+            timeout: Duration::from_secs(2),
+        };
+        let server = TestServer::start_with(state, ServerConfig::default()).await.unwrap();
+
+        // with EDNS the advertised payload size is rewritten too, which re-encodes the response again.
+        for edns in [false, true] {
+            let response = server.query_raw(&mixed_case_query(21, edns)).await.unwrap();
+
+            assert_eq!(&response[12..12 + MIXED_CASE_QNAME.len()], MIXED_CASE_QNAME);
+            assert_eq!(DnsMessage::decode(&response).unwrap().answers().len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolved_response_uses_the_case_of_the_query() {
+        let name = DomainName::from_ascii("example.com").unwrap();
+        let mut response = DnsMessageBuilder::new()
+            .with_response(DnsResponseCode::NoError)
+            .add_question(DnsQuestion::new(name.clone(), RecordType::A, ClassType::IN))
+            .build();
+        response.flags.response = true;
+        let state = ServerState {
+            resolver: Arc::new(MockResolver::new().with_response(name, RecordType::A, response)),
+            middlewares: Arc::new(vec![Arc::new(Reencode)]),
+            global: Arc::new(()),
+            timeout: Duration::from_secs(2),
+        };
+        let server = TestServer::start_with(state, ServerConfig::default()).await.unwrap();
+
+        let response = server.query_raw(&mixed_case_query(22, false)).await.unwrap();
+
+        assert_eq!(&response[12..12 + MIXED_CASE_QNAME.len()], MIXED_CASE_QNAME);
+    }
+}
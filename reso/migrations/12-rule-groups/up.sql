@@ -0,0 +1,35 @@
+CREATE TABLE rule_groups (
+    id         BLOB    PRIMARY KEY NOT NULL,
+    name       TEXT    NOT NULL UNIQUE,
+    created_at INTEGER NOT NULL
+);
+
+CREATE TABLE rule_group_clients (
+    client     TEXT    PRIMARY KEY NOT NULL,
+    group_id   BLOB    NOT NULL REFERENCES rule_groups(id) ON DELETE CASCADE,
+    created_at INTEGER NOT NULL
+);
+
+-- a domain may have one rule per group besides its global one, so groups can override global rules.
+CREATE TABLE domain_rules_new (
+    id              BLOB    PRIMARY KEY NOT NULL,
+    domain          TEXT    NOT NULL,
+    action          TEXT    NOT NULL DEFAULT 'block' CHECK (action IN ('block', 'allow')),
+    created_at      INTEGER NOT NULL,
+    enabled         INTEGER NOT NULL DEFAULT 1,
+    subscription_id BLOB    REFERENCES list_subscriptions(id) ON DELETE CASCADE,
+    match_type      TEXT    NOT NULL DEFAULT 'domain' CHECK (match_type IN ('exact', 'wildcard', 'domain')),
+    -- rules without a group apply to every client.
+    group_id        BLOB    REFERENCES rule_groups(id) ON DELETE CASCADE,
+    CHECK (enabled IN (0, 1))
+);
+
+INSERT INTO domain_rules_new (id, domain, action, created_at, enabled, subscription_id, match_type)
+    SELECT id, domain, action, created_at, enabled, subscription_id, match_type FROM domain_rules;
+
+DROP TABLE domain_rules;
+ALTER TABLE domain_rules_new RENAME TO domain_rules;
+
+-- NULLs are distinct in a unique index, so global rules (without a group) are keyed on an empty group id.
+CREATE UNIQUE INDEX idx_domain_rules_domain_group ON domain_rules (domain, IFNULL(group_id, X''));
+CREATE INDEX idx_domain_rules_subscription_id ON domain_rules (subscription_id);
@@ -1,12 +1,19 @@
+use std::convert::Infallible;
+
 use anyhow::Context;
 use axum::{
     Json, Router,
     extract::{Query, State},
     middleware,
-    response::Result,
+    response::{
+        Result,
+        sse::{Event, KeepAlive, Sse},
+    },
     routing::get,
 };
+use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
+use tokio_stream::wrappers::BroadcastStream;
 
 use crate::{
     database::models::activity_log::{self, ActivityLog, ListFilter, SortColumn, SortDir},
@@ -22,6 +29,7 @@ use super::{
 pub fn create_activity_router(global: SharedGlobal) -> Router<SharedGlobal> {
     Router::new()
         .route("/", get(activity))
+        .route("/stream", get(activity_stream))
         .layer(middleware::from_fn_with_state(
             (global, AllowedAuthMethods::Session | AllowedAuthMethods::ApiKey),
             auth_middleware,
@@ -138,6 +146,39 @@ pub async fn activity(
     Ok(Json(PagedResponse::new(activities, total, top, skip)))
 }
 
+/// Push activity to the client as it is recorded.
+///
+/// Subscribers that fall too far behind skip the events they missed.
+pub async fn activity_stream(global: State<SharedGlobal>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(global.metrics.subscribe_activity()).filter_map(|log| async move {
+        let log = match log {
+            Ok(log) => log,
+            Err(e) => {
+                tracing::debug!("activity stream lagged: {}", e);
+                return None;
+            }
+        };
+
+        let activity = match Activity::try_from(log) {
+            Ok(activity) => activity,
+            Err(e) => {
+                tracing::error!("failed to convert activity: {:?}", e);
+                return None;
+            }
+        };
+
+        match Event::default().json_data(activity) {
+            Ok(event) => Some(Ok(event)),
+            Err(e) => {
+                tracing::error!("failed to serialize activity: {:?}", e);
+                None
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct Activity {
     pub timestamp: i64,
@@ -35,3 +35,25 @@ pub async fn update(global: State<SharedGlobal>, Json(config): Json<Config>) ->
     }
     Ok(Json(global.config.get_config()))
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::response::IntoResponse;
+
+    use super::*;
+    use crate::global::setup_test_global;
+
+    #[tokio::test]
+    async fn test_config_response_has_no_secrets() {
+        let global = setup_test_global().await;
+
+        let response = config(State(global)).await.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap().to_lowercase();
+
+        assert!(body.contains("\"upstreams\""));
+        for secret in ["secret", "password", "cipher", "key"] {
+            assert!(!body.contains(secret), "config response exposes {secret}");
+        }
+    }
+}
@@ -2,8 +2,10 @@ use crate::{
     database::models::{
         ListAction, MatchType,
         domain_rule::{self, DomainRule},
+        rule_group::RuleGroup,
     },
     global::SharedGlobal,
+    uuid::EntityId,
 };
 use axum::{
     Json, Router,
@@ -71,6 +73,9 @@ pub struct AddDomainPayload {
     match_type: MatchType,
     #[serde(default = "default_action")]
     action: ListAction,
+    /// Restrict the rule to the clients of this rule group.
+    #[serde(default)]
+    group_id: Option<EntityId<RuleGroup>>,
 }
 
 fn default_match_type() -> MatchType {
@@ -84,6 +89,9 @@ fn default_action() -> ListAction {
 #[derive(Deserialize)]
 pub struct DomainPayload {
     domain: String,
+    /// The rule group of the rule, the global rule if unset.
+    #[serde(default)]
+    group_id: Option<EntityId<RuleGroup>>,
 }
 
 pub async fn add_domain(
@@ -92,18 +100,24 @@ pub async fn add_domain(
 ) -> Result<StatusCode, ApiError> {
     global
         .domain_rules
-        .add_domain(&payload.domain, payload.match_type, payload.action)
+        .add_domain(&payload.domain, payload.match_type, payload.action, payload.group_id)
         .await?;
     Ok(StatusCode::CREATED)
 }
 
 pub async fn remove_domain(global: State<SharedGlobal>, Json(payload): Json<DomainPayload>) -> Result<(), ApiError> {
-    global.domain_rules.remove_domain(&payload.domain).await?;
+    global
+        .domain_rules
+        .remove_domain(&payload.domain, payload.group_id.as_ref())
+        .await?;
     Ok(())
 }
 
 pub async fn toggle_domain(global: State<SharedGlobal>, Json(payload): Json<DomainPayload>) -> Result<(), ApiError> {
-    global.domain_rules.toggle_domain(&payload.domain).await?;
+    global
+        .domain_rules
+        .toggle_domain(&payload.domain, payload.group_id.as_ref())
+        .await?;
     Ok(())
 }
 
@@ -111,6 +125,9 @@ pub async fn toggle_domain(global: State<SharedGlobal>, Json(payload): Json<Doma
 pub struct UpdateDomainPayload {
     domain: String,
     action: ListAction,
+    /// The rule group of the rule, the global rule if unset.
+    #[serde(default)]
+    group_id: Option<EntityId<RuleGroup>>,
 }
 
 pub async fn update_domain(
@@ -119,7 +136,7 @@ pub async fn update_domain(
 ) -> Result<(), ApiError> {
     global
         .domain_rules
-        .update_domain_action(&payload.domain, payload.action)
+        .update_domain_action(&payload.domain, payload.group_id.as_ref(), payload.action)
         .await?;
     Ok(())
 }
@@ -0,0 +1,176 @@
+use std::{
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{Json, Router, extract::State, http::StatusCode, routing::get};
+use serde::Serialize;
+use tokio::net::TcpStream;
+
+use crate::{database::CoreDatabasePool, global::SharedGlobal, services::config::Upstream};
+
+/// How long the result of an upstream reachability check is reused.
+const UPSTREAM_CHECK_TTL: Duration = Duration::from_secs(5);
+
+/// How long to wait for an upstream to accept a connection.
+const UPSTREAM_CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Result of an upstream reachability check.
+pub struct UpstreamCheck {
+    checked_at: Instant,
+    upstreams: Vec<SocketAddr>,
+    reachable: bool,
+}
+
+/// Unauthenticated liveness and readiness probes.
+pub fn create_health_router() -> Router<SharedGlobal> {
+    Router::new().route("/health", get(health)).route("/ready", get(ready))
+}
+
+#[derive(Serialize)]
+pub struct HealthResponse {
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct ReadinessResponse {
+    /// Whether the core database answers queries.
+    database: bool,
+    /// Whether at least one upstream accepts connections, always true when the active resolver doesn't forward.
+    upstreams: bool,
+}
+
+impl ReadinessResponse {
+    fn status_code(&self) -> StatusCode {
+        if self.database && self.upstreams {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
+
+pub async fn health() -> Json<HealthResponse> {
+    Json(HealthResponse { status: "ok" })
+}
+
+pub async fn ready(global: State<SharedGlobal>) -> (StatusCode, Json<ReadinessResponse>) {
+    let config = global.config.get_config();
+    let upstreams = config.dns.active.forwards().then(|| {
+        config
+            .dns
+            .forwarder
+            .upstreams()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|u| match u {
+                Upstream::Plain { endpoint } => endpoint.socket_addr().ok(),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let readiness = readiness(&global.core_database, &global.upstream_check, upstreams.as_deref()).await;
+    (readiness.status_code(), Json(readiness))
+}
+
+/// Check the database and, unless `upstreams` is `None` because nothing is forwarded, the upstreams.
+async fn readiness(
+    db: &CoreDatabasePool,
+    last_check: &Mutex<Option<UpstreamCheck>>,
+    upstreams: Option<&[SocketAddr]>,
+) -> ReadinessResponse {
+    let database = db
+        .interact(|c| c.query_row("SELECT 1", [], |r| r.get::<_, i64>(0)))
+        .await
+        .is_ok();
+
+    let upstreams = match upstreams {
+        Some(upstreams) => upstreams_reachable(last_check, upstreams).await,
+        None => true,
+    };
+
+    ReadinessResponse { database, upstreams }
+}
+
+/// Whether any of the upstreams accepts a TCP connection, reusing a recent result for the same upstreams.
+async fn upstreams_reachable(last_check: &Mutex<Option<UpstreamCheck>>, upstreams: &[SocketAddr]) -> bool {
+    if upstreams.is_empty() {
+        return false;
+    }
+
+    if let Some(check) = &*last_check.lock().unwrap()
+        && check.upstreams == upstreams
+        && check.checked_at.elapsed() < UPSTREAM_CHECK_TTL
+    {
+        return check.reachable;
+    }
+
+    let probes = upstreams
+        .iter()
+        .map(|addr| tokio::time::timeout(UPSTREAM_CONNECT_TIMEOUT, TcpStream::connect(*addr)));
+    let reachable = futures::future::join_all(probes)
+        .await
+        .iter()
+        .any(|r| matches!(r, Ok(Ok(_))));
+
+    *last_check.lock().unwrap() = Some(UpstreamCheck {
+        checked_at: Instant::now(),
+        upstreams: upstreams.to_vec(),
+        reachable,
+    });
+
+    reachable
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::response::IntoResponse;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::database::setup_core_test_db;
+
+    #[tokio::test]
+    async fn test_health_is_ok() {
+        assert_eq!(health().await.into_response().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_not_ready_without_upstreams() {
+        let fixture = setup_core_test_db().await.unwrap();
+
+        let readiness = readiness(&fixture.conn, &Mutex::default(), Some(&[])).await;
+
+        assert!(readiness.database);
+        assert!(!readiness.upstreams);
+        assert_eq!(readiness.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_ready_without_forwarding() {
+        let fixture = setup_core_test_db().await.unwrap();
+
+        let readiness = readiness(&fixture.conn, &Mutex::default(), None).await;
+
+        assert!(readiness.upstreams);
+        assert_eq!(readiness.status_code(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_ready_with_reachable_upstream() {
+        let fixture = setup_core_test_db().await.unwrap();
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        let readiness = readiness(
+            &fixture.conn,
+            &Mutex::default(),
+            Some(&[upstream.local_addr().unwrap()]),
+        )
+        .await;
+
+        assert!(readiness.upstreams);
+        assert_eq!(readiness.status_code(), StatusCode::OK);
+    }
+}
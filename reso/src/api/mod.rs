@@ -14,8 +14,10 @@ use axum::{
 };
 use config::create_config_router;
 use domain_rules::create_domain_rules_router;
+use health::create_health_router;
 use list_subscriptions::create_list_subscriptions_router;
 use local_records::create_local_records_router;
+use rule_groups::create_rule_groups_router;
 use stats::create_stats_router;
 use tower_http::cors::{AllowMethods, CorsLayer};
 
@@ -26,11 +28,15 @@ mod config;
 mod cookie;
 mod domain_rules;
 mod error;
+mod health;
 mod list_subscriptions;
 mod local_records;
 mod pagination;
+mod rule_groups;
 mod stats;
 
+pub use health::UpstreamCheck;
+
 use crate::global::SharedGlobal;
 
 pub async fn serve_web(
@@ -39,12 +45,14 @@ pub async fn serve_web(
     shutdown: tokio_util::sync::CancellationToken,
 ) -> anyhow::Result<()> {
     let api = Router::new()
+        .merge(create_health_router())
         .nest("/auth", create_auth_router(global.clone()))
         .nest("/stats", create_stats_router(global.clone()))
         .nest("/activity", create_activity_router(global.clone()))
         .nest("/domain-rules", create_domain_rules_router(global.clone()))
         .nest("/list-subscriptions", create_list_subscriptions_router(global.clone()))
         .nest("/local-records", create_local_records_router(global.clone()))
+        .nest("/rule-groups", create_rule_groups_router(global.clone()))
         .nest("/config", create_config_router(global.clone()))
         .nest("/api-keys", create_api_keys_router(global.clone()));
 
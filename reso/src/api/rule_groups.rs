@@ -0,0 +1,100 @@
+use std::net::IpAddr;
+
+use axum::{
+    Json, Router,
+    extract::State,
+    http::StatusCode,
+    middleware,
+    routing::{delete, get, post},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{database::models::rule_group::RuleGroup, global::SharedGlobal, uuid::EntityId};
+
+use super::{
+    auth::{AllowedAuthMethods, auth_middleware},
+    error::ApiError,
+};
+
+pub fn create_rule_groups_router(global: SharedGlobal) -> Router<SharedGlobal> {
+    Router::new()
+        .route("/", get(list))
+        .route("/", post(add))
+        .route("/", delete(remove))
+        .route("/clients", post(assign_client))
+        .route("/clients", delete(unassign_client))
+        .layer(middleware::from_fn_with_state(
+            (global, AllowedAuthMethods::Session | AllowedAuthMethods::ApiKey),
+            auth_middleware,
+        ))
+}
+
+#[derive(Serialize)]
+pub struct RuleGroupResponse {
+    pub id: EntityId<RuleGroup>,
+    pub name: String,
+    pub created_at: i64,
+    pub clients: Vec<IpAddr>,
+}
+
+pub async fn list(global: State<SharedGlobal>) -> Result<Json<Vec<RuleGroupResponse>>, ApiError> {
+    let groups = global.domain_rules.list_rule_groups().await?;
+    Ok(Json(
+        groups
+            .into_iter()
+            .map(|(group, clients)| RuleGroupResponse {
+                id: group.id,
+                name: group.name,
+                created_at: group.created_at,
+                clients,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct AddPayload {
+    name: String,
+}
+
+pub async fn add(global: State<SharedGlobal>, Json(payload): Json<AddPayload>) -> Result<StatusCode, ApiError> {
+    global.domain_rules.add_rule_group(&payload.name).await?;
+    Ok(StatusCode::CREATED)
+}
+
+#[derive(Deserialize)]
+pub struct IdPayload {
+    id: EntityId<RuleGroup>,
+}
+
+pub async fn remove(global: State<SharedGlobal>, Json(payload): Json<IdPayload>) -> Result<(), ApiError> {
+    global.domain_rules.remove_rule_group(payload.id).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct AssignClientPayload {
+    group_id: EntityId<RuleGroup>,
+    client: String,
+}
+
+pub async fn assign_client(
+    global: State<SharedGlobal>,
+    Json(payload): Json<AssignClientPayload>,
+) -> Result<(), ApiError> {
+    global
+        .domain_rules
+        .assign_client(payload.group_id, &payload.client)
+        .await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct ClientPayload {
+    client: String,
+}
+
+pub async fn unassign_client(global: State<SharedGlobal>, Json(payload): Json<ClientPayload>) -> Result<(), ApiError> {
+    global.domain_rules.unassign_client(&payload.client).await?;
+    Ok(())
+}
@@ -4,6 +4,7 @@ use axum::{
     middleware,
     routing::get,
 };
+use reso_cache::CacheStats;
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -21,7 +22,10 @@ use super::{
 pub fn create_stats_router(global: SharedGlobal) -> Router<SharedGlobal> {
     Router::new()
         .route("/live", get(live_stats))
+        .route("/cache", get(cache_stats))
+        .route("/truncation", get(truncation_stats))
         .route("/top", get(top))
+        .route("/top-blocked", get(top_blocked))
         .route("/timeline", get(timeline))
         .layer(middleware::from_fn_with_state(
             (global, AllowedAuthMethods::Session | AllowedAuthMethods::ApiKey),
@@ -33,6 +37,29 @@ pub async fn live_stats(global: State<SharedGlobal>) -> Json<LiveStats> {
     Json(global.stats.live().await)
 }
 
+pub async fn cache_stats(global: State<SharedGlobal>) -> Json<CacheStats> {
+    Json(global.cache.stats())
+}
+
+#[derive(Serialize)]
+pub struct TruncationStatsResponse {
+    /// UDP responses sent to clients with TC set.
+    pub truncated_responses: u64,
+    /// UDP responses from upstreams with TC set.
+    pub upstream_truncated: u64,
+    /// Queries retried over TCP after a truncated upstream response.
+    pub tcp_fallbacks: u64,
+}
+
+pub async fn truncation_stats(global: State<SharedGlobal>) -> Json<TruncationStatsResponse> {
+    let forwarder = global.forwarder_truncation.stats();
+    Json(TruncationStatsResponse {
+        truncated_responses: global.server_truncation.stats().truncated,
+        upstream_truncated: forwarder.truncated,
+        tcp_fallbacks: forwarder.tcp_fallbacks,
+    })
+}
+
 fn default_top() -> usize {
     10
 }
@@ -82,17 +109,24 @@ pub struct TopResponse {
 
 const MAX_TOP_LIMIT: usize = 100;
 
-pub async fn top(global: State<SharedGlobal>, query: Query<TopQuery>) -> Result<Json<TopResponse>, ApiError> {
-    let since = range_to_duration(&query.range);
-    let db = &global.metrics_database;
-
-    let db_top: i64 = query.top.try_into().map_err(|_| ApiError::bad_request())?;
+/// Validate the requested number of top entries, returning `None` if it is out of range.
+fn top_limit(top: usize) -> Option<i64> {
+    let db_top: i64 = top.try_into().ok()?;
 
     // Limit the maximum number of entries to prevent abuse
     if db_top <= 0 || db_top > MAX_TOP_LIMIT as i64 {
-        return Err(ApiError::bad_request());
+        return None;
     }
 
+    Some(db_top)
+}
+
+pub async fn top(global: State<SharedGlobal>, query: Query<TopQuery>) -> Result<Json<TopResponse>, ApiError> {
+    let since = range_to_duration(&query.range);
+    let db = &global.metrics_database;
+
+    let db_top = top_limit(query.top).ok_or_else(ApiError::bad_request)?;
+
     let (clients, domains, blocked_domains) = match tokio::join!(
         client_metrics::top_clients(db, since, db_top),
         domain_metrics::top_domains(db, since, db_top),
@@ -116,6 +150,22 @@ pub async fn top(global: State<SharedGlobal>, query: Query<TopQuery>) -> Result<
     }))
 }
 
+pub async fn top_blocked(global: State<SharedGlobal>, query: Query<TopQuery>) -> Result<Json<Vec<TopEntry>>, ApiError> {
+    let since = range_to_duration(&query.range);
+    let db_top = top_limit(query.top).ok_or_else(ApiError::bad_request)?;
+
+    let rows = domain_metrics::top_blocked(&global.metrics_database, since, db_top)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to get top blocked domains: {}", e);
+            ApiError::server_error()
+        })?;
+
+    Ok(Json(
+        rows.into_iter().map(|(name, count)| TopEntry { name, count }).collect(),
+    ))
+}
+
 #[derive(Deserialize)]
 pub struct TimelineQuery {
     #[serde(default = "default_range")]
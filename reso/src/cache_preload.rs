@@ -0,0 +1,187 @@
+use std::{net::Ipv4Addr, path::Path, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use reso_cache::DnsMessageCache;
+use reso_context::{DnsRequestCtx, RequestType};
+use reso_dns::{ClassType, DnsMessageBuilder, DnsQuestion, RecordType, domain_name::DomainName};
+use reso_resolver::DynResolver;
+
+/// Timeout for resolving a single preloaded name.
+const PRELOAD_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Parse a preload list with one `name [type]` pair per line, the type defaults to `A`.
+///
+/// Blank lines and lines starting with `#` are skipped.
+pub fn parse_preload_list(input: &str) -> anyhow::Result<Vec<(DomainName, RecordType)>> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            let mut parts = line.split_whitespace();
+            let name = parts.next().unwrap_or_default();
+            let name = DomainName::from_user(name).with_context(|| format!("line {line_number}: invalid name"))?;
+
+            let record_type = match parts.next() {
+                Some(record_type) => record_type
+                    .to_ascii_uppercase()
+                    .parse::<RecordType>()
+                    .map_err(|_| anyhow::anyhow!("line {line_number}: unknown record type {record_type}"))?,
+                None => RecordType::A,
+            };
+
+            if parts.next().is_some() {
+                anyhow::bail!("line {line_number}: expected a name and an optional record type");
+            }
+
+            Ok((name, record_type))
+        })
+        .collect()
+}
+
+/// Read a preload list from `path`, see [`parse_preload_list`].
+pub fn read_preload_list(path: &Path) -> anyhow::Result<Vec<(DomainName, RecordType)>> {
+    let input = std::fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+    parse_preload_list(&input).with_context(|| format!("failed to parse {:?}", path))
+}
+
+/// Resolve every entry through `resolver` and cache the responses, returning how many were cached.
+pub async fn preload_cache<G, L>(
+    resolver: &DynResolver<G, L>,
+    global: Arc<G>,
+    cache: &DnsMessageCache,
+    entries: &[(DomainName, RecordType)],
+) -> usize
+where
+    G: Send + Sync + 'static,
+    L: Default + Send + Sync,
+{
+    let mut cached = 0;
+
+    for (name, record_type) in entries {
+        let query = DnsMessageBuilder::new()
+            .with_id(rand::random())
+            .add_question(DnsQuestion::new(name.clone(), *record_type, ClassType::IN))
+            .build();
+        let Ok(raw) = query.encode() else {
+            continue;
+        };
+
+        let ctx = DnsRequestCtx::new(
+            PRELOAD_TIMEOUT,
+            Ipv4Addr::LOCALHOST.into(),
+            RequestType::UDP,
+            raw,
+            global.clone(),
+            L::default(),
+        );
+
+        let response = match resolver.resolve(&ctx).await {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::debug!(qname = %name, qtype = ?record_type, error = %e, "failed to preload name");
+                continue;
+            }
+        };
+
+        match response.message() {
+            Ok(message) if cache.insert(&query, message).await => cached += 1,
+            Ok(_) => {}
+            Err(e) => tracing::debug!(qname = %name, error = %e, "failed to decode preloaded response"),
+        }
+    }
+
+    cached
+}
+
+#[cfg(test)]
+mod tests {
+    use reso_cache::{CacheKey, CacheResult};
+    use reso_dns::{DnsMessage, DnsRecord, DnsResponseCode, message::DnsRecordData};
+    use reso_resolver::mock::MockResolver;
+
+    use super::*;
+
+    fn response(name: &DomainName, record_type: RecordType, data: DnsRecordData) -> DnsMessage {
+        let mut response = DnsMessageBuilder::new()
+            .with_response(DnsResponseCode::NoError)
+            .add_question(DnsQuestion::new(name.clone(), record_type, ClassType::IN))
+            .add_answer(DnsRecord::new(name.clone(), record_type, ClassType::IN, 300, data))
+            .build();
+        response.flags.response = true;
+        response
+    }
+
+    fn key(name: &DomainName, record_type: RecordType) -> CacheKey {
+        CacheKey {
+            name: name.clone(),
+            class_type: ClassType::IN,
+            record_type,
+            do_bit: false,
+        }
+    }
+
+    #[test]
+    fn test_parses_preload_list() {
+        let entries = parse_preload_list("# popular names\nexample.com\n\nexample.org aaaa\n").unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                (DomainName::from_ascii("example.com").unwrap(), RecordType::A),
+                (DomainName::from_ascii("example.org").unwrap(), RecordType::AAAA),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_preload_list() {
+        assert!(parse_preload_list("example.com NOPE").is_err());
+        assert!(parse_preload_list("example.com A extra").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_preloads_names_into_cache() {
+        let com = DomainName::from_ascii("example.com").unwrap();
+        let org = DomainName::from_ascii("example.org").unwrap();
+        let missing = DomainName::from_ascii("missing.example").unwrap();
+        let resolver = MockResolver::new()
+            .with_response(
+                com.clone(),
+                RecordType::A,
+                response(&com, RecordType::A, DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1))),
+            )
+            .with_response(
+                org.clone(),
+                RecordType::AAAA,
+                response(
+                    &org,
+                    RecordType::AAAA,
+                    DnsRecordData::Ipv6("2001:db8::1".parse().unwrap()),
+                ),
+            );
+        let cache = DnsMessageCache::default();
+
+        let entries = vec![
+            (com.clone(), RecordType::A),
+            (org.clone(), RecordType::AAAA),
+            (missing.clone(), RecordType::A),
+        ];
+        let cached = preload_cache::<(), ()>(&resolver, Arc::new(()), &cache, &entries).await;
+
+        assert_eq!(cached, 2);
+        assert!(matches!(
+            cache.lookup(&key(&com, RecordType::A)).await,
+            CacheResult::Positive { .. }
+        ));
+        assert!(matches!(
+            cache.lookup(&key(&org, RecordType::AAAA)).await,
+            CacheResult::Positive { .. }
+        ));
+        assert!(matches!(
+            cache.lookup(&key(&missing, RecordType::A)).await,
+            CacheResult::Miss
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use reso_resolver::zone::StaticResolver;
+
+use crate::{
+    database::{connect_core_db, models::config as db_config},
+    env_config::EnvConfig,
+    server_builder::ptr_resolver,
+    services::config::{ActiveResolver, Config, Upstream},
+};
+
+/// Check the configuration stored in the core database and print a report, without starting the server.
+///
+/// Returns whether the configuration is valid.
+pub async fn check_config(env: &EnvConfig) -> anyhow::Result<bool> {
+    let map = if Path::new(&env.db_path).exists() {
+        let db = connect_core_db(&env.db_path).await?;
+        db_config::all(&db).await?
+    } else {
+        println!("no database at {}, checking the default configuration", env.db_path);
+        HashMap::new()
+    };
+
+    let problems = config_problems(&map);
+    for problem in &problems {
+        println!("error: {problem}");
+    }
+
+    if problems.is_empty() {
+        println!("configuration is valid");
+    } else {
+        println!("found {} problem(s) in the configuration", problems.len());
+    }
+
+    Ok(problems.is_empty())
+}
+
+/// Problems with the configuration stored as `map`, empty if it is valid.
+fn config_problems(map: &HashMap<String, String>) -> Vec<String> {
+    let mut problems = Config::invalid_values(map);
+    let config = Config::from_kv(map);
+
+    let parsed = config.to_kv();
+    let known: HashSet<&str> = parsed.iter().map(|(key, _)| key.as_str()).collect();
+
+    let mut unknown: Vec<&String> = map.keys().filter(|key| !known.contains(key.as_str())).collect();
+    unknown.sort();
+    problems.extend(unknown.into_iter().map(|key| format!("{key}: unknown setting")));
+
+    for (i, spec) in config.dns.forwarder.upstreams.iter().enumerate() {
+        match spec.parse() {
+            Ok(Upstream::Plain { .. }) => {}
+            Ok(_) => problems.push(format!(
+                "dns.forwarder.upstreams[{i}]: {:?} is not supported yet and is skipped",
+                spec.0
+            )),
+            Err(e) => problems.push(format!("dns.forwarder.upstreams[{i}]: {e:#}")),
+        }
+    }
+
+    resolver_problems(&config, &config.dns.active, "dns.active", &mut problems);
+
+    problems
+}
+
+/// Add the problems of `resolver` at `path` to `problems`.
+fn resolver_problems(config: &Config, resolver: &ActiveResolver, path: &str, problems: &mut Vec<String>) {
+    match resolver {
+        ActiveResolver::Forwarder => {
+            if config.dns.forwarder.upstreams.is_empty() {
+                problems.push(format!("{path}: the forwarder has no upstreams"));
+            }
+        }
+        ActiveResolver::Static { zones } => {
+            if let Err(e) = StaticResolver::load(zones) {
+                problems.push(format!("{path}: {e:#}"));
+            }
+        }
+        ActiveResolver::Ptr {
+            prefixes,
+            hosts,
+            domain,
+        } => {
+            if let Err(e) = ptr_resolver(prefixes, hosts, domain.as_deref()) {
+                problems.push(format!("{path}: {e:#}"));
+            }
+        }
+        ActiveResolver::Chain { resolvers } => {
+            if resolvers.is_empty() {
+                problems.push(format!("{path}: the chain has no resolvers"));
+            }
+            for (i, resolver) in resolvers.iter().enumerate() {
+                resolver_problems(config, resolver, &format!("{path}.resolvers[{i}]"), problems);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::config::UpstreamSpec;
+
+    fn stored(config: &Config) -> HashMap<String, String> {
+        config.to_kv().into_iter().collect()
+    }
+
+    #[test]
+    fn test_valid_config_has_no_problems() {
+        let mut config = Config::default();
+        config.dns.forwarder.upstreams = vec![UpstreamSpec("1.1.1.1:53".to_string())];
+        let mut map = stored(&config);
+        // formatting of JSON values doesn't matter.
+        map.insert("dns.cache_bypass".to_string(), "[ ]".to_string());
+
+        assert_eq!(config_problems(&map), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_reports_invalid_config() {
+        let mut config = Config::default();
+        config.dns.forwarder.upstreams = vec![UpstreamSpec("1.1.1.1:notaport".to_string())];
+        config.dns.active = ActiveResolver::Chain {
+            resolvers: vec![
+                ActiveResolver::Forwarder,
+                ActiveResolver::Static {
+                    zones: vec!["/nonexistent/example.com.zone".into()],
+                },
+            ],
+        };
+        let mut map = stored(&config);
+        map.insert("dns.timout".to_string(), "3000".to_string());
+        map.insert("dns.minimal_any".to_string(), "yes".to_string());
+
+        let problems = config_problems(&map);
+
+        assert_eq!(problems.len(), 4, "{problems:#?}");
+        assert!(problems[0].starts_with("dns.minimal_any: \"yes\" is not valid"));
+        assert_eq!(problems[1], "dns.timout: unknown setting");
+        assert!(problems[2].starts_with("dns.forwarder.upstreams[0]: invalid host[:port]"));
+        assert!(problems[3].starts_with("dns.active.resolvers[1]: failed to read"));
+    }
+
+    #[test]
+    fn test_missing_database_checks_defaults() {
+        assert_eq!(
+            config_problems(&HashMap::new()),
+            vec!["dns.active: the forwarder has no upstreams"]
+        );
+    }
+}
@@ -33,6 +33,15 @@ impl DatabaseError {
                 && err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
         )
     }
+
+    pub fn is_foreign_key_violation(&self) -> bool {
+        matches!(
+            self,
+            DatabaseError::Query(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == rusqlite::ErrorCode::ConstraintViolation
+                && err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY
+        )
+    }
 }
 
 impl From<deadpool_sqlite::InteractError> for DatabaseError {
@@ -69,9 +78,22 @@ pub type CoreDatabasePool = DatabasePool<CoreDb>;
 pub type MetricsDatabasePool = DatabasePool<MetricsDb>;
 
 pub async fn connect_core_db(db_path: &str) -> anyhow::Result<CoreDatabasePool> {
+    core_db_pool(db_path, DB_POOL_SIZE)
+}
+
+/// Open a core database that lives in memory, for tests that don't need it on disk.
+///
+/// Every SQLite connection to `:memory:` gets its own database, so the pool holds a single connection that
+/// keeps the database alive as long as the pool.
+#[cfg(test)]
+pub(crate) async fn connect_core_memory_db() -> anyhow::Result<CoreDatabasePool> {
+    core_db_pool(":memory:", 1)
+}
+
+fn core_db_pool(db_path: &str, max_size: usize) -> anyhow::Result<CoreDatabasePool> {
     let pool = Config::new(db_path)
         .builder(Runtime::Tokio1)?
-        .max_size(DB_POOL_SIZE)
+        .max_size(max_size)
         .post_create(Hook::async_fn(|conn, _| {
             Box::pin(async move {
                 conn.interact(|c| {
@@ -153,16 +175,13 @@ use tempfile::NamedTempFile;
 #[cfg(test)]
 pub struct CoreDbFixture {
     pub conn: CoreDatabasePool,
-    _temp_file: NamedTempFile,
 }
 
 #[cfg(test)]
 pub(crate) async fn setup_core_test_db() -> anyhow::Result<CoreDbFixture> {
-    let _temp_file = NamedTempFile::new()?;
-    let db_path = _temp_file.path().to_str().unwrap();
-    let conn = connect_core_db(db_path).await?;
+    let conn = connect_core_memory_db().await?;
     run_core_db_migrations(&conn).await?;
-    Ok(CoreDbFixture { _temp_file, conn })
+    Ok(CoreDbFixture { conn })
 }
 
 #[cfg(test)]
@@ -184,8 +203,34 @@ pub(crate) async fn setup_metrics_test_db() -> anyhow::Result<MetricsDbFixture>
 mod tests {
     use super::*;
 
+    use crate::database::models::{
+        user::{self, User},
+        user_session::{self, UserSession},
+    };
+    use crate::uuid::EntityId;
+
     #[tokio::test]
     pub async fn test_database_setup() {
         setup_core_test_db().await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_memory_db_migrates_and_persists_across_queries() {
+        let db = connect_core_memory_db().await.unwrap();
+        run_core_db_migrations(&db).await.unwrap();
+
+        let user = User::new("admin", "hash");
+        user::insert(&db, user.clone()).await.unwrap();
+        let session = UserSession::new(user.id.clone());
+        user_session::insert(&db, session.clone()).await.unwrap();
+
+        assert_eq!(user::find_by_name(&db, "admin").await.unwrap(), Some(user));
+        assert!(user_session::find_by_id(&db, session.id).await.unwrap().is_some());
+
+        // foreign keys are enforced in memory too.
+        let err = user_session::insert(&db, UserSession::new(EntityId::new()))
+            .await
+            .unwrap_err();
+        assert!(err.is_foreign_key_violation());
+    }
 }
@@ -1,3 +1,4 @@
+use reso_context::RequestType;
 use rusqlite::{params, types::Value};
 
 use crate::database::models::Page;
@@ -132,6 +133,9 @@ pub struct Stats {
     pub cached: i64,
     pub errors: i64,
     pub sum_duration: i64,
+    pub udp: i64,
+    pub tcp: i64,
+    pub doh: i64,
 }
 
 pub async fn stats(db: &MetricsDatabasePool) -> Result<Stats, DatabaseError> {
@@ -143,10 +147,17 @@ pub async fn stats(db: &MetricsDatabasePool) -> Result<Stats, DatabaseError> {
                 COALESCE(SUM(CASE WHEN blocked = 1 THEN 1 ELSE 0 END), 0) as blocked,
                 COALESCE(SUM(CASE WHEN cache_hit = 1 THEN 1 ELSE 0 END), 0) as cached,
                 COALESCE(SUM(CASE WHEN kind = 'error' THEN 1 ELSE 0 END), 0) as errors,
-                COALESCE(SUM(dur_ms), 0) as sum_duration
+                COALESCE(SUM(dur_ms), 0) as sum_duration,
+                COALESCE(SUM(CASE WHEN transport = ?1 THEN 1 ELSE 0 END), 0) as udp,
+                COALESCE(SUM(CASE WHEN transport = ?2 THEN 1 ELSE 0 END), 0) as tcp,
+                COALESCE(SUM(CASE WHEN transport = ?3 THEN 1 ELSE 0 END), 0) as doh
             FROM activity_log
             "#,
-            [],
+            [
+                RequestType::UDP as i64,
+                RequestType::TCP as i64,
+                RequestType::DOH as i64,
+            ],
             |r| {
                 Ok(Stats {
                     total: r.get(0)?,
@@ -154,6 +165,9 @@ pub async fn stats(db: &MetricsDatabasePool) -> Result<Stats, DatabaseError> {
                     cached: r.get(2)?,
                     errors: r.get(3)?,
                     sum_duration: r.get(4)?,
+                    udp: r.get(5)?,
+                    tcp: r.get(6)?,
+                    doh: r.get(7)?,
                 })
             },
         )
@@ -813,6 +827,7 @@ mod tests {
         assert_eq!(stats.cached, 0);
         assert_eq!(stats.errors, 0);
         assert_eq!(stats.sum_duration, 0);
+        assert_eq!(stats.udp + stats.tcp + stats.doh, 0);
     }
 
     #[tokio::test]
@@ -825,7 +840,10 @@ mod tests {
         let mut blocked = make_query(2000);
         blocked.blocked = Some(true);
 
-        batch_insert(&db.conn, &[cached, blocked, make_query(3000), make_error(4000)])
+        let mut udp = make_query(3000);
+        udp.transport = RequestType::UDP as i64;
+
+        batch_insert(&db.conn, &[cached, blocked, udp, make_error(4000)])
             .await
             .unwrap();
 
@@ -836,5 +854,8 @@ mod tests {
         assert_eq!(stats.cached, 1);
         assert_eq!(stats.errors, 1);
         assert_eq!(stats.sum_duration, 10 + 10 + 10 + 50);
+        assert_eq!(stats.udp, 1);
+        assert_eq!(stats.tcp, 3);
+        assert_eq!(stats.doh, 0);
     }
 }
@@ -85,7 +85,7 @@ pub async fn top_blocked(
                  FROM metrics_by_domain
                  WHERE bucket_ts >= ?1 AND blocked_count > 0
                  GROUP BY qname
-                 ORDER BY count DESC
+                 ORDER BY count DESC, qname
                  LIMIT ?2",
         )?;
         let iter = stmt.query_map(params![since, limit], |r| Ok((r.get(0)?, r.get(1)?)))?;
@@ -269,6 +269,30 @@ mod tests {
         assert_eq!(result[1].1, 2);
     }
 
+    #[tokio::test]
+    async fn top_blocked_ranks_across_buckets() {
+        let db = setup_metrics_test_db().await.unwrap();
+        let rows = vec![
+            make_domain_metrics(1000, "ads.com", 10, 4),
+            make_domain_metrics(2000, "ads.com", 10, 4),
+            make_domain_metrics(1000, "tracker.com", 6, 6),
+            make_domain_metrics(2000, "beacon.com", 6, 6),
+            make_domain_metrics(1000, "telemetry.com", 1, 1),
+            make_domain_metrics(500, "old.com", 100, 100),
+        ];
+        batch_upsert(&db.conn, &rows).await.unwrap();
+
+        let result = top_blocked(&db.conn, 1000, 3).await.unwrap();
+        assert_eq!(
+            result,
+            vec![
+                ("ads.com".to_string(), 8),
+                ("beacon.com".to_string(), 6),
+                ("tracker.com".to_string(), 6),
+            ]
+        );
+    }
+
     use crate::metrics::task::{DAY_MS, HOUR_MS, MINUTE_MS};
 
     #[tokio::test]
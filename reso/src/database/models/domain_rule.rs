@@ -9,7 +9,7 @@ use reso_list::DomainPattern;
 use crate::{
     database::{
         CoreDatabasePool, DatabaseError,
-        models::{ListAction, MatchType, list_subscription::ListSubscription},
+        models::{ListAction, MatchType, list_subscription::ListSubscription, rule_group::RuleGroup},
         query::WhereBuilder,
     },
     time::now_millis,
@@ -25,6 +25,8 @@ pub struct DomainRule {
     pub created_at: i64,
     pub enabled: bool,
     pub subscription_id: Option<EntityId<ListSubscription>>,
+    /// The rule group this rule belongs to, rules without a group apply to every client.
+    pub group_id: Option<EntityId<RuleGroup>>,
 }
 
 impl DomainRule {
@@ -37,6 +39,7 @@ impl DomainRule {
             created_at: now_millis(),
             enabled: true,
             subscription_id: None,
+            group_id: None,
         }
     }
 
@@ -47,12 +50,25 @@ impl DomainRule {
             MatchType::Domain => DomainPattern::Domain(&self.domain),
         }
     }
+
+    fn from_row(r: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(DomainRule {
+            id: EntityId::from(r.get::<_, Uuid>(0)?),
+            domain: r.get(1)?,
+            action: r.get(2)?,
+            match_type: r.get(3)?,
+            created_at: r.get(4)?,
+            enabled: r.get(5)?,
+            subscription_id: r.get::<_, Option<Uuid>>(6)?.map(EntityId::from),
+            group_id: r.get::<_, Option<Uuid>>(7)?.map(EntityId::from),
+        })
+    }
 }
 
 pub async fn insert(db: &CoreDatabasePool, domain_rule: DomainRule) -> Result<(), DatabaseError> {
     db.interact(move |c| {
             c.execute(
-                "INSERT INTO domain_rules (id, domain, action, match_type, created_at, enabled, subscription_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                "INSERT INTO domain_rules (id, domain, action, match_type, created_at, enabled, subscription_id, group_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
                 params![
                     domain_rule.id.id(),
                     domain_rule.domain.as_str(),
@@ -61,6 +77,7 @@ pub async fn insert(db: &CoreDatabasePool, domain_rule: DomainRule) -> Result<()
                     domain_rule.created_at,
                     domain_rule.enabled,
                     domain_rule.subscription_id.as_ref().map(|id| *id.id()),
+                    domain_rule.group_id.as_ref().map(|id| *id.id()),
                 ],
             )?;
             Ok(())
@@ -69,10 +86,21 @@ pub async fn insert(db: &CoreDatabasePool, domain_rule: DomainRule) -> Result<()
     Ok(())
 }
 
-pub async fn delete(db: &CoreDatabasePool, domain: &str) -> Result<bool, DatabaseError> {
+/// Delete the rule for `domain` in `group_id`, or the global rule if `None`.
+pub async fn delete(
+    db: &CoreDatabasePool,
+    domain: &str,
+    group_id: Option<&EntityId<RuleGroup>>,
+) -> Result<bool, DatabaseError> {
     let domain = domain.to_string();
+    let group_id = group_id.map(|id| *id.id());
     let rows = db
-        .interact(move |c| c.execute("DELETE FROM domain_rules WHERE domain = ?1", params![domain]))
+        .interact(move |c| {
+            c.execute(
+                "DELETE FROM domain_rules WHERE domain = ?1 AND group_id IS ?2",
+                params![domain, group_id],
+            )
+        })
         .await?;
     Ok(rows > 0)
 }
@@ -92,7 +120,7 @@ pub async fn list(
 
         let sql = format!(
             r#"
-                    SELECT id, domain, action, match_type, created_at, enabled, subscription_id
+                    SELECT id, domain, action, match_type, created_at, enabled, subscription_id, group_id
                     FROM domain_rules
                     WHERE 1=1 {where_clause}
                     ORDER BY created_at DESC
@@ -103,42 +131,36 @@ pub async fn list(
         list_params.extend(filter_params);
 
         let mut stmt = c.prepare(&sql)?;
-        let iter = stmt.query_map(rusqlite::params_from_iter(&list_params), |r| {
-            Ok(DomainRule {
-                id: EntityId::from(r.get::<_, Uuid>(0)?),
-                domain: r.get(1)?,
-                action: r.get(2)?,
-                match_type: r.get(3)?,
-                created_at: r.get(4)?,
-                enabled: r.get(5)?,
-                subscription_id: r.get::<_, Option<Uuid>>(6)?.map(EntityId::from),
-            })
-        })?;
+        let iter = stmt.query_map(rusqlite::params_from_iter(&list_params), DomainRule::from_row)?;
         iter.collect::<rusqlite::Result<Vec<_>>>()
     })
     .await
 }
 
+/// List the enabled rules with the given action that apply to every client.
 pub async fn list_enabled_by_action(
     db: &CoreDatabasePool,
     action: ListAction,
 ) -> Result<Vec<DomainRule>, DatabaseError> {
     db.interact(move |c| {
         let mut stmt = c.prepare(
-            "SELECT id, domain, action, match_type, created_at, enabled, subscription_id \
-                 FROM domain_rules WHERE action = ?1 AND enabled = 1 ORDER BY created_at",
+            "SELECT id, domain, action, match_type, created_at, enabled, subscription_id, group_id \
+                 FROM domain_rules WHERE action = ?1 AND enabled = 1 AND group_id IS NULL ORDER BY created_at",
         )?;
-        let iter = stmt.query_map(params![action], |r| {
-            Ok(DomainRule {
-                id: EntityId::from(r.get::<_, Uuid>(0)?),
-                domain: r.get(1)?,
-                action: r.get(2)?,
-                match_type: r.get(3)?,
-                created_at: r.get(4)?,
-                enabled: r.get(5)?,
-                subscription_id: r.get::<_, Option<Uuid>>(6)?.map(EntityId::from),
-            })
-        })?;
+        let iter = stmt.query_map(params![action], DomainRule::from_row)?;
+        iter.collect::<rusqlite::Result<Vec<_>>>()
+    })
+    .await
+}
+
+/// List the enabled rules that belong to a rule group.
+pub async fn list_enabled_grouped(db: &CoreDatabasePool) -> Result<Vec<DomainRule>, DatabaseError> {
+    db.interact(move |c| {
+        let mut stmt = c.prepare(
+            "SELECT id, domain, action, match_type, created_at, enabled, subscription_id, group_id \
+                 FROM domain_rules WHERE enabled = 1 AND group_id IS NOT NULL ORDER BY created_at",
+        )?;
+        let iter = stmt.query_map([], DomainRule::from_row)?;
         iter.collect::<rusqlite::Result<Vec<_>>>()
     })
     .await
@@ -149,47 +171,50 @@ pub async fn list_all(db: &CoreDatabasePool) -> Result<Vec<DomainRule>, Database
     db.interact(move |c| {
         let mut stmt = c.prepare(
             r#"
-                    SELECT id, domain, action, match_type, created_at, enabled, subscription_id
+                    SELECT id, domain, action, match_type, created_at, enabled, subscription_id, group_id
                     FROM domain_rules
                     ORDER BY created_at
                     "#,
         )?;
-        let iter = stmt.query_map([], |r| {
-            Ok(DomainRule {
-                id: EntityId::from(r.get::<_, Uuid>(0)?),
-                domain: r.get(1)?,
-                action: r.get(2)?,
-                match_type: r.get(3)?,
-                created_at: r.get(4)?,
-                enabled: r.get(5)?,
-                subscription_id: r.get::<_, Option<Uuid>>(6)?.map(EntityId::from),
-            })
-        })?;
+        let iter = stmt.query_map([], DomainRule::from_row)?;
         iter.collect::<rusqlite::Result<Vec<_>>>()
     })
     .await
 }
 
-pub async fn update_action(db: &CoreDatabasePool, domain: &str, action: ListAction) -> Result<bool, DatabaseError> {
+/// Set the action of the rule for `domain` in `group_id`, or of the global rule if `None`.
+pub async fn update_action(
+    db: &CoreDatabasePool,
+    domain: &str,
+    group_id: Option<&EntityId<RuleGroup>>,
+    action: ListAction,
+) -> Result<bool, DatabaseError> {
     let domain = domain.to_string();
+    let group_id = group_id.map(|id| *id.id());
     let rows = db
         .interact(move |c| {
             c.execute(
-                "UPDATE domain_rules SET action = ?1, subscription_id = NULL WHERE domain = ?2",
-                params![action, domain],
+                "UPDATE domain_rules SET action = ?1, subscription_id = NULL WHERE domain = ?2 AND group_id IS ?3",
+                params![action, domain, group_id],
             )
         })
         .await?;
     Ok(rows > 0)
 }
 
-pub async fn toggle(db: &CoreDatabasePool, domain: &str) -> Result<bool, DatabaseError> {
+/// Toggle the rule for `domain` in `group_id`, or the global rule if `None`.
+pub async fn toggle(
+    db: &CoreDatabasePool,
+    domain: &str,
+    group_id: Option<&EntityId<RuleGroup>>,
+) -> Result<bool, DatabaseError> {
     let domain = domain.to_string();
+    let group_id = group_id.map(|id| *id.id());
     let rows = db
         .interact(move |c| {
             c.execute(
-                "UPDATE domain_rules SET enabled = NOT enabled WHERE domain = ?1",
-                params![domain],
+                "UPDATE domain_rules SET enabled = NOT enabled WHERE domain = ?1 AND group_id IS ?2",
+                params![domain, group_id],
             )
         })
         .await?;
@@ -235,7 +260,7 @@ pub async fn sync_subscription(
                 {
                     let new_ids: Vec<(Uuid, String, MatchType, ListAction)> = {
                         let mut stmt = tx.prepare(
-                            "SELECT s.domain, s.action, s.match_type FROM temp.domain_rules_sync_staging s WHERE NOT EXISTS (SELECT 1 FROM domain_rules WHERE domain = s.domain)",
+                            "SELECT s.domain, s.action, s.match_type FROM temp.domain_rules_sync_staging s WHERE NOT EXISTS (SELECT 1 FROM domain_rules WHERE domain = s.domain AND group_id IS NULL)",
                         )?;
                         stmt.query_map([], |r| {
                             Ok((r.get::<_, String>(0)?, r.get::<_, ListAction>(1)?, r.get::<_, MatchType>(2)?))
@@ -293,7 +318,10 @@ pub async fn count(db: &CoreDatabasePool, search: Option<String>) -> Result<i64,
 mod tests {
     use super::*;
     use crate::database::{
-        models::list_subscription::{self, ListSubscription},
+        models::{
+            list_subscription::{self, ListSubscription},
+            rule_group,
+        },
         setup_core_test_db,
     };
 
@@ -345,12 +373,12 @@ mod tests {
         let before = list(&db.conn, 1, 0, None).await.unwrap();
         assert!(before[0].enabled);
 
-        toggle(&db.conn, "toggle.com").await.unwrap();
+        toggle(&db.conn, "toggle.com", None).await.unwrap();
 
         let after = list(&db.conn, 1, 0, None).await.unwrap();
         assert!(!after[0].enabled);
 
-        toggle(&db.conn, "toggle.com").await.unwrap();
+        toggle(&db.conn, "toggle.com", None).await.unwrap();
 
         let restored = list(&db.conn, 1, 0, None).await.unwrap();
         assert!(restored[0].enabled);
@@ -364,7 +392,7 @@ mod tests {
         insert(&db.conn, rule).await.unwrap();
 
         assert_eq!(count(&db.conn, None).await.unwrap(), 1);
-        delete(&db.conn, "delete-me.com").await.unwrap();
+        delete(&db.conn, "delete-me.com", None).await.unwrap();
         assert_eq!(count(&db.conn, None).await.unwrap(), 0);
     }
 
@@ -377,6 +405,30 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[tokio::test]
+    async fn test_group_rule_for_globally_ruled_domain() {
+        let db = setup_core_test_db().await.unwrap();
+        let group = RuleGroup::new("kids".into());
+        rule_group::insert(&db.conn, group.clone()).await.unwrap();
+
+        insert(&db.conn, DomainRule::new("games.com".into())).await.unwrap();
+        let mut grouped = DomainRule::new("games.com".into());
+        grouped.group_id = Some(group.id.clone());
+        insert(&db.conn, grouped).await.unwrap();
+
+        let mut duplicate = DomainRule::new("games.com".into());
+        duplicate.group_id = Some(group.id.clone());
+        assert!(insert(&db.conn, duplicate).await.is_err());
+
+        assert!(toggle(&db.conn, "games.com", Some(&group.id)).await.unwrap());
+        assert!(delete(&db.conn, "games.com", None).await.unwrap());
+
+        let rules = list_all(&db.conn).await.unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].group_id, Some(group.id));
+        assert!(!rules[0].enabled);
+    }
+
     #[tokio::test]
     async fn test_sync_subscription() {
         let db = setup_core_test_db().await.unwrap();
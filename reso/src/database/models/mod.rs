@@ -6,6 +6,7 @@ pub mod domain_metrics;
 pub mod domain_rule;
 pub mod list_subscription;
 pub mod local_record;
+pub mod rule_group;
 pub mod user;
 pub mod user_session;
 
@@ -0,0 +1,178 @@
+use std::net::IpAddr;
+
+use rusqlite::params;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    database::{CoreDatabasePool, DatabaseError},
+    time::now_millis,
+    uuid::EntityId,
+};
+
+/// Named group of domain rules that only applies to the clients assigned to it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct RuleGroup {
+    pub id: EntityId<Self>,
+    pub name: String,
+    pub created_at: i64,
+}
+
+impl RuleGroup {
+    pub fn new(name: String) -> Self {
+        Self {
+            id: EntityId::new(),
+            name,
+            created_at: now_millis(),
+        }
+    }
+}
+
+/// Assignment of a client address to a rule group.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RuleGroupClient {
+    pub client: IpAddr,
+    pub group_id: EntityId<RuleGroup>,
+    pub created_at: i64,
+}
+
+pub async fn insert(db: &CoreDatabasePool, group: RuleGroup) -> Result<(), DatabaseError> {
+    db.interact(move |c| {
+        c.execute(
+            "INSERT INTO rule_groups (id, name, created_at) VALUES (?1, ?2, ?3)",
+            params![group.id.id(), group.name.as_str(), group.created_at],
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+pub async fn list(db: &CoreDatabasePool) -> Result<Vec<RuleGroup>, DatabaseError> {
+    db.interact(move |c| {
+        let mut stmt = c.prepare("SELECT id, name, created_at FROM rule_groups ORDER BY created_at")?;
+        let iter = stmt.query_map([], |r| {
+            Ok(RuleGroup {
+                id: EntityId::from(r.get::<_, Uuid>(0)?),
+                name: r.get(1)?,
+                created_at: r.get(2)?,
+            })
+        })?;
+        iter.collect::<rusqlite::Result<Vec<_>>>()
+    })
+    .await
+}
+
+pub async fn delete_by_id(db: &CoreDatabasePool, id: EntityId<RuleGroup>) -> Result<bool, DatabaseError> {
+    let rows = db
+        .interact(move |c| c.execute("DELETE FROM rule_groups WHERE id = ?1", params![id.id()]))
+        .await?;
+    Ok(rows > 0)
+}
+
+/// Assign a client to a group, replacing any previous assignment.
+pub async fn assign_client(
+    db: &CoreDatabasePool,
+    client: IpAddr,
+    group_id: EntityId<RuleGroup>,
+) -> Result<(), DatabaseError> {
+    let now = now_millis();
+    db.interact(move |c| {
+        c.execute(
+            "INSERT INTO rule_group_clients (client, group_id, created_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(client) DO UPDATE SET group_id = excluded.group_id",
+            params![client.to_string(), group_id.id(), now],
+        )?;
+        Ok(())
+    })
+    .await?;
+    Ok(())
+}
+
+pub async fn unassign_client(db: &CoreDatabasePool, client: IpAddr) -> Result<bool, DatabaseError> {
+    let rows = db
+        .interact(move |c| {
+            c.execute(
+                "DELETE FROM rule_group_clients WHERE client = ?1",
+                params![client.to_string()],
+            )
+        })
+        .await?;
+    Ok(rows > 0)
+}
+
+pub async fn list_clients(db: &CoreDatabasePool) -> Result<Vec<RuleGroupClient>, DatabaseError> {
+    db.interact(move |c| {
+        let mut stmt = c.prepare("SELECT client, group_id, created_at FROM rule_group_clients ORDER BY created_at")?;
+        let iter = stmt.query_map([], |r| {
+            let client: String = r.get(0)?;
+            Ok(RuleGroupClient {
+                client: client.parse().map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+                })?,
+                group_id: EntityId::from(r.get::<_, Uuid>(1)?),
+                created_at: r.get(2)?,
+            })
+        })?;
+        iter.collect::<rusqlite::Result<Vec<_>>>()
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::setup_core_test_db;
+
+    #[tokio::test]
+    async fn test_insert_and_list() {
+        let db = setup_core_test_db().await.unwrap();
+        let group = RuleGroup::new("kids".into());
+        insert(&db.conn, group.clone()).await.unwrap();
+
+        assert_eq!(list(&db.conn).await.unwrap(), vec![group]);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_name_fails() {
+        let db = setup_core_test_db().await.unwrap();
+        insert(&db.conn, RuleGroup::new("kids".into())).await.unwrap();
+
+        let err = insert(&db.conn, RuleGroup::new("kids".into())).await.unwrap_err();
+        assert!(err.is_unique_constraint_violation());
+    }
+
+    #[tokio::test]
+    async fn test_assign_client_replaces_group() {
+        let db = setup_core_test_db().await.unwrap();
+        let kids = RuleGroup::new("kids".into());
+        let adults = RuleGroup::new("adults".into());
+        insert(&db.conn, kids.clone()).await.unwrap();
+        insert(&db.conn, adults.clone()).await.unwrap();
+
+        let client: IpAddr = "192.168.1.20".parse().unwrap();
+        assign_client(&db.conn, client, kids.id).await.unwrap();
+        assign_client(&db.conn, client, adults.id.clone()).await.unwrap();
+
+        let clients = list_clients(&db.conn).await.unwrap();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].client, client);
+        assert_eq!(clients[0].group_id, adults.id);
+
+        assert!(unassign_client(&db.conn, client).await.unwrap());
+        assert!(list_clients(&db.conn).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_group_removes_clients() {
+        let db = setup_core_test_db().await.unwrap();
+        let group = RuleGroup::new("kids".into());
+        insert(&db.conn, group.clone()).await.unwrap();
+        assign_client(&db.conn, "10.0.0.2".parse().unwrap(), group.id.clone())
+            .await
+            .unwrap();
+
+        assert!(delete_by_id(&db.conn, group.id).await.unwrap());
+        assert!(list_clients(&db.conn).await.unwrap().is_empty());
+    }
+}
@@ -6,7 +6,7 @@ use std::{
     io::Write,
     net::SocketAddr,
     os::unix::fs::OpenOptionsExt,
-    path::Path,
+    path::{Path, PathBuf},
     str::FromStr,
 };
 use tracing::Level;
@@ -22,6 +22,10 @@ pub struct EnvConfig {
     pub dns_server_address: SocketAddr,
     pub http_server_address: SocketAddr,
     pub cookie_secret: [u8; 32],
+    /// File with names to resolve into the cache at startup.
+    pub cache_preload_path: Option<PathBuf>,
+    /// File the cache is saved to on shutdown and restored from at startup.
+    pub cache_snapshot_path: Option<PathBuf>,
 }
 
 impl EnvConfig {
@@ -71,6 +75,9 @@ impl EnvConfig {
 
         let cookie_secret = load_or_create_session_secret(&session_secret_path)?;
 
+        let cache_preload_path = env::var("RESO_CACHE_PRELOAD_PATH").ok().map(PathBuf::from);
+        let cache_snapshot_path = env::var("RESO_CACHE_SNAPSHOT_PATH").ok().map(PathBuf::from);
+
         Ok(Self {
             log_level,
             db_path,
@@ -78,6 +85,8 @@ impl EnvConfig {
             dns_server_address: SocketAddr::from_str(&dns_server_address)?,
             http_server_address: SocketAddr::from_str(&http_server_address)?,
             cookie_secret,
+            cache_preload_path,
+            cache_snapshot_path,
         })
     }
 }
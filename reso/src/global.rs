@@ -1,9 +1,11 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use aes_gcm::Aes256Gcm;
 use reso_cache::DnsMessageCache;
+use reso_resolver::truncation::TruncationCounters;
 
 use crate::{
+    api::UpstreamCheck,
     database::{CoreDatabasePool, MetricsDatabasePool},
     metrics::service::{MetricsHandle, Stats},
     services::{
@@ -24,7 +26,45 @@ pub struct Global {
     pub config: ConfigService,
     pub auth: AuthService,
     pub stats: Stats,
+    /// UDP responses sent to clients with TC set.
+    pub server_truncation: Arc<TruncationCounters>,
+    /// Truncated upstream responses and TCP fallbacks of the forwarder, kept across config changes.
+    pub forwarder_truncation: Arc<TruncationCounters>,
+    /// Last upstream reachability check of the readiness probe.
+    pub upstream_check: Mutex<Option<UpstreamCheck>>,
     pub core_database: Arc<CoreDatabasePool>,
     pub metrics_database: Arc<MetricsDatabasePool>,
     pub cipher: Aes256Gcm,
 }
+
+/// Global state backed by fresh test databases and the default config.
+#[cfg(test)]
+pub(crate) async fn setup_test_global() -> SharedGlobal {
+    use aes_gcm::{AesGcm, KeyInit};
+
+    use crate::{
+        database::{setup_core_test_db, setup_metrics_test_db},
+        metrics::service::MetricsService,
+    };
+
+    let core = Arc::new(setup_core_test_db().await.unwrap().conn);
+    let metrics = Arc::new(setup_metrics_test_db().await.unwrap().conn);
+    let (handle, stats, _service) = MetricsService::new(metrics.clone(), 16).await.unwrap();
+
+    Arc::new(Global {
+        cache: Default::default(),
+        domain_rules: DomainRulesService::initialize(core.clone()).await.unwrap(),
+        local_records: LocalRecordService::initialize(core.clone()).await.unwrap(),
+        api_keys: ApiKeysService::new(core.clone()),
+        config: ConfigService::initialize(core.clone()).await.unwrap(),
+        auth: AuthService::new(core.clone()),
+        cipher: AesGcm::new(&[0u8; 32].into()),
+        metrics: handle,
+        stats,
+        server_truncation: Arc::default(),
+        forwarder_truncation: Arc::default(),
+        upstream_check: Default::default(),
+        core_database: core,
+        metrics_database: metrics,
+    })
+}
@@ -7,7 +7,7 @@ use database::{connect_core_db, run_core_db_migrations};
 use env_config::EnvConfig;
 use global::{Global, SharedGlobal};
 use metrics::{service::MetricsService, task::run_metrics_truncation};
-use reso_cache::DnsMessageCache;
+use reso_cache::{CacheSnapshot, DnsMessageCache};
 use server_builder::{build_dns_server, update_server_state_on_config_changes};
 use services::{
     auth::AuthService,
@@ -26,6 +26,8 @@ use crate::{
     services::{api_keys::ApiKeysService, local_records::LocalRecordService},
 };
 mod api;
+mod cache_preload;
+mod check_config;
 mod database;
 mod env_config;
 mod global;
@@ -33,6 +35,7 @@ mod local;
 mod metrics;
 mod middleware;
 mod ratelimit;
+mod serve_stale;
 mod server_builder;
 mod services;
 mod time;
@@ -51,6 +54,13 @@ fn main() -> anyhow::Result<()> {
 }
 
 async fn run() -> anyhow::Result<()> {
+    // validates the stored configuration and exits, without binding any sockets.
+    if std::env::args().skip(1).any(|arg| arg == "--check-config") {
+        let config = EnvConfig::from_env()?;
+        let valid = check_config::check_config(&config).await?;
+        std::process::exit(if valid { 0 } else { 1 });
+    }
+
     print_logo();
 
     let (nb, _guard) = non_blocking(std::io::stdout());
@@ -86,17 +96,43 @@ async fn run() -> anyhow::Result<()> {
         cipher,
         metrics: handle,
         stats,
+        server_truncation: Arc::default(),
+        forwarder_truncation: Arc::default(),
+        upstream_check: Default::default(),
         core_database: core_db_connection,
         metrics_database: metrics_db_connection.clone(),
     });
 
+    if let Some(path) = config.cache_snapshot_path.as_deref().filter(|path| path.exists()) {
+        match CacheSnapshot::read(path) {
+            Ok(snapshot) => {
+                let total = snapshot.len();
+                let restored = global.cache.restore(snapshot).await;
+                tracing::info!("restored {} of {} cache entries", restored, total);
+            }
+            Err(e) => tracing::warn!("failed to restore cache snapshot: {:#}", e),
+        }
+    }
+
     let server = build_dns_server(global.clone()).await?;
 
+    if let Some(path) = &config.cache_preload_path {
+        let entries = cache_preload::read_preload_list(path)?;
+        let resolver = server.resolver();
+        let preload_global = global.clone();
+        tokio::spawn(async move {
+            let cached =
+                cache_preload::preload_cache(&*resolver, preload_global.clone(), &preload_global.cache, &entries).await;
+            tracing::info!("preloaded {} of {} names into the cache", cached, entries.len());
+        });
+    }
+
     let shutdown = tokio_util::sync::CancellationToken::new();
 
     let dns_udp_shutdown = shutdown.child_token();
     let dns_tcp_shutdown = shutdown.child_token();
-    let metrics_shutdown = shutdown.child_token();
+    // stopped separately once nothing records metrics anymore, so the tail of the log is flushed.
+    let metrics_shutdown = tokio_util::sync::CancellationToken::new();
     let web_shutdown = shutdown.child_token();
 
     let udp_clone = server.clone();
@@ -113,7 +149,11 @@ async fn run() -> anyhow::Result<()> {
         }
     });
 
-    let metrics_handle = tokio::spawn(metrics_service.run(metrics_shutdown.clone()));
+    let metrics_handle = tokio::spawn(
+        metrics_service
+            .with_config(global.config.subscribe())
+            .run(metrics_shutdown.clone()),
+    );
 
     let truncate_shutdown = shutdown.child_token();
     let truncate_db = metrics_db_connection.clone();
@@ -138,6 +178,22 @@ async fn run() -> anyhow::Result<()> {
     let _config_watch_handle =
         tokio::spawn(async move { update_server_state_on_config_changes(task_global, server).await });
 
+    // SIGHUP re-reads the config from the database, the task above then rebuilds the server state. The transport
+    // settings of `ServerConfig`, like `recv_size` and `max_udp_response`, are only applied on restart.
+    #[cfg(unix)]
+    {
+        let mut sighup = signal::unix::signal(signal::unix::SignalKind::hangup())?;
+        let reload_global = global.clone();
+        tokio::spawn(async move {
+            while sighup.recv().await.is_some() {
+                tracing::info!("SIGHUP received, reloading configuration");
+                if let Err(e) = reload_global.config.reload().await {
+                    tracing::error!("failed to reload configuration, keeping the current one: {:#}", e);
+                }
+            }
+        });
+    }
+
     let subscription_sync_shutdown = shutdown.child_token();
     let subscription_sync_global = global.clone();
     tokio::spawn(async move { run_subscription_sync(subscription_sync_global, subscription_sync_shutdown).await });
@@ -171,6 +227,22 @@ async fn run() -> anyhow::Result<()> {
         Err(_) => tracing::warn!("drain timeout, forcing shutdown"),
     }
 
+    if let Some(path) = &config.cache_snapshot_path {
+        let snapshot = global.cache.snapshot();
+        match snapshot.write(path) {
+            Ok(()) => tracing::info!("saved {} cache entries", snapshot.len()),
+            Err(e) => tracing::error!("failed to save cache snapshot: {:#}", e),
+        }
+    }
+
+    tracing::info!("waiting for metrics service to shut down");
+    if let Err(e) = global.metrics.shutdown(Duration::from_secs(5)).await {
+        tracing::error!("failed to flush metrics: {}", e);
+        metrics_shutdown.cancel();
+    }
+    let _ = metrics_handle.await;
+    tracing::info!("metrics service shut down");
+
     if let Err(e) = &global
         .metrics_database
         .interact(|c| c.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);"))
@@ -187,10 +259,6 @@ async fn run() -> anyhow::Result<()> {
         tracing::error!("failed to checkpoint core database: {}", e);
     }
 
-    tracing::info!("waiting for metrics service to shut down");
-    let _ = metrics_handle.await;
-    tracing::info!("metrics service shut down");
-
     tracing::info!("shutdown complete");
 
     Ok(())
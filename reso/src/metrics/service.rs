@@ -1,10 +1,12 @@
 use std::{collections::HashMap, sync::Arc, time::Duration};
 
+use reso_context::RequestType;
 use serde::Serialize;
 use tokio::{
     sync::{
-        RwLock,
+        RwLock, broadcast,
         mpsc::{self, Receiver, Sender},
+        oneshot, watch,
     },
     time::{self, MissedTickBehavior},
 };
@@ -18,10 +20,11 @@ use crate::database::{
         domain_metrics::{self, DomainMetrics},
     },
 };
+use crate::services::config::{Config, LogsConfig};
 
 pub enum MetricsMessage {
-    #[allow(dead_code)]
-    Shutdown,
+    /// Flush everything recorded so far and stop, acknowledging once the flush is done.
+    Shutdown(oneshot::Sender<()>),
     Query(QueryLogEvent),
     Error(ErrorLogEvent),
 }
@@ -30,33 +33,64 @@ pub enum MetricsMessage {
 pub struct MetricsService {
     connection: Arc<MetricsDatabasePool>,
     rx: Receiver<MetricsMessage>,
-    batch: Vec<ActivityLog>,
+    batch: Vec<BatchedLog>,
     buffer_size: usize,
     live_stats: Arc<RwLock<LiveStats>>,
+    activity_tx: broadcast::Sender<ActivityLog>,
+    client_limiter: ClientLogLimiter,
+    config_rx: Option<watch::Receiver<Arc<Config>>>,
 }
 
+/// Activity log waiting for the next flush.
+struct BatchedLog {
+    log: ActivityLog,
+    /// Whether the log is stored, it is counted in the metric buckets either way.
+    persist: bool,
+}
+
+/// Number of activity logs buffered per live subscriber before it starts missing events.
+const ACTIVITY_BROADCAST_CAPACITY: usize = 256;
+
 #[derive(Clone)]
-pub struct MetricsHandle(Sender<MetricsMessage>);
+pub struct MetricsHandle {
+    tx: Sender<MetricsMessage>,
+    activity_tx: broadcast::Sender<ActivityLog>,
+}
 
 impl MetricsHandle {
-    #[allow(dead_code)]
-    pub fn shutdown(&self) {
-        if let Err(e) = self.0.try_send(MetricsMessage::Shutdown) {
-            tracing::error!("failed to send shutdown signal to metrics service {}", e)
-        }
+    /// Stop the metrics service, returning once all events recorded before this call are written to the database.
+    pub async fn shutdown(&self, timeout: Duration) -> anyhow::Result<()> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+
+        time::timeout(timeout, async {
+            self.tx
+                .send(MetricsMessage::Shutdown(ack_tx))
+                .await
+                .map_err(|_| anyhow::anyhow!("metrics service is not running"))?;
+            ack_rx
+                .await
+                .map_err(|_| anyhow::anyhow!("metrics service stopped before flushing"))
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out waiting for metrics to flush"))?
     }
 
     pub fn query(&self, event: QueryLogEvent) {
-        if let Err(e) = self.0.try_send(MetricsMessage::Query(event)) {
+        if let Err(e) = self.tx.try_send(MetricsMessage::Query(event)) {
             tracing::error!("failed to record query metric: {}", e)
         }
     }
 
     pub fn error(&self, error: ErrorLogEvent) {
-        if let Err(e) = self.0.try_send(MetricsMessage::Error(error)) {
+        if let Err(e) = self.tx.try_send(MetricsMessage::Error(error)) {
             tracing::error!("failed to record error metric: {}", e)
         }
     }
+
+    /// Subscribe to activity logs as they are recorded.
+    pub fn subscribe_activity(&self) -> broadcast::Receiver<ActivityLog> {
+        self.activity_tx.subscribe()
+    }
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -69,6 +103,12 @@ pub struct LiveStats {
     pub cached: usize,
     /// Total errors
     pub errors: usize,
+    /// Total requests received over UDP
+    pub udp: usize,
+    /// Total requests received over TCP
+    pub tcp: usize,
+    /// Total requests received over DoH
+    pub doh: usize,
     /// Sum of the duration of all requests
     pub sum_duration: u128,
     /// Live since
@@ -80,19 +120,86 @@ impl LiveStats {
         self.total += 1;
         self.blocked += if stats.blocked { 1 } else { 0 };
         self.cached += if stats.cache_hit { 1 } else { 0 };
+        self.apply_transport(stats.transport);
         self.sum_duration += stats.dur_ms as u128
     }
     fn apply_error(&mut self, error: &ErrorLogEvent) {
         self.total += 1;
         self.errors += 1;
+        self.apply_transport(error.transport);
         self.sum_duration += error.dur_ms as u128;
     }
+    fn apply_transport(&mut self, transport: RequestType) {
+        match transport {
+            RequestType::UDP => self.udp += 1,
+            RequestType::TCP => self.tcp += 1,
+            RequestType::DOH => self.doh += 1,
+        }
+    }
 }
 
 pub struct Stats {
     query: Arc<RwLock<LiveStats>>,
 }
 
+/// Caps how many activity logs of a single client are stored per bucket interval, so a client flooding the
+/// server can't bloat the database and crowd everyone else out of the query log.
+#[derive(Default)]
+struct ClientLogLimiter {
+    /// Logs stored per client and interval before sampling starts, 0 disables the limit.
+    limit: u64,
+    /// Once a client is over the limit, 1 in this many of its logs is stored, 0 stores none.
+    sample_rate: u64,
+    /// Start of the interval `counts` belong to.
+    window_ts: i64,
+    counts: HashMap<String, u64>,
+}
+
+impl ClientLogLimiter {
+    fn configure(&mut self, config: &LogsConfig) {
+        self.limit = config.client_limit;
+        self.sample_rate = config.client_sample_rate;
+    }
+
+    /// Count a log of `client` at `ts_ms` and return whether it should be stored.
+    fn admit(&mut self, client: &str, ts_ms: i64) -> bool {
+        if self.limit == 0 {
+            return true;
+        }
+
+        let window_ts = (ts_ms / MetricsService::BUCKET_INTERVAL_MS) * MetricsService::BUCKET_INTERVAL_MS;
+        // late events of a previous interval are counted in the current one.
+        if window_ts > self.window_ts {
+            self.window_ts = window_ts;
+            self.counts.clear();
+        }
+
+        let count = match self.counts.get_mut(client) {
+            Some(count) => {
+                *count += 1;
+                *count
+            }
+            None => {
+                self.counts.insert(client.to_string(), 1);
+                1
+            }
+        };
+
+        if count <= self.limit {
+            return true;
+        }
+        if count == self.limit + 1 {
+            tracing::debug!(
+                "client {} exceeded {} activity logs per interval, sampling its logs",
+                client,
+                self.limit
+            );
+        }
+
+        self.sample_rate > 0 && (count - self.limit).is_multiple_of(self.sample_rate)
+    }
+}
+
 impl Stats {
     pub async fn init(db: &MetricsDatabasePool) -> anyhow::Result<Self> {
         let activity_stats = activity_log::stats(db).await?;
@@ -106,6 +213,9 @@ impl Stats {
                 blocked: activity_stats.blocked as usize,
                 cached: activity_stats.cached as usize,
                 errors: activity_stats.errors as usize,
+                udp: activity_stats.udp as usize,
+                tcp: activity_stats.tcp as usize,
+                doh: activity_stats.doh as usize,
                 sum_duration: activity_stats.sum_duration as u128,
                 live_since: ts_ms,
             })),
@@ -125,8 +235,12 @@ impl MetricsService {
         let live = Stats::init(&connection).await?;
 
         let (tx, rx) = mpsc::channel::<MetricsMessage>(buffer_size);
+        let (activity_tx, _) = broadcast::channel(ACTIVITY_BROADCAST_CAPACITY);
         Ok((
-            MetricsHandle(tx),
+            MetricsHandle {
+                tx,
+                activity_tx: activity_tx.clone(),
+            },
             Stats {
                 query: live.query.clone(),
             },
@@ -136,10 +250,20 @@ impl MetricsService {
                 batch: Vec::with_capacity(buffer_size),
                 buffer_size,
                 live_stats: live.query.clone(),
+                activity_tx,
+                client_limiter: ClientLogLimiter::default(),
+                config_rx: None,
             },
         ))
     }
 
+    /// Apply the log settings of `config_rx`, following any changes to them.
+    pub fn with_config(mut self, mut config_rx: watch::Receiver<Arc<Config>>) -> Self {
+        self.client_limiter.configure(&config_rx.borrow_and_update().logs);
+        self.config_rx = Some(config_rx);
+        self
+    }
+
     /// Interval for bucketing metrics in milliseconds.
     const BUCKET_INTERVAL_MS: i64 = 60_000; // 1 min.
 
@@ -158,39 +282,28 @@ impl MetricsService {
                 }
                 _ = shutdown.cancelled() => {
                     tracing::info!("shutting down metrics service");
-
-                    // drain any buffered messages before flushing
-                    while let Ok(msg) = self.rx.try_recv() {
-                        match msg {
-                            MetricsMessage::Query(ev) => {
-                                self.live_stats.write().await.apply_event(&ev);
-                                self.batch.push(ev.into_db_model());
-                            },
-                            MetricsMessage::Error(ev) => {
-                                self.live_stats.write().await.apply_error(&ev);
-                                self.batch.push(ev.into_db_model());
-                            },
-                            MetricsMessage::Shutdown => break,
-                        }
-                    }
-
-                    self.flush_events().await;
+                    self.drain_and_flush(Vec::new()).await;
                     break;
                 },
                 msg = self.rx.recv() => {
                     match msg {
-                        None | Some(MetricsMessage::Shutdown) => {
+                        None => {
                             tracing::info!("shutting down metrics service");
                             self.flush_events().await;
                             break;
                         },
+                        Some(MetricsMessage::Shutdown(ack)) => {
+                            tracing::info!("shutting down metrics service");
+                            self.drain_and_flush(vec![ack]).await;
+                            break;
+                        },
                         Some(MetricsMessage::Query(ev)) => {
                             self.live_stats.write().await.apply_event(&ev);
-                            self.batch.push(ev.into_db_model());
+                            self.record(ev.into_db_model());
                         },
                         Some(MetricsMessage::Error(ev)) => {
                             self.live_stats.write().await.apply_error(&ev);
-                            self.batch.push(ev.into_db_model());
+                            self.record(ev.into_db_model());
                         }
                     }
                 }
@@ -200,6 +313,44 @@ impl MetricsService {
         Ok(())
     }
 
+    /// Record any messages still buffered in the channel, flush them and acknowledge pending shutdown requests.
+    async fn drain_and_flush(&mut self, mut acks: Vec<oneshot::Sender<()>>) {
+        while let Ok(msg) = self.rx.try_recv() {
+            match msg {
+                MetricsMessage::Query(ev) => {
+                    self.live_stats.write().await.apply_event(&ev);
+                    self.record(ev.into_db_model());
+                }
+                MetricsMessage::Error(ev) => {
+                    self.live_stats.write().await.apply_error(&ev);
+                    self.record(ev.into_db_model());
+                }
+                MetricsMessage::Shutdown(ack) => acks.push(ack),
+            }
+        }
+
+        self.flush_events().await;
+
+        for ack in acks {
+            // the caller may have given up waiting.
+            let _ = ack.send(());
+        }
+    }
+
+    /// Queue an activity log for the next flush and publish it to live subscribers.
+    fn record(&mut self, log: ActivityLog) {
+        if let Some(config_rx) = &mut self.config_rx
+            && config_rx.has_changed().unwrap_or(false)
+        {
+            self.client_limiter.configure(&config_rx.borrow_and_update().logs);
+        }
+
+        // sending only fails when nobody is subscribed.
+        let _ = self.activity_tx.send(log.clone());
+        let persist = self.client_limiter.admit(&log.client, log.ts_ms);
+        self.batch.push(BatchedLog { log, persist });
+    }
+
     async fn flush_events(&mut self) {
         if self.batch.is_empty() {
             return;
@@ -208,7 +359,7 @@ impl MetricsService {
         let mut client_map: HashMap<(i64, String), ClientMetrics> = HashMap::with_capacity(self.batch.len());
         let mut domain_map: HashMap<(i64, String), DomainMetrics> = HashMap::with_capacity(self.batch.len());
 
-        for event in &self.batch {
+        for BatchedLog { log: event, .. } in &self.batch {
             // floor to nearest bucket interval
             let bucket_ts = (event.ts_ms / Self::BUCKET_INTERVAL_MS) * Self::BUCKET_INTERVAL_MS;
 
@@ -259,13 +410,17 @@ impl MetricsService {
             Err(e) => tracing::error!("failed to upsert domain metrics: {}", e),
         }
 
-        match activity_log::batch_insert(&self.connection, &self.batch).await {
-            Ok(()) => tracing::debug!("flushed {} activity logs", self.batch.len()),
+        let logs: Vec<ActivityLog> = self
+            .batch
+            .drain(..)
+            .filter_map(|batched| batched.persist.then_some(batched.log))
+            .collect();
+
+        match activity_log::batch_insert(&self.connection, &logs).await {
+            Ok(()) => tracing::debug!("flushed {} activity logs", logs.len()),
             Err(e) => tracing::error!("failed to insert activity logs: {}", e),
         }
 
-        self.batch.clear();
-
         // during high loads, it's possible for the batch to grow outside of the original buffer capacity.
         // this is fine, but we want to shrink it back down to save memory once the load subsides.
         if self.batch.capacity() >= self.buffer_size.saturating_mul(2) {
@@ -273,3 +428,185 @@ impl MetricsService {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use reso_dns::{DnsResponseCode, domain_name::DomainName, message::RecordType};
+
+    use super::*;
+    use crate::database::setup_metrics_test_db;
+
+    fn empty_stats() -> LiveStats {
+        LiveStats {
+            total: 0,
+            blocked: 0,
+            cached: 0,
+            errors: 0,
+            udp: 0,
+            tcp: 0,
+            doh: 0,
+            sum_duration: 0,
+            live_since: 0,
+        }
+    }
+
+    fn make_event(transport: RequestType) -> QueryLogEvent {
+        QueryLogEvent {
+            ts_ms: 0,
+            transport,
+            client: "127.0.0.1".to_string(),
+            qname: DomainName::from_ascii("example.com").unwrap(),
+            qtype: RecordType::A,
+            rcode: DnsResponseCode::NoError,
+            dur_ms: 1,
+            cache_hit: false,
+            blocked: false,
+            rate_limited: false,
+        }
+    }
+
+    #[test]
+    fn test_apply_event_counts_per_transport() {
+        let mut stats = empty_stats();
+
+        stats.apply_event(&make_event(RequestType::UDP));
+        stats.apply_event(&make_event(RequestType::UDP));
+        stats.apply_event(&make_event(RequestType::TCP));
+        stats.apply_event(&make_event(RequestType::DOH));
+
+        assert_eq!(stats.total, 4);
+        assert_eq!(stats.udp, 2);
+        assert_eq!(stats.tcp, 1);
+        assert_eq!(stats.doh, 1);
+    }
+
+    #[test]
+    fn test_apply_error_counts_per_transport() {
+        let mut stats = empty_stats();
+
+        stats.apply_error(&ErrorLogEvent {
+            ts_ms: 0,
+            transport: RequestType::DOH,
+            client: "127.0.0.1".to_string(),
+            message: "timeout".to_string(),
+            r#type: reso_context::ErrorType::Timeout,
+            dur_ms: 1,
+            qname: None,
+            qtype: None,
+        });
+
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.doh, 1);
+        assert_eq!(stats.udp + stats.tcp, 0);
+    }
+
+    #[tokio::test]
+    async fn test_recorded_activity_is_broadcast() {
+        let fixture = setup_metrics_test_db().await.unwrap();
+        let (handle, _stats, service) = MetricsService::new(Arc::new(fixture.conn), 16).await.unwrap();
+        let mut activity = handle.subscribe_activity();
+
+        let shutdown = tokio_util::sync::CancellationToken::new();
+        let service_shutdown = shutdown.clone();
+        let service = tokio::spawn(async move { service.run(service_shutdown).await });
+
+        handle.query(make_event(RequestType::UDP));
+
+        let log = tokio::time::timeout(Duration::from_secs(2), activity.recv())
+            .await
+            .expect("activity should be broadcast")
+            .unwrap();
+        assert_eq!(log.kind, "query");
+        assert_eq!(log.qname.as_deref(), Some("example.com"));
+
+        shutdown.cancel();
+        service.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_flushes_buffered_events() {
+        let fixture = setup_metrics_test_db().await.unwrap();
+        let conn = Arc::new(fixture.conn);
+        let (handle, _stats, service) = MetricsService::new(conn.clone(), 16).await.unwrap();
+
+        // the periodic flush never fires within the test, so only shutdown writes the events.
+        let service = tokio::spawn(service.run(tokio_util::sync::CancellationToken::new()));
+
+        for transport in [RequestType::UDP, RequestType::TCP, RequestType::DOH] {
+            handle.query(make_event(transport));
+        }
+        handle.error(ErrorLogEvent {
+            ts_ms: 0,
+            transport: RequestType::UDP,
+            client: "127.0.0.1".to_string(),
+            message: "timeout".to_string(),
+            r#type: reso_context::ErrorType::Timeout,
+            dur_ms: 1,
+            qname: None,
+            qtype: None,
+        });
+
+        handle.shutdown(Duration::from_secs(5)).await.unwrap();
+        service.await.unwrap().unwrap();
+
+        let stats = activity_log::stats(&conn).await.unwrap();
+        assert_eq!(stats.total, 4);
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.udp, 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_logs_are_sampled_over_the_limit() {
+        let fixture = setup_metrics_test_db().await.unwrap();
+        let conn = Arc::new(fixture.conn);
+        let (handle, stats, service) = MetricsService::new(conn.clone(), 256).await.unwrap();
+
+        let mut config = Config::default();
+        config.logs.client_limit = 10;
+        config.logs.client_sample_rate = 20;
+        let (_config_tx, config_rx) = watch::channel(Arc::new(config));
+        let service = tokio::spawn(
+            service
+                .with_config(config_rx)
+                .run(tokio_util::sync::CancellationToken::new()),
+        );
+
+        // a burst of 100 queries from one client within the same interval, and one from another client.
+        for _ in 0..100 {
+            handle.query(make_event(RequestType::UDP));
+        }
+        let mut other = make_event(RequestType::UDP);
+        other.client = "192.0.2.1".to_string();
+        handle.query(other);
+
+        handle.shutdown(Duration::from_secs(5)).await.unwrap();
+        service.await.unwrap().unwrap();
+
+        // the first 10 and 1 in 20 of the remaining 90, plus the other client's query.
+        let persisted = activity_log::stats(&conn).await.unwrap();
+        assert_eq!(persisted.total, 10 + 4 + 1);
+
+        assert_eq!(stats.live().await.total, 101);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_fails_when_service_is_not_running() {
+        let fixture = setup_metrics_test_db().await.unwrap();
+        let (handle, _stats, service) = MetricsService::new(Arc::new(fixture.conn), 16).await.unwrap();
+        drop(service);
+
+        assert!(handle.shutdown(Duration::from_secs(1)).await.is_err());
+    }
+
+    #[test]
+    fn test_live_stats_serializes_transport_counters() {
+        let mut stats = empty_stats();
+        stats.apply_event(&make_event(RequestType::TCP));
+
+        let json = serde_json::to_value(&stats).unwrap();
+
+        assert_eq!(json["udp"], 0);
+        assert_eq!(json["tcp"], 1);
+        assert_eq!(json["doh"], 0);
+    }
+}
@@ -18,9 +18,6 @@ static ICLOUD_RELAY_DOMAINS: LazyLock<Vec<DomainName>> = LazyLock::new(|| {
 static DESIGNATED_RESOLVER_ZONE: LazyLock<DomainName> =
     LazyLock::new(|| DomainName::from_ascii("resolver.arpa").unwrap());
 
-static DESIGNATED_RESOLVER_SUFFIX: LazyLock<String> =
-    LazyLock::new(|| format!(".{}", DESIGNATED_RESOLVER_ZONE.as_str()));
-
 static FIREFOX_CANARY_DOMAIN: LazyLock<DomainName> =
     LazyLock::new(|| DomainName::from_ascii("use-application-dns.net").unwrap());
 
@@ -82,9 +79,7 @@ impl DnsMiddleware<Global, Local> for BlockResolverPrivacyMiddleware {
             }
 
             // Designated Resolver
-            if config.dns.security.block_designated_resolver
-                && (qname == &*DESIGNATED_RESOLVER_ZONE || qname.ends_with(&*DESIGNATED_RESOLVER_SUFFIX))
-            {
+            if config.dns.security.block_designated_resolver && qname.is_subdomain_of(&DESIGNATED_RESOLVER_ZONE) {
                 let flags = build_flags(message);
                 let builder = DnsMessageBuilder::new()
                     .with_id(message.id)
@@ -106,14 +101,8 @@ impl DnsMiddleware<Global, Local> for BlockResolverPrivacyMiddleware {
 }
 
 fn build_flags(message: &DnsMessage) -> DnsFlags {
-    DnsFlags::new(
-        true,
-        message.flags.opcode,
-        false,
-        false,
-        message.flags.recursion_desired,
-        true,
-        false,
-        message.flags.checking_disabled,
-    )
+    DnsFlags::response()
+        .with_opcode(message.flags.opcode)
+        .with_recursion_desired(message.flags.recursion_desired)
+        .with_checking_disabled(message.flags.checking_disabled)
 }
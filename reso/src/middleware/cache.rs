@@ -1,96 +1,173 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use async_trait::async_trait;
-use reso_cache::{CacheKey, CacheResult, NegKind};
+use reso_cache::{CacheKey, CacheResult, NegKind, NegativeResult};
 use reso_context::{DnsMiddleware, DnsRequestCtx, DnsResponse};
-use reso_dns::{DnsFlags, DnsMessage, DnsMessageBuilder, DnsOpcode, DnsResponseCode, message::EdnsOptionCode};
+use reso_dns::{DnsFlags, DnsMessage, DnsMessageBuilder, DnsRecord, DnsResponseCode, message::EdnsOptionCode};
+use reso_list::{DomainListMatcher, DomainPattern, NormalizedDomain};
 
 use crate::{global::Global, local::Local, middleware::echo_edns};
 
 fn cache_response_flags(query: &DnsMessage) -> DnsFlags {
-    DnsFlags::new(
-        true,
-        DnsOpcode::Query,
-        false,
-        false,
-        query.flags.recursion_desired,
-        true,
-        false,
-        query.flags.checking_disabled,
+    DnsFlags::response()
+        .with_recursion_desired(query.flags.recursion_desired)
+        .with_checking_disabled(query.flags.checking_disabled)
+}
+
+/// Rotate the records of every RRset left by `shift`.
+///
+/// Records of one RRset are adjacent in a cached answer, so each run with the same name, type and class is
+/// rotated on its own and the order of the RRsets themselves, e.g. a CNAME chain, is kept.
+fn rotate_rrsets(records: &mut [DnsRecord], shift: usize) {
+    let mut rest = records;
+    while let Some(first) = rest.first() {
+        let len = rest
+            .iter()
+            .take_while(|r| r.name == first.name && r.record_type == first.record_type && r.class == first.class)
+            .count();
+        let (rrset, tail) = rest.split_at_mut(len);
+        rrset.rotate_left(shift % len);
+        rest = tail;
+    }
+}
+
+/// Build the NXDOMAIN or NODATA response for a cached denial of `query`.
+pub(crate) fn negative_response(query: &DnsMessage, result: NegativeResult) -> DnsMessage {
+    let response_code = match result.kind {
+        NegKind::NxDomain => DnsResponseCode::NxDomain,
+        NegKind::NoData => DnsResponseCode::NoError,
+    };
+
+    echo_edns(
+        query,
+        DnsMessageBuilder::new()
+            .with_id(query.id)
+            .with_flags(cache_response_flags(query))
+            .with_response(response_code)
+            .with_questions(query.questions().to_vec())
+            .with_answers(result.answer_records.to_vec())
+            .with_authority_records(vec![result.soa_record]),
     )
+    .build()
+}
+
+/// Build the response for a cache lookup, or `None` on a miss.
+///
+/// Positive answers have their RRsets rotated by `rotation`, see [`rotate_rrsets`].
+/// The response keeps the message it was encoded from, so later middlewares don't decode it again.
+fn cached_response(query: &DnsMessage, result: CacheResult, rotation: usize) -> anyhow::Result<Option<DnsResponse>> {
+    let message = match result {
+        CacheResult::Negative(result) => negative_response(query, result),
+
+        CacheResult::Positive { records, ttl } => {
+            let mut answers: Vec<_> = records
+                .iter()
+                .cloned()
+                .map(|mut r| {
+                    r.ttl = ttl;
+                    r
+                })
+                .collect();
+            rotate_rrsets(&mut answers, rotation);
+
+            let builder = DnsMessageBuilder::new()
+                .with_id(query.id)
+                .with_flags(cache_response_flags(query))
+                .with_questions(query.questions().to_vec())
+                .with_answers(answers);
+
+            echo_edns(query, builder).build()
+        }
+
+        CacheResult::Miss => return Ok(None),
+    };
+
+    let bytes = message.encode()?;
+    Ok(Some(DnsResponse::from_parsed(bytes, message)))
 }
 
 /// Caching middleware that serves responses from cache if available.
-pub struct CacheMiddleware;
+pub struct CacheMiddleware {
+    rotate_answers: bool,
+    hits: AtomicUsize,
+    /// Names that are never looked up in or inserted into the cache.
+    bypass: Option<DomainListMatcher>,
+}
 
-#[async_trait]
-impl DnsMiddleware<Global, Local> for CacheMiddleware {
-    async fn on_query(&self, ctx: &mut DnsRequestCtx<Global, Local>) -> anyhow::Result<Option<DnsResponse>> {
-        let message = ctx.message()?;
+impl CacheMiddleware {
+    /// Create a cache middleware, optionally rotating the order of cached RRsets on every hit.
+    pub fn new(rotate_answers: bool) -> Self {
+        Self {
+            rotate_answers,
+            hits: AtomicUsize::new(0),
+            bypass: None,
+        }
+    }
+
+    /// Never cache `names` and their subdomains, so they are always resolved fresh. Invalid names are skipped.
+    pub fn with_bypass<'a>(mut self, names: impl IntoIterator<Item = &'a str>) -> Self {
+        let patterns: Vec<_> = names
+            .into_iter()
+            .filter(|name| reso_list::normalize(name).is_ok())
+            .map(DomainPattern::Domain)
+            .collect();
+        if !patterns.is_empty() {
+            self.bypass = DomainListMatcher::load(patterns).ok();
+        }
+        self
+    }
 
-        // Skip cache if the query has EDNS Client Subnet.
-        let has_ecs = message
+    /// Whether responses to `query` may be served from and stored in the cache.
+    ///
+    /// Queries with EDNS Client Subnet and queries for bypassed names are always resolved.
+    fn is_cacheable(&self, query: &DnsMessage) -> bool {
+        let has_ecs = query
             .edns()
             .as_ref()
-            .map(|e| e.options.iter().any(|o| o.code == EdnsOptionCode::ClientSubnet))
-            .unwrap_or(false);
-
+            .is_some_and(|e| e.options.iter().any(|o| o.code == EdnsOptionCode::ClientSubnet));
         if has_ecs {
-            return Ok(None);
+            return false;
         }
 
-        let cache_key = CacheKey::try_from(message)?;
+        let Some(bypass) = &self.bypass else {
+            return true;
+        };
 
-        let mut cache_hit = false;
+        query.questions().first().is_none_or(|q| {
+            NormalizedDomain::from_labels(q.qname.label_iter()).is_ok_and(|name| !bypass.matches(&name))
+        })
+    }
 
-        let resp = match ctx.global().cache.lookup(&cache_key).await {
-            CacheResult::Negative(result) => {
-                cache_hit = true;
-                let response_code = match result.kind {
-                    NegKind::NxDomain => DnsResponseCode::NxDomain,
-                    NegKind::NoData => DnsResponseCode::NoError,
-                };
+    /// How far to rotate the RRsets of the next cache hit.
+    fn next_rotation(&self) -> usize {
+        if self.rotate_answers {
+            self.hits.fetch_add(1, Ordering::Relaxed)
+        } else {
+            0
+        }
+    }
+}
 
-                let builder = echo_edns(
-                    message,
-                    DnsMessageBuilder::new()
-                        .with_id(message.id)
-                        .with_flags(cache_response_flags(message))
-                        .with_response(response_code)
-                        .with_questions(message.questions().to_vec())
-                        .with_answers(result.answer_records.to_vec())
-                        .with_authority_records(vec![result.soa_record]),
-                );
-
-                let bytes = builder.build().encode()?;
-                Ok(Some(DnsResponse::from_bytes(bytes)))
-            }
-
-            CacheResult::Positive { records, ttl } => {
-                cache_hit = true;
-
-                let answers: Vec<_> = records
-                    .iter()
-                    .cloned()
-                    .map(|mut r| {
-                        r.ttl = ttl;
-                        r
-                    })
-                    .collect();
+#[async_trait]
+impl DnsMiddleware<Global, Local> for CacheMiddleware {
+    async fn on_query(&self, ctx: &mut DnsRequestCtx<Global, Local>) -> anyhow::Result<Option<DnsResponse>> {
+        let message = ctx.message()?;
+
+        if !self.is_cacheable(message) {
+            return Ok(None);
+        }
 
-                let builder = DnsMessageBuilder::new()
-                    .with_id(message.id)
-                    .with_flags(cache_response_flags(message))
-                    .with_questions(message.questions().to_vec())
-                    .with_answers(answers);
+        let cache_key = CacheKey::try_from(message)?;
 
-                let bytes = echo_edns(message, builder).build().encode()?;
-                Ok(Some(DnsResponse::from_bytes(bytes)))
-            }
+        let result = ctx.global().cache.lookup(&cache_key).await;
+        ctx.local_mut().cache_hit = !matches!(result, CacheResult::Miss);
 
-            CacheResult::Miss => Ok(None),
+        let rotation = match result {
+            CacheResult::Positive { .. } => self.next_rotation(),
+            _ => 0,
         };
 
-        ctx.local_mut().cache_hit = cache_hit;
-        return resp;
+        cached_response(ctx.message()?, result, rotation)
     }
 
     async fn on_response(
@@ -100,13 +177,8 @@ impl DnsMiddleware<Global, Local> for CacheMiddleware {
     ) -> anyhow::Result<()> {
         let message = ctx.message()?;
 
-        let has_ecs = message
-            .edns()
-            .as_ref()
-            .map(|e| e.options.iter().any(|o| o.code == EdnsOptionCode::ClientSubnet))
-            .unwrap_or(false);
-
-        let should_cache = !ctx.local().cache_hit && !has_ecs && !ctx.local().blocked && !ctx.local().rate_limited;
+        let should_cache =
+            !ctx.local().cache_hit && !ctx.local().blocked && !ctx.local().rate_limited && self.is_cacheable(message);
 
         if should_cache {
             ctx.global().cache.insert(message, response.message()?).await;
@@ -115,3 +187,217 @@ impl DnsMiddleware<Global, Local> for CacheMiddleware {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, sync::Arc};
+
+    use reso_cache::NegativeResult;
+    use reso_dns::{
+        ClassType, DnsQuestion, DnsRecord, EdnsBuilder, RecordType, domain_name::DomainName, message::DnsRecordData,
+    };
+
+    use super::*;
+
+    fn query() -> DnsMessage {
+        DnsMessageBuilder::new()
+            .with_id(7)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+            ))
+            .build()
+    }
+
+    fn query_for(name: &str) -> DnsMessage {
+        DnsMessageBuilder::new()
+            .with_id(7)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii(name).unwrap(),
+                RecordType::A,
+                ClassType::IN,
+            ))
+            .build()
+    }
+
+    #[test]
+    fn test_bypassed_names_are_never_cached() {
+        let cache = CacheMiddleware::new(false).with_bypass(["internal.lan", "Dyn.Example.COM."]);
+
+        assert!(!cache.is_cacheable(&query_for("internal.lan")));
+        assert!(!cache.is_cacheable(&query_for("host.internal.lan")));
+        assert!(!cache.is_cacheable(&query_for("a.dyn.example.com")));
+
+        assert!(cache.is_cacheable(&query_for("notinternal.lan")));
+        assert!(cache.is_cacheable(&query_for("example.com")));
+    }
+
+    #[test]
+    fn test_client_subnet_queries_are_never_cached() {
+        let mut query = query_for("example.com");
+        query.set_edns(Some(
+            EdnsBuilder::new()
+                .add_client_subnet("192.0.2.1".parse().unwrap(), 24)
+                .build(),
+        ));
+
+        assert!(!CacheMiddleware::new(false).is_cacheable(&query));
+        assert!(CacheMiddleware::new(false).is_cacheable(&query_for("example.com")));
+    }
+
+    #[test]
+    fn test_positive_hit_is_served_decoded() {
+        let record = DnsRecord::new(
+            DomainName::from_ascii("example.com").unwrap(),
+            RecordType::A,
+            ClassType::IN,
+            300,
+            DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1)),
+        );
+        let result = CacheResult::Positive {
+            records: Arc::from([record]),
+            ttl: 42,
+        };
+
+        let response = cached_response(&query(), result, 0).unwrap().unwrap();
+
+        assert!(response.is_decoded());
+        let message = response.message().unwrap();
+        assert_eq!(message.id, 7);
+        assert_eq!(message.response_code(), DnsResponseCode::NoError);
+        assert_eq!(message.answers()[0].ttl, 42);
+        assert_eq!(
+            DnsMessage::decode(&response.bytes()).unwrap().answers(),
+            message.answers()
+        );
+    }
+
+    // RD=0 queries can't be forwarded, but may still be answered from the cache.
+    #[test]
+    fn test_hit_is_served_without_recursion_desired() {
+        let mut query = query();
+        query.flags.recursion_desired = false;
+        let result = CacheResult::Positive {
+            records: Arc::from([DnsRecord::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+                300,
+                DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1)),
+            )]),
+            ttl: 42,
+        };
+
+        let response = cached_response(&query, result, 0).unwrap().unwrap();
+        let message = response.message().unwrap();
+
+        assert!(!message.flags.recursion_desired);
+        assert_eq!(message.answers().len(), 1);
+    }
+
+    #[test]
+    fn test_negative_hit_is_served_decoded() {
+        let soa = DnsRecord::new(
+            DomainName::from_ascii("example.com").unwrap(),
+            RecordType::SOA,
+            ClassType::IN,
+            300,
+            DnsRecordData::SOA {
+                mname: DomainName::from_ascii("ns1.example.com").unwrap(),
+                rname: DomainName::from_ascii("hostmaster.example.com").unwrap(),
+                serial: 1,
+                refresh: 7200,
+                retry: 3600,
+                expire: 1209600,
+                minimum: 300,
+            },
+        );
+        let result = CacheResult::Negative(NegativeResult {
+            kind: NegKind::NxDomain,
+            soa_record: soa,
+            answer_records: Arc::from([]),
+        });
+
+        let response = cached_response(&query(), result, 0).unwrap().unwrap();
+
+        assert!(response.is_decoded());
+        assert_eq!(response.message().unwrap().response_code(), DnsResponseCode::NxDomain);
+    }
+
+    #[test]
+    fn test_miss_has_no_response() {
+        assert!(cached_response(&query(), CacheResult::Miss, 0).unwrap().is_none());
+    }
+
+    fn record(name: &str, record_type: RecordType, data: DnsRecordData) -> DnsRecord {
+        DnsRecord::new(
+            DomainName::from_ascii(name).unwrap(),
+            record_type,
+            ClassType::IN,
+            300,
+            data,
+        )
+    }
+
+    fn a(name: &str, last: u8) -> DnsRecord {
+        record(name, RecordType::A, DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, last)))
+    }
+
+    #[test]
+    fn test_successive_hits_rotate_rrset() {
+        let cache = CacheMiddleware::new(true);
+        let records: Arc<[DnsRecord]> = Arc::from([a("example.com", 1), a("example.com", 2), a("example.com", 3)]);
+
+        let orders: Vec<Vec<DnsRecord>> = (0..4)
+            .map(|_| {
+                let result = CacheResult::Positive {
+                    records: records.clone(),
+                    ttl: 42,
+                };
+                let response = cached_response(&query(), result, cache.next_rotation())
+                    .unwrap()
+                    .unwrap();
+                response
+                    .message()
+                    .unwrap()
+                    .answers()
+                    .iter()
+                    .cloned()
+                    .map(|mut r| {
+                        r.ttl = 300;
+                        r
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let expected = |order: [u8; 3]| order.map(|last| a("example.com", last)).to_vec();
+        assert_eq!(orders[0], expected([1, 2, 3]));
+        assert_eq!(orders[1], expected([2, 3, 1]));
+        assert_eq!(orders[2], expected([3, 1, 2]));
+        assert_eq!(orders[3], expected([1, 2, 3]));
+    }
+
+    #[test]
+    fn test_rotation_keeps_rrsets_in_place() {
+        let cname = record(
+            "www.example.com",
+            RecordType::CNAME,
+            DnsRecordData::DomainName(DomainName::from_ascii("example.com").unwrap()),
+        );
+        let mut records = vec![cname.clone(), a("example.com", 1), a("example.com", 2)];
+
+        rotate_rrsets(&mut records, 1);
+
+        assert_eq!(records, vec![cname, a("example.com", 2), a("example.com", 1)]);
+    }
+
+    #[test]
+    fn test_rotation_disabled_keeps_order() {
+        let cache = CacheMiddleware::new(false);
+
+        assert_eq!(cache.next_rotation(), 0);
+        assert_eq!(cache.next_rotation(), 0);
+    }
+}
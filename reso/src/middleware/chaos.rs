@@ -0,0 +1,197 @@
+use std::sync::LazyLock;
+
+use async_trait::async_trait;
+use reso_context::{DnsMiddleware, DnsRequestCtx, DnsResponse};
+use reso_dns::{
+    ClassType, DnsMessage, DnsMessageBuilder, DnsRecord, DnsResponseCode, RecordType, domain_name::DomainName,
+    message::DnsRecordData,
+};
+
+use crate::{global::Global, local::Local, middleware::echo_edns};
+
+static VERSION_NAMES: LazyLock<[DomainName; 2]> = LazyLock::new(|| {
+    [
+        DomainName::from_ascii("version.bind").unwrap(),
+        DomainName::from_ascii("version.server").unwrap(),
+    ]
+});
+
+static ID_NAMES: LazyLock<[DomainName; 2]> = LazyLock::new(|| {
+    [
+        DomainName::from_ascii("id.server").unwrap(),
+        DomainName::from_ascii("hostname.bind").unwrap(),
+    ]
+});
+
+/// Longest character-string in a TXT record.
+const MAX_TXT_CHUNK: usize = 255;
+
+/// Middleware that answers the CHAOS class TXT queries used to identify a server (RFC 4892),
+/// `version.bind` and `id.server`, so they never reach an upstream.
+///
+/// Queries for a string that is not configured are refused.
+pub struct ChaosMiddleware {
+    version: String,
+    id: String,
+}
+
+impl ChaosMiddleware {
+    /// Create the middleware answering with `version` and `id`, an empty string refuses the query.
+    pub fn new(version: impl Into<String>, id: impl Into<String>) -> Self {
+        Self {
+            version: version.into(),
+            id: id.into(),
+        }
+    }
+
+    /// Build the response for a server identification query, or `None` for any other query.
+    fn answer(&self, query: &DnsMessage) -> Option<DnsMessage> {
+        let question = query.questions().first()?;
+        if question.qclass != ClassType::CH {
+            return None;
+        }
+
+        let value = if VERSION_NAMES.contains(&question.qname) {
+            &self.version
+        } else if ID_NAMES.contains(&question.qname) {
+            &self.id
+        } else {
+            return None;
+        };
+
+        if value.is_empty() || !matches!(question.qtype, RecordType::TXT | RecordType::ANY) {
+            return Some(DnsMessage::response_from_query(query, DnsResponseCode::Refused));
+        }
+
+        let mut flags = DnsMessage::response_from_query(query, DnsResponseCode::NoError).flags;
+        flags.authorative_answer = true;
+
+        let answer = DnsRecord::new(
+            question.qname.clone(),
+            RecordType::TXT,
+            ClassType::CH,
+            0,
+            DnsRecordData::Text(txt_chunks(value)),
+        );
+
+        let builder = DnsMessageBuilder::new()
+            .with_id(query.id)
+            .with_flags(flags)
+            .with_response(DnsResponseCode::NoError)
+            .with_questions(query.questions().to_vec())
+            .add_answer(answer);
+
+        Some(echo_edns(query, builder).build())
+    }
+}
+
+/// Split `value` into TXT character-strings, without splitting a character.
+fn txt_chunks(value: &str) -> Vec<Box<str>> {
+    let mut chunks = Vec::new();
+    let mut rest = value;
+
+    while !rest.is_empty() {
+        let mut end = rest.len().min(MAX_TXT_CHUNK);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(Box::from(chunk));
+        rest = tail;
+    }
+
+    chunks
+}
+
+#[async_trait]
+impl DnsMiddleware<Global, Local> for ChaosMiddleware {
+    async fn on_query(&self, ctx: &mut DnsRequestCtx<Global, Local>) -> anyhow::Result<Option<DnsResponse>> {
+        let Some(response) = self.answer(ctx.message()?) else {
+            return Ok(None);
+        };
+
+        let bytes = response.encode()?;
+
+        Ok(Some(DnsResponse::from_parsed(bytes, response)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reso_dns::DnsQuestion;
+
+    use super::*;
+
+    fn query(name: &str, qtype: RecordType, qclass: ClassType) -> DnsMessage {
+        DnsMessageBuilder::new()
+            .with_id(11)
+            .add_question(DnsQuestion::new(DomainName::from_ascii(name).unwrap(), qtype, qclass))
+            .build()
+    }
+
+    fn text(response: &DnsMessage) -> Vec<Box<str>> {
+        match &response.answers()[0].data {
+            DnsRecordData::Text(chunks) => chunks.clone(),
+            other => panic!("expected TXT data, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_answers_configured_version_and_id() {
+        let chaos = ChaosMiddleware::new("reso 1.0", "ns1");
+
+        let version = chaos
+            .answer(&query("version.bind", RecordType::TXT, ClassType::CH))
+            .unwrap();
+        assert_eq!(version.id, 11);
+        assert_eq!(version.response_code(), DnsResponseCode::NoError);
+        assert!(version.flags.authorative_answer);
+        assert_eq!(version.answers()[0].class, ClassType::CH);
+        assert_eq!(text(&version), vec![Box::from("reso 1.0")]);
+
+        let id = chaos
+            .answer(&query("id.server", RecordType::TXT, ClassType::CH))
+            .unwrap();
+        assert_eq!(text(&id), vec![Box::from("ns1")]);
+
+        // the encoded response must decode again.
+        assert_eq!(DnsMessage::decode(&version.encode().unwrap()).unwrap(), version);
+    }
+
+    #[test]
+    fn test_refuses_when_disabled() {
+        let chaos = ChaosMiddleware::new("", "ns1");
+
+        let response = chaos
+            .answer(&query("version.bind", RecordType::TXT, ClassType::CH))
+            .unwrap();
+
+        assert_eq!(response.response_code(), DnsResponseCode::Refused);
+        assert!(response.answers().is_empty());
+    }
+
+    #[test]
+    fn test_ignores_other_queries() {
+        let chaos = ChaosMiddleware::new("reso 1.0", "ns1");
+
+        assert!(
+            chaos
+                .answer(&query("version.bind", RecordType::TXT, ClassType::IN))
+                .is_none()
+        );
+        assert!(
+            chaos
+                .answer(&query("example.com", RecordType::TXT, ClassType::CH))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_long_values_are_split_into_chunks() {
+        let value = "ü".repeat(200);
+        let chunks = txt_chunks(&value);
+
+        assert!(chunks.iter().all(|c| c.len() <= MAX_TXT_CHUNK));
+        assert_eq!(chunks.concat(), value);
+    }
+}
@@ -0,0 +1,137 @@
+use async_trait::async_trait;
+use reso_context::{DnsMiddleware, DnsRequestCtx, DnsResponse};
+use reso_dns::DnsMessage;
+
+use crate::{global::Global, local::Local};
+
+/// Middleware that strips DNSSEC records from responses to clients that did not set the DO bit.
+///
+/// Must run its `on_response` after the cache has stored the full response, so clients that do
+/// set the DO bit still get the DNSSEC records from the cache.
+pub struct DnssecFilterMiddleware;
+
+#[async_trait]
+impl DnsMiddleware<Global, Local> for DnssecFilterMiddleware {
+    async fn on_response(
+        &self,
+        ctx: &mut DnsRequestCtx<Global, Local>,
+        response: &mut DnsResponse,
+    ) -> anyhow::Result<()> {
+        if let Some(message) = strip_dnssec(ctx.message()?, response.message()?) {
+            let bytes = message.encode()?;
+            *response = DnsResponse::from_parsed(bytes, message);
+        }
+
+        Ok(())
+    }
+}
+
+/// Return the response without DNSSEC records if the query did not set the DO bit and the response has any,
+/// other than records of the queried type.
+fn strip_dnssec(query: &DnsMessage, response: &DnsMessage) -> Option<DnsMessage> {
+    if query.edns().as_ref().is_some_and(|edns| edns.do_bit()) {
+        return None;
+    }
+
+    let qtype = query.questions().first()?.qtype;
+    if !response.has_strippable_dnssec_records(qtype) {
+        return None;
+    }
+
+    let mut response = response.clone();
+    response.strip_dnssec_records(qtype);
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use reso_dns::{
+        ClassType, DnsMessageBuilder, DnsQuestion, DnsRecord, Edns, RecordType, domain_name::DomainName,
+        message::DnsRecordData,
+    };
+
+    use super::*;
+
+    fn query(do_bit: bool) -> DnsMessage {
+        query_for(RecordType::A, do_bit)
+    }
+
+    fn query_for(qtype: RecordType, do_bit: bool) -> DnsMessage {
+        let mut edns = Edns::default();
+        edns.set_do_bit(do_bit);
+        DnsMessageBuilder::new()
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                qtype,
+                ClassType::IN,
+            ))
+            .with_edns(edns)
+            .build()
+    }
+
+    fn record(record_type: RecordType, data: DnsRecordData) -> DnsRecord {
+        DnsRecord::new(
+            DomainName::from_ascii("example.com").unwrap(),
+            record_type,
+            ClassType::IN,
+            300,
+            data,
+        )
+    }
+
+    fn signed_response() -> DnsMessage {
+        DnsMessageBuilder::new()
+            .add_answer(record(RecordType::A, DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1))))
+            .add_answer(record(RecordType::RRSIG, DnsRecordData::Raw(vec![1, 2, 3])))
+            .add_authority_record(record(RecordType::NSEC, DnsRecordData::Raw(vec![4, 5])))
+            .add_authority_record(record(RecordType::DS, DnsRecordData::Raw(vec![6])))
+            .build()
+    }
+
+    #[test]
+    fn test_strips_dnssec_for_non_do_client() {
+        let stripped = strip_dnssec(&query(false), &signed_response()).expect("response should be stripped");
+
+        assert_eq!(stripped.answers().len(), 1);
+        assert_eq!(stripped.answers()[0].record_type(), RecordType::A);
+        assert!(stripped.authority_records().is_empty());
+
+        let without_edns = DnsMessageBuilder::new()
+            .add_question(query(false).questions()[0].clone())
+            .build();
+        assert!(strip_dnssec(&without_edns, &signed_response()).is_some());
+    }
+
+    #[test]
+    fn test_keeps_dnssec_records_of_the_queried_type() {
+        for qtype in [RecordType::DS, RecordType::DNSKEY] {
+            let response = DnsMessageBuilder::new()
+                .add_answer(record(qtype, DnsRecordData::Raw(vec![1, 2, 3])))
+                .add_answer(record(RecordType::RRSIG, DnsRecordData::Raw(vec![4, 5])))
+                .build();
+
+            let stripped = strip_dnssec(&query_for(qtype, false), &response).expect("RRSIG should be stripped");
+
+            assert_eq!(stripped.answers().len(), 1, "{qtype:?}");
+            assert_eq!(stripped.answers()[0].record_type(), qtype);
+        }
+
+        // nothing else to strip, so the response is left as is.
+        let ds_only = DnsMessageBuilder::new()
+            .add_answer(record(RecordType::DS, DnsRecordData::Raw(vec![1])))
+            .build();
+        assert!(strip_dnssec(&query_for(RecordType::DS, false), &ds_only).is_none());
+    }
+
+    #[test]
+    fn test_keeps_dnssec_records_for_any_queries() {
+        assert!(strip_dnssec(&query_for(RecordType::ANY, false), &signed_response()).is_none());
+    }
+
+    #[test]
+    fn test_preserves_dnssec_for_do_client() {
+        assert!(strip_dnssec(&query(true), &signed_response()).is_none());
+    }
+}
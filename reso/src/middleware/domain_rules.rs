@@ -1,8 +1,18 @@
 use async_trait::async_trait;
 use reso_context::{DnsMiddleware, DnsRequestCtx, DnsResponse};
-use reso_dns::{DnsFlags, DnsMessageBuilder, DnsResponseCode};
+use reso_dns::{
+    ClassType, DnsFlags, DnsMessage, DnsMessageBuilder, DnsQuestion, DnsRecord, DnsResponseCode, EdnsOption,
+    RecordType,
+    domain_name::DomainName,
+    message::{DnsRecordData, EdnsOptionCode, EdnsOptionData, ExtendedDnsErrorInfoCode},
+};
 
-use crate::{global::Global, local::Local};
+use crate::{
+    global::Global,
+    local::Local,
+    middleware::echo_edns,
+    services::config::{BlockResponsePolicy, BlockingConfig},
+};
 
 /// Middleware that blocks queries for blocked domain names.
 pub struct DomainRulesMiddleware;
@@ -13,25 +23,13 @@ impl DnsMiddleware<Global, Local> for DomainRulesMiddleware {
         let message = ctx.message()?;
 
         if let Some(question) = message.questions().first()
-            && ctx.global().domain_rules.is_blocked(&question.qname)
+            && ctx
+                .global()
+                .domain_rules
+                .is_blocked(&question.qname, ctx.request_address())
         {
-            let flags = DnsFlags::new(
-                true,
-                message.flags.opcode,
-                false,
-                false,
-                message.flags.recursion_desired,
-                true,
-                false,
-                message.flags.checking_disabled,
-            );
-
-            let message = DnsMessageBuilder::new()
-                .with_id(message.id)
-                .with_flags(flags)
-                .with_questions(message.questions().to_vec())
-                .with_response(DnsResponseCode::NxDomain)
-                .build();
+            let config = ctx.global().config.get_config();
+            let message = blocked_response(message, &config.dns.blocking);
 
             let bytes = message.encode()?;
 
@@ -43,3 +41,217 @@ impl DnsMiddleware<Global, Local> for DomainRulesMiddleware {
         Ok(None)
     }
 }
+
+/// Build the response for a blocked query according to the configured policy.
+pub(crate) fn blocked_response(query: &DnsMessage, config: &BlockingConfig) -> DnsMessage {
+    let flags = DnsFlags::response()
+        .with_opcode(query.flags.opcode)
+        .with_recursion_desired(query.flags.recursion_desired)
+        .with_checking_disabled(query.flags.checking_disabled);
+
+    let response_code = match config.response_policy {
+        BlockResponsePolicy::NxDomain => DnsResponseCode::NxDomain,
+        BlockResponsePolicy::Refused => DnsResponseCode::Refused,
+        BlockResponsePolicy::NoData | BlockResponsePolicy::Sinkhole => DnsResponseCode::NoError,
+    };
+
+    let mut builder = DnsMessageBuilder::new()
+        .with_id(query.id)
+        .with_flags(flags)
+        .with_questions(query.questions().to_vec())
+        .with_response(response_code);
+
+    if let Some(question) = query.questions().first()
+        && config.response_policy != BlockResponsePolicy::Refused
+    {
+        let answer = match config.response_policy {
+            BlockResponsePolicy::Sinkhole => sinkhole_answer(question, config),
+            _ => None,
+        };
+        builder = match answer {
+            Some(answer) => builder.add_answer(answer),
+            // Negative responses carry an SOA, so clients cache them for at most the block TTL.
+            None => builder.add_authority_record(blocked_soa(question, config.ttl)),
+        };
+    }
+
+    let mut response = echo_edns(query, builder).build();
+
+    // Only clients that sent an OPT record can receive the extended error.
+    if let Some(mut edns) = response.edns().clone() {
+        edns.options.push(EdnsOption::new(
+            EdnsOptionCode::ExtendedDnsError,
+            EdnsOptionData::ExtendedError {
+                info_code: ExtendedDnsErrorInfoCode::Blocked,
+                extra_text: None,
+            },
+        ));
+        response.set_edns(Some(edns));
+    }
+
+    response
+}
+
+/// The sinkhole record for a blocked A or AAAA query, `None` for other types.
+fn sinkhole_answer(question: &DnsQuestion, config: &BlockingConfig) -> Option<DnsRecord> {
+    let data = match question.qtype {
+        RecordType::A => DnsRecordData::Ipv4(config.sinkhole_ipv4),
+        RecordType::AAAA => DnsRecordData::Ipv6(config.sinkhole_ipv6),
+        _ => return None,
+    };
+
+    Some(DnsRecord::new(
+        question.qname.clone(),
+        question.qtype,
+        ClassType::IN,
+        config.ttl,
+        data,
+    ))
+}
+
+/// SOA for negative blocked responses, clients cache the response for at most `ttl` seconds (RFC 2308).
+fn blocked_soa(question: &DnsQuestion, ttl: u32) -> DnsRecord {
+    DnsRecord::new(
+        question.qname.clone(),
+        RecordType::SOA,
+        ClassType::IN,
+        ttl,
+        DnsRecordData::SOA {
+            mname: DomainName::from_ascii("localhost").expect("valid name"),
+            rname: DomainName::from_ascii("hostmaster.localhost").expect("valid name"),
+            serial: 1,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: ttl,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use reso_dns::Edns;
+
+    use super::*;
+
+    fn query(qtype: RecordType) -> DnsMessage {
+        DnsMessageBuilder::new()
+            .with_id(42)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("ads.example.com").unwrap(),
+                qtype,
+                ClassType::IN,
+            ))
+            .with_edns(Edns::default())
+            .build()
+    }
+
+    fn config(response_policy: BlockResponsePolicy) -> BlockingConfig {
+        BlockingConfig {
+            response_policy,
+            sinkhole_ipv4: Ipv4Addr::new(192, 0, 2, 1),
+            sinkhole_ipv6: Ipv6Addr::LOCALHOST,
+            ttl: 30,
+            blocked_ips: vec![],
+        }
+    }
+
+    fn soa_ttl(response: &DnsMessage) -> Option<u32> {
+        let soa = response.authority_records().first()?;
+        match soa.data {
+            DnsRecordData::SOA { minimum, .. } if soa.record_type == RecordType::SOA => Some(soa.ttl.min(minimum)),
+            _ => None,
+        }
+    }
+
+    fn assert_blocked_ede(response: &DnsMessage) {
+        let options = &response.edns().as_ref().expect("query had an OPT record").options;
+        assert!(options.iter().any(|o| matches!(
+            o.data,
+            Some(EdnsOptionData::ExtendedError {
+                info_code: ExtendedDnsErrorInfoCode::Blocked,
+                ..
+            })
+        )));
+    }
+
+    #[test]
+    fn test_default_policy_is_nxdomain() {
+        assert_eq!(BlockingConfig::default().response_policy, BlockResponsePolicy::NxDomain);
+        assert_eq!(BlockingConfig::default().ttl, 60);
+    }
+
+    #[test]
+    fn test_nxdomain_policy() {
+        let response = blocked_response(&query(RecordType::A), &config(BlockResponsePolicy::NxDomain));
+
+        assert_eq!(response.id, 42);
+        assert!(response.flags.response);
+        assert_eq!(response.response_code(), DnsResponseCode::NxDomain);
+        assert!(response.answers().is_empty());
+        assert_eq!(soa_ttl(&response), Some(30));
+        assert_blocked_ede(&response);
+    }
+
+    #[test]
+    fn test_refused_policy() {
+        let response = blocked_response(&query(RecordType::A), &config(BlockResponsePolicy::Refused));
+
+        assert_eq!(response.response_code(), DnsResponseCode::Refused);
+        assert!(response.answers().is_empty());
+        assert!(response.authority_records().is_empty());
+        assert_blocked_ede(&response);
+    }
+
+    #[test]
+    fn test_nodata_policy() {
+        let response = blocked_response(&query(RecordType::A), &config(BlockResponsePolicy::NoData));
+
+        assert_eq!(response.response_code(), DnsResponseCode::NoError);
+        assert!(response.answers().is_empty());
+        assert_eq!(soa_ttl(&response), Some(30));
+        assert_blocked_ede(&response);
+    }
+
+    #[test]
+    fn test_sinkhole_policy() {
+        let response = blocked_response(&query(RecordType::A), &config(BlockResponsePolicy::Sinkhole));
+
+        assert_eq!(response.response_code(), DnsResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers()[0].data,
+            DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1))
+        );
+        assert_eq!(response.answers()[0].ttl, 30);
+        assert!(response.authority_records().is_empty());
+        assert_blocked_ede(&response);
+
+        let response = blocked_response(&query(RecordType::AAAA), &config(BlockResponsePolicy::Sinkhole));
+        assert_eq!(response.answers()[0].data, DnsRecordData::Ipv6(Ipv6Addr::LOCALHOST));
+
+        // Other types have no sinkhole address and get NODATA.
+        let response = blocked_response(&query(RecordType::MX), &config(BlockResponsePolicy::Sinkhole));
+        assert_eq!(response.response_code(), DnsResponseCode::NoError);
+        assert!(response.answers().is_empty());
+        assert_eq!(soa_ttl(&response), Some(30));
+    }
+
+    #[test]
+    fn test_no_ede_without_edns() {
+        let query = DnsMessageBuilder::new()
+            .with_id(1)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("ads.example.com").unwrap(),
+                RecordType::A,
+                ClassType::IN,
+            ))
+            .build();
+
+        let response = blocked_response(&query, &config(BlockResponsePolicy::NxDomain));
+
+        assert!(response.edns().is_none());
+    }
+}
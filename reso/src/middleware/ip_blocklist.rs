@@ -0,0 +1,114 @@
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use reso_context::{DnsMiddleware, DnsRequestCtx, DnsResponse};
+use reso_dns::{DnsMessage, message::DnsRecordData};
+use reso_resolver::ptr::IpPrefix;
+
+use crate::{global::Global, local::Local, middleware::domain_rules::blocked_response};
+
+/// Middleware that blocks responses answering with an address in a blocked network.
+///
+/// Threat feeds often list malicious addresses rather than names, this catches names that resolve to them.
+pub struct IpBlocklistMiddleware {
+    prefixes: Vec<IpPrefix>,
+}
+
+impl IpBlocklistMiddleware {
+    pub fn new(prefixes: impl IntoIterator<Item = IpPrefix>) -> Self {
+        Self {
+            prefixes: prefixes.into_iter().collect(),
+        }
+    }
+
+    /// Whether any A or AAAA answer of `response` is in a blocked network.
+    fn is_blocked(&self, response: &DnsMessage) -> bool {
+        answer_addresses(response).any(|ip| self.prefixes.iter().any(|prefix| prefix.contains(&ip)))
+    }
+}
+
+/// Addresses of the A and AAAA records in the answer section.
+fn answer_addresses(response: &DnsMessage) -> impl Iterator<Item = IpAddr> + '_ {
+    response.answers().iter().filter_map(|record| match record.data {
+        DnsRecordData::Ipv4(ip) => Some(IpAddr::V4(ip)),
+        DnsRecordData::Ipv6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    })
+}
+
+#[async_trait]
+impl DnsMiddleware<Global, Local> for IpBlocklistMiddleware {
+    async fn on_response(
+        &self,
+        ctx: &mut DnsRequestCtx<Global, Local>,
+        response: &mut DnsResponse,
+    ) -> anyhow::Result<()> {
+        if !self.is_blocked(response.message()?) {
+            return Ok(());
+        }
+
+        let config = ctx.global().config.get_config();
+        let message = blocked_response(ctx.message()?, &config.dns.blocking);
+        let bytes = message.encode()?;
+
+        ctx.local_mut().blocked = true;
+        *response = DnsResponse::from_parsed(bytes, message);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use reso_dns::{ClassType, DnsMessageBuilder, DnsRecord, RecordType, domain_name::DomainName};
+
+    use super::*;
+
+    fn middleware() -> IpBlocklistMiddleware {
+        IpBlocklistMiddleware::new(["203.0.113.0/24", "2001:db8:bad::/48"].map(|p| p.parse().unwrap()))
+    }
+
+    fn response(data: Vec<DnsRecordData>) -> DnsMessage {
+        data.into_iter()
+            .fold(DnsMessageBuilder::new(), |builder, data| {
+                let record_type = match data {
+                    DnsRecordData::Ipv4(_) => RecordType::A,
+                    DnsRecordData::Ipv6(_) => RecordType::AAAA,
+                    _ => RecordType::CNAME,
+                };
+                builder.add_answer(DnsRecord::new(
+                    DomainName::from_ascii("example.com").unwrap(),
+                    record_type,
+                    ClassType::IN,
+                    300,
+                    data,
+                ))
+            })
+            .build()
+    }
+
+    #[test]
+    fn test_blocks_response_with_blocked_address() {
+        let middleware = middleware();
+
+        assert!(middleware.is_blocked(&response(vec![
+            DnsRecordData::Ipv4(Ipv4Addr::new(198, 51, 100, 1)),
+            DnsRecordData::Ipv4(Ipv4Addr::new(203, 0, 113, 7)),
+        ])));
+        assert!(middleware.is_blocked(&response(vec![DnsRecordData::Ipv6("2001:db8:bad::1".parse().unwrap())])));
+    }
+
+    #[test]
+    fn test_clean_response_passes() {
+        let middleware = middleware();
+
+        assert!(!middleware.is_blocked(&response(vec![
+            DnsRecordData::Ipv4(Ipv4Addr::new(198, 51, 100, 1)),
+            DnsRecordData::Ipv6(Ipv6Addr::LOCALHOST),
+            DnsRecordData::DomainName(DomainName::from_ascii("cdn.example.net").unwrap()),
+        ])));
+        assert!(!middleware.is_blocked(&response(vec![])));
+    }
+}
@@ -1,9 +1,10 @@
 use async_trait::async_trait;
 use reso_context::{DnsMiddleware, DnsRequestCtx, DnsResponse};
-use reso_dns::{DnsFlags, DnsMessageBuilder, DnsOpcode, DnsResponseCode, RecordType};
+use reso_dns::{DnsFlags, DnsMessage, DnsMessageBuilder, DnsRecord, DnsResponseCode, RecordType};
 
 use crate::{global::Global, local::Local, middleware::echo_edns};
 
+/// Middleware that answers queries for names overridden with a local record, without resolving them.
 pub struct LocalRecordsMiddleware;
 
 #[async_trait]
@@ -25,31 +26,87 @@ impl DnsMiddleware<Global, Local> for LocalRecordsMiddleware {
             None => return Ok(None),
         };
 
-        let answers = resolved.into_iter().map(|r| r.record).collect();
-
-        let flags = DnsFlags::new(
-            true,
-            DnsOpcode::Query,
-            true, // authoritative
-            false,
-            message.flags.recursion_desired,
-            true,
-            false,
-            message.flags.checking_disabled,
-        );
-
-        let bytes = echo_edns(
-            message,
-            DnsMessageBuilder::new()
-                .with_id(message.id)
-                .with_flags(flags)
-                .with_response(DnsResponseCode::NoError)
-                .with_questions(message.questions().to_vec())
-                .with_answers(answers),
+        let response = local_response(message, resolved.into_iter().map(|r| r.record).collect());
+        let bytes = response.encode()?;
+
+        Ok(Some(DnsResponse::from_parsed(bytes, response)))
+    }
+}
+
+/// Build the response answering `query` with the local `answers`.
+///
+/// Local records override what the name's authoritative servers would answer, so the response is not
+/// marked authoritative.
+fn local_response(query: &DnsMessage, answers: Vec<DnsRecord>) -> DnsMessage {
+    let flags = DnsFlags::response()
+        .with_recursion_desired(query.flags.recursion_desired)
+        .with_checking_disabled(query.flags.checking_disabled);
+
+    echo_edns(
+        query,
+        DnsMessageBuilder::new()
+            .with_id(query.id)
+            .with_flags(flags)
+            .with_response(DnsResponseCode::NoError)
+            .with_questions(query.questions().to_vec())
+            .with_answers(answers),
+    )
+    .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use reso_dns::{ClassType, DnsQuestion, Edns, domain_name::DomainName, message::DnsRecordData};
+
+    use super::*;
+
+    fn query(edns: Option<Edns>) -> DnsMessage {
+        let builder = DnsMessageBuilder::new().with_id(5).add_question(DnsQuestion::new(
+            DomainName::from_ascii("printer.local").unwrap(),
+            RecordType::A,
+            ClassType::IN,
+        ));
+        match edns {
+            Some(edns) => builder.with_edns(edns).build(),
+            None => builder.build(),
+        }
+    }
+
+    fn printer(ttl: u32) -> DnsRecord {
+        DnsRecord::new(
+            DomainName::from_ascii("printer.local").unwrap(),
+            RecordType::A,
+            ClassType::IN,
+            ttl,
+            DnsRecordData::Ipv4(Ipv4Addr::new(192, 168, 1, 50)),
         )
-        .build()
-        .encode()?;
+    }
+
+    #[test]
+    fn test_answers_with_the_local_record() {
+        let response = local_response(&query(None), vec![printer(120)]);
+
+        assert_eq!(response.id, 5);
+        assert!(response.flags.response);
+        assert!(!response.flags.authorative_answer);
+        assert_eq!(response.response_code(), DnsResponseCode::NoError);
+        assert_eq!(response.questions(), query(None).questions());
+        assert_eq!(response.answers(), &[printer(120)]);
+        assert!(response.edns().is_none());
+
+        // the encoded response must decode again.
+        assert_eq!(DnsMessage::decode(&response.encode().unwrap()).unwrap(), response);
+    }
+
+    #[test]
+    fn test_echoes_edns() {
+        let mut edns = Edns::default();
+        edns.set_do_bit(true);
+
+        let response = local_response(&query(Some(edns)), vec![printer(120)]);
 
-        Ok(Some(DnsResponse::from_bytes(bytes)))
+        assert!(response.edns().as_ref().is_some_and(Edns::do_bit));
     }
 }
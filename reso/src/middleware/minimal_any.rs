@@ -0,0 +1,115 @@
+use async_trait::async_trait;
+use reso_context::{DnsMiddleware, DnsRequestCtx, DnsResponse};
+use reso_dns::{
+    DnsFlags, DnsMessage, DnsMessageBuilder, DnsRecord, DnsResponseCode, RecordType, message::DnsRecordData,
+};
+
+use crate::{global::Global, local::Local, middleware::echo_edns};
+
+/// TTL of the synthesized HINFO record, RFC 8482 recommends a long one so resolvers can cache it.
+const MINIMAL_ANY_TTL: u32 = 86400;
+
+/// Middleware that answers ANY queries with a single synthesized HINFO record (RFC 8482)
+/// instead of forwarding them, which keeps ANY from being useful for amplification.
+pub struct MinimalAnyMiddleware;
+
+#[async_trait]
+impl DnsMiddleware<Global, Local> for MinimalAnyMiddleware {
+    async fn on_query(&self, ctx: &mut DnsRequestCtx<Global, Local>) -> anyhow::Result<Option<DnsResponse>> {
+        let Some(response) = minimal_any_response(ctx.message()?) else {
+            return Ok(None);
+        };
+
+        let bytes = response.encode()?;
+        Ok(Some(DnsResponse::from_parsed(bytes, response)))
+    }
+}
+
+/// Build the RFC 8482 response for an ANY query, or `None` for any other query type.
+fn minimal_any_response(query: &DnsMessage) -> Option<DnsMessage> {
+    let question = query.questions().first()?;
+    if question.qtype != RecordType::ANY {
+        return None;
+    }
+
+    let hinfo = DnsRecord::new(
+        question.qname.clone(),
+        RecordType::HINFO,
+        question.qclass,
+        MINIMAL_ANY_TTL,
+        DnsRecordData::Hinfo {
+            cpu: b"RFC8482".as_slice().into(),
+            os: Box::default(),
+        },
+    );
+
+    let flags = DnsFlags::response()
+        .with_opcode(query.flags.opcode)
+        .with_recursion_desired(query.flags.recursion_desired)
+        .with_checking_disabled(query.flags.checking_disabled);
+
+    let builder = DnsMessageBuilder::new()
+        .with_id(query.id)
+        .with_flags(flags)
+        .with_response(DnsResponseCode::NoError)
+        .with_questions(query.questions().to_vec())
+        .add_answer(hinfo);
+
+    Some(echo_edns(query, builder).build())
+}
+
+#[cfg(test)]
+mod tests {
+    use reso_dns::{ClassType, DnsQuestion, domain_name::DomainName};
+
+    use super::*;
+    use crate::{server_builder::server_middlewares, services::config::Config};
+
+    fn query(qtype: RecordType) -> DnsMessage {
+        DnsMessageBuilder::new()
+            .with_id(42)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                qtype,
+                ClassType::IN,
+            ))
+            .build()
+    }
+
+    #[test]
+    fn test_any_query_gets_hinfo() {
+        let query = query(RecordType::ANY);
+
+        let response = minimal_any_response(&query).expect("ANY should be answered");
+        let decoded = DnsMessage::decode(&response.encode().unwrap()).unwrap();
+
+        assert_eq!(decoded.id, 42);
+        assert_eq!(decoded.response_code(), DnsResponseCode::NoError);
+        assert_eq!(decoded.questions(), query.questions());
+        assert_eq!(decoded.answers().len(), 1);
+        assert_eq!(decoded.answers()[0].record_type, RecordType::HINFO);
+        assert_eq!(
+            decoded.answers()[0].data,
+            DnsRecordData::Hinfo {
+                cpu: b"RFC8482".as_slice().into(),
+                os: Box::default(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_other_queries_are_forwarded() {
+        assert!(minimal_any_response(&query(RecordType::A)).is_none());
+    }
+
+    #[test]
+    fn test_only_registered_when_enabled() {
+        let mut config = Config::default();
+        let disabled = server_middlewares(&config).len();
+
+        config.dns.minimal_any = true;
+        let enabled = server_middlewares(&config).len();
+
+        assert_eq!(enabled, disabled + 1);
+    }
+}
@@ -0,0 +1,136 @@
+use async_trait::async_trait;
+use reso_context::{DnsMiddleware, DnsRequestCtx, DnsResponse};
+use reso_dns::{DnsMessage, DnsRecord, DnsResponseCode, RecordType};
+
+use crate::{global::Global, local::Local};
+
+/// Middleware that drops the authority and additional sections from responses.
+///
+/// The OPT record is kept, so EDNS behaves the same as without this middleware. The SOA of negative responses
+/// is kept too, since clients need it to cache the NXDOMAIN or NODATA answer (RFC 2308).
+pub struct MinimalResponsesMiddleware;
+
+#[async_trait]
+impl DnsMiddleware<Global, Local> for MinimalResponsesMiddleware {
+    async fn on_response(
+        &self,
+        _ctx: &mut DnsRequestCtx<Global, Local>,
+        response: &mut DnsResponse,
+    ) -> anyhow::Result<()> {
+        if let Some(message) = minimize(response.message()?) {
+            let bytes = message.encode()?;
+            *response = DnsResponse::from_parsed(bytes, message);
+        }
+
+        Ok(())
+    }
+}
+
+/// Return the response without authority and additional records, or `None` if it has none to drop.
+fn minimize(response: &DnsMessage) -> Option<DnsMessage> {
+    let negative = response.answers().is_empty() || response.response_code() == DnsResponseCode::NxDomain;
+    let keep = |record: &DnsRecord| negative && record.record_type == RecordType::SOA;
+
+    if response.authority_records().iter().all(keep) && response.additional_records().is_empty() {
+        return None;
+    }
+
+    let mut response = response.clone();
+    response.retain_authority_records(keep);
+    response.clear_additional_records();
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use reso_dns::{
+        ClassType, DnsMessageBuilder, DnsRecord, Edns, RecordType, domain_name::DomainName, message::DnsRecordData,
+    };
+
+    use super::*;
+
+    fn record(record_type: RecordType, data: DnsRecordData) -> DnsRecord {
+        DnsRecord::new(
+            DomainName::from_ascii("example.com").unwrap(),
+            record_type,
+            ClassType::IN,
+            300,
+            data,
+        )
+    }
+
+    #[test]
+    fn test_drops_authority_and_additional() {
+        let ns = DomainName::from_ascii("ns1.example.com").unwrap();
+        let response = DnsMessageBuilder::new()
+            .add_answer(record(RecordType::A, DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1))))
+            .add_authority_record(record(RecordType::NS, DnsRecordData::DomainName(ns)))
+            .add_additional_record(record(RecordType::A, DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 53))))
+            .with_edns(Edns::default())
+            .build();
+
+        let minimized = minimize(&response).expect("response should be minimized");
+        let decoded = DnsMessage::decode(&minimized.encode().unwrap()).unwrap();
+
+        assert_eq!(decoded.answers(), response.answers());
+        assert!(decoded.authority_records().is_empty());
+        assert!(decoded.additional_records().is_empty());
+        assert!(decoded.edns().is_some());
+    }
+
+    #[test]
+    fn test_keeps_soa_of_negative_responses() {
+        let soa = record(
+            RecordType::SOA,
+            DnsRecordData::SOA {
+                mname: DomainName::from_ascii("ns1.example.com").unwrap(),
+                rname: DomainName::from_ascii("hostmaster.example.com").unwrap(),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            },
+        );
+        let ns = record(
+            RecordType::NS,
+            DnsRecordData::DomainName(DomainName::from_ascii("ns1.example.com").unwrap()),
+        );
+
+        let nodata = DnsMessageBuilder::new()
+            .add_authority_record(soa.clone())
+            .add_authority_record(ns.clone())
+            .build();
+        let minimized = minimize(&nodata).expect("response should be minimized");
+        assert_eq!(minimized.authority_records(), std::slice::from_ref(&soa));
+
+        let mut nxdomain = DnsMessageBuilder::new()
+            .add_answer(record(
+                RecordType::CNAME,
+                DnsRecordData::DomainName(DomainName::from_ascii("missing.example.com").unwrap()),
+            ))
+            .add_authority_record(soa.clone())
+            .build();
+        nxdomain.set_response_code(DnsResponseCode::NxDomain);
+        assert!(minimize(&nxdomain).is_none());
+
+        let positive = DnsMessageBuilder::new()
+            .add_answer(record(RecordType::A, DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1))))
+            .add_authority_record(soa)
+            .build();
+        let minimized = minimize(&positive).expect("response should be minimized");
+        assert!(minimized.authority_records().is_empty());
+    }
+
+    #[test]
+    fn test_leaves_minimal_response_untouched() {
+        let response = DnsMessageBuilder::new()
+            .add_answer(record(RecordType::A, DnsRecordData::Ipv4(Ipv4Addr::new(192, 0, 2, 1))))
+            .with_edns(Edns::default())
+            .build();
+
+        assert!(minimize(&response).is_none());
+    }
+}
@@ -2,9 +2,16 @@ use reso_dns::{DnsMessage, DnsMessageBuilder, Edns};
 
 pub mod block_resolver_privacy;
 pub mod cache;
+pub mod chaos;
+pub mod dnssec;
 pub mod domain_rules;
+pub mod ip_blocklist;
 pub mod local_records;
 pub mod metrics;
+pub mod minimal_any;
+pub mod minimal_responses;
+pub mod nsid;
+pub mod qtype_filter;
 pub mod ratelimit;
 pub mod reso;
 
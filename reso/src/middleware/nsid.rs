@@ -0,0 +1,142 @@
+use async_trait::async_trait;
+use reso_context::{DnsMiddleware, DnsRequestCtx, DnsResponse};
+use reso_dns::{
+    DnsMessage, Edns,
+    message::{EdnsOption, EdnsOptionCode, EdnsOptionData},
+};
+
+use crate::{global::Global, local::Local};
+
+/// Middleware that identifies this server with an NSID option (RFC 5001) when a client asks for it.
+pub struct NsidMiddleware {
+    nsid: Vec<u8>,
+}
+
+impl NsidMiddleware {
+    /// Create the middleware, answering NSID requests with `nsid`.
+    pub fn new(nsid: impl Into<Vec<u8>>) -> Self {
+        Self { nsid: nsid.into() }
+    }
+}
+
+#[async_trait]
+impl DnsMiddleware<Global, Local> for NsidMiddleware {
+    async fn on_response(
+        &self,
+        ctx: &mut DnsRequestCtx<Global, Local>,
+        response: &mut DnsResponse,
+    ) -> anyhow::Result<()> {
+        if let Some(message) = with_nsid(ctx.message()?, response.message()?, &self.nsid) {
+            let bytes = message.encode()?;
+            *response = DnsResponse::from_parsed(bytes, message);
+        }
+
+        Ok(())
+    }
+}
+
+/// Return the response with an NSID option set to `nsid`, or `None` if the query did not request one.
+///
+/// An NSID already in the response, e.g. from an upstream, is replaced.
+fn with_nsid(query: &DnsMessage, response: &DnsMessage, nsid: &[u8]) -> Option<DnsMessage> {
+    let requested = query
+        .edns()
+        .as_ref()
+        .is_some_and(|edns| edns.options.iter().any(|o| o.code == EdnsOptionCode::NSID));
+    if !requested {
+        return None;
+    }
+
+    let mut edns = response.edns().clone().unwrap_or_else(|| {
+        let mut edns = Edns::default();
+        edns.set_do_bit(query.edns().as_ref().is_some_and(Edns::do_bit));
+        edns
+    });
+    edns.options.retain(|o| o.code != EdnsOptionCode::NSID);
+    edns.options.push(EdnsOption::new(
+        EdnsOptionCode::NSID,
+        EdnsOptionData::Raw(nsid.to_vec()),
+    ));
+
+    let mut response = response.clone();
+    response.set_edns(Some(edns));
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use reso_dns::{
+        ClassType, DnsMessageBuilder, DnsQuestion, DnsResponseCode, EdnsBuilder, RecordType, domain_name::DomainName,
+    };
+
+    use super::*;
+    use crate::{server_builder::server_middlewares, services::config::Config};
+
+    fn query(edns: Option<Edns>) -> DnsMessage {
+        let builder = DnsMessageBuilder::new().with_id(9).add_question(DnsQuestion::new(
+            DomainName::from_ascii("example.com").unwrap(),
+            RecordType::A,
+            ClassType::IN,
+        ));
+        match edns {
+            Some(edns) => builder.with_edns(edns),
+            None => builder,
+        }
+        .build()
+    }
+
+    fn nsid(message: &DnsMessage) -> Option<&EdnsOptionData> {
+        message
+            .edns()
+            .as_ref()?
+            .options
+            .iter()
+            .find(|o| o.code == EdnsOptionCode::NSID)?
+            .data()
+    }
+
+    #[test]
+    fn test_nsid_request_gets_server_identifier() {
+        let query = query(Some(EdnsBuilder::new().add_nsid(&[]).build()));
+        let response = DnsMessage::response_from_query(&query, DnsResponseCode::NoError);
+
+        let response = with_nsid(&query, &response, b"resolver-1").expect("NSID should be added");
+        let decoded = DnsMessage::decode(&response.encode().unwrap()).unwrap();
+
+        assert_eq!(nsid(&decoded), Some(&EdnsOptionData::Raw(b"resolver-1".to_vec())));
+    }
+
+    #[test]
+    fn test_upstream_nsid_is_replaced() {
+        let query = query(Some(EdnsBuilder::new().add_nsid(&[]).build()));
+        let response = DnsMessageBuilder::new()
+            .with_id(9)
+            .with_edns(EdnsBuilder::new().add_nsid(b"upstream").build())
+            .build();
+
+        let response = with_nsid(&query, &response, b"resolver-1").unwrap();
+        let options = &response.edns().as_ref().unwrap().options;
+
+        assert_eq!(options.len(), 1);
+        assert_eq!(nsid(&response), Some(&EdnsOptionData::Raw(b"resolver-1".to_vec())));
+    }
+
+    #[test]
+    fn test_queries_without_nsid_are_untouched() {
+        let response = DnsMessageBuilder::new().with_id(9).build();
+
+        assert!(with_nsid(&query(None), &response, b"resolver-1").is_none());
+        assert!(with_nsid(&query(Some(Edns::default())), &response, b"resolver-1").is_none());
+    }
+
+    #[test]
+    fn test_only_registered_when_configured() {
+        let mut config = Config::default();
+        let disabled = server_middlewares(&config).len();
+
+        config.dns.nsid = "resolver-1".to_string();
+        let enabled = server_middlewares(&config).len();
+
+        assert_eq!(enabled, disabled + 1);
+    }
+}
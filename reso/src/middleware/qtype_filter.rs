@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use reso_context::{DnsMiddleware, DnsRequestCtx, DnsResponse};
+use reso_dns::{DnsMessage, DnsResponseCode, RecordType};
+
+use crate::{global::Global, local::Local};
+
+/// Middleware that refuses queries for blocked record types, e.g. HTTPS or SVCB.
+pub struct QtypeFilterMiddleware {
+    blocked: HashSet<RecordType>,
+}
+
+impl QtypeFilterMiddleware {
+    /// Create a middleware refusing queries for any of `blocked`.
+    pub fn new(blocked: impl IntoIterator<Item = RecordType>) -> Self {
+        Self {
+            blocked: blocked.into_iter().collect(),
+        }
+    }
+
+    /// Build the REFUSED response for a query of a blocked type, or `None` if it is allowed.
+    fn refused_response(&self, query: &DnsMessage) -> Option<DnsMessage> {
+        let question = query.questions().first()?;
+        if !self.blocked.contains(&question.qtype) {
+            return None;
+        }
+
+        Some(DnsMessage::response_from_query(query, DnsResponseCode::Refused))
+    }
+}
+
+#[async_trait]
+impl DnsMiddleware<Global, Local> for QtypeFilterMiddleware {
+    async fn on_query(&self, ctx: &mut DnsRequestCtx<Global, Local>) -> anyhow::Result<Option<DnsResponse>> {
+        let Some(response) = self.refused_response(ctx.message()?) else {
+            return Ok(None);
+        };
+
+        let bytes = response.encode()?;
+        ctx.local_mut().blocked = true;
+
+        Ok(Some(DnsResponse::from_parsed(bytes, response)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reso_dns::{ClassType, DnsMessageBuilder, DnsQuestion, domain_name::DomainName};
+
+    use super::*;
+    use crate::{server_builder::server_middlewares, services::config::Config};
+
+    fn query(qtype: RecordType) -> DnsMessage {
+        DnsMessageBuilder::new()
+            .with_id(3)
+            .add_question(DnsQuestion::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                qtype,
+                ClassType::IN,
+            ))
+            .build()
+    }
+
+    #[test]
+    fn test_blocked_qtype_is_refused() {
+        let filter = QtypeFilterMiddleware::new([RecordType::HTTPS, RecordType::SVCB]);
+
+        let response = filter
+            .refused_response(&query(RecordType::HTTPS))
+            .expect("HTTPS should be refused");
+
+        assert_eq!(response.id, 3);
+        assert_eq!(response.response_code(), DnsResponseCode::Refused);
+        assert_eq!(response.questions(), query(RecordType::HTTPS).questions());
+    }
+
+    #[test]
+    fn test_allowed_qtype_passes_through() {
+        let filter = QtypeFilterMiddleware::new([RecordType::HTTPS]);
+
+        assert!(filter.refused_response(&query(RecordType::A)).is_none());
+        assert!(filter.refused_response(&query(RecordType::AAAA)).is_none());
+    }
+
+    #[test]
+    fn test_only_registered_when_configured() {
+        let mut config = Config::default();
+        let disabled = server_middlewares(&config).len();
+
+        config.dns.blocked_qtypes = vec!["HTTPS".to_string()];
+        let enabled = server_middlewares(&config).len();
+
+        assert_eq!(enabled, disabled + 1);
+    }
+}
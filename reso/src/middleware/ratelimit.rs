@@ -22,16 +22,11 @@ impl RateLimitMiddleware {
 }
 
 fn ratelimit_response_flags(query: &DnsMessage) -> DnsFlags {
-    DnsFlags::new(
-        true,
-        query.flags.opcode,
-        true,
-        false,
-        query.flags.recursion_desired,
-        true,
-        false,
-        query.flags.checking_disabled,
-    )
+    DnsFlags::response()
+        .with_opcode(query.flags.opcode)
+        .with_authorative_answer(true)
+        .with_recursion_desired(query.flags.recursion_desired)
+        .with_checking_disabled(query.flags.checking_disabled)
 }
 
 #[async_trait]
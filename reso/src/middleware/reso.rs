@@ -6,7 +6,7 @@ use std::{
 use async_trait::async_trait;
 use reso_context::{DnsMiddleware, DnsRequestCtx, DnsResponse};
 use reso_dns::{
-    ClassType, DnsFlags, DnsMessageBuilder, DnsOpcode, DnsRecord, DnsResponseCode, RecordType, domain_name::DomainName,
+    ClassType, DnsFlags, DnsMessageBuilder, DnsRecord, DnsResponseCode, RecordType, domain_name::DomainName,
     message::DnsRecordData,
 };
 
@@ -65,16 +65,9 @@ impl DnsMiddleware<Global, Local> for ResoLocalMiddleware {
             _ => return Ok(None),
         };
 
-        let flags = DnsFlags::new(
-            true,
-            DnsOpcode::Query,
-            false,
-            false,
-            message.flags.recursion_desired,
-            true,
-            false,
-            message.flags.checking_disabled,
-        );
+        let flags = DnsFlags::response()
+            .with_recursion_desired(message.flags.recursion_desired)
+            .with_checking_disabled(message.flags.checking_disabled);
 
         let bytes = echo_edns(
             message,
@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reso_cache::{CacheKey, NegKind, NegativeResult};
+use reso_context::{DnsRequestCtx, DnsResponse};
+use reso_dns::{
+    DnsMessage, EdnsOption,
+    message::{EdnsOptionCode, EdnsOptionData, ExtendedDnsErrorInfoCode},
+};
+use reso_resolver::{DnsResolver, DynResolver, ResolveError};
+
+use crate::{global::Global, local::Local, middleware::cache::negative_response};
+
+/// Resolver that answers with an expired NXDOMAIN or NODATA from the cache when the wrapped resolver can't
+/// reach its upstreams (RFC 8767), for as long as the cache keeps negative entries around after expiry.
+///
+/// Any other failure is passed through unchanged.
+pub struct StaleNegativeResolver {
+    inner: Arc<DynResolver<Global, Local>>,
+}
+
+impl StaleNegativeResolver {
+    pub fn new(inner: Arc<DynResolver<Global, Local>>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl DnsResolver<Global, Local> for StaleNegativeResolver {
+    async fn resolve(&self, ctx: &DnsRequestCtx<Global, Local>) -> Result<DnsResponse, ResolveError> {
+        let error = match self.inner.resolve(ctx).await {
+            Err(e @ (ResolveError::Timeout | ResolveError::NoReachableUpstream)) => e,
+            result => return result,
+        };
+
+        let Ok(query) = ctx.message() else {
+            return Err(error);
+        };
+        let Ok(key) = CacheKey::try_from(query) else {
+            return Err(error);
+        };
+        let Some(result) = ctx.global().cache.lookup_stale_negative(&key).await else {
+            return Err(error);
+        };
+
+        tracing::debug!("upstreams unavailable, serving stale negative answer for {}", key.name);
+
+        let response = stale_response(query, result);
+        let bytes = response.encode().map_err(|e| ResolveError::Other(e.to_string()))?;
+        Ok(DnsResponse::from_parsed(bytes, response))
+    }
+}
+
+/// Build the response for a stale denial of `query`.
+///
+/// Clients that sent an OPT record are told the answer is stale with an extended DNS error (RFC 8914).
+fn stale_response(query: &DnsMessage, result: NegativeResult) -> DnsMessage {
+    let info_code = match result.kind {
+        NegKind::NxDomain => ExtendedDnsErrorInfoCode::StaleNxDomainAnswer,
+        NegKind::NoData => ExtendedDnsErrorInfoCode::StaleAnswer,
+    };
+
+    let mut response = negative_response(query, result);
+
+    if let Some(mut edns) = response.edns().clone() {
+        edns.options.push(EdnsOption::new(
+            EdnsOptionCode::ExtendedDnsError,
+            EdnsOptionData::ExtendedError {
+                info_code,
+                extra_text: None,
+            },
+        ));
+        response.set_edns(Some(edns));
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use reso_dns::{
+        ClassType, DnsMessageBuilder, DnsQuestion, DnsRecord, DnsResponseCode, EdnsBuilder, RecordType,
+        domain_name::DomainName, message::DnsRecordData,
+    };
+
+    use super::*;
+
+    fn query(edns: bool) -> DnsMessage {
+        let builder = DnsMessageBuilder::new().with_id(7).add_question(DnsQuestion::new(
+            DomainName::from_ascii("gone.example.com").unwrap(),
+            RecordType::A,
+            ClassType::IN,
+        ));
+        if edns {
+            builder.with_edns(EdnsBuilder::new().build()).build()
+        } else {
+            builder.build()
+        }
+    }
+
+    fn result(kind: NegKind) -> NegativeResult {
+        NegativeResult {
+            kind,
+            soa_record: DnsRecord::new(
+                DomainName::from_ascii("example.com").unwrap(),
+                RecordType::SOA,
+                ClassType::IN,
+                30,
+                DnsRecordData::SOA {
+                    mname: DomainName::from_ascii("ns1.example.com").unwrap(),
+                    rname: DomainName::from_ascii("hostmaster.example.com").unwrap(),
+                    serial: 1,
+                    refresh: 3600,
+                    retry: 600,
+                    expire: 86_400,
+                    minimum: 300,
+                },
+            ),
+            answer_records: Arc::new([]),
+        }
+    }
+
+    fn extended_error(response: &DnsMessage) -> Option<ExtendedDnsErrorInfoCode> {
+        response
+            .edns()
+            .as_ref()?
+            .options
+            .iter()
+            .find_map(|option| match &option.data {
+                Some(EdnsOptionData::ExtendedError { info_code, .. }) => Some(*info_code),
+                _ => None,
+            })
+    }
+
+    #[test]
+    fn test_stale_nxdomain_is_marked_stale() {
+        let response = stale_response(&query(true), result(NegKind::NxDomain));
+
+        assert_eq!(response.id, 7);
+        assert_eq!(response.response_code(), DnsResponseCode::NxDomain);
+        assert_eq!(response.authority_records()[0].ttl, 30);
+        assert_eq!(
+            extended_error(&response),
+            Some(ExtendedDnsErrorInfoCode::StaleNxDomainAnswer)
+        );
+
+        // the encoded response must decode again.
+        assert_eq!(DnsMessage::decode(&response.encode().unwrap()).unwrap(), response);
+    }
+
+    #[test]
+    fn test_stale_nodata_is_marked_stale() {
+        let response = stale_response(&query(true), result(NegKind::NoData));
+
+        assert_eq!(response.response_code(), DnsResponseCode::NoError);
+        assert!(response.answers().is_empty());
+        assert_eq!(extended_error(&response), Some(ExtendedDnsErrorInfoCode::StaleAnswer));
+    }
+
+    #[test]
+    fn test_no_extended_error_without_edns() {
+        let response = stale_response(&query(false), result(NegKind::NxDomain));
+
+        assert!(response.edns().is_none());
+    }
+}
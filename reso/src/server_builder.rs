@@ -1,23 +1,40 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
+use anyhow::Context;
 use futures::StreamExt;
 use reso_context::DnsMiddleware;
-use reso_resolver::forwarder::resolver::ForwardResolver;
-use reso_server::{DnsServer, ServerMiddlewares, ServerState};
+use reso_dns::domain_name::DomainName;
+use reso_resolver::{
+    DynResolver,
+    chain::ChainResolver,
+    forwarder::resolver::ForwardResolver,
+    ptr::{IpPrefix, LocalPtrResolver},
+    truncation::TruncationCounters,
+    zone::StaticResolver,
+};
+use reso_server::{DnsServer, ServerConfig, ServerMiddlewares, ServerState};
 use tokio_stream::wrappers::WatchStream;
 
 use crate::{
     global::{Global, SharedGlobal},
     local::Local,
     middleware::{
-        block_resolver_privacy::BlockResolverPrivacyMiddleware, cache::CacheMiddleware,
-        domain_rules::DomainRulesMiddleware, local_records::LocalRecordsMiddleware, metrics::MetricsMiddleware,
+        block_resolver_privacy::BlockResolverPrivacyMiddleware, cache::CacheMiddleware, chaos::ChaosMiddleware,
+        dnssec::DnssecFilterMiddleware, domain_rules::DomainRulesMiddleware, ip_blocklist::IpBlocklistMiddleware,
+        local_records::LocalRecordsMiddleware, metrics::MetricsMiddleware, minimal_any::MinimalAnyMiddleware,
+        minimal_responses::MinimalResponsesMiddleware, nsid::NsidMiddleware, qtype_filter::QtypeFilterMiddleware,
         ratelimit::RateLimitMiddleware, reso::ResoLocalMiddleware,
     },
     ratelimit::RateLimitConfig,
+    serve_stale::StaleNegativeResolver,
     services::{
         self,
-        config::{ActiveResolver, Config, Upstream},
+        config::{ActiveResolver, Config, ForwarderConfig, Upstream},
     },
 };
 
@@ -25,6 +42,16 @@ pub fn server_middlewares(config: &Config) -> ServerMiddlewares<Global, Local> {
     let mut middlewares: Vec<Arc<dyn DnsMiddleware<Global, Local> + 'static>> =
         vec![Arc::new(MetricsMiddleware), Arc::new(ResoLocalMiddleware::new())];
 
+    // Registered early so the option is added to every response, including ones answered by later middlewares.
+    if !config.dns.nsid.is_empty() {
+        middlewares.push(Arc::new(NsidMiddleware::new(config.dns.nsid.as_bytes())));
+    }
+
+    middlewares.push(Arc::new(ChaosMiddleware::new(
+        config.dns.chaos_version.as_str(),
+        config.dns.chaos_id.as_str(),
+    )));
+
     if config.dns.security.block_designated_resolver
         || config.dns.security.block_icloud_private_relay
         || config.dns.security.block_firefox_canary
@@ -32,6 +59,10 @@ pub fn server_middlewares(config: &Config) -> ServerMiddlewares<Global, Local> {
         middlewares.push(Arc::new(BlockResolverPrivacyMiddleware));
     }
 
+    if config.dns.minimal_any {
+        middlewares.push(Arc::new(MinimalAnyMiddleware));
+    }
+
     middlewares.push(Arc::new(LocalRecordsMiddleware));
 
     if config.dns.rate_limit.enabled {
@@ -42,8 +73,25 @@ pub fn server_middlewares(config: &Config) -> ServerMiddlewares<Global, Local> {
         middlewares.push(Arc::new(RateLimitMiddleware::new(ratelimit_config)));
     }
 
+    if !config.dns.blocked_qtypes.is_empty() {
+        let blocked = config.dns.blocked_qtypes.iter().filter_map(|qtype| qtype.parse().ok());
+        middlewares.push(Arc::new(QtypeFilterMiddleware::new(blocked)));
+    }
+
     middlewares.push(Arc::new(DomainRulesMiddleware));
-    middlewares.push(Arc::new(CacheMiddleware));
+    // Registered before the cache so cached responses are checked against the current list.
+    if !config.dns.blocking.blocked_ips.is_empty() {
+        let prefixes = config.dns.blocking.blocked_ips.iter().filter_map(|p| p.parse().ok());
+        middlewares.push(Arc::new(IpBlocklistMiddleware::new(prefixes)));
+    }
+    // Registered before the cache so these trim responses after they are cached.
+    middlewares.push(Arc::new(DnssecFilterMiddleware));
+    if config.dns.minimal_responses {
+        middlewares.push(Arc::new(MinimalResponsesMiddleware));
+    }
+    middlewares.push(Arc::new(
+        CacheMiddleware::new(config.dns.rotate_answers).with_bypass(config.dns.cache_bypass.iter().map(String::as_str)),
+    ));
 
     Arc::new(middlewares)
 }
@@ -65,19 +113,102 @@ async fn create_server_state(
         })
         .collect::<Vec<_>>();
 
-    let resolver = match &config.dns.active {
-        ActiveResolver::Forwarder => ForwardResolver::new(&upstreams).await?,
-    };
+    let mut resolver = build_resolver(
+        &config.dns.active,
+        &upstreams,
+        &config.dns.forwarder,
+        &global.forwarder_truncation,
+    )
+    .await?;
+
+    // the cache outlives the server state, so its floors are replaced along with the state.
+    global.cache.set_ttl_floors(
+        config
+            .dns
+            .cache_ttl_floors
+            .iter()
+            .filter_map(|(name, ttl)| Some((DomainName::from_user(name).ok()?, *ttl))),
+    );
+    global.cache.set_ttl_jitter(config.dns.cache_ttl_jitter_percent);
+
+    global
+        .cache
+        .set_negative_stale_window(Duration::from_secs(config.dns.negative_stale_secs));
+    if config.dns.negative_stale_secs > 0 {
+        resolver = Arc::new(StaleNegativeResolver::new(resolver));
+    }
 
     Ok(ServerState {
         timeout: Duration::from_millis(config.dns.timeout),
         global: global.clone(),
         middlewares: server_middlewares(config),
-        resolver: Arc::new(resolver),
+        resolver,
+    })
+}
+
+/// Builds the resolver for the active resolver config.
+async fn build_resolver(
+    active: &ActiveResolver,
+    upstreams: &[SocketAddr],
+    forwarder: &ForwarderConfig,
+    truncation: &Arc<TruncationCounters>,
+) -> anyhow::Result<Arc<DynResolver<Global, Local>>> {
+    Ok(match active {
+        ActiveResolver::Forwarder => Arc::new(
+            ForwardResolver::new(upstreams)
+                .await?
+                .with_edns_udp_payload_size(forwarder.edns_udp_payload_size)
+                .with_ecs_mode(forwarder.ecs_mode.into())
+                .with_upstream_selection(forwarder.upstream_selection.into())
+                .with_edns_option_filter((&forwarder.edns_options).into())
+                .with_tcp_fast_open(forwarder.tcp_fast_open)
+                .with_truncation_counters(truncation.clone()),
+        ),
+        ActiveResolver::Static { zones } => Arc::new(StaticResolver::load(zones)?),
+        ActiveResolver::Ptr {
+            prefixes,
+            hosts,
+            domain,
+        } => Arc::new(ptr_resolver(prefixes, hosts, domain.as_deref())?),
+        ActiveResolver::Chain { resolvers } => {
+            let mut chain = Vec::with_capacity(resolvers.len());
+            for resolver in resolvers {
+                chain.push(Box::pin(build_resolver(resolver, upstreams, forwarder, truncation)).await?);
+            }
+            Arc::new(ChainResolver::new(chain))
+        }
     })
 }
 
+/// Builds the resolver answering PTR queries for the local networks in `prefixes`.
+pub fn ptr_resolver(
+    prefixes: &[String],
+    hosts: &BTreeMap<IpAddr, String>,
+    domain: Option<&str>,
+) -> anyhow::Result<LocalPtrResolver> {
+    let prefixes = prefixes
+        .iter()
+        .map(|p| p.parse::<IpPrefix>())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let names = hosts
+        .iter()
+        .map(|(ip, name)| {
+            let name = DomainName::from_user(name).with_context(|| format!("invalid name for {ip}"))?;
+            Ok((*ip, name))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let mut resolver = LocalPtrResolver::new(prefixes, names);
+    if let Some(domain) = domain {
+        resolver = resolver.with_domain(DomainName::from_user(domain).context("invalid PTR domain")?);
+    }
+    Ok(resolver)
+}
+
 /// Starts a background task that updates the server state based on configuration change events.
+///
+/// Only the `ServerState` is replaced. The `ServerConfig` the server was built with, e.g. `recv_size`,
+/// `max_udp_response` and the connection limits, stays in effect until restart.
 pub async fn update_server_state_on_config_changes(global: SharedGlobal, server: Arc<DnsServer<Global, Local>>) {
     let mut rx = global.config.subscribe();
 
@@ -101,5 +232,53 @@ pub async fn update_server_state_on_config_changes(global: SharedGlobal, server:
 pub async fn build_dns_server(global: SharedGlobal) -> anyhow::Result<Arc<DnsServer<Global, Local>>> {
     let config = global.config.get_config();
     let server_state = create_server_state(&global, &config).await?;
-    Ok(Arc::new(DnsServer::new(server_state)))
+    let server_config = ServerConfig {
+        recv_size: config.dns.recv_size,
+        max_udp_response: (config.dns.max_udp_response > 0).then_some(config.dns.max_udp_response),
+        max_concurrent_requests: config.dns.max_concurrent_requests,
+        max_tcp_connections: config.dns.max_tcp_connections,
+        tcp_idle_timeout: Duration::from_millis(config.dns.tcp_idle_timeout),
+        tcp_read_timeout: Duration::from_millis(config.dns.tcp_read_timeout),
+        udp_timeout: (config.dns.udp_timeout > 0).then(|| Duration::from_millis(config.dns.udp_timeout)),
+        tcp_timeout: (config.dns.tcp_timeout > 0).then(|| Duration::from_millis(config.dns.tcp_timeout)),
+        doh_timeout: (config.dns.doh_timeout > 0).then(|| Duration::from_millis(config.dns.doh_timeout)),
+    };
+    Ok(Arc::new(
+        DnsServer::new(server_state, server_config)?.with_truncation_counters(global.server_truncation.clone()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{global::setup_test_global, services::config::UpstreamSpec};
+
+    fn test_config(timeout: u64) -> Config {
+        let mut config = Config::default();
+        config.dns.timeout = timeout;
+        config.dns.forwarder.upstreams = vec![UpstreamSpec("127.0.0.1:5353".to_string())];
+        config
+    }
+
+    #[tokio::test]
+    async fn test_config_changes_swap_the_server_state() {
+        let global = setup_test_global().await;
+        global.config.update_config(test_config(3000)).await.unwrap();
+        let server = build_dns_server(global.clone()).await.unwrap();
+        let initial = server.resolver();
+
+        tokio::spawn(update_server_state_on_config_changes(global.clone(), server.clone()));
+        // let the task subscribe before the change is sent.
+        tokio::task::yield_now().await;
+
+        global.config.update_config(test_config(1234)).await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while Arc::ptr_eq(&initial, &server.resolver()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("server state was not swapped");
+    }
 }
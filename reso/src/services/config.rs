@@ -1,11 +1,19 @@
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{Context, Result, bail};
 use arc_swap::ArcSwap;
+use reso_dns::{RecordType, domain_name::DomainName, message::EdnsOptionCode};
+use reso_resolver::forwarder::{
+    UpstreamSelection,
+    resolver::{DEFAULT_EDNS_UDP_PAYLOAD_SIZE, EcsMode, EdnsOptionFilter},
+};
+use reso_resolver::ptr::IpPrefix;
+use reso_server::{DEFAULT_RECV_SIZE, MIN_RECV_SIZE, ServerConfig};
 use serde::{Deserialize, Serialize};
 use url::Url;
 
@@ -29,6 +37,16 @@ pub struct LogsConfig {
     pub retention_secs: u64,
     /// How often to run the truncation job in seconds.
     pub truncate_interval_secs: u64,
+    /// Activity logs stored per client and minute before the rest are sampled, 0 disables the limit.
+    #[serde(default)]
+    pub client_limit: u64,
+    /// Once a client is over `client_limit`, only 1 in this many of its activity logs is stored, 0 stores none.
+    #[serde(default = "default_client_sample_rate")]
+    pub client_sample_rate: u64,
+}
+
+fn default_client_sample_rate() -> u64 {
+    100
 }
 
 #[derive(Serialize, Deserialize)]
@@ -43,12 +61,133 @@ pub struct DnsConfig {
     pub rate_limit: RateLimitConfigModel,
     /// Security related config.
     pub security: SecurityConfig,
+    /// How blocked queries are answered.
+    #[serde(default)]
+    pub blocking: BlockingConfig,
+    /// Whether to drop the authority and additional sections from responses.
+    #[serde(default)]
+    pub minimal_responses: bool,
+    /// Whether to answer ANY queries with a synthesized HINFO record (RFC 8482).
+    #[serde(default)]
+    pub minimal_any: bool,
+    /// Whether to rotate the order of records within each RRset on every cache hit.
+    #[serde(default)]
+    pub rotate_answers: bool,
+    /// Names that are never cached, each with its subdomains, e.g. zones with dynamic records.
+    #[serde(default)]
+    pub cache_bypass: Vec<String>,
+    /// Minimum cache TTL in seconds per name, each applying to its subdomains too, e.g. for CDN endpoints.
+    #[serde(default)]
+    pub cache_ttl_floors: BTreeMap<String, u32>,
+    /// Maximum share of the TTL, in percent, randomly cut from the lifetime of each cached answer, so answers
+    /// that share a TTL aren't refreshed in a burst. Disabled if zero.
+    #[serde(default = "default_cache_ttl_jitter_percent")]
+    pub cache_ttl_jitter_percent: u8,
+    /// Seconds an expired NXDOMAIN or NODATA may still be answered from the cache while upstreams are
+    /// unreachable (RFC 8767), disabled if zero.
+    #[serde(default)]
+    pub negative_stale_secs: u64,
+    /// Server identifier returned to clients that send an NSID option (RFC 5001), disabled if empty.
+    #[serde(default)]
+    pub nsid: String,
+    /// Answer to CHAOS TXT `version.bind` queries, refused if empty.
+    #[serde(default)]
+    pub chaos_version: String,
+    /// Answer to CHAOS TXT `id.server` queries, refused if empty.
+    #[serde(default)]
+    pub chaos_id: String,
+    /// Record types whose queries are refused, e.g. `HTTPS`.
+    #[serde(default)]
+    pub blocked_qtypes: Vec<String>,
+    /// Size of the UDP receive buffer and the EDNS payload size advertised to clients, applied on restart.
+    #[serde(default = "default_recv_size")]
+    pub recv_size: u16,
+    /// Largest UDP response in bytes, whatever buffer size the client advertises, applied on restart. Larger
+    /// responses are truncated so clients retry over TCP, which limits amplification. 0 disables the cap.
+    #[serde(default)]
+    pub max_udp_response: u16,
+    /// Maximum number of requests processed at once per transport, further requests are refused, applied on
+    /// restart.
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
+    /// Maximum number of open TCP connections, further connections wait until one closes, applied on restart.
+    #[serde(default = "default_max_tcp_connections")]
+    pub max_tcp_connections: usize,
+    /// Milliseconds an inbound TCP or DoH connection may sit idle between queries before it is closed, applied
+    /// on restart.
+    #[serde(default = "default_tcp_idle_timeout")]
+    pub tcp_idle_timeout: u64,
+    /// Milliseconds allowed to read a TCP query once its length was received, applied on restart.
+    #[serde(default = "default_tcp_read_timeout")]
+    pub tcp_read_timeout: u64,
+    /// Timeout for UDP queries in milliseconds, `timeout` is used if zero. Applied on restart.
+    #[serde(default)]
+    pub udp_timeout: u64,
+    /// Timeout for TCP queries in milliseconds, `timeout` is used if zero. Applied on restart.
+    #[serde(default)]
+    pub tcp_timeout: u64,
+    /// Timeout for DoH queries in milliseconds, `timeout` is used if zero. Applied on restart.
+    #[serde(default)]
+    pub doh_timeout: u64,
 }
 
-#[derive(Serialize, Deserialize)]
+fn default_cache_ttl_jitter_percent() -> u8 {
+    5
+}
+
+fn default_recv_size() -> u16 {
+    DEFAULT_RECV_SIZE
+}
+
+fn default_max_concurrent_requests() -> usize {
+    ServerConfig::default().max_concurrent_requests
+}
+
+fn default_max_tcp_connections() -> usize {
+    ServerConfig::default().max_tcp_connections
+}
+
+fn default_tcp_idle_timeout() -> u64 {
+    ServerConfig::default().tcp_idle_timeout.as_millis() as u64
+}
+
+fn default_tcp_read_timeout() -> u64 {
+    ServerConfig::default().tcp_read_timeout.as_millis() as u64
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ActiveResolver {
-    #[serde(rename = "forwarder")]
     Forwarder,
+    /// Answer authoritatively from zone files.
+    Static {
+        zones: Vec<PathBuf>,
+    },
+    /// Answer PTR queries for addresses in local networks, e.g. `10.0.0.0/8`.
+    Ptr {
+        prefixes: Vec<String>,
+        /// Names of known local addresses.
+        #[serde(default)]
+        hosts: BTreeMap<IpAddr, String>,
+        /// Domain for generated names of unknown local addresses, which are NXDOMAIN when unset.
+        #[serde(default)]
+        domain: Option<String>,
+    },
+    /// Try each resolver in order until one of them succeeds.
+    Chain {
+        resolvers: Vec<ActiveResolver>,
+    },
+}
+
+impl ActiveResolver {
+    /// Whether queries may be forwarded to the upstreams.
+    pub fn forwards(&self) -> bool {
+        match self {
+            ActiveResolver::Forwarder => true,
+            ActiveResolver::Static { .. } | ActiveResolver::Ptr { .. } => false,
+            ActiveResolver::Chain { resolvers } => resolvers.iter().any(ActiveResolver::forwards),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -80,7 +219,13 @@ pub struct HostPort {
 
 impl HostPort {
     pub fn socket_addr(&self) -> anyhow::Result<SocketAddr> {
-        Ok(SocketAddr::from_str(&format!("{}:{}", self.host, self.port))?)
+        // IPv6 hosts, including scoped ones like `fe80::1%2`, need brackets to be followed by a port.
+        let addr = if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        };
+        Ok(SocketAddr::from_str(&addr)?)
     }
 }
 
@@ -140,6 +285,22 @@ fn split_host_port(s: &str) -> Result<(String, Option<u16>)> {
         bail!("empty upstream");
     }
 
+    // bracketed IPv6, e.g. `[::1]` or `[::1]:53`.
+    if let Some(rest) = s.strip_prefix('[') {
+        let (host, after) = rest.split_once(']').context("missing closing bracket")?;
+        if host.is_empty() {
+            bail!("empty upstream");
+        }
+        let port = match after {
+            "" => None,
+            _ => {
+                let port = after.strip_prefix(':').context("expected a port after the address")?;
+                Some(port.parse().with_context(|| format!("invalid port: {port:?}"))?)
+            }
+        };
+        return Ok((host.to_string(), port));
+    }
+
     if let Some((host, port)) = s.rsplit_once(':')
         && !host.contains(':')
         && !host.is_empty()
@@ -154,6 +315,90 @@ fn split_host_port(s: &str) -> Result<(String, Option<u16>)> {
 #[derive(Serialize, Deserialize)]
 pub struct ForwarderConfig {
     pub upstreams: Vec<UpstreamSpec>,
+    /// UDP payload size advertised to upstreams, regardless of what the client sent.
+    #[serde(default = "default_edns_udp_payload_size")]
+    pub edns_udp_payload_size: u16,
+    /// How the client subnet (ECS) of forwarded queries is set.
+    #[serde(default)]
+    pub ecs_mode: EcsModeConfig,
+    /// How the first upstream tried for a query is chosen.
+    #[serde(default)]
+    pub upstream_selection: UpstreamSelectionConfig,
+    /// Which EDNS options of the client's query are forwarded upstream.
+    #[serde(default)]
+    pub edns_options: EdnsOptionFilterConfig,
+    /// Whether upstream TCP connections use TCP Fast Open, only supported on Linux.
+    #[serde(default)]
+    pub tcp_fast_open: bool,
+}
+
+fn default_edns_udp_payload_size() -> u16 {
+    DEFAULT_EDNS_UDP_PAYLOAD_SIZE
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EcsModeConfig {
+    /// Forward the client's subnet option, if any, unchanged.
+    #[default]
+    Off,
+    /// Send only the address family with a source prefix of 0, opting out of location based answers.
+    Zeroed,
+    /// Send the client's subnet, truncated to the given prefix lengths.
+    Forward { v4_prefix: u8, v6_prefix: u8 },
+}
+
+impl From<EcsModeConfig> for EcsMode {
+    fn from(mode: EcsModeConfig) -> Self {
+        match mode {
+            EcsModeConfig::Off => EcsMode::Off,
+            EcsModeConfig::Zeroed => EcsMode::Zeroed,
+            EcsModeConfig::Forward { v4_prefix, v6_prefix } => EcsMode::Forward { v4_prefix, v6_prefix },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamSelectionConfig {
+    /// Cycle through the upstreams in order.
+    #[default]
+    RoundRobin,
+    /// Prefer upstreams with a lower recent average latency, still probing the slower ones.
+    Latency,
+}
+
+impl From<UpstreamSelectionConfig> for UpstreamSelection {
+    fn from(selection: UpstreamSelectionConfig) -> Self {
+        match selection {
+            UpstreamSelectionConfig::RoundRobin => UpstreamSelection::RoundRobin,
+            UpstreamSelectionConfig::Latency => UpstreamSelection::Latency,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdnsOptionFilterConfig {
+    /// Forward every option.
+    #[default]
+    All,
+    /// Forward only options reso knows, dropping unknown ones.
+    Known,
+    /// Forward only options with one of these codes.
+    Allow(Vec<u16>),
+}
+
+impl From<&EdnsOptionFilterConfig> for EdnsOptionFilter {
+    fn from(filter: &EdnsOptionFilterConfig) -> Self {
+        match filter {
+            EdnsOptionFilterConfig::All => EdnsOptionFilter::All,
+            EdnsOptionFilterConfig::Known => EdnsOptionFilter::Known,
+            EdnsOptionFilterConfig::Allow(codes) => {
+                EdnsOptionFilter::Allow(codes.iter().copied().map(EdnsOptionCode::from).collect())
+            }
+        }
+    }
 }
 
 impl ForwarderConfig {
@@ -179,7 +424,73 @@ pub struct SecurityConfig {
     pub block_firefox_canary: bool,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct BlockingConfig {
+    /// The response sent for blocked queries.
+    pub response_policy: BlockResponsePolicy,
+    /// Address answered for blocked A queries with the sinkhole policy.
+    pub sinkhole_ipv4: Ipv4Addr,
+    /// Address answered for blocked AAAA queries with the sinkhole policy.
+    pub sinkhole_ipv6: Ipv6Addr,
+    /// TTL of synthesized blocked answers and of the SOA in negative blocked responses, in seconds.
+    #[serde(default = "default_block_ttl")]
+    pub ttl: u32,
+    /// Networks in CIDR notation, responses answering with an address in one of them are blocked.
+    #[serde(default)]
+    pub blocked_ips: Vec<String>,
+}
+
+/// Default TTL of blocked responses, kept short so unblocking takes effect quickly.
+pub const DEFAULT_BLOCK_TTL: u32 = 60;
+
+fn default_block_ttl() -> u32 {
+    DEFAULT_BLOCK_TTL
+}
+
+impl Default for BlockingConfig {
+    fn default() -> Self {
+        Self {
+            response_policy: BlockResponsePolicy::NxDomain,
+            sinkhole_ipv4: Ipv4Addr::UNSPECIFIED,
+            sinkhole_ipv6: Ipv6Addr::UNSPECIFIED,
+            ttl: DEFAULT_BLOCK_TTL,
+            blocked_ips: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockResponsePolicy {
+    /// Answer with NXDOMAIN.
+    #[serde(rename = "nxdomain")]
+    NxDomain,
+    /// Answer with REFUSED.
+    #[serde(rename = "refused")]
+    Refused,
+    /// Answer with NOERROR and no records.
+    #[serde(rename = "nodata")]
+    NoData,
+    /// Answer A and AAAA queries with the sinkhole addresses, other types get NODATA.
+    #[serde(rename = "sinkhole")]
+    Sinkhole,
+}
+
 impl Config {
+    /// Settings in `map` whose stored value doesn't parse, each described with the value used instead.
+    ///
+    /// `from_kv` replaces such values with their default, so they are found by reading the parsed value back.
+    pub fn invalid_values(map: &HashMap<String, String>) -> Vec<String> {
+        Self::from_kv(map)
+            .to_kv()
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let stored = map.get(&key)?;
+                (!same_value(stored, &value))
+                    .then(|| format!("{key}: {stored:?} is not valid, {value:?} is used instead"))
+            })
+            .collect()
+    }
+
     pub fn from_kv(map: &HashMap<String, String>) -> Self {
         let defaults = Self::default();
 
@@ -190,7 +501,12 @@ impl Config {
 
         let active = map
             .get("dns.active")
-            .and_then(|v| serde_json::from_value::<ActiveResolver>(serde_json::Value::String(v.clone())).ok())
+            .and_then(|v| {
+                // Resolvers with settings are stored as JSON, the forwarder as a plain string.
+                serde_json::from_str::<ActiveResolver>(v)
+                    .or_else(|_| serde_json::from_value::<ActiveResolver>(serde_json::Value::String(v.clone())))
+                    .ok()
+            })
             .unwrap_or(defaults.dns.active);
 
         let upstreams = map
@@ -199,6 +515,31 @@ impl Config {
             .map(|specs| specs.into_iter().map(UpstreamSpec).collect())
             .unwrap_or(defaults.dns.forwarder.upstreams);
 
+        let edns_udp_payload_size = map
+            .get("dns.forwarder.edns_udp_payload_size")
+            .and_then(|v| v.parse::<u16>().ok())
+            .unwrap_or(defaults.dns.forwarder.edns_udp_payload_size);
+
+        let ecs_mode = map
+            .get("dns.forwarder.ecs_mode")
+            .and_then(|v| serde_json::from_str::<EcsModeConfig>(v).ok())
+            .unwrap_or(defaults.dns.forwarder.ecs_mode);
+
+        let upstream_selection = map
+            .get("dns.forwarder.upstream_selection")
+            .and_then(|v| serde_json::from_str::<UpstreamSelectionConfig>(v).ok())
+            .unwrap_or(defaults.dns.forwarder.upstream_selection);
+
+        let edns_options = map
+            .get("dns.forwarder.edns_options")
+            .and_then(|v| serde_json::from_str::<EdnsOptionFilterConfig>(v).ok())
+            .unwrap_or(defaults.dns.forwarder.edns_options);
+
+        let tcp_fast_open = map
+            .get("dns.forwarder.tcp_fast_open")
+            .and_then(|v| v.parse::<bool>().ok())
+            .unwrap_or(defaults.dns.forwarder.tcp_fast_open);
+
         let rate_limit_enabled = map
             .get("dns.rate_limit.enabled")
             .and_then(|v| v.parse::<bool>().ok())
@@ -229,6 +570,157 @@ impl Config {
             .and_then(|v| v.parse::<bool>().ok())
             .unwrap_or(defaults.dns.security.block_firefox_canary);
 
+        let response_policy = map
+            .get("dns.blocking.response_policy")
+            .and_then(|v| serde_json::from_value::<BlockResponsePolicy>(serde_json::Value::String(v.clone())).ok())
+            .unwrap_or(defaults.dns.blocking.response_policy);
+
+        let sinkhole_ipv4 = map
+            .get("dns.blocking.sinkhole_ipv4")
+            .and_then(|v| v.parse::<Ipv4Addr>().ok())
+            .unwrap_or(defaults.dns.blocking.sinkhole_ipv4);
+
+        let sinkhole_ipv6 = map
+            .get("dns.blocking.sinkhole_ipv6")
+            .and_then(|v| v.parse::<Ipv6Addr>().ok())
+            .unwrap_or(defaults.dns.blocking.sinkhole_ipv6);
+
+        let block_ttl = map
+            .get("dns.blocking.ttl")
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(defaults.dns.blocking.ttl);
+
+        let blocked_ips = map
+            .get("dns.blocking.blocked_ips")
+            .and_then(|v| serde_json::from_str::<Vec<String>>(v).ok())
+            .map(|prefixes| {
+                prefixes
+                    .into_iter()
+                    .filter(|prefix| prefix.parse::<IpPrefix>().is_ok())
+                    .collect()
+            })
+            .unwrap_or(defaults.dns.blocking.blocked_ips);
+
+        let minimal_responses = map
+            .get("dns.minimal_responses")
+            .and_then(|v| v.parse::<bool>().ok())
+            .unwrap_or(defaults.dns.minimal_responses);
+
+        let minimal_any = map
+            .get("dns.minimal_any")
+            .and_then(|v| v.parse::<bool>().ok())
+            .unwrap_or(defaults.dns.minimal_any);
+
+        let rotate_answers = map
+            .get("dns.rotate_answers")
+            .and_then(|v| v.parse::<bool>().ok())
+            .unwrap_or(defaults.dns.rotate_answers);
+
+        let cache_bypass = map
+            .get("dns.cache_bypass")
+            .and_then(|v| serde_json::from_str::<Vec<String>>(v).ok())
+            .map(|names| {
+                names
+                    .into_iter()
+                    .filter(|name| reso_list::normalize(name).is_ok())
+                    .collect()
+            })
+            .unwrap_or(defaults.dns.cache_bypass);
+
+        let cache_ttl_floors = map
+            .get("dns.cache_ttl_floors")
+            .and_then(|v| serde_json::from_str::<BTreeMap<String, u32>>(v).ok())
+            .map(|floors| {
+                floors
+                    .into_iter()
+                    .filter(|(name, _)| DomainName::from_user(name).is_ok())
+                    .collect()
+            })
+            .unwrap_or(defaults.dns.cache_ttl_floors);
+
+        let cache_ttl_jitter_percent = map
+            .get("dns.cache_ttl_jitter_percent")
+            .and_then(|v| v.parse::<u8>().ok())
+            .filter(|percent| *percent <= 100)
+            .unwrap_or(defaults.dns.cache_ttl_jitter_percent);
+
+        let negative_stale_secs = map
+            .get("dns.negative_stale_secs")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(defaults.dns.negative_stale_secs);
+
+        let nsid = map.get("dns.nsid").cloned().unwrap_or(defaults.dns.nsid);
+
+        let chaos_version = map
+            .get("dns.chaos_version")
+            .cloned()
+            .unwrap_or(defaults.dns.chaos_version);
+
+        let chaos_id = map.get("dns.chaos_id").cloned().unwrap_or(defaults.dns.chaos_id);
+
+        let blocked_qtypes = map
+            .get("dns.blocked_qtypes")
+            .and_then(|v| serde_json::from_str::<Vec<String>>(v).ok())
+            .map(|qtypes| {
+                qtypes
+                    .into_iter()
+                    .map(|qtype| qtype.to_ascii_uppercase())
+                    .filter(|qtype| qtype.parse::<RecordType>().is_ok())
+                    .collect()
+            })
+            .unwrap_or(defaults.dns.blocked_qtypes);
+
+        let recv_size = map
+            .get("dns.recv_size")
+            .and_then(|v| v.parse::<u16>().ok())
+            .filter(|size| *size >= MIN_RECV_SIZE)
+            .unwrap_or(defaults.dns.recv_size);
+
+        let max_udp_response = map
+            .get("dns.max_udp_response")
+            .and_then(|v| v.parse::<u16>().ok())
+            .filter(|size| *size == 0 || *size >= MIN_RECV_SIZE)
+            .unwrap_or(defaults.dns.max_udp_response);
+
+        let max_concurrent_requests = map
+            .get("dns.max_concurrent_requests")
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|limit| *limit > 0)
+            .unwrap_or(defaults.dns.max_concurrent_requests);
+
+        let max_tcp_connections = map
+            .get("dns.max_tcp_connections")
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|limit| *limit > 0)
+            .unwrap_or(defaults.dns.max_tcp_connections);
+
+        let tcp_idle_timeout = map
+            .get("dns.tcp_idle_timeout")
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|timeout| *timeout > 0)
+            .unwrap_or(defaults.dns.tcp_idle_timeout);
+
+        let tcp_read_timeout = map
+            .get("dns.tcp_read_timeout")
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|timeout| *timeout > 0)
+            .unwrap_or(defaults.dns.tcp_read_timeout);
+
+        let udp_timeout = map
+            .get("dns.udp_timeout")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(defaults.dns.udp_timeout);
+
+        let tcp_timeout = map
+            .get("dns.tcp_timeout")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(defaults.dns.tcp_timeout);
+
+        let doh_timeout = map
+            .get("dns.doh_timeout")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(defaults.dns.doh_timeout);
+
         let logs_enabled = map
             .get("logs.enabled")
             .and_then(|v| v.parse::<bool>().ok())
@@ -244,11 +736,28 @@ impl Config {
             .and_then(|v| v.parse::<u64>().ok())
             .unwrap_or(defaults.logs.truncate_interval_secs);
 
+        let client_limit = map
+            .get("logs.client_limit")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(defaults.logs.client_limit);
+
+        let client_sample_rate = map
+            .get("logs.client_sample_rate")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(defaults.logs.client_sample_rate);
+
         Self {
             dns: DnsConfig {
                 timeout,
                 active,
-                forwarder: ForwarderConfig { upstreams },
+                forwarder: ForwarderConfig {
+                    upstreams,
+                    edns_udp_payload_size,
+                    ecs_mode,
+                    upstream_selection,
+                    edns_options,
+                    tcp_fast_open,
+                },
                 rate_limit: RateLimitConfigModel {
                     enabled: rate_limit_enabled,
                     window_duration,
@@ -259,18 +768,55 @@ impl Config {
                     block_designated_resolver,
                     block_firefox_canary,
                 },
+                blocking: BlockingConfig {
+                    response_policy,
+                    sinkhole_ipv4,
+                    sinkhole_ipv6,
+                    ttl: block_ttl,
+                    blocked_ips,
+                },
+                minimal_responses,
+                minimal_any,
+                rotate_answers,
+                cache_bypass,
+                cache_ttl_floors,
+                cache_ttl_jitter_percent,
+                negative_stale_secs,
+                nsid,
+                chaos_version,
+                chaos_id,
+                blocked_qtypes,
+                recv_size,
+                max_udp_response,
+                max_concurrent_requests,
+                max_tcp_connections,
+                tcp_idle_timeout,
+                tcp_read_timeout,
+                udp_timeout,
+                tcp_timeout,
+                doh_timeout,
             },
             logs: LogsConfig {
                 enabled: logs_enabled,
                 retention_secs,
                 truncate_interval_secs,
+                client_limit,
+                client_sample_rate,
             },
         }
     }
 
     pub fn to_kv(&self) -> Vec<(String, String)> {
         let active_str = match &self.dns.active {
-            ActiveResolver::Forwarder => "forwarder",
+            ActiveResolver::Forwarder => "forwarder".to_string(),
+            active => serde_json::to_string(active).unwrap_or_else(|_| "forwarder".to_string()),
+        };
+
+        let response_policy_str = match &self.dns.blocking.response_policy {
+            BlockResponsePolicy::NxDomain => "nxdomain",
+            BlockResponsePolicy::Refused => "refused",
+            BlockResponsePolicy::NoData => "nodata",
+            BlockResponsePolicy::Sinkhole => "sinkhole",
         };
 
         let upstreams_json =
@@ -279,8 +825,29 @@ impl Config {
 
         vec![
             ("dns.timeout".to_string(), self.dns.timeout.to_string()),
-            ("dns.active".to_string(), active_str.to_string()),
+            ("dns.active".to_string(), active_str),
             ("dns.forwarder.upstreams".to_string(), upstreams_json),
+            (
+                "dns.forwarder.edns_udp_payload_size".to_string(),
+                self.dns.forwarder.edns_udp_payload_size.to_string(),
+            ),
+            (
+                "dns.forwarder.ecs_mode".to_string(),
+                serde_json::to_string(&self.dns.forwarder.ecs_mode).unwrap_or_else(|_| "\"off\"".to_string()),
+            ),
+            (
+                "dns.forwarder.upstream_selection".to_string(),
+                serde_json::to_string(&self.dns.forwarder.upstream_selection)
+                    .unwrap_or_else(|_| "\"round_robin\"".to_string()),
+            ),
+            (
+                "dns.forwarder.edns_options".to_string(),
+                serde_json::to_string(&self.dns.forwarder.edns_options).unwrap_or_else(|_| "\"all\"".to_string()),
+            ),
+            (
+                "dns.forwarder.tcp_fast_open".to_string(),
+                self.dns.forwarder.tcp_fast_open.to_string(),
+            ),
             (
                 "dns.rate_limit.enabled".to_string(),
                 self.dns.rate_limit.enabled.to_string(),
@@ -299,6 +866,11 @@ impl Config {
                 "logs.truncate_interval_secs".to_string(),
                 self.logs.truncate_interval_secs.to_string(),
             ),
+            ("logs.client_limit".to_string(), self.logs.client_limit.to_string()),
+            (
+                "logs.client_sample_rate".to_string(),
+                self.logs.client_sample_rate.to_string(),
+            ),
             (
                 "dns.security.block_icloud_private_relay".to_string(),
                 self.dns.security.block_icloud_private_relay.to_string(),
@@ -311,6 +883,76 @@ impl Config {
                 "dns.security.block_firefox_canary".to_string(),
                 self.dns.security.block_firefox_canary.to_string(),
             ),
+            (
+                "dns.blocking.response_policy".to_string(),
+                response_policy_str.to_string(),
+            ),
+            (
+                "dns.blocking.sinkhole_ipv4".to_string(),
+                self.dns.blocking.sinkhole_ipv4.to_string(),
+            ),
+            (
+                "dns.blocking.sinkhole_ipv6".to_string(),
+                self.dns.blocking.sinkhole_ipv6.to_string(),
+            ),
+            ("dns.blocking.ttl".to_string(), self.dns.blocking.ttl.to_string()),
+            (
+                "dns.blocking.blocked_ips".to_string(),
+                serde_json::to_string(&self.dns.blocking.blocked_ips).unwrap_or_else(|_| "[]".to_string()),
+            ),
+            (
+                "dns.minimal_responses".to_string(),
+                self.dns.minimal_responses.to_string(),
+            ),
+            ("dns.minimal_any".to_string(), self.dns.minimal_any.to_string()),
+            ("dns.rotate_answers".to_string(), self.dns.rotate_answers.to_string()),
+            (
+                "dns.cache_bypass".to_string(),
+                serde_json::to_string(&self.dns.cache_bypass).unwrap_or_else(|_| "[]".to_string()),
+            ),
+            (
+                "dns.cache_ttl_floors".to_string(),
+                serde_json::to_string(&self.dns.cache_ttl_floors).unwrap_or_else(|_| "{}".to_string()),
+            ),
+            (
+                "dns.cache_ttl_jitter_percent".to_string(),
+                self.dns.cache_ttl_jitter_percent.to_string(),
+            ),
+            (
+                "dns.negative_stale_secs".to_string(),
+                self.dns.negative_stale_secs.to_string(),
+            ),
+            ("dns.nsid".to_string(), self.dns.nsid.clone()),
+            ("dns.chaos_version".to_string(), self.dns.chaos_version.clone()),
+            ("dns.chaos_id".to_string(), self.dns.chaos_id.clone()),
+            (
+                "dns.blocked_qtypes".to_string(),
+                serde_json::to_string(&self.dns.blocked_qtypes).unwrap_or_else(|_| "[]".to_string()),
+            ),
+            ("dns.recv_size".to_string(), self.dns.recv_size.to_string()),
+            (
+                "dns.max_udp_response".to_string(),
+                self.dns.max_udp_response.to_string(),
+            ),
+            (
+                "dns.max_concurrent_requests".to_string(),
+                self.dns.max_concurrent_requests.to_string(),
+            ),
+            (
+                "dns.max_tcp_connections".to_string(),
+                self.dns.max_tcp_connections.to_string(),
+            ),
+            (
+                "dns.tcp_idle_timeout".to_string(),
+                self.dns.tcp_idle_timeout.to_string(),
+            ),
+            (
+                "dns.tcp_read_timeout".to_string(),
+                self.dns.tcp_read_timeout.to_string(),
+            ),
+            ("dns.udp_timeout".to_string(), self.dns.udp_timeout.to_string()),
+            ("dns.tcp_timeout".to_string(), self.dns.tcp_timeout.to_string()),
+            ("dns.doh_timeout".to_string(), self.dns.doh_timeout.to_string()),
         ]
     }
 }
@@ -321,7 +963,14 @@ impl Default for Config {
             dns: DnsConfig {
                 timeout: Duration::from_secs(3).as_millis() as u64,
                 active: ActiveResolver::Forwarder,
-                forwarder: ForwarderConfig { upstreams: vec![] },
+                forwarder: ForwarderConfig {
+                    upstreams: vec![],
+                    edns_udp_payload_size: DEFAULT_EDNS_UDP_PAYLOAD_SIZE,
+                    ecs_mode: EcsModeConfig::Off,
+                    upstream_selection: UpstreamSelectionConfig::RoundRobin,
+                    edns_options: EdnsOptionFilterConfig::All,
+                    tcp_fast_open: false,
+                },
                 rate_limit: RateLimitConfigModel {
                     enabled: false,
                     window_duration: Duration::from_secs(10).as_secs() as usize,
@@ -332,16 +981,54 @@ impl Default for Config {
                     block_designated_resolver: true,
                     block_firefox_canary: true,
                 },
+                blocking: BlockingConfig::default(),
+                minimal_responses: false,
+                minimal_any: false,
+                rotate_answers: false,
+                cache_bypass: vec![],
+                cache_ttl_floors: BTreeMap::new(),
+                cache_ttl_jitter_percent: default_cache_ttl_jitter_percent(),
+                negative_stale_secs: 0,
+                nsid: String::new(),
+                chaos_version: String::new(),
+                chaos_id: String::new(),
+                blocked_qtypes: vec![],
+                recv_size: DEFAULT_RECV_SIZE,
+                max_udp_response: 0,
+                max_concurrent_requests: default_max_concurrent_requests(),
+                max_tcp_connections: default_max_tcp_connections(),
+                tcp_idle_timeout: default_tcp_idle_timeout(),
+                tcp_read_timeout: default_tcp_read_timeout(),
+                udp_timeout: 0,
+                tcp_timeout: 0,
+                doh_timeout: 0,
             },
             logs: LogsConfig {
                 enabled: false,
                 retention_secs: 7 * 24 * 3600,
                 truncate_interval_secs: 3600,
+                client_limit: 0,
+                client_sample_rate: default_client_sample_rate(),
             },
         }
     }
 }
 
+/// Whether two stored values are the same, ignoring the formatting of JSON values.
+fn same_value(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+
+    match (
+        serde_json::from_str::<serde_json::Value>(a),
+        serde_json::from_str::<serde_json::Value>(b),
+    ) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
 /// Service for managing the server configuration
 pub struct ConfigService {
     db: Arc<CoreDatabasePool>,
@@ -365,9 +1052,14 @@ impl ConfigService {
     }
 
     /// Initialize the configuration from the database.
+    async fn initialize_config(db: &CoreDatabasePool) -> anyhow::Result<Config> {
+        Ok(Config::from_kv(&Self::stored_config(db).await?))
+    }
+
+    /// Read the stored configuration from the database.
     /// Missing keys are seeded with defaults so that new config fields
     /// are automatically populated for existing databases.
-    async fn initialize_config(db: &CoreDatabasePool) -> anyhow::Result<Config> {
+    async fn stored_config(db: &CoreDatabasePool) -> anyhow::Result<HashMap<String, String>> {
         let map = db_config::all(db).await?;
 
         let default_config = Config::default();
@@ -382,7 +1074,7 @@ impl ConfigService {
             db_config::batch_set(db, missing).await?;
         }
 
-        Ok(Config::from_kv(&map))
+        Ok(map)
     }
 
     /// Updates the configuration and notify the subscribers.
@@ -394,6 +1086,24 @@ impl ConfigService {
         Ok(())
     }
 
+    /// Re-read the configuration from the database and notify the subscribers, so changes made outside of the
+    /// API are applied without a restart.
+    ///
+    /// Fails without applying anything if a stored value doesn't parse. Settings documented as applied on restart,
+    /// like the UDP receive size and the connection limits, are read but keep their current value until then.
+    pub async fn reload(&self) -> anyhow::Result<()> {
+        let map = Self::stored_config(&self.db).await?;
+        let invalid = Config::invalid_values(&map);
+        if !invalid.is_empty() {
+            bail!("invalid configuration: {}", invalid.join("; "));
+        }
+
+        let config = Arc::new(Config::from_kv(&map));
+        self.config.store(config.clone());
+        self.tx.send_replace(config);
+        Ok(())
+    }
+
     /// Get the config.
     pub fn get_config(&self) -> Arc<Config> {
         self.config.load_full()
@@ -404,3 +1114,488 @@ impl ConfigService {
         self.tx.subscribe()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::setup_core_test_db;
+
+    #[tokio::test]
+    async fn test_reload_applies_changes_made_in_the_database() {
+        let db = setup_core_test_db().await.unwrap();
+        let db = Arc::new(db.conn);
+        let service = ConfigService::initialize(db.clone()).await.unwrap();
+        let mut rx = service.subscribe();
+        rx.mark_unchanged();
+
+        db_config::set(&db, "dns.timeout", "1234").await.unwrap();
+        // not applied until reloaded.
+        assert_eq!(service.get_config().dns.timeout, 3000);
+
+        service.reload().await.unwrap();
+
+        assert_eq!(service.get_config().dns.timeout, 1234);
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(rx.borrow_and_update().dns.timeout, 1234);
+    }
+
+    #[tokio::test]
+    async fn test_reload_rejects_invalid_values() {
+        let db = setup_core_test_db().await.unwrap();
+        let db = Arc::new(db.conn);
+        let service = ConfigService::initialize(db.clone()).await.unwrap();
+        let mut rx = service.subscribe();
+        rx.mark_unchanged();
+
+        db_config::set(&db, "dns.timeout", "1234").await.unwrap();
+        db_config::set(&db, "dns.minimal_any", "yes").await.unwrap();
+
+        let err = service.reload().await.unwrap_err();
+
+        assert!(err.to_string().contains("dns.minimal_any"), "{err:#}");
+        assert_eq!(service.get_config().dns.timeout, 3000);
+        assert!(!rx.has_changed().unwrap());
+    }
+
+    fn active_from_kv(value: &str) -> ActiveResolver {
+        let map = HashMap::from([("dns.active".to_string(), value.to_string())]);
+        Config::from_kv(&map).dns.active
+    }
+
+    fn roundtrip(active: ActiveResolver) -> ActiveResolver {
+        let config = Config {
+            dns: DnsConfig {
+                active,
+                ..Config::default().dns
+            },
+            ..Config::default()
+        };
+        Config::from_kv(&config.to_kv().into_iter().collect()).dns.active
+    }
+
+    #[test]
+    fn test_parses_forwarder_resolver() {
+        assert_eq!(active_from_kv("forwarder"), ActiveResolver::Forwarder);
+        assert_eq!(roundtrip(ActiveResolver::Forwarder), ActiveResolver::Forwarder);
+    }
+
+    #[test]
+    fn test_parses_static_resolver() {
+        let expected = ActiveResolver::Static {
+            zones: vec![PathBuf::from("/etc/reso/example.com.zone")],
+        };
+
+        assert_eq!(
+            active_from_kv(r#"{"static":{"zones":["/etc/reso/example.com.zone"]}}"#),
+            expected
+        );
+        assert_eq!(roundtrip(expected.clone()), expected);
+    }
+
+    #[test]
+    fn test_parses_ptr_resolver() {
+        let expected = ActiveResolver::Ptr {
+            prefixes: vec!["10.0.0.0/8".to_string()],
+            hosts: BTreeMap::from([("10.0.0.5".parse().unwrap(), "nas.lan".to_string())]),
+            domain: None,
+        };
+
+        assert_eq!(
+            active_from_kv(r#"{"ptr":{"prefixes":["10.0.0.0/8"],"hosts":{"10.0.0.5":"nas.lan"}}}"#),
+            expected
+        );
+        assert_eq!(roundtrip(expected.clone()), expected);
+    }
+
+    #[test]
+    fn test_parses_chain_resolver() {
+        let expected = ActiveResolver::Chain {
+            resolvers: vec![
+                ActiveResolver::Static {
+                    zones: vec![PathBuf::from("home.zone")],
+                },
+                ActiveResolver::Forwarder,
+            ],
+        };
+
+        assert_eq!(
+            active_from_kv(r#"{"chain":{"resolvers":[{"static":{"zones":["home.zone"]}},"forwarder"]}}"#),
+            expected
+        );
+        assert_eq!(roundtrip(expected.clone()), expected);
+    }
+
+    #[test]
+    fn test_forwards() {
+        let zones = ActiveResolver::Static {
+            zones: vec![PathBuf::from("home.zone")],
+        };
+
+        assert!(ActiveResolver::Forwarder.forwards());
+        assert!(!zones.forwards());
+        assert!(
+            ActiveResolver::Chain {
+                resolvers: vec![zones.clone(), ActiveResolver::Forwarder],
+            }
+            .forwards()
+        );
+        assert!(!ActiveResolver::Chain { resolvers: vec![zones] }.forwards());
+    }
+
+    /// Every field name in a serialized config, joined with dots.
+    fn field_paths(value: &serde_json::Value, prefix: &str, paths: &mut Vec<String>) {
+        if let serde_json::Value::Object(map) = value {
+            for (key, value) in map {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                field_paths(value, &path, paths);
+                paths.push(path);
+            }
+        }
+    }
+
+    #[test]
+    fn test_serialized_config_has_no_secrets() {
+        let mut paths = Vec::new();
+        field_paths(&serde_json::to_value(Config::default()).unwrap(), "", &mut paths);
+
+        for expected in ["dns.timeout", "dns.forwarder.upstreams", "logs.retention_secs"] {
+            assert!(paths.iter().any(|p| p == expected), "missing {expected}");
+        }
+        for path in &paths {
+            let path = path.to_lowercase();
+            assert!(
+                !["secret", "password", "cipher", "key"].iter().any(|s| path.contains(s)),
+                "config exposes sensitive field {path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_resolver_falls_back_to_default() {
+        assert_eq!(active_from_kv(r#"{"recursive":{}}"#), ActiveResolver::Forwarder);
+    }
+
+    #[test]
+    fn test_edns_udp_payload_size_defaults_and_roundtrips() {
+        assert_eq!(
+            Config::from_kv(&HashMap::new()).dns.forwarder.edns_udp_payload_size,
+            1232
+        );
+
+        let mut config = Config::default();
+        config.dns.forwarder.edns_udp_payload_size = 1400;
+        let parsed = Config::from_kv(&config.to_kv().into_iter().collect());
+
+        assert_eq!(parsed.dns.forwarder.edns_udp_payload_size, 1400);
+    }
+
+    #[test]
+    fn test_ecs_mode_defaults_and_roundtrips() {
+        assert_eq!(
+            Config::from_kv(&HashMap::new()).dns.forwarder.ecs_mode,
+            EcsModeConfig::Off
+        );
+
+        let mut config = Config::default();
+        config.dns.forwarder.ecs_mode = EcsModeConfig::Forward {
+            v4_prefix: 24,
+            v6_prefix: 56,
+        };
+        let kv: HashMap<_, _> = config.to_kv().into_iter().collect();
+
+        assert_eq!(
+            kv["dns.forwarder.ecs_mode"],
+            r#"{"forward":{"v4_prefix":24,"v6_prefix":56}}"#
+        );
+        assert_eq!(
+            Config::from_kv(&kv).dns.forwarder.ecs_mode,
+            EcsModeConfig::Forward {
+                v4_prefix: 24,
+                v6_prefix: 56,
+            }
+        );
+        assert_eq!(
+            Config::from_kv(&HashMap::from([(
+                "dns.forwarder.ecs_mode".to_string(),
+                r#""zeroed""#.to_string()
+            )]))
+            .dns
+            .forwarder
+            .ecs_mode,
+            EcsModeConfig::Zeroed
+        );
+    }
+
+    #[test]
+    fn test_upstream_selection_defaults_and_roundtrips() {
+        assert_eq!(
+            Config::from_kv(&HashMap::new()).dns.forwarder.upstream_selection,
+            UpstreamSelectionConfig::RoundRobin
+        );
+
+        let mut config = Config::default();
+        config.dns.forwarder.upstream_selection = UpstreamSelectionConfig::Latency;
+        let kv: HashMap<_, _> = config.to_kv().into_iter().collect();
+
+        assert_eq!(kv["dns.forwarder.upstream_selection"], r#""latency""#);
+        assert_eq!(
+            Config::from_kv(&kv).dns.forwarder.upstream_selection,
+            UpstreamSelectionConfig::Latency
+        );
+    }
+
+    #[test]
+    fn test_tcp_fast_open_defaults_and_roundtrips() {
+        assert!(!Config::from_kv(&HashMap::new()).dns.forwarder.tcp_fast_open);
+
+        let mut config = Config::default();
+        config.dns.forwarder.tcp_fast_open = true;
+        let parsed = Config::from_kv(&config.to_kv().into_iter().collect());
+
+        assert!(parsed.dns.forwarder.tcp_fast_open);
+    }
+
+    #[test]
+    fn test_edns_options_defaults_and_roundtrips() {
+        assert_eq!(
+            Config::from_kv(&HashMap::new()).dns.forwarder.edns_options,
+            EdnsOptionFilterConfig::All
+        );
+
+        let mut config = Config::default();
+        config.dns.forwarder.edns_options = EdnsOptionFilterConfig::Allow(vec![3, 65001]);
+        let kv: HashMap<_, _> = config.to_kv().into_iter().collect();
+
+        assert_eq!(kv["dns.forwarder.edns_options"], r#"{"allow":[3,65001]}"#);
+        let edns_options = Config::from_kv(&kv).dns.forwarder.edns_options;
+        assert_eq!(edns_options, EdnsOptionFilterConfig::Allow(vec![3, 65001]));
+        assert_eq!(
+            EdnsOptionFilter::from(&edns_options),
+            EdnsOptionFilter::Allow(vec![EdnsOptionCode::NSID, EdnsOptionCode::Unknown(65001)])
+        );
+    }
+
+    #[test]
+    fn test_recv_size_defaults_and_rejects_out_of_range() {
+        assert_eq!(Config::from_kv(&HashMap::new()).dns.recv_size, 1232);
+
+        for invalid in ["511", "0", "65536", "-1", "big"] {
+            let map = HashMap::from([("dns.recv_size".to_string(), invalid.to_string())]);
+            assert_eq!(Config::from_kv(&map).dns.recv_size, 1232, "accepted {invalid}");
+        }
+
+        let mut config = Config::default();
+        config.dns.recv_size = 4096;
+        let parsed = Config::from_kv(&config.to_kv().into_iter().collect());
+
+        assert_eq!(parsed.dns.recv_size, 4096);
+    }
+
+    #[test]
+    fn test_max_udp_response_roundtrip() {
+        assert_eq!(Config::from_kv(&HashMap::new()).dns.max_udp_response, 0);
+
+        let map = HashMap::from([("dns.max_udp_response".to_string(), "100".to_string())]);
+        assert_eq!(Config::from_kv(&map).dns.max_udp_response, 0);
+
+        let mut config = Config::default();
+        config.dns.max_udp_response = 1232;
+        let parsed = Config::from_kv(&config.to_kv().into_iter().collect());
+
+        assert_eq!(parsed.dns.max_udp_response, 1232);
+    }
+
+    #[test]
+    fn test_connection_limits_roundtrip() {
+        let defaults = Config::from_kv(&HashMap::new());
+        assert_eq!(defaults.dns.max_concurrent_requests, 4096);
+        assert_eq!(defaults.dns.max_tcp_connections, 1024);
+
+        let map = HashMap::from([
+            ("dns.max_concurrent_requests".to_string(), "0".to_string()),
+            ("dns.max_tcp_connections".to_string(), "0".to_string()),
+        ]);
+        let parsed = Config::from_kv(&map);
+        assert_eq!(parsed.dns.max_concurrent_requests, 4096);
+        assert_eq!(parsed.dns.max_tcp_connections, 1024);
+
+        let mut config = Config::default();
+        config.dns.max_concurrent_requests = 128;
+        config.dns.max_tcp_connections = 16;
+        let parsed = Config::from_kv(&config.to_kv().into_iter().collect());
+
+        assert_eq!(parsed.dns.max_concurrent_requests, 128);
+        assert_eq!(parsed.dns.max_tcp_connections, 16);
+    }
+
+    #[test]
+    fn test_tcp_connection_timeouts_roundtrip() {
+        let defaults = Config::from_kv(&HashMap::new());
+        assert_eq!(defaults.dns.tcp_idle_timeout, 10_000);
+        assert_eq!(defaults.dns.tcp_read_timeout, 2000);
+
+        let map = HashMap::from([("dns.tcp_idle_timeout".to_string(), "0".to_string())]);
+        assert_eq!(Config::from_kv(&map).dns.tcp_idle_timeout, 10_000);
+
+        let mut config = Config::default();
+        config.dns.tcp_idle_timeout = 30_000;
+        config.dns.tcp_read_timeout = 500;
+        let parsed = Config::from_kv(&config.to_kv().into_iter().collect());
+
+        assert_eq!(parsed.dns.tcp_idle_timeout, 30_000);
+        assert_eq!(parsed.dns.tcp_read_timeout, 500);
+    }
+
+    #[test]
+    fn test_transport_timeouts_roundtrip() {
+        let defaults = Config::from_kv(&HashMap::new());
+        assert_eq!(defaults.dns.udp_timeout, 0);
+        assert_eq!(defaults.dns.tcp_timeout, 0);
+        assert_eq!(defaults.dns.doh_timeout, 0);
+
+        let mut config = Config::default();
+        config.dns.udp_timeout = 1000;
+        config.dns.tcp_timeout = 4000;
+        config.dns.doh_timeout = 5000;
+        let parsed = Config::from_kv(&config.to_kv().into_iter().collect());
+
+        assert_eq!(parsed.dns.udp_timeout, 1000);
+        assert_eq!(parsed.dns.tcp_timeout, 4000);
+        assert_eq!(parsed.dns.doh_timeout, 5000);
+    }
+
+    #[test]
+    fn test_client_log_limit_roundtrip() {
+        let defaults = Config::from_kv(&HashMap::new());
+        assert_eq!(defaults.logs.client_limit, 0);
+        assert_eq!(defaults.logs.client_sample_rate, 100);
+
+        let mut config = Config::default();
+        config.logs.client_limit = 500;
+        config.logs.client_sample_rate = 10;
+        let parsed = Config::from_kv(&config.to_kv().into_iter().collect());
+
+        assert_eq!(parsed.logs.client_limit, 500);
+        assert_eq!(parsed.logs.client_sample_rate, 10);
+    }
+
+    #[test]
+    fn test_blocked_qtypes_are_normalized_and_roundtrip() {
+        let map = HashMap::from([(
+            "dns.blocked_qtypes".to_string(),
+            r#"["https", "SVCB", "NOPE"]"#.to_string(),
+        )]);
+        let config = Config::from_kv(&map);
+
+        assert_eq!(config.dns.blocked_qtypes, vec!["HTTPS", "SVCB"]);
+
+        let parsed = Config::from_kv(&config.to_kv().into_iter().collect());
+        assert_eq!(parsed.dns.blocked_qtypes, vec!["HTTPS", "SVCB"]);
+    }
+
+    #[test]
+    fn test_blocked_ips_roundtrip_and_skip_invalid() {
+        let mut config = Config::default();
+        config.dns.blocking.blocked_ips = vec!["203.0.113.0/24".to_string(), "2001:db8::/32".to_string()];
+        let parsed = Config::from_kv(&config.to_kv().into_iter().collect());
+
+        assert_eq!(parsed.dns.blocking.blocked_ips, vec!["203.0.113.0/24", "2001:db8::/32"]);
+        assert!(Config::from_kv(&HashMap::new()).dns.blocking.blocked_ips.is_empty());
+
+        let parsed = Config::from_kv(&HashMap::from([(
+            "dns.blocking.blocked_ips".to_string(),
+            r#"["10.0.0.0/8","not a prefix","10.0.0.0/33"]"#.to_string(),
+        )]));
+        assert_eq!(parsed.dns.blocking.blocked_ips, vec!["10.0.0.0/8"]);
+    }
+
+    #[test]
+    fn test_cache_bypass_roundtrips() {
+        let mut config = Config::default();
+        config.dns.cache_bypass = vec!["internal.lan".to_string(), "dyn.example.com".to_string()];
+
+        let parsed = Config::from_kv(&config.to_kv().into_iter().collect());
+
+        assert_eq!(parsed.dns.cache_bypass, vec!["internal.lan", "dyn.example.com"]);
+        assert!(Config::from_kv(&HashMap::new()).dns.cache_bypass.is_empty());
+    }
+
+    #[test]
+    fn test_cache_ttl_floors_roundtrip() {
+        let mut config = Config::default();
+        config.dns.cache_ttl_floors =
+            BTreeMap::from([("cdn.example.com".to_string(), 3600), ("not a name".to_string(), 600)]);
+
+        let parsed = Config::from_kv(&config.to_kv().into_iter().collect());
+
+        assert_eq!(
+            parsed.dns.cache_ttl_floors,
+            BTreeMap::from([("cdn.example.com".to_string(), 3600)])
+        );
+        assert!(Config::from_kv(&HashMap::new()).dns.cache_ttl_floors.is_empty());
+    }
+
+    #[test]
+    fn test_cache_ttl_jitter_percent_roundtrip() {
+        let mut config = Config::default();
+        config.dns.cache_ttl_jitter_percent = 20;
+
+        let parsed = Config::from_kv(&config.to_kv().into_iter().collect());
+
+        assert_eq!(parsed.dns.cache_ttl_jitter_percent, 20);
+        assert_eq!(Config::from_kv(&HashMap::new()).dns.cache_ttl_jitter_percent, 5);
+
+        let map = HashMap::from([("dns.cache_ttl_jitter_percent".to_string(), "150".to_string())]);
+        assert_eq!(Config::from_kv(&map).dns.cache_ttl_jitter_percent, 5);
+    }
+
+    #[test]
+    fn test_negative_stale_secs_roundtrip() {
+        let mut config = Config::default();
+        config.dns.negative_stale_secs = 3600;
+
+        let parsed = Config::from_kv(&config.to_kv().into_iter().collect());
+
+        assert_eq!(parsed.dns.negative_stale_secs, 3600);
+        assert_eq!(Config::from_kv(&HashMap::new()).dns.negative_stale_secs, 0);
+    }
+
+    fn plain_socket_addr(spec: &str) -> SocketAddr {
+        match UpstreamSpec(spec.to_string()).parse().unwrap() {
+            Upstream::Plain { endpoint } => endpoint.socket_addr().unwrap(),
+            other => panic!("expected a plain upstream, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parses_ipv6_upstreams() {
+        assert_eq!(plain_socket_addr("[::1]:5353"), "[::1]:5353".parse().unwrap());
+        assert_eq!(plain_socket_addr("[2001:db8::1]"), "[2001:db8::1]:53".parse().unwrap());
+        assert_eq!(plain_socket_addr("2001:db8::1"), "[2001:db8::1]:53".parse().unwrap());
+        assert_eq!(plain_socket_addr("udp://[::1]:5353"), "[::1]:5353".parse().unwrap());
+        assert_eq!(plain_socket_addr("127.0.0.1:5353"), "127.0.0.1:5353".parse().unwrap());
+    }
+
+    #[test]
+    fn test_parses_scoped_ipv6_upstream() {
+        let SocketAddr::V6(addr) = plain_socket_addr("[fe80::1%2]:53") else {
+            panic!("expected an IPv6 address");
+        };
+        assert_eq!(addr.scope_id(), 2);
+        assert_eq!(addr.port(), 53);
+    }
+
+    #[test]
+    fn test_rejects_malformed_ipv6_upstreams() {
+        for spec in ["[::1", "[]:53", "[::1]53", "[::1]:port"] {
+            assert!(
+                UpstreamSpec(spec.to_string()).parse().is_err(),
+                "{spec} should be rejected"
+            );
+        }
+    }
+}
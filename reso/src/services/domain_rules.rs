@@ -1,9 +1,9 @@
 use futures::StreamExt;
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashMap, net::IpAddr, sync::Arc, time::Duration};
 
 use arc_swap::ArcSwap;
 use reso_dns::domain_name::DomainName;
-use reso_list::{DomainListMatcher, DomainPattern, parser::RuleType};
+use reso_list::{DomainListMatcher, DomainPattern, NormalizedDomain, parser::RuleType};
 use tokio::{
     sync::Mutex,
     time::{self, MissedTickBehavior},
@@ -16,6 +16,7 @@ use crate::{
             ListAction, MatchType,
             domain_rule::{self, DomainRule},
             list_subscription::{self, ListSubscription},
+            rule_group::{self, RuleGroup},
         },
     },
     global::SharedGlobal,
@@ -33,6 +34,14 @@ fn normalize_bare_domain(input: &str) -> Result<String, ServiceError> {
     Ok(name.to_string())
 }
 
+/// Parse a client IP address from user input.
+fn parse_client(input: &str) -> Result<IpAddr, ServiceError> {
+    input
+        .trim()
+        .parse()
+        .map_err(|_| ServiceError::BadRequest("Invalid client IP address".into()))
+}
+
 /// Normalize a plain domain string from the subscription parser.
 fn normalize_base(s: &str) -> Option<String> {
     DomainName::from_user(s).ok().map(|n| n.to_string())
@@ -41,6 +50,16 @@ fn normalize_base(s: &str) -> Option<String> {
 pub struct Matchers {
     pub blocklist_matcher: Arc<DomainListMatcher>,
     pub allow_list_matcher: Arc<DomainListMatcher>,
+    /// Per rule group matchers, applied on top of the global matchers.
+    pub group_matchers: Arc<HashMap<EntityId<RuleGroup>, GroupMatchers>>,
+    /// The rule group each assigned client belongs to.
+    pub client_groups: Arc<HashMap<IpAddr, EntityId<RuleGroup>>>,
+}
+
+#[derive(Default)]
+pub struct GroupMatchers {
+    pub blocklist_matcher: DomainListMatcher,
+    pub allow_list_matcher: DomainListMatcher,
 }
 
 impl Matchers {
@@ -48,6 +67,7 @@ impl Matchers {
     pub async fn load(db: &CoreDatabasePool) -> anyhow::Result<Self> {
         let allow_list = domain_rule::list_enabled_by_action(db, ListAction::Allow).await?;
         let block_list = domain_rule::list_enabled_by_action(db, ListAction::Block).await?;
+        let (group_matchers, client_groups) = Self::load_groups(db).await?;
         Ok(Self {
             blocklist_matcher: Arc::new(DomainListMatcher::load(
                 block_list.iter().filter(|d| d.enabled).map(|d| d.to_domain_pattern()),
@@ -55,8 +75,71 @@ impl Matchers {
             allow_list_matcher: Arc::new(DomainListMatcher::load(
                 allow_list.iter().filter(|d| d.enabled).map(|d| d.to_domain_pattern()),
             )?),
+            group_matchers: Arc::new(group_matchers),
+            client_groups: Arc::new(client_groups),
         })
     }
+
+    /// Load the rule group matchers and client assignments from db.
+    #[allow(clippy::type_complexity)]
+    async fn load_groups(
+        db: &CoreDatabasePool,
+    ) -> anyhow::Result<(
+        HashMap<EntityId<RuleGroup>, GroupMatchers>,
+        HashMap<IpAddr, EntityId<RuleGroup>>,
+    )> {
+        let rules = domain_rule::list_enabled_grouped(db).await?;
+
+        let mut rules_by_group: HashMap<&EntityId<RuleGroup>, Vec<&DomainRule>> = HashMap::new();
+        for rule in &rules {
+            if let Some(group_id) = &rule.group_id {
+                rules_by_group.entry(group_id).or_default().push(rule);
+            }
+        }
+
+        let mut group_matchers = HashMap::with_capacity(rules_by_group.len());
+        for (group_id, rules) in rules_by_group {
+            let patterns = |action: ListAction| {
+                rules
+                    .iter()
+                    .filter(move |r| r.action == action)
+                    .map(|r| r.to_domain_pattern())
+            };
+            group_matchers.insert(
+                group_id.clone(),
+                GroupMatchers {
+                    blocklist_matcher: DomainListMatcher::load(patterns(ListAction::Block))?,
+                    allow_list_matcher: DomainListMatcher::load(patterns(ListAction::Allow))?,
+                },
+            );
+        }
+
+        let client_groups = rule_group::list_clients(db)
+            .await?
+            .into_iter()
+            .map(|c| (c.client, c.group_id))
+            .collect();
+
+        Ok((group_matchers, client_groups))
+    }
+
+    /// Check if a domain name is blocked for the given client.
+    /// Allow rules, global or from the client's group, take precedence over block rules.
+    pub fn is_blocked(&self, name: &NormalizedDomain, client: IpAddr) -> bool {
+        let group = self
+            .client_groups
+            .get(&client)
+            .and_then(|group_id| self.group_matchers.get(group_id));
+
+        let blocked = self.blocklist_matcher.matches(name) || group.is_some_and(|g| g.blocklist_matcher.matches(name));
+        if !blocked {
+            return false;
+        }
+
+        let allowed =
+            self.allow_list_matcher.matches(name) || group.is_some_and(|g| g.allow_list_matcher.matches(name));
+        !allowed
+    }
 }
 
 const SUBSCRIPTION_SYNC_INTERVAL_SECS: u64 = 60 * 60 * 24; // 24 hours
@@ -80,39 +163,50 @@ impl DomainRulesService {
     }
 
     /// Add a new domain rule with the given domain, match type, and action.
+    /// Rules with a group only apply to the clients assigned to that group.
     pub async fn add_domain(
         &self,
         domain: &str,
         match_type: MatchType,
         action: ListAction,
+        group_id: Option<EntityId<RuleGroup>>,
     ) -> Result<(), ServiceError> {
         let domain = normalize_bare_domain(domain)?;
 
         let mut rule = DomainRule::new(domain);
         rule.action = action;
         rule.match_type = match_type;
+        rule.group_id = group_id;
+        let grouped = rule.group_id.is_some();
 
         domain_rule::insert(&self.connection, rule).await.map_err(|e| {
             if e.is_unique_constraint_violation() {
                 ServiceError::Conflict("Domain already has a rule".into())
+            } else if e.is_foreign_key_violation() {
+                ServiceError::NotFound("Rule group not found".into())
             } else {
                 ServiceError::Internal(e.into())
             }
         })?;
 
-        match action {
-            ListAction::Allow => self.reload_allow_list().await?,
-            ListAction::Block => self.reload_blocklist().await?,
+        match (grouped, action) {
+            (true, _) => self.reload_all().await?,
+            (false, ListAction::Allow) => self.reload_allow_list().await?,
+            (false, ListAction::Block) => self.reload_blocklist().await?,
         }
 
         Ok(())
     }
 
-    /// Remove a domain rule by domain pattern.
-    pub async fn remove_domain(&self, domain: &str) -> Result<(), ServiceError> {
+    /// Remove the rule for a domain pattern in a rule group, or the global rule if `group_id` is `None`.
+    pub async fn remove_domain(
+        &self,
+        domain: &str,
+        group_id: Option<&EntityId<RuleGroup>>,
+    ) -> Result<(), ServiceError> {
         let domain = normalize_bare_domain(domain)?;
 
-        let changed = domain_rule::delete(&self.connection, &domain).await?;
+        let changed = domain_rule::delete(&self.connection, &domain, group_id).await?;
 
         if !changed {
             return Err(ServiceError::NotFound("Domain not found".into()));
@@ -122,11 +216,16 @@ impl DomainRulesService {
         Ok(())
     }
 
-    /// Update the action of an existing domain rule.
-    pub async fn update_domain_action(&self, domain: &str, action: ListAction) -> Result<(), ServiceError> {
+    /// Update the action of an existing domain rule, in a rule group or the global one if `group_id` is `None`.
+    pub async fn update_domain_action(
+        &self,
+        domain: &str,
+        group_id: Option<&EntityId<RuleGroup>>,
+        action: ListAction,
+    ) -> Result<(), ServiceError> {
         let domain = normalize_bare_domain(domain)?;
 
-        let changed = domain_rule::update_action(&self.connection, &domain, action).await?;
+        let changed = domain_rule::update_action(&self.connection, &domain, group_id, action).await?;
 
         if !changed {
             return Err(ServiceError::NotFound("Domain not found".into()));
@@ -136,11 +235,16 @@ impl DomainRulesService {
         Ok(())
     }
 
-    /// Toggle the enabled state of an individual domain rule.
-    pub async fn toggle_domain(&self, domain: &str) -> Result<(), ServiceError> {
+    /// Toggle the enabled state of an individual domain rule, in a rule group or the global one if `group_id`
+    /// is `None`.
+    pub async fn toggle_domain(
+        &self,
+        domain: &str,
+        group_id: Option<&EntityId<RuleGroup>>,
+    ) -> Result<(), ServiceError> {
         let domain = normalize_bare_domain(domain)?;
 
-        let changed = domain_rule::toggle(&self.connection, &domain).await?;
+        let changed = domain_rule::toggle(&self.connection, &domain, group_id).await?;
 
         if !changed {
             return Err(ServiceError::NotFound("Domain not found".into()));
@@ -178,6 +282,8 @@ impl DomainRulesService {
             Arc::new(Matchers {
                 allow_list_matcher: Arc::clone(&new_matcher),
                 blocklist_matcher: Arc::clone(&current.blocklist_matcher),
+                group_matchers: Arc::clone(&current.group_matchers),
+                client_groups: Arc::clone(&current.client_groups),
             })
         });
 
@@ -196,18 +302,101 @@ impl DomainRulesService {
             Arc::new(Matchers {
                 blocklist_matcher: Arc::clone(&new_matcher),
                 allow_list_matcher: Arc::clone(&current.allow_list_matcher),
+                group_matchers: Arc::clone(&current.group_matchers),
+                client_groups: Arc::clone(&current.client_groups),
             })
         });
 
         Ok(())
     }
-    /// Check if a given domain name is blocked by the matcher.
-    pub fn is_blocked(&self, name: &str) -> bool {
-        let matchers = self.matchers.load();
-        if matchers.blocklist_matcher.exists(name) {
-            return !matchers.allow_list_matcher.exists(name);
+    /// Check if a given domain name is blocked for the given client.
+    /// The name is normalized from its wire labels, so Unicode and punycode forms match the same rules.
+    pub fn is_blocked(&self, name: &DomainName, client: IpAddr) -> bool {
+        match NormalizedDomain::from_labels(name.label_iter()) {
+            Ok(name) => self.matchers.load().is_blocked(&name, client),
+            Err(e) => {
+                tracing::debug!("not matching undecodable name {}: {}", name, e);
+                false
+            }
         }
-        false
+    }
+
+    /// List all rule groups with their assigned clients.
+    pub async fn list_rule_groups(&self) -> Result<Vec<(RuleGroup, Vec<IpAddr>)>, ServiceError> {
+        let groups = rule_group::list(&self.connection).await?;
+        let clients = rule_group::list_clients(&self.connection).await?;
+
+        Ok(groups
+            .into_iter()
+            .map(|group| {
+                let group_clients = clients
+                    .iter()
+                    .filter(|c| c.group_id == group.id)
+                    .map(|c| c.client)
+                    .collect();
+                (group, group_clients)
+            })
+            .collect())
+    }
+
+    /// Add a new rule group with the given name.
+    pub async fn add_rule_group(&self, name: &str) -> Result<(), ServiceError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ServiceError::BadRequest("Group name must not be empty".into()));
+        }
+
+        rule_group::insert(&self.connection, RuleGroup::new(name.to_string()))
+            .await
+            .map_err(|e| {
+                if e.is_unique_constraint_violation() {
+                    ServiceError::Conflict("A group with the same name already exists".into())
+                } else {
+                    ServiceError::Internal(e.into())
+                }
+            })?;
+        Ok(())
+    }
+
+    /// Remove a rule group by ID, along with its rules and client assignments.
+    pub async fn remove_rule_group(&self, id: EntityId<RuleGroup>) -> Result<(), ServiceError> {
+        let changed = rule_group::delete_by_id(&self.connection, id).await?;
+        if !changed {
+            return Err(ServiceError::NotFound("Group not found".into()));
+        }
+        self.reload_all().await?;
+        Ok(())
+    }
+
+    /// Assign a client to a rule group, replacing any previous assignment.
+    pub async fn assign_client(&self, group_id: EntityId<RuleGroup>, client: &str) -> Result<(), ServiceError> {
+        let client = parse_client(client)?;
+
+        rule_group::assign_client(&self.connection, client, group_id)
+            .await
+            .map_err(|e| {
+                if e.is_foreign_key_violation() {
+                    ServiceError::NotFound("Group not found".into())
+                } else {
+                    ServiceError::Internal(e.into())
+                }
+            })?;
+
+        self.reload_all().await?;
+        Ok(())
+    }
+
+    /// Remove the rule group assignment of a client.
+    pub async fn unassign_client(&self, client: &str) -> Result<(), ServiceError> {
+        let client = parse_client(client)?;
+
+        let changed = rule_group::unassign_client(&self.connection, client).await?;
+        if !changed {
+            return Err(ServiceError::NotFound("Client not assigned to a group".into()));
+        }
+
+        self.reload_all().await?;
+        Ok(())
     }
 
     /// List all subscriptions with their current domain counts (derived from domain_rules).
@@ -581,3 +770,150 @@ fn validate_list_subscription_url(url: &str) -> Result<(), ServiceError> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::setup_core_test_db;
+
+    fn name(s: &str) -> NormalizedDomain {
+        reso_list::normalize(s).unwrap()
+    }
+
+    fn wire_name(name: &DomainName) -> NormalizedDomain {
+        NormalizedDomain::from_labels(name.label_iter()).unwrap()
+    }
+
+    async fn insert_rule(
+        db: &CoreDatabasePool,
+        domain: &str,
+        action: ListAction,
+        group_id: Option<EntityId<RuleGroup>>,
+    ) {
+        let mut rule = DomainRule::new(domain.into());
+        rule.action = action;
+        rule.group_id = group_id;
+        domain_rule::insert(db, rule).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_group_rules_only_apply_to_assigned_clients() {
+        let db = setup_core_test_db().await.unwrap();
+        let kids = RuleGroup::new("kids".into());
+        let adults = RuleGroup::new("adults".into());
+        rule_group::insert(&db.conn, kids.clone()).await.unwrap();
+        rule_group::insert(&db.conn, adults.clone()).await.unwrap();
+
+        let kid: IpAddr = "192.168.1.20".parse().unwrap();
+        let adult: IpAddr = "192.168.1.30".parse().unwrap();
+        let unassigned: IpAddr = "192.168.1.40".parse().unwrap();
+        rule_group::assign_client(&db.conn, kid, kids.id.clone()).await.unwrap();
+        rule_group::assign_client(&db.conn, adult, adults.id).await.unwrap();
+
+        insert_rule(&db.conn, "games.example", ListAction::Block, Some(kids.id)).await;
+
+        let matchers = Matchers::load(&db.conn).await.unwrap();
+
+        assert!(matchers.is_blocked(&name("games.example"), kid));
+        assert!(matchers.is_blocked(&name("play.games.example"), kid));
+        assert!(!matchers.is_blocked(&name("games.example"), adult));
+        assert!(!matchers.is_blocked(&name("games.example"), unassigned));
+    }
+
+    #[tokio::test]
+    async fn test_group_allow_overrides_global_block() {
+        let db = setup_core_test_db().await.unwrap();
+        let admins = RuleGroup::new("admins".into());
+        rule_group::insert(&db.conn, admins.clone()).await.unwrap();
+
+        let admin: IpAddr = "10.0.0.2".parse().unwrap();
+        let other: IpAddr = "10.0.0.3".parse().unwrap();
+        rule_group::assign_client(&db.conn, admin, admins.id.clone())
+            .await
+            .unwrap();
+
+        insert_rule(&db.conn, "ads.example", ListAction::Block, None).await;
+        insert_rule(&db.conn, "metrics.ads.example", ListAction::Allow, Some(admins.id)).await;
+
+        let matchers = Matchers::load(&db.conn).await.unwrap();
+
+        assert!(matchers.is_blocked(&name("ads.example"), admin));
+        assert!(!matchers.is_blocked(&name("metrics.ads.example"), admin));
+        assert!(matchers.is_blocked(&name("metrics.ads.example"), other));
+    }
+
+    #[tokio::test]
+    async fn test_group_allow_overrides_exact_global_block() {
+        let db = setup_core_test_db().await.unwrap();
+        let admins = RuleGroup::new("admins".into());
+        rule_group::insert(&db.conn, admins.clone()).await.unwrap();
+        let admin: IpAddr = "10.0.0.2".parse().unwrap();
+        let other: IpAddr = "10.0.0.3".parse().unwrap();
+        rule_group::assign_client(&db.conn, admin, admins.id.clone())
+            .await
+            .unwrap();
+
+        let service = DomainRulesService::initialize(Arc::new(db.conn)).await.unwrap();
+        let qname = DomainName::from_ascii("tracker.example").unwrap();
+
+        // the same domain has a global rule and a rule in the group.
+        service
+            .add_domain("tracker.example", MatchType::Exact, ListAction::Block, None)
+            .await
+            .unwrap();
+        service
+            .add_domain(
+                "tracker.example",
+                MatchType::Exact,
+                ListAction::Allow,
+                Some(admins.id.clone()),
+            )
+            .await
+            .unwrap();
+
+        assert!(!service.is_blocked(&qname, admin));
+        assert!(service.is_blocked(&qname, other));
+
+        // a second rule for the domain in the same group conflicts.
+        let duplicate = service
+            .add_domain(
+                "tracker.example",
+                MatchType::Exact,
+                ListAction::Block,
+                Some(admins.id.clone()),
+            )
+            .await;
+        assert!(matches!(duplicate, Err(ServiceError::Conflict(_))));
+
+        // removing the group rule leaves the global one in place.
+        service
+            .remove_domain("tracker.example", Some(&admins.id))
+            .await
+            .unwrap();
+        assert!(service.is_blocked(&qname, admin));
+    }
+
+    #[tokio::test]
+    async fn test_unicode_rule_matches_punycode_query() {
+        let db = setup_core_test_db().await.unwrap();
+        insert_rule(&db.conn, "bücher.example", ListAction::Block, None).await;
+        let client: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let matchers = Matchers::load(&db.conn).await.unwrap();
+        let qname = DomainName::from_ascii("www.xn--bcher-kva.example").unwrap();
+
+        assert!(matchers.is_blocked(&wire_name(&qname), client));
+    }
+
+    #[tokio::test]
+    async fn test_punycode_rule_matches_unicode_query() {
+        let db = setup_core_test_db().await.unwrap();
+        insert_rule(&db.conn, "xn--bcher-kva.example", ListAction::Block, None).await;
+        let client: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let matchers = Matchers::load(&db.conn).await.unwrap();
+        let qname = DomainName::from_labels(&["www".as_bytes(), "bücher".as_bytes(), b"example"]).unwrap();
+
+        assert!(matchers.is_blocked(&wire_name(&qname), client));
+    }
+}
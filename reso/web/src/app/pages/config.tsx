@@ -40,7 +40,7 @@ export default function ConfigPage() {
 				rate_limit: data.rate_limit,
 				security: data.security,
 			},
-			logs: data.logs,
+			logs: { ...config.data.logs, ...data.logs },
 		};
 
 		updateConfig.mutate(updatedConfig, {
@@ -83,18 +83,18 @@ export default function DomainRulesPage() {
 		setShowRuleDialog(false);
 	};
 
-	const handleRemoveRule = async (domain: string) => {
+	const handleRemoveRule = async (rule: DomainRule) => {
 		try {
-			await removeRule.mutateAsync(domain);
+			await removeRule.mutateAsync(rule);
 		} catch (e) {
 			toastError(e);
 			return;
 		}
 		invalidateRules();
-		if (editingRule?.domain === domain) setEditingRule(null);
+		if (editingRule?.id === rule.id) setEditingRule(null);
 	};
 
-	const handleToggleRule = async (domain: string) => {
+	const handleToggleRule = async (rule: DomainRule) => {
 		const previous = queryClient.getQueryData<PagedResponse<DomainRule>>(
 			domainRulesQueryKey(page, debouncedSearch),
 		);
@@ -105,14 +105,14 @@ export default function DomainRulesPage() {
 				return {
 					...old,
 					items: old.items.map((d) =>
-						d.domain === domain ? { ...d, enabled: !d.enabled } : d,
+						d.id === rule.id ? { ...d, enabled: !d.enabled } : d,
 					),
 				};
 			},
 		);
 
 		try {
-			await toggleRule.mutateAsync(domain);
+			await toggleRule.mutateAsync(rule);
 		} catch (e) {
 			queryClient.setQueryData(
 				domainRulesQueryKey(page, debouncedSearch),
@@ -122,8 +122,9 @@ export default function DomainRulesPage() {
 		}
 	};
 
-	const handleEditRule = async (domain: string, action: ListAction) => {
-		await updateRule.mutateAsync({ domain, action });
+	const handleEditRule = async (action: ListAction) => {
+		if (!editingRule) return;
+		await updateRule.mutateAsync({ rule: editingRule, action });
 		invalidateRules();
 		setEditingRule(null);
 	};
@@ -25,8 +25,8 @@ interface DomainRulesGridProps {
 	onPageChange: (page: number) => void;
 	search: string;
 	onSearchChange: (value: string) => void;
-	onRemove: (domain: string) => void;
-	onToggle: (domain: string) => void;
+	onRemove: (rule: DomainRule) => void;
+	onToggle: (rule: DomainRule) => void;
 	onEdit: (rule: DomainRule) => void;
 	isLoading: boolean;
 }
@@ -97,7 +97,7 @@ export function DomainRulesGrid({
 						<ToggleButton
 							enabled={row.original.enabled}
 							label='rule'
-							onToggle={() => onToggle(row.original.domain)}
+							onToggle={() => onToggle(row.original)}
 						/>
 					</Table.Cell>
 				),
@@ -126,7 +126,7 @@ export function DomainRulesGrid({
 				header: '',
 				cell: ({ row }) => (
 					<ConfirmDeleteButton
-						onConfirm={() => onRemove(row.original.domain)}
+						onConfirm={() => onRemove(row.original)}
 					/>
 				),
 			}),
@@ -19,7 +19,7 @@ import { MatchTypeBadge } from '@/components/MatchTypeBadge';
 interface EditRuleDialogProps {
 	rule: DomainRule;
 	onClose: () => void;
-	onSubmit: (domain: string, action: ListAction) => Promise<void>;
+	onSubmit: (action: ListAction) => Promise<void>;
 }
 
 export function EditRuleDialog({
@@ -36,7 +36,7 @@ export function EditRuleDialog({
 		setIsSubmitting(true);
 		setError(null);
 		try {
-			await onSubmit(rule.domain, action);
+			await onSubmit(action);
 		} catch (e) {
 			setError(await getErrorMessage(e));
 		} finally {
@@ -1,9 +1,11 @@
 import { useMutation } from '@tanstack/react-query';
 import { useApiClient } from '@/contexts/ApiClientContext';
+import type { DomainRule } from '@/lib/api/domain-rules';
 
 export function useRemoveDomainRule() {
 	const apiClient = useApiClient();
 	return useMutation({
-		mutationFn: async (domain: string) => apiClient.domainRules.remove(domain),
+		mutationFn: async (rule: DomainRule) =>
+			apiClient.domainRules.remove(rule.domain, rule.group_id),
 	});
 }
@@ -1,9 +1,11 @@
 import { useMutation } from '@tanstack/react-query';
 import { useApiClient } from '@/contexts/ApiClientContext';
+import type { DomainRule } from '@/lib/api/domain-rules';
 
 export function useToggleDomainRule() {
 	const apiClient = useApiClient();
 	return useMutation({
-		mutationFn: async (domain: string) => apiClient.domainRules.toggle(domain),
+		mutationFn: async (rule: DomainRule) =>
+			apiClient.domainRules.toggle(rule.domain, rule.group_id),
 	});
 }
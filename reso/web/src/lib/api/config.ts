@@ -29,9 +29,15 @@ export interface LogsConfig {
 	enabled: boolean;
 	retention_secs: number;
 	truncate_interval_secs: number;
+	client_limit: number;
+	client_sample_rate: number;
 }
 
-export type ActiveResolver = 'forwarder';
+export type ActiveResolver =
+	| 'forwarder'
+	| { static: { zones: string[] } }
+	| { ptr: { prefixes: string[]; hosts?: Record<string, string>; domain?: string | null } }
+	| { chain: { resolvers: ActiveResolver[] } };
 
 export interface RateLimitConfig {
 	enabled: boolean;
@@ -45,6 +51,37 @@ export interface DnsConfig {
 	forwarder: ForwarderConfig;
 	rate_limit: RateLimitConfig;
 	security: SecurityConfig;
+	blocking: BlockingConfig;
+	minimal_responses: boolean;
+	minimal_any: boolean;
+	rotate_answers: boolean;
+	cache_bypass: string[];
+	cache_ttl_floors: Record<string, number>;
+	cache_ttl_jitter_percent: number;
+	negative_stale_secs: number;
+	nsid: string;
+	chaos_version: string;
+	chaos_id: string;
+	blocked_qtypes: string[];
+	recv_size: number;
+	max_udp_response: number;
+	max_concurrent_requests: number;
+	max_tcp_connections: number;
+	tcp_idle_timeout: number;
+	tcp_read_timeout: number;
+	udp_timeout: number;
+	tcp_timeout: number;
+	doh_timeout: number;
+}
+
+export type BlockResponsePolicy = 'nxdomain' | 'refused' | 'nodata' | 'sinkhole';
+
+export interface BlockingConfig {
+	response_policy: BlockResponsePolicy;
+	sinkhole_ipv4: string;
+	sinkhole_ipv6: string;
+	ttl: number;
+	blocked_ips: string[];
 }
 
 export interface SecurityConfig {
@@ -55,6 +92,17 @@ export interface SecurityConfig {
 
 export type Upstream = string;
 
+export type EcsMode = 'off' | 'zeroed' | { forward: { v4_prefix: number; v6_prefix: number } };
+
+export type UpstreamSelection = 'round_robin' | 'latency';
+
+export type EdnsOptionFilter = 'all' | 'known' | { allow: number[] };
+
 export interface ForwarderConfig {
 	upstreams: string[];
+	edns_udp_payload_size: number;
+	ecs_mode: EcsMode;
+	upstream_selection: UpstreamSelection;
+	edns_options: EdnsOptionFilter;
+	tcp_fast_open: boolean;
 }
@@ -26,8 +26,10 @@ export class DomainRules {
 		return await response.json<PagedResponse<DomainRule>>();
 	}
 
-	public async remove(domain: string) {
-		await this.httpClient.delete('api/domain-rules', { json: { domain } });
+	public async remove(domain: string, groupId: string | null = null) {
+		await this.httpClient.delete('api/domain-rules', {
+			json: { domain, group_id: groupId },
+		});
 	}
 
 	public async create(
@@ -40,14 +42,20 @@ export class DomainRules {
 		});
 	}
 
-	public async toggle(domain: string) {
+	public async toggle(domain: string, groupId: string | null = null) {
 		await this.httpClient.patch('api/domain-rules/toggle', {
-			json: { domain },
+			json: { domain, group_id: groupId },
 		});
 	}
 
-	public async update(domain: string, action: ListAction) {
-		await this.httpClient.put('api/domain-rules', { json: { domain, action } });
+	public async update(
+		domain: string,
+		action: ListAction,
+		groupId: string | null = null,
+	) {
+		await this.httpClient.put('api/domain-rules', {
+			json: { domain, action, group_id: groupId },
+		});
 	}
 }
 
@@ -59,4 +67,5 @@ export interface DomainRule {
 	created_at: number;
 	enabled: boolean;
 	subscription_id: string | null;
+	group_id: string | null;
 }
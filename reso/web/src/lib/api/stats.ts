@@ -13,6 +13,16 @@ export class Stats {
 		return json;
 	}
 
+	public async cache() {
+		const response = await this.httpClient.get('api/stats/cache');
+		return response.json<CacheStats>();
+	}
+
+	public async truncation() {
+		const response = await this.httpClient.get('api/stats/truncation');
+		return response.json<TruncationStats>();
+	}
+
 	public async top(range: TopRange) {
 		const response = await this.httpClient.get('api/stats/top', {
 			searchParams: { range },
@@ -33,10 +43,25 @@ export interface LiveStats {
 	blocked: number;
 	cached: number;
 	errors: number;
+	udp: number;
+	tcp: number;
+	doh: number;
 	sum_duration: number;
 	live_since: number;
 }
 
+export interface CacheStats {
+	entries: number;
+	size_evictions: number;
+	expirations: number;
+}
+
+export interface TruncationStats {
+	truncated_responses: number;
+	upstream_truncated: number;
+	tcp_fallbacks: number;
+}
+
 export type TopRange =
 	| '5min'
 	| 'hour'